@@ -0,0 +1,450 @@
+#![allow(non_snake_case)]
+
+//! Core stacking logic, usable independently of the `imgstack` CLI (e.g.
+//! from a GUI embedding this crate). This is a deliberately small surface:
+//! it covers the associative combine modes that only need the input images
+//! themselves, not file paths, calibration frames, or CLI-only concerns
+//! like progress reporting. The full command-line tool builds on top of
+//! this with its own richer pipeline (video decoding, dark/flat
+//! calibration, streaming reduce for `sigma-clip`/`median`/`percentile`,
+//! EXIF handling, and so on), which doesn't fit a "stack some images"
+//! function signature and stays in `main.rs`.
+//!
+//! ```
+//! use image::{DynamicImage, RgbImage, Rgb};
+//! use imgstack::{stack, Mode};
+//!
+//! let a = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([10, 10, 10])));
+//! let b = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([20, 20, 20])));
+//! let c = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([30, 30, 30])));
+//!
+//! let result = stack([a, b, c], Mode::Average).unwrap();
+//! assert_eq!(result.to_rgb8().get_pixel(0, 0), &Rgb([20, 20, 20]));
+//! ```
+//!
+//! [`Stacker`] covers the same modes incrementally, for callers (e.g. a live
+//! capture loop) that want to feed frames in one at a time instead of
+//! collecting a fixed list up front:
+//!
+//! ```
+//! use image::{DynamicImage, RgbImage, Rgb};
+//! use imgstack::{Stacker, Mode};
+//!
+//! let mut stacker = Stacker::new(2, 2, Mode::Average);
+//! stacker.push(&DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([10, 10, 10])))).unwrap();
+//! stacker.push(&DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([20, 20, 20])))).unwrap();
+//! let result = stacker.finish().unwrap();
+//! assert_eq!(result.to_rgb8().get_pixel(0, 0), &Rgb([15, 15, 15]));
+//! ```
+//!
+//! [`stack`] only covers [`Mode`]'s fixed set of reductions; implement
+//! [`StackOp`] and call [`stack_with`] to plug in your own without waiting
+//! for a new variant:
+//!
+//! ```
+//! use image::{DynamicImage, RgbImage, Rgb};
+//! use imgstack::{stack_with, StackOp};
+//!
+//! struct MaxChannelOp;
+//! impl StackOp for MaxChannelOp {
+//!     type Acc = u8;
+//!     fn init(&self, sample: Rgb<u8>) -> u8 { sample.0.into_iter().max().unwrap() }
+//!     fn accumulate(&self, acc: &mut u8, sample: Rgb<u8>) {
+//!         *acc = (*acc).max(sample.0.into_iter().max().unwrap());
+//!     }
+//!     fn finalize(&self, acc: u8, _count: u32) -> Rgb<u8> { Rgb([acc, acc, acc]) }
+//! }
+//!
+//! let a = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([10, 40, 10])));
+//! let b = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([50, 20, 10])));
+//! let result = stack_with([a, b], MaxChannelOp).unwrap();
+//! assert_eq!(result.to_rgb8().get_pixel(0, 0), &Rgb([50, 50, 50]));
+//! ```
+
+use anyhow::{anyhow, Result as AResult};
+use image::{DynamicImage, Pixel, Rgb, Rgb32FImage, RgbImage};
+
+/// Combine modes exposed by the library API. This is a subset of the CLI's
+/// full `Mode` enum: streaming modes (`sigma-clip`, `median`, `percentile`),
+/// `difference`, and `alpha-over` all need extra parameters or per-frame
+/// ordering beyond what `stack` takes, so they aren't exposed here yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+	Sum,
+	SumOverflow,
+	Min,
+	Max,
+	Average,
+	StdDev,
+}
+
+fn u8CombineOp(mode: Mode) -> fn(u8, u8) -> u8 {
+	match mode {
+		Mode::Sum => |a, b| a.saturating_add(b),
+		Mode::SumOverflow => |a, b| a.wrapping_add(b),
+		Mode::Min => u8::min,
+		Mode::Max => u8::max,
+		Mode::Average | Mode::StdDev => unreachable!("Average/StdDev accumulate in f32, not u8"),
+	}
+}
+
+/// A per-pixel reduction rule, letting a caller plug in a custom combine op
+/// via [`stack_with`] without waiting for a new [`Mode`] variant. Every
+/// built-in `Mode` is itself just a `StackOp` impl (see `SumOp` and friends
+/// below); `stack` dispatches to them internally, so a custom op costs
+/// nothing extra over the built-ins.
+pub trait StackOp {
+	/// Per-pixel running state, seeded from that pixel's first sample.
+	type Acc;
+	fn init(&self, sample: Rgb<u8>) -> Self::Acc;
+	fn accumulate(&self, acc: &mut Self::Acc, sample: Rgb<u8>);
+	fn finalize(&self, acc: Self::Acc, count: u32) -> Rgb<u8>;
+}
+
+/// `Mode::Sum`: saturating per-channel addition.
+pub struct SumOp;
+impl StackOp for SumOp {
+	type Acc = Rgb<u8>;
+	fn init(&self, sample: Rgb<u8>) -> Rgb<u8> {
+		sample
+	}
+	fn accumulate(&self, acc: &mut Rgb<u8>, sample: Rgb<u8>) {
+		acc.apply2(&sample, u8CombineOp(Mode::Sum));
+	}
+	fn finalize(&self, acc: Rgb<u8>, _count: u32) -> Rgb<u8> {
+		acc
+	}
+}
+
+/// `Mode::SumOverflow`: wrapping per-channel addition.
+pub struct SumOverflowOp;
+impl StackOp for SumOverflowOp {
+	type Acc = Rgb<u8>;
+	fn init(&self, sample: Rgb<u8>) -> Rgb<u8> {
+		sample
+	}
+	fn accumulate(&self, acc: &mut Rgb<u8>, sample: Rgb<u8>) {
+		acc.apply2(&sample, u8CombineOp(Mode::SumOverflow));
+	}
+	fn finalize(&self, acc: Rgb<u8>, _count: u32) -> Rgb<u8> {
+		acc
+	}
+}
+
+/// `Mode::Min`: per-channel minimum.
+pub struct MinOp;
+impl StackOp for MinOp {
+	type Acc = Rgb<u8>;
+	fn init(&self, sample: Rgb<u8>) -> Rgb<u8> {
+		sample
+	}
+	fn accumulate(&self, acc: &mut Rgb<u8>, sample: Rgb<u8>) {
+		acc.apply2(&sample, u8::min);
+	}
+	fn finalize(&self, acc: Rgb<u8>, _count: u32) -> Rgb<u8> {
+		acc
+	}
+}
+
+/// `Mode::Max`: per-channel maximum.
+pub struct MaxOp;
+impl StackOp for MaxOp {
+	type Acc = Rgb<u8>;
+	fn init(&self, sample: Rgb<u8>) -> Rgb<u8> {
+		sample
+	}
+	fn accumulate(&self, acc: &mut Rgb<u8>, sample: Rgb<u8>) {
+		acc.apply2(&sample, u8::max);
+	}
+	fn finalize(&self, acc: Rgb<u8>, _count: u32) -> Rgb<u8> {
+		acc
+	}
+}
+
+/// `Mode::Average`: per-channel mean, accumulated in `f32` to avoid overflow
+/// and rounded to the nearest `u8` at the end.
+pub struct AverageOp;
+impl StackOp for AverageOp {
+	type Acc = [f32; 3];
+	fn init(&self, sample: Rgb<u8>) -> [f32; 3] {
+		sample.0.map(|c| c as f32)
+	}
+	fn accumulate(&self, acc: &mut [f32; 3], sample: Rgb<u8>) {
+		for (a, b) in acc.iter_mut().zip(sample.0) {
+			*a += b as f32;
+		}
+	}
+	fn finalize(&self, acc: [f32; 3], count: u32) -> Rgb<u8> {
+		Rgb(acc.map(|v| (v / count as f32).round().clamp(0.0, 255.0) as u8))
+	}
+}
+
+/// `Mode::StdDev`: per-channel population standard deviation, via a running
+/// sum and sum-of-squares.
+pub struct StdDevOp;
+impl StackOp for StdDevOp {
+	type Acc = ([f32; 3], [f32; 3]);
+	fn init(&self, sample: Rgb<u8>) -> ([f32; 3], [f32; 3]) {
+		let v = sample.0.map(|c| c as f32);
+		(v, v.map(|c| c * c))
+	}
+	fn accumulate(&self, (sum, sumSq): &mut ([f32; 3], [f32; 3]), sample: Rgb<u8>) {
+		for i in 0..3 {
+			let v = sample.0[i] as f32;
+			sum[i] += v;
+			sumSq[i] += v * v;
+		}
+	}
+	fn finalize(&self, (sum, sumSq): ([f32; 3], [f32; 3]), count: u32) -> Rgb<u8> {
+		let n = count as f32;
+		Rgb(std::array::from_fn(|i| {
+			let mean = sum[i] / n;
+			((sumSq[i] / n - mean * mean).max(0.0).sqrt()).round().clamp(0.0, 255.0) as u8
+		}))
+	}
+}
+
+/// Stacks `inputs` together using a custom [`StackOp`], the same way
+/// [`stack`] does for a built-in [`Mode`]. This is the extension point for
+/// reductions beyond the built-in set: implement `StackOp` and pass an
+/// instance here instead of waiting for a new `Mode` variant. Every input is
+/// converted to 8-bit RGB first, same as `stack`. Returns an error if
+/// `inputs` is empty, or if a later frame's dimensions don't match the
+/// first's.
+pub fn stack_with<Op: StackOp>(inputs: impl IntoIterator<Item = DynamicImage>, op: Op) -> AResult<DynamicImage> {
+	let mut inputs = inputs.into_iter().map(|img| img.into_rgb8());
+	let first = inputs.next().ok_or_else(|| anyhow!("No images given to stack"))?;
+	let (width, height) = first.dimensions();
+
+	let mut accs: Vec<Op::Acc> = first.pixels().map(|&sample| op.init(sample)).collect();
+	let mut count = 1u32;
+	for frame in inputs {
+		if frame.dimensions() != (width, height) {
+			return Err(anyhow!(
+				"stack_with expected every frame to be {width}x{height} but got {}x{}",
+				frame.width(),
+				frame.height()
+			));
+		}
+		for (acc, &sample) in accs.iter_mut().zip(frame.pixels()) {
+			op.accumulate(acc, sample);
+		}
+		count += 1;
+	}
+
+	let mut result = RgbImage::new(width, height);
+	for (pixel, acc) in result.pixels_mut().zip(accs) {
+		*pixel = op.finalize(acc, count);
+	}
+	Ok(DynamicImage::ImageRgb8(result))
+}
+
+/// Stacks `inputs` together using `mode`, returning a single combined image.
+/// Every input is converted to 8-bit RGB first (this entry point trades HDR
+/// precision and grayscale/alpha handling for simplicity; use the CLI
+/// directly if you need those). Returns an error if `inputs` is empty.
+/// Each `Mode` is just a built-in [`StackOp`] under the hood (see
+/// [`stack_with`] to plug in your own).
+pub fn stack(inputs: impl IntoIterator<Item = DynamicImage>, mode: Mode) -> AResult<DynamicImage> {
+	match mode {
+		Mode::Sum => stack_with(inputs, SumOp),
+		Mode::SumOverflow => stack_with(inputs, SumOverflowOp),
+		Mode::Min => stack_with(inputs, MinOp),
+		Mode::Max => stack_with(inputs, MaxOp),
+		Mode::Average => stack_with(inputs, AverageOp),
+		Mode::StdDev => stack_with(inputs, StdDevOp),
+	}
+}
+
+fn imageToF32(img: &RgbImage) -> Rgb32FImage {
+	DynamicImage::ImageRgb8(img.clone()).into_rgb32f()
+}
+
+/// The running accumulator behind a [`Stacker`], holding exactly the state
+/// `stack` would otherwise fold in one pass: nothing yet, a running u8
+/// combine, or a running f32 sum (plus sum-of-squares for `StdDev`). Plain
+/// data with no file handles or other CLI-only state, so a caller wanting
+/// `--checkpoint`-style resumability across sessions can serialize a
+/// `Stacker`'s fields itself.
+enum StackerState {
+	U8(RgbImage),
+	Average { sum: Rgb32FImage },
+	StdDev { sum: Rgb32FImage, sumSq: Rgb32FImage },
+}
+
+/// Incremental counterpart to [`stack`], for callers (e.g. a live capture
+/// loop) that want to push frames in as they arrive rather than handing
+/// `stack` a fixed list. Holds the same running accumulator `stack` folds
+/// internally; `push` folds one frame in at a time, and `finish` produces
+/// the same result `stack` would have for the same frames in the same
+/// order.
+pub struct Stacker {
+	mode: Mode,
+	width: u32,
+	height: u32,
+	count: u32,
+	state: Option<StackerState>,
+}
+
+impl Stacker {
+	/// Starts a new accumulation for frames of `width` by `height`, combined
+	/// with `mode`.
+	pub fn new(width: u32, height: u32, mode: Mode) -> Self {
+		Stacker { mode, width, height, count: 0, state: None }
+	}
+
+	/// Folds `img` into the running accumulator. Errors if `img`'s
+	/// dimensions don't match the ones `Stacker` was constructed with.
+	pub fn push(&mut self, img: &DynamicImage) -> AResult<()> {
+		if img.width() != self.width || img.height() != self.height {
+			return Err(anyhow!(
+				"Stacker expected a {}x{} frame but got {}x{}",
+				self.width,
+				self.height,
+				img.width(),
+				img.height()
+			));
+		}
+		let img = img.clone().into_rgb8();
+		self.state = Some(match (self.mode, self.state.take()) {
+			(Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max, None) => StackerState::U8(img),
+			(Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max, Some(StackerState::U8(mut acc))) => {
+				let op = u8CombineOp(self.mode);
+				acc.pixels_mut().zip(img.pixels()).for_each(|(a, b)| a.apply2(b, op));
+				StackerState::U8(acc)
+			},
+			(Mode::Average, None) => StackerState::Average { sum: imageToF32(&img) },
+			(Mode::Average, Some(StackerState::Average { mut sum })) => {
+				let frame = imageToF32(&img);
+				sum.pixels_mut().zip(frame.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a + b));
+				StackerState::Average { sum }
+			},
+			(Mode::StdDev, None) => {
+				let frame = imageToF32(&img);
+				let mut sumSq = frame.clone();
+				sumSq.pixels_mut().for_each(|p| p.apply(|v| v * v));
+				StackerState::StdDev { sum: frame, sumSq }
+			},
+			(Mode::StdDev, Some(StackerState::StdDev { mut sum, mut sumSq })) => {
+				let frame = imageToF32(&img);
+				sum.pixels_mut().zip(frame.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a + b));
+				sumSq.pixels_mut().zip(frame.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a + b * b));
+				StackerState::StdDev { sum, sumSq }
+			},
+			(_, Some(_)) => unreachable!("state is only ever created for `self.mode`, which never changes after `new`"),
+		});
+		self.count += 1;
+		Ok(())
+	}
+
+	/// Produces the combined image from every frame pushed so far. Errors if
+	/// no frames were pushed, matching `stack`'s error for an empty input.
+	pub fn finish(self) -> AResult<DynamicImage> {
+		let count = self.count;
+		match self.state {
+			None => Err(anyhow!("No images given to stack")),
+			Some(StackerState::U8(img)) => Ok(DynamicImage::ImageRgb8(img)),
+			Some(StackerState::Average { mut sum }) => {
+				sum.pixels_mut().for_each(|p| p.apply(|v| v / count as f32));
+				Ok(DynamicImage::ImageRgb32F(sum).into_rgb8().into())
+			},
+			Some(StackerState::StdDev { sum, sumSq }) => {
+				let mut result = sum;
+				result.pixels_mut().zip(sumSq.pixels()).for_each(|(mean, sq)| {
+					mean.apply2(sq, |sum, sumSq| {
+						let mean = sum / count as f32;
+						((sumSq / count as f32) - mean * mean).max(0.0).sqrt()
+					})
+				});
+				Ok(DynamicImage::ImageRgb32F(result).into_rgb8().into())
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn solidImage(width: u32, height: u32, value: u8) -> DynamicImage {
+		DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([value, value, value])))
+	}
+
+	#[test]
+	fn stackSumsWithSaturation() {
+		let result = stack([solidImage(1, 1, 200), solidImage(1, 1, 100)], Mode::Sum).unwrap();
+		assert_eq!(*result.to_rgb8().get_pixel(0, 0), Rgb([255, 255, 255]));
+	}
+
+	#[test]
+	fn stackAveragesToTheMean() {
+		let result = stack([solidImage(1, 1, 10), solidImage(1, 1, 20), solidImage(1, 1, 30)], Mode::Average).unwrap();
+		assert_eq!(*result.to_rgb8().get_pixel(0, 0), Rgb([20, 20, 20]));
+	}
+
+	#[test]
+	fn stackErrorsOnEmptyInput() {
+		assert!(stack(std::iter::empty(), Mode::Sum).is_err());
+	}
+
+	#[test]
+	fn stackerMatchesStackForTheSameFramesAndMode() {
+		let frames = [solidImage(1, 1, 10), solidImage(1, 1, 20), solidImage(1, 1, 30)];
+		let mut stacker = Stacker::new(1, 1, Mode::Average);
+		for frame in &frames {
+			stacker.push(frame).unwrap();
+		}
+		let stackerResult = stacker.finish().unwrap();
+		let stackResult = stack(frames, Mode::Average).unwrap();
+		assert_eq!(*stackerResult.to_rgb8().get_pixel(0, 0), *stackResult.to_rgb8().get_pixel(0, 0));
+	}
+
+	#[test]
+	fn stackerRejectsAMismatchedFrameSize() {
+		let mut stacker = Stacker::new(2, 2, Mode::Sum);
+		assert!(stacker.push(&solidImage(1, 1, 10)).is_err());
+	}
+
+	#[test]
+	fn stackerFinishErrorsWithNoFramesPushed() {
+		assert!(Stacker::new(1, 1, Mode::Sum).finish().is_err());
+	}
+
+	/// A custom `StackOp`, exercising the extension point `stack_with` is
+	/// meant for: takes the per-channel median of exactly 3 samples.
+	struct MedianOfThreeOp;
+	impl StackOp for MedianOfThreeOp {
+		type Acc = Vec<Rgb<u8>>;
+		fn init(&self, sample: Rgb<u8>) -> Vec<Rgb<u8>> {
+			vec![sample]
+		}
+		fn accumulate(&self, acc: &mut Vec<Rgb<u8>>, sample: Rgb<u8>) {
+			acc.push(sample);
+		}
+		fn finalize(&self, acc: Vec<Rgb<u8>>, _count: u32) -> Rgb<u8> {
+			let channel = |i: usize| {
+				let mut values: Vec<u8> = acc.iter().map(|p| p.0[i]).collect();
+				values.sort_unstable();
+				values[values.len() / 2]
+			};
+			Rgb([channel(0), channel(1), channel(2)])
+		}
+	}
+
+	#[test]
+	fn stackWithRunsACustomOp() {
+		let frames = [solidImage(1, 1, 10), solidImage(1, 1, 200), solidImage(1, 1, 20)];
+		let result = stack_with(frames, MedianOfThreeOp).unwrap();
+		assert_eq!(*result.to_rgb8().get_pixel(0, 0), Rgb([20, 20, 20]));
+	}
+
+	#[test]
+	fn stackWithErrorsOnEmptyInput() {
+		assert!(stack_with(std::iter::empty(), SumOp).is_err());
+	}
+
+	#[test]
+	fn stackWithRejectsAMismatchedFrameSize() {
+		assert!(stack_with([solidImage(2, 2, 10), solidImage(1, 1, 10)], SumOp).is_err());
+	}
+}
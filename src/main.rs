@@ -1,14 +1,26 @@
 #![allow(non_snake_case)]
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs::OpenOptions;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result as AResult};
 use clap::{Parser, ValueEnum};
+use ffmpeg_next as ffmpeg;
 use image::buffer::ConvertBuffer;
+use image::codecs::gif::GifEncoder;
 use image::io::Reader as ImageReader;
-use image::{image_dimensions, GenericImageView, Pixel, Rgb32FImage, RgbImage};
+use image::metadata::Orientation;
+use image::{
+	image_dimensions, Delay, DynamicImage, Frame, GrayImage, ImageBuffer, ImageEncoder, Luma, Pixel, Rgb, Rgb32FImage, Rgba, RgbImage, RgbaImage,
+};
+use rayon::prelude::*;
+use rustfft::num_complex::Complex32;
+use rustfft::FftPlanner;
+use tempfile::NamedTempFile;
 
 /// A tool to merge together batches of images, e.g. light painting or faking
 /// long exposures.
@@ -18,135 +30,10057 @@ struct Args {
 	#[arg(short, long)]
 	output: PathBuf,
 
-	/// Input images.
-	#[arg(required = true)]
+	/// Input images or videos.
+	#[arg(required_unless_present = "inputsFrom")]
 	inputs: Vec<PathBuf>,
 
+	/// Suppress the progress bar. Warnings are still printed. Useful for
+	/// scripts and other non-interactive output.
+	#[arg(short, long, default_value_t = false)]
+	quiet: bool,
+
+	/// Print each frame's dimensions, detected format, min/max/mean
+	/// luminance, and decode time as it's processed, above the progress bar.
+	/// Overridden by `--quiet`.
+	#[arg(short, long, default_value_t = false)]
+	verbose: bool,
+
+	/// Descend into subdirectories when an input is a directory, instead of
+	/// only stacking the images directly inside it.
+	#[arg(long, default_value_t = false)]
+	recursive: bool,
+
+	/// Read additional input paths from this file, one per line, appended
+	/// after any given on the command line. Pass `-` to read from stdin
+	/// instead. Blank lines and lines starting with `#` are ignored, and
+	/// relative paths are resolved against this file's directory rather
+	/// than the current working directory.
+	#[arg(long = "inputs-from")]
+	inputsFrom: Option<PathBuf>,
+
+	/// Sorts inputs before processing, for modes where frame order matters
+	/// (`sum-overflow`, `difference`) rather than relying on argument/glob
+	/// order. Ties are broken by path name for determinism.
+	#[arg(long, default_value = "none")]
+	sort: SortOrder,
+
+	/// Caps the number of inputs actually stacked, sampling this many out of
+	/// however many were given (after `--sort`), for a fast representative
+	/// preview of a huge capture set instead of the full stack. A no-op if
+	/// there aren't more inputs than this to begin with. Which frames were
+	/// picked is printed to stderr.
+	#[arg(long = "max-frames")]
+	maxFrames: Option<usize>,
+
+	/// How `--max-frames` picks its subset: `even` (default) takes evenly
+	/// spaced frames across the sequence, including the first and last;
+	/// `random` takes a `--seed`-determined random subset instead.
+	#[arg(long, default_value = "even")]
+	sample: SampleStrategy,
+
+	/// Seed for `--sample random`'s frame selection, so a run can be
+	/// reproduced exactly. Ignored otherwise.
+	#[arg(long, default_value_t = 0)]
+	seed: u64,
+
+	/// Restricts the inputs (after `--sort`) to a `start:end` window,
+	/// Python-slice-style: either side may be omitted (`:200`, `100:`) and
+	/// either side may be negative to count back from the end (`-10:` is the
+	/// last ten frames). Applied before `--max-frames`, and unlike it,
+	/// always picks a contiguous run rather than a representative subset.
+	/// The resolved frame count is printed to stderr.
+	#[arg(long)]
+	range: Option<FrameRange>,
+
+	/// Keeps only every `n`th frame of the (already `--range`-restricted)
+	/// input list, starting with the first. A no-op at the default of `1`.
+	#[arg(long, default_value_t = 1)]
+	step: usize,
+
+	/// Force this decoder for every input instead of guessing the format
+	/// from each file's header. Useful for headerless or mislabeled exports
+	/// that `with_guessed_format` misidentifies.
+	#[arg(long = "input-format")]
+	inputFormat: Option<InputFormat>,
+
+	/// Decode inputs from a memory-mapped view of the file instead of a
+	/// buffered read. Cuts syscall overhead and an extra copy for large
+	/// uncompressed formats (BMP/TIFF) with random-access decoders; video
+	/// and RAW inputs, which decode through ffmpeg/imagepipe directly from
+	/// the path, are unaffected either way.
+	#[arg(long = "mmap", default_value_t = false)]
+	mmap: bool,
+
+	/// Decode inputs in their raw sensor orientation instead of applying
+	/// their EXIF `Orientation` tag first. By default, an input tagged as
+	/// rotated 90°/180°/270° (or flipped) is reoriented to display
+	/// orientation before the dimension check and accumulation, so a mix of
+	/// portrait and landscape captures of the same scene still line up; pass
+	/// this if your files are already normalized and you'd rather skip the
+	/// EXIF read.
+	#[arg(long = "ignore-orientation", default_value_t = false)]
+	ignoreOrientation: bool,
+
 	/// Image processing mode.
 	#[arg(short, long, default_value = "sum")]
 	mode: Mode,
 
+	/// Computes several single-pass modes from one decode of the inputs,
+	/// instead of running `imgstack` once per mode and re-reading/
+	/// re-decoding the whole stack each time (decoding, not accumulating, is
+	/// the expensive part). Comma-separated, e.g. `average,max`. Only
+	/// single-pass modes are supported (`sum`, `sum-overflow`, `min`, `max`,
+	/// `average`, `range`, `stddev`); every other mode needs either the
+	/// all-samples streaming pipeline or its own dedicated code path, so
+	/// can't share a decode with anything else. Overrides `--mode`, and
+	/// requires `--output` to contain a `{mode}` placeholder, substituted
+	/// with each mode's name (e.g. `out-{mode}.png` becomes
+	/// `out-average.png`, `out-max.png`, ...). Deliberately much simpler
+	/// than a normal run: `--align`, calibration frames, `--roi`, weighting,
+	/// and checkpointing aren't supported here.
+	#[arg(long = "modes", value_delimiter = ',')]
+	modes: Option<Vec<Mode>>,
+
+	/// Convenience shortcut for building a master calibration frame out of a
+	/// set of captures (e.g. dark-frame shots to feed `--dark`, or `--bias`,
+	/// on some future stack): picks median for `dark`/`bias` and average for
+	/// `flat`, overriding `--mode` entirely. It's the same underlying
+	/// median/average code either way; this just saves remembering which
+	/// mode goes with which frame type.
+	#[arg(long = "make-master")]
+	makeMaster: Option<MasterKind>,
+
+	/// Run each channel through its own independent mode, comma-separated as
+	/// `<r>,<g>,<b>`, overriding `--mode` entirely. Only modes whose formula
+	/// folds each channel independently qualify (see [`Mode::
+	/// isChannelIndependent`]); everything else (calibration, weighting,
+	/// alignment, `--animate`, and so on) is unsupported here, since none of
+	/// the qualifying modes need it. Useful for e.g. scientific stacks that
+	/// want `max` on one channel and `average` on the others.
+	#[arg(long = "mode-per-channel", value_delimiter = ',')]
+	modePerChannel: Option<Vec<Mode>>,
+
 	/// Allow overwriting output file.
 	#[arg(short = 'y', long, default_value_t = false)]
 	overwrite: bool,
+
+	/// Create the output path's parent directory (and any missing
+	/// ancestors) if it doesn't already exist, instead of failing. Without
+	/// this, a missing output directory is still caught up front, before any
+	/// decoding or stacking work happens, rather than surfacing as a
+	/// filesystem error only once the run is otherwise done.
+	#[arg(long = "create-dirs", default_value_t = false)]
+	createDirs: bool,
+
+	/// Log a warning and drop an input, instead of aborting the whole run,
+	/// if it fails to decode (e.g. a truncated file). The run still fails if
+	/// every input is dropped this way. Doesn't apply to `--dark`, `--flat`,
+	/// or `--mask`, which must always be readable.
+	#[arg(long = "skip-errors", default_value_t = false)]
+	skipErrors: bool,
+
+	/// Fail instead of warning when the same file (after resolving symlinks
+	/// and relative paths) appears more than once in the input list. Useful
+	/// when building a large list by globbing and appending, where a
+	/// duplicate would otherwise silently bias averages and rejections.
+	#[arg(long = "no-duplicates", default_value_t = false)]
+	noDuplicates: bool,
+
+	/// Subtract the per-pixel minimum across the whole stack (the static
+	/// background) from every frame before accumulating. Removes light
+	/// pollution gradients from star trails, leaving just the moving
+	/// highlights. Needs every frame decoded and held at once to compute the
+	/// background first, so it only supports associative modes, not the
+	/// single-pass streaming modes (`sigma-clip`, `median`, etc).
+	#[arg(long = "subtract-background", default_value_t = false)]
+	subtractBackground: bool,
+
+	/// Validate inputs (existence, dimensions, detectable formats) and print
+	/// a summary, without decoding frames or writing output. Exits nonzero
+	/// if anything would fail. Dimensions are queried without a full decode
+	/// where possible, so this stays fast even on large stacks.
+	#[arg(long, default_value_t = false)]
+	check: bool,
+
+	/// Number of worker threads to decode and combine images with.
+	/// Defaults to the number of available CPUs.
+	#[arg(short, long)]
+	threads: Option<usize>,
+
+	/// Sample video inputs at this many frames per second, instead of every
+	/// decoded frame.
+	#[arg(long)]
+	fps: Option<f64>,
+
+	/// Only keep every Nth decoded frame from video inputs. Ignored if `fps`
+	/// is given.
+	#[arg(long = "frame-step")]
+	frameStep: Option<u64>,
+
+	/// Ignore video frames before this timestamp, in seconds.
+	#[arg(long)]
+	start: Option<f64>,
+
+	/// Ignore video frames after this timestamp, in seconds.
+	#[arg(long)]
+	end: Option<f64>,
+
+	/// Fixed rescale divisor for `sum-scaled` mode, applied instead of the
+	/// observed max sample. Useful for keeping brightness comparable across
+	/// separate runs (e.g. `--chunk-size` windows), which would otherwise
+	/// each pick their own max and rescale differently.
+	#[arg(long = "sum-divisor")]
+	sumDivisor: Option<u32>,
+
+	/// Right-shifts `sum-raw` mode's per-channel `u32` sum by this many bits
+	/// before writing it, so the result fits a 16-bit PNG/TIFF output instead
+	/// of needing a floating-point format. Loses the low `n` bits of
+	/// precision; pick the smallest shift that keeps the brightest pixel
+	/// under 65536.
+	#[arg(long = "sum-shift")]
+	sumShift: Option<u32>,
+
+	/// Outlier threshold, in standard deviations, for `sigma-clip` mode. Also
+	/// known as kappa in kappa-sigma clipping terminology.
+	#[arg(long, visible_alias = "kappa", default_value_t = 3.0)]
+	sigma: f32,
+
+	/// Number of reject-and-recompute passes for `sigma-clip` mode.
+	#[arg(long, visible_alias = "sigma-iterations", default_value_t = 2)]
+	iterations: u32,
+
+	/// Stop `sigma-clip`/`winsor-sigma` mode's iteration loop early, per pixel
+	/// and channel, once a pass rejects (or, for `winsor-sigma`, clamps) zero
+	/// samples, rather than always running the full `--sigma-iterations`
+	/// count. `--sigma-iterations` remains the upper bound. Prints the
+	/// average number of iterations actually run, across every pixel and
+	/// channel, to stderr once the stack completes.
+	#[arg(long = "sigma-converge", default_value_t = false)]
+	sigmaConverge: bool,
+
+	/// Percentile to take per pixel and channel, from 0.0 to 100.0, for
+	/// `percentile` mode. 50.0 is equivalent to `median` mode.
+	#[arg(long, default_value_t = 50.0)]
+	percentile: f32,
+
+	/// Take this percentile (0.0 to 100.0) per pixel and channel instead of
+	/// the true maximum, for `max` mode. Rejects a lone hot-pixel frame that
+	/// would otherwise dominate every pixel it touches; `100.0` reduces
+	/// exactly to plain `max`. Needs a per-pixel sample buffer like
+	/// `percentile` mode, so setting this routes `max` through the same
+	/// streaming reduce instead of the cheap pairwise accumulator.
+	#[arg(long = "max-percentile")]
+	maxPercentile: Option<f32>,
+
+	/// Take this percentile (0.0 to 100.0) per pixel and channel instead of
+	/// the true minimum, for `min` mode. Rejects a lone dead-pixel frame;
+	/// `0.0` reduces exactly to plain `min`. Same streaming-reduce tradeoff
+	/// as `--max-percentile`.
+	#[arg(long = "min-percentile")]
+	minPercentile: Option<f32>,
+
+	/// Outlier threshold, in scaled median absolute deviations from the
+	/// median, for `mad-reject` mode.
+	#[arg(long = "mad-threshold", default_value_t = 3.0)]
+	madThreshold: f32,
+
+	/// Fraction of samples to discard from each end before averaging, for
+	/// `trimmed-mean` mode. `0.0` is equivalent to `average` mode; `0.5`
+	/// degenerates to `median` mode.
+	#[arg(long = "trim-fraction", default_value_t = 0.1)]
+	trimFraction: f32,
+
+	/// Row band height for `sigma-clip`/`median`/`percentile` mode's streaming
+	/// reduce, in pixels. Peak memory for those modes is roughly
+	/// `inputs.len() * width * tile-height` pixels, so lowering this trades
+	/// throughput for a smaller peak on very large stacks. Doesn't apply to
+	/// `median` mode's default histogram reducer (see `--median-exact`),
+	/// whose peak memory per band is `256 * width * tile-height` regardless
+	/// of `inputs.len()`.
+	#[arg(long = "tile-height", default_value_t = DEFAULT_TILE_HEIGHT)]
+	tileHeight: u32,
+
+	/// `median` mode normally reduces each band through a 256-bin per-pixel
+	/// histogram, which keeps peak memory independent of how many frames are
+	/// stacked (see `--tile-height`). Streaming sources are always 8-bit
+	/// (see `StreamingSource`), so this produces bit-identical results to
+	/// sorting; this flag falls back to the older sort-the-samples reducer
+	/// anyway, as an escape hatch in case that assumption ever changes or
+	/// the histogram path turns out to have a bug the sorted one doesn't.
+	/// No effect with any other mode.
+	#[arg(long = "median-exact", default_value_t = false)]
+	medianExact: bool,
+
+	/// Writes a grayscale image to this path recording how many samples
+	/// survived rejection at each pixel (averaged across channels), scaled
+	/// so the max observed count maps to 255. Only meaningful for the
+	/// rejection modes (`sigma-clip`, `mad-reject`, `trimmed-mean`); a no-op
+	/// with a warning for every other mode.
+	#[arg(long = "count-map")]
+	countMap: Option<PathBuf>,
+
+	/// Writes an image to this path colorizing how many samples were
+	/// *rejected* (as opposed to `--count-map`'s survivor count) at each
+	/// pixel, from blue (none rejected) through red (most rejected), scaled
+	/// so the max observed rejection count maps to solid red. Makes it easy
+	/// to spot whether a moving object was fully rejected or left ghosts.
+	/// Only meaningful for the rejection modes (`sigma-clip`, `mad-reject`,
+	/// `trimmed-mean`); a no-op with a warning for every other mode.
+	#[arg(long = "rejection-map")]
+	rejectionMap: Option<PathBuf>,
+
+	/// Writes an image to this path encoding how many times each pixel/
+	/// channel overflowed and wrapped around, for `sum-overflow` mode: the
+	/// low byte of the accumulated `u32` sum is unaffected and remains the
+	/// visible output, while this records the high bits (`sum >> 8`, clamped
+	/// to 255) that would otherwise be discarded. Only meaningful for
+	/// `sum-overflow` mode; a no-op with a warning for every other mode.
+	#[arg(long = "overflow-map")]
+	overflowMap: Option<PathBuf>,
+
+	/// Writes an image to this path encoding which input frame "won" at each
+	/// pixel, for the selection-based modes (`focus-stack`, `lighten-luma`,
+	/// `darken-luma`): pixel value N means input frame N contributed that
+	/// pixel. 8-bit grayscale for up to 256 inputs, 16-bit beyond that. Useful
+	/// for visualizing the composition and debugging selection artifacts.
+	/// Only meaningful for those modes; a no-op with a warning for every
+	/// other mode.
+	#[arg(long = "source-map")]
+	sourceMap: Option<PathBuf>,
+
+	/// Writes a JSON object to this path (or to stdout, if `-`) summarizing
+	/// the run: input count, resolution, mode, per-channel min/max/mean of
+	/// the output, elapsed time in seconds, and any warnings printed during
+	/// the run. Purely a sidecar report; never changes the image output.
+	#[arg(long = "stats-json")]
+	statsJson: Option<PathBuf>,
+
+	/// Writes a per-input CSV log to this path (or to stdout, if `-`): one row
+	/// per input with its path, resolution, guessed format, EXIF exposure
+	/// time (if any), applied weight, applied gain, and whether it was kept
+	/// or skipped by `--skip-errors`. Unlike `--stats-json`, this is per-frame
+	/// and meant to be read by a human, not just machine-parsed. Only
+	/// produced by the ordinary weighted decode path, so it forces that path
+	/// even for modes that would otherwise qualify for the streamlined
+	/// pipeline; not supported with `--modes` or `--chunk-size`, which each
+	/// call the stacking path more than once and would overwrite it.
+	#[arg(long)]
+	log: Option<PathBuf>,
+
+	/// Fraction (0.0-1.0) of output channel samples sitting at the 0 or 255
+	/// extremes above which a warning is printed, suggesting `sum-scaled` or
+	/// `average` instead. Catches, e.g., `sum` mode over many frames leaving
+	/// the output almost entirely white, which usually isn't intended. Set
+	/// to `1.0` to disable. Purely a guardrail; never changes the image
+	/// output.
+	#[arg(long = "clip-warn-threshold", default_value_t = 0.5)]
+	clipWarnThreshold: f32,
+
+	/// Same check as `--clip-warn-threshold`, but exits with an error instead
+	/// of a warning once the clipped fraction exceeds this threshold. For
+	/// automated pipelines that want to catch bad parameter choices rather
+	/// than silently write a mostly-clipped output.
+	#[arg(long = "error-on-clip")]
+	errorOnClip: Option<f32>,
+
+	/// Prints wall-clock time, time spent decoding vs. accumulating vs.
+	/// saving, and effective megapixels/second to stderr once the stack
+	/// completes. Purely diagnostic; never changes the image output. The
+	/// decode/accumulate split isn't meaningful for every mode (`alpha-over`
+	/// fuses decode and save into one pass; `--mode-per-channel` fuses decode
+	/// into its own per-channel accumulation), so those report all their
+	/// time under whichever bucket dominates rather than a true split.
+	#[arg(long = "timings", default_value_t = false)]
+	timings: bool,
+
+	/// Multiplier applied to `stddev` mode's output, so faint noise can be
+	/// amplified for inspection. The scaled result is clamped into range
+	/// when written to an 8-bit output format.
+	#[arg(long, default_value_t = 1.0)]
+	stddevScale: f32,
+
+	/// Floor applied to each normalized (`0.0..=1.0`) channel sample before
+	/// taking its log for `geometric-mean` mode, so a single black pixel
+	/// anywhere in the stack doesn't force the whole result to `-inf`.
+	#[arg(long = "geomean-epsilon", default_value_t = 1.0 / 255.0)]
+	geomeanEpsilon: f32,
+
+	/// Floor applied to each normalized (`0.0..=1.0`) channel sample before
+	/// taking its reciprocal for `harmonic-mean` mode, so a single zero pixel
+	/// anywhere in the stack doesn't force the whole result to `inf`.
+	#[arg(long = "harmonic-epsilon", default_value_t = 1.0 / 255.0)]
+	harmonicEpsilon: f32,
+
+	/// How `difference` mode reduces per-frame differences from the base
+	/// frame across the remaining inputs.
+	#[arg(long = "difference-reduce", default_value = "sum")]
+	differenceReduce: DifferenceReduce,
+
+	/// `blend` mode's mix factor between its two inputs: `0.0` is entirely
+	/// the first input, `1.0` is entirely the second. No effect with any
+	/// other mode.
+	#[arg(long, default_value_t = 0.5)]
+	opacity: f32,
+
+	/// Reverses `fade` mode's direction, so the output morphs from the last
+	/// input towards the first instead of first towards last. No effect with
+	/// any other mode.
+	#[arg(long = "fade-reverse", default_value_t = false)]
+	fadeReverse: bool,
+
+	/// Per-frame falloff for `comet` mode's highlight trail: each frame's
+	/// contribution to the trail is scaled by this value raised to its
+	/// distance from the last input, so `1.0` never fades (equivalent to
+	/// `max` for the trail) and smaller values give a shorter, faster-fading
+	/// tail. Must be greater than `0.0` and at most `1.0`. No effect with any
+	/// other mode.
+	#[arg(long = "comet-decay", default_value_t = 0.7)]
+	cometDecay: f32,
+
+	/// Gamma to accumulate in: each input sample is decoded with
+	/// `v.powf(1/gamma)` before accumulation, and the output is re-encoded
+	/// with the inverse, `v.powf(gamma)`. Lets highlights be weighted more or
+	/// less heavily while stacking without changing the final image's
+	/// apparent brightness. `1.0` (the default) is a no-op, producing
+	/// byte-identical results to not passing this flag at all. Independent
+	/// of `--color-space linear`, which always uses the sRGB transfer
+	/// function specifically to fix `average` mode's math; the two compose
+	/// if both are given. Only supported for `sum`/`sum-overflow`/`min`/
+	/// `max`/`average`, since `stddev`'s output is a magnitude rather than a
+	/// sample value and has no principled inverse gamma to apply.
+	#[arg(long, default_value_t = 1.0)]
+	gamma: f32,
+
+	/// Output format, overriding the one inferred from `--output`'s extension.
+	#[arg(long, default_value = "auto")]
+	format: OutputFormat,
+
+	/// PNG compression level, overriding the mapping `--quality` would
+	/// otherwise imply. Only affects PNG output.
+	#[arg(long = "png-compression")]
+	pngCompression: Option<PngCompression>,
+
+	/// Output quality, from 1 (worst) to 100 (best). For `jpeg` this is the
+	/// usual lossy quality; for `png` it's mapped onto the encoder's
+	/// compression level instead (higher means smaller files, not better
+	/// fidelity, since PNG is always lossless). Not meaningful for other
+	/// formats: notably, `tiff`'s encoder has no adjustable compression
+	/// level to map this onto.
+	#[arg(long)]
+	quality: Option<u8>,
+
+	/// Write TIFF output as 32-bit float samples instead of tonemapping (or,
+	/// for a 16-bit-capable HDR result, downsampling) to 8/16-bit integers.
+	/// Preserves full precision for downstream tone mapping. Only affects
+	/// `tiff`/`tif` output; a warning is printed and this is ignored for
+	/// every other format.
+	#[arg(long = "float-output", default_value_t = false)]
+	floatOutput: bool,
+
+	/// Forces the final output's integer pixel depth, independent of the
+	/// save format's own default and the accumulator's precision: `8`
+	/// truncates even a 16-bit/HDR result down to 8 bits per channel
+	/// (dithered per `--dither`/`--rounding`, same as ever), `16` promotes
+	/// even a plain 8-bit result up to 16 bits per channel. Only PNG and
+	/// TIFF can store 16-bit samples; errors if `--bit-depth 16` is combined
+	/// with any other integer format. Leave unset to keep the default of
+	/// writing 16 bits only when the accumulated result is already
+	/// float-precision. Has no effect together with `--float-output` or a
+	/// floating-point format (`hdr`/`exr`), which stay float regardless.
+	#[arg(long = "bit-depth")]
+	bitDepth: Option<u8>,
+
+	/// Alongside `--output`, writes the accumulated float buffer to this
+	/// path as OpenEXR or float TIFF (inferred from the extension), before
+	/// any dithering/tonemapping down to 8 bits. A later `imgstack` run can
+	/// re-ingest it losslessly as a float input, for chaining stacks across
+	/// multiple passes without losing precision in between. A no-op with a
+	/// warning for modes whose result has no float data to preserve (i.e.
+	/// everything except when `--float-output`, a 16-bit/HDR input, or
+	/// `--gamma` forces one).
+	#[arg(long)]
+	intermediate: Option<PathBuf>,
+
+	/// Alongside `--output`, writes every processed input (decoded,
+	/// calibrated, aligned, cropped-to-overlap) as a page of a multi-page
+	/// TIFF at this path, in input order, before the mode-specific
+	/// accumulation that discards them. Unlike `--intermediate`, which
+	/// preserves the one combined result, this preserves the whole aligned
+	/// dataset, so it can be archived or re-stacked in another tool without
+	/// re-running alignment/calibration. Not available with modes that
+	/// stream samples instead of materializing every frame (`sigma-clip`,
+	/// `median`, `--max-percentile`, etc.), or with `alpha-over`/`blend`,
+	/// which don't go through the aligned-frames stage at all.
+	#[arg(long = "stack-tiff")]
+	stackTiff: Option<PathBuf>,
+
+	/// Dither when quantizing a float-precision accumulation result (e.g.
+	/// from `average`, `sum-scaled`, or any HDR input) down to 8-bit output,
+	/// to break up banding in smooth gradients like sky backgrounds. Off by
+	/// default, so existing output is unchanged.
+	#[arg(long, default_value = "none")]
+	dither: Dither,
+
+	/// How to quantize a float-precision accumulation result (e.g. from
+	/// `average`) down to 8-bit output when `--dither` is left at `none`
+	/// (the other dithering modes do their own rounding as part of
+	/// diffusing the error). Defaults to `round`, which fixes the downward
+	/// bias `truncate` used to introduce: a two-frame average of 0 and 1
+	/// rounds to 1, not 0.
+	#[arg(long, default_value = "round")]
+	rounding: Rounding,
+
+	/// Compress a float result's highlights into 0.0-1.0 before 8-bit
+	/// conversion, instead of a hard clamp. Useful for modes that can
+	/// legitimately exceed the display range, like `sum-scaled` or `average`
+	/// in linear color space; without it those highlights just blow out to
+	/// flat white. Operates in linear light, so it interacts correctly with
+	/// `--color-space` and applies uniformly regardless of which one was used
+	/// to accumulate. `none` matches every release before `--tonemap`
+	/// existed.
+	#[arg(long, default_value = "none")]
+	tonemap: Tonemap,
+
+	/// Write a progressive-stacking animation here, with one frame per input
+	/// showing the accumulator after incorporating it. Not supported with
+	/// streaming modes (`sigma-clip`, `median`, `percentile`) or `min` mode
+	/// (a running minimum only ever gets darker, which isn't an interesting
+	/// animation). Frames are downscaled to a manageable size to keep the
+	/// GIF small; use `--delay` to control playback speed (e.g. `1000 / fps`
+	/// for a target frame rate).
+	#[arg(long)]
+	animate: Option<PathBuf>,
+
+	/// Delay between animation frames, in milliseconds.
+	#[arg(long, default_value_t = 100)]
+	delay: u32,
+
+	/// Color space `average` mode accumulates in. Input samples are
+	/// gamma-encoded sRGB, so averaging them directly (`srgb`) is
+	/// mathematically wrong and skews results too dark on gradients;
+	/// `linear` decodes to linear light before averaging and re-encodes
+	/// afterwards, which is correct but changes existing `average` output.
+	#[arg(long = "color-space", default_value = "linear")]
+	colorSpace: ColorSpace,
+
+	/// Precision of the running mean `average`/`fade` accumulate into. `f64`
+	/// trades memory for accuracy on very large or very precise stacks,
+	/// where `f32`'s mantissa can start losing bits as the mean converges.
+	/// Doesn't affect any other mode, or the precision of the saved output
+	/// (see `--bit-depth`/`--float-output` for that).
+	#[arg(long = "accum-precision", default_value = "f32")]
+	accumPrecision: AccumPrecision,
+
+	/// Per-input weights for `average` mode, comma-separated and matching
+	/// `inputs` one-to-one, e.g. `2.0,1.0,1.0` to weight the first input
+	/// twice as heavily as the rest. Frames decoded from a video input all
+	/// inherit that video's weight. Defaults to equal weighting.
+	#[arg(long, value_delimiter = ',')]
+	weights: Option<Vec<f32>>,
+
+	/// Weight `average` mode's inputs by their EXIF `ExposureTime` tag,
+	/// instead of weighting them equally, so longer exposures contribute
+	/// more light. Inputs missing the tag fall back to a weight of 1.0.
+	/// Mutually exclusive with `--weights`.
+	#[arg(long = "weight-by-exposure", default_value_t = false)]
+	weightByExposure: bool,
+
+	/// Weight `average` mode's frames by a focus metric (variance of the
+	/// Laplacian on luminance), instead of weighting them equally, so blurry
+	/// frames contribute less to the result. Unlike `--weights`/
+	/// `--weight-by-exposure`, this is computed per decoded frame rather than
+	/// per input, so a video input's frames can each get their own weight.
+	/// Each frame's computed score is printed to stderr. Mutually exclusive
+	/// with `--weights` and `--weight-by-exposure`.
+	#[arg(long = "weight-by-sharpness", default_value_t = false)]
+	weightBySharpness: bool,
+
+	/// Scales every frame's brightness so its mean luminance matches the
+	/// first frame's, before accumulation. Useful when bracketed frames'
+	/// auto-exposure drifted slightly, which would otherwise pull a plain
+	/// average toward whichever frame happened to be brightest. Unlike
+	/// `--weight-by-exposure`, this rescales pixel values themselves rather
+	/// than the frame's contribution weight. Applied scale factors are
+	/// printed to stderr and clamped so a near-black frame doesn't get
+	/// amplified into noise.
+	#[arg(long = "match-exposure", default_value_t = false)]
+	matchExposure: bool,
+
+	/// Supplies `--match-exposure`'s target mean luminance from this image
+	/// instead of the first input, so a series of separate stacking runs can
+	/// all match the same external reference and stay photometrically
+	/// consistent with each other. Decoded once and dimension-agnostic, since
+	/// only its mean luminance is used. Has no effect without
+	/// `--match-exposure`.
+	#[arg(long = "exposure-reference")]
+	exposureReference: Option<PathBuf>,
+
+	/// Excludes whole frames that differ too much from the rest before
+	/// stacking, e.g. a passing car or cloud shadow in an outdoor timelapse.
+	/// Downscales every frame's luminance to a cheap thumbnail, takes the
+	/// per-pixel median across all of them, and drops any frame whose mean
+	/// absolute difference from that median thumbnail (as a fraction of full
+	/// scale, 0.0-1.0) exceeds this threshold. Complementary to the per-pixel
+	/// rejection modes (`sigma-clip`, `mad-reject`, `trimmed-mean`), which
+	/// reject individual outlier samples rather than whole frames, and much
+	/// cheaper since it only touches thumbnails. Excluded frames are reported
+	/// to stderr. Applied before `--align`/`--subtract-background`/
+	/// `--match-exposure`.
+	#[arg(long = "reject-outlier-frames")]
+	rejectOutlierFrames: Option<f32>,
+
+	/// Multiplies every input's pixel values by a gain factor before
+	/// accumulation, in float, clamped to each frame's valid range afterward.
+	/// Comma-separated matching `inputs` one-to-one, e.g. `1.0,2.0,1.0` to
+	/// double the second input's brightness; frames decoded from a video
+	/// input all inherit that video's gain. A single value (not a list)
+	/// applies uniformly to every input instead. Unlike `--weights`, this
+	/// rescales the pixel values every mode sees, not just `average`'s
+	/// weighted-mean contribution, so it's the right tool when you know the
+	/// exact correction a frame needs rather than a relative weighting.
+	#[arg(long = "input-gain", value_delimiter = ',')]
+	inputGain: Option<Vec<f32>>,
+
+	/// Clamps every input's channel samples into `lo,hi` before accumulation,
+	/// treating a sample outside the range as clamped to the nearest bound
+	/// rather than excluded. `lo,hi` are either raw bytes (`10,240`) or
+	/// already-normalized fractions (`0.04,0.94`); a value above `1.0` is
+	/// assumed to be a byte. For scientific captures with a known valid
+	/// intensity band, e.g. to floor a sensor's fixed black-level offset or
+	/// flatten saturated highlights the same way in every mode. See also
+	/// `--clip-range`, which excludes rather than clamps, for the modes that
+	/// keep a per-pixel sample buffer.
+	#[arg(long = "clamp-range")]
+	clampRange: Option<SampleRange>,
+
+	/// Excludes, rather than clamps, out-of-`lo,hi` samples from the
+	/// per-pixel statistics computed by the streaming-pipeline modes
+	/// (`median`, `percentile`, `sigma-clip`, `winsor-sigma`, `mad-reject`,
+	/// `most-frequent`, `trimmed-mean`, and `max`/`min` when routed through
+	/// `--max-percentile`/`--min-percentile`; see `Mode::needsStreamingPipeline`).
+	/// Has no effect on any other mode, which never builds a per-pixel sample
+	/// buffer to exclude from. If every sample at a pixel falls outside the
+	/// range, falls back to using them all rather than reducing an empty set.
+	/// Same `lo,hi` syntax as `--clamp-range`; the two options compose.
+	#[arg(long = "clip-range")]
+	clipRange: Option<SampleRange>,
+
+	/// Excludes samples at or beyond `--clip-low`/`--clip-high` (pure-black
+	/// or pure-white by default) from the streaming-pipeline modes' per-pixel
+	/// statistics (same modes as `--clip-range`, which this composes with)
+	/// and from `exposure-fusion`'s per-pixel blend weights, since a clipped
+	/// sample carries no real information and shouldn't pull an average or
+	/// win a blend. Has no effect on any other mode, including `average`,
+	/// which keeps a single running weighted mean rather than a per-pixel
+	/// sample buffer to exclude from. If every sample at a pixel is clipped,
+	/// falls back to using them all rather than reducing an empty set.
+	#[arg(long = "ignore-clipped", default_value_t = false)]
+	ignoreClipped: bool,
+
+	/// Lower bound for `--ignore-clipped`, as a raw byte (`0..=255`).
+	#[arg(long = "clip-low", default_value_t = 0)]
+	clipLow: u8,
+
+	/// Upper bound for `--ignore-clipped`, as a raw byte (`0..=255`).
+	#[arg(long = "clip-high", default_value_t = 255)]
+	clipHigh: u8,
+
+	/// Neighborhood radius, in pixels, for smoothing `focus-stack` mode's
+	/// per-pixel sharpness maps before picking the sharpest frame at each
+	/// location. `0` disables smoothing, which makes the selection noisy
+	/// (isolated pixels flickering between frames); larger values favor
+	/// coherent, contiguous regions from the same frame at the cost of a
+	/// less locally-precise focus boundary.
+	#[arg(long = "focus-radius", default_value_t = 2)]
+	focusRadius: u32,
+
+	/// For `--mode average`: split each frame into luma (Y) and chroma
+	/// (Cb/Cr) before stacking, average luma across every frame for low
+	/// noise, and take chroma from `--chroma-source` instead of also
+	/// averaging it, which would otherwise smear a moving subject's color
+	/// across frames. The split and recombination round-trip losslessly
+	/// when every frame agrees.
+	#[arg(long = "luma-chroma-split")]
+	lumaChromaSplit: bool,
+
+	/// Where `--luma-chroma-split` takes its chroma channels from.
+	#[arg(long = "chroma-source", default_value = "median")]
+	chromaSource: ChromaSource,
+
+	/// Per-channel weights `r,g,b` for every luminance calculation in the
+	/// tool (lighten/darken-by-luma, sharpness weighting, exposure matching,
+	/// `--luma-chroma-split`, `--remove-gradient`'s star rejection, `--align`,
+	/// and `--mask`'s threshold), normalized to sum to 1. Defaults to Rec.
+	/// 709; use Rec. 601 (`0.299,0.587,0.114`) to match older tools, or equal
+	/// weighting (`1,1,1`) for scientific monochrome-from-RGB use.
+	#[arg(long = "luma-coeffs", value_delimiter = ',', default_value = "0.2126,0.7152,0.0722")]
+	lumaCoeffs: Vec<f32>,
+
+	/// Crop every input (and the output) to this region before stacking:
+	/// `x,y,w,h`. Must fit within the first image's dimensions.
+	#[arg(long, value_delimiter = ',')]
+	roi: Option<Vec<u32>>,
+
+	/// Resize every input to the first image's dimensions instead of
+	/// aborting when inputs' sizes don't match.
+	#[arg(long)]
+	resize: Option<ResizeFilter>,
+
+	/// Detect star-like bright points in every input and shift each frame to
+	/// line them up with the first, correcting for drift in hand-held or
+	/// untracked astrophotography stacks before combining. Translation-only:
+	/// rotation between frames isn't corrected. Only supported in modes that
+	/// keep every frame's accumulator around (not the streaming modes).
+	#[arg(long)]
+	align: Option<Align>,
+
+	/// How many of the brightest detected stars to use for `--align stars`.
+	/// More stars make the offset vote more robust to a false detection, at
+	/// the cost of detection time.
+	#[arg(long = "align-star-count", default_value_t = 50)]
+	alignStarCount: usize,
+
+	/// Reject a detected `--align phase` offset larger than this many pixels
+	/// (in either axis) as a misdetection, aborting the run instead of
+	/// silently applying a bogus shift. Unset by default, i.e. no limit.
+	#[arg(long = "align-max-shift")]
+	alignMaxShift: Option<u32>,
+
+	/// File of known per-input pixel shifts, skipping `--align`'s detection:
+	/// one `dx dy` pair per line, in input order. Useful when the alignment
+	/// is already known (e.g. computed externally). Mutually exclusive with
+	/// `--align`. Errors if the line count doesn't match the input count.
+	#[arg(long)]
+	offsets: Option<PathBuf>,
+
+	/// After `--align`/`--offsets` shifts every frame, crop the output to the
+	/// rectangle every frame actually covers, instead of leaving the
+	/// partially-covered borders darkened by frames that don't reach them.
+	/// A no-op when no shift ended up being applied.
+	#[arg(long = "crop-overlap", default_value_t = false)]
+	cropOverlap: bool,
+
+	/// Copy EXIF metadata from the first input into the output file,
+	/// refreshing `DateTime` and adding a `Software` tag naming this tool.
+	/// Only supported for jpeg output.
+	#[arg(long = "copy-exif", default_value_t = false)]
+	copyExif: bool,
+
+	/// Master bias frame (zero-exposure read noise) to subtract (saturating)
+	/// from every input before stacking, ahead of `--dark`. Must match the
+	/// inputs' dimensions exactly.
+	#[arg(long)]
+	bias: Option<PathBuf>,
+
+	/// Master dark frame to subtract (saturating) from every input before
+	/// stacking, to remove sensor thermal noise and hot pixels. Must match
+	/// the inputs' dimensions exactly. Applied after `--bias`.
+	#[arg(long)]
+	dark: Option<PathBuf>,
+
+	/// Master flat frame to divide every input by (normalized to the flat's
+	/// own mean level) before stacking, to correct lens vignetting and dust
+	/// shadows. Must match the inputs' dimensions exactly.
+	#[arg(long)]
+	flat: Option<PathBuf>,
+
+	/// List of known hot/dead pixel coordinates (one `x y` pair per line) to
+	/// replace with the per-channel median of their in-bounds neighbors,
+	/// before stacking. Cheaper and more targeted than a statistical
+	/// rejection mode when the sensor's defect map is already known.
+	/// Coordinates outside the input dimensions are skipped with a warning
+	/// rather than failing.
+	#[arg(long = "bad-pixels")]
+	badPixels: Option<PathBuf>,
+
+	/// Grayscale image, the same size as the inputs, restricting which
+	/// pixels participate in stacking: only pixels where the mask is above
+	/// `--mask-threshold` are combined, everything else keeps the first
+	/// input's value untouched.
+	#[arg(long)]
+	mask: Option<PathBuf>,
+
+	/// Minimum `--mask` sample value (0-255) for a pixel to participate in
+	/// stacking. Defaults to 0, i.e. any nonzero mask value counts.
+	#[arg(long = "mask-threshold", default_value_t = 0)]
+	maskThreshold: u8,
+
+	/// Linearly rescale the stacked result so its darkest sample maps to 0
+	/// and its brightest maps to 255, in float, after stacking but before
+	/// saving. Useful after summing many frames, which otherwise leaves the
+	/// result in a narrow dark band. Reports the detected min/max to stderr.
+	#[arg(long)]
+	normalize: bool,
+
+	/// Whether `--normalize` stretches each channel independently or uses
+	/// one shared min/max across all channels. `per-channel` maximizes
+	/// contrast but can shift color balance; `global` preserves color
+	/// balance at the cost of a smaller stretch.
+	#[arg(long = "normalize-mode", default_value = "global")]
+	normalizeMode: NormalizeMode,
+
+	/// Flatten a smooth background gradient (light pollution, vignetting)
+	/// out of the stacked result, after stacking but before `--normalize`.
+	/// Fits a `--gradient-degree` 2D polynomial per channel to whatever's
+	/// left after excluding pixels brighter than 2 standard deviations above
+	/// the mean luminance (assumed to be stars, not background), subtracts
+	/// the fit, and adds back the background's own mean so overall
+	/// brightness is preserved.
+	#[arg(long = "remove-gradient")]
+	removeGradient: bool,
+
+	/// Polynomial order for `--remove-gradient`'s background fit. Higher
+	/// orders can chase more complex gradients but are more prone to
+	/// overfitting a sparse or unevenly distributed set of background
+	/// pixels.
+	#[arg(long = "gradient-degree", default_value_t = 2)]
+	gradientDegree: u32,
+
+	/// Approximate flat-fielding without dedicated flat frames: blur the
+	/// stacked result with a large Gaussian (`--self-flat-radius`) to
+	/// estimate its own vignetting/illumination profile, then divide by that
+	/// profile normalized to its own mean, preserving overall brightness.
+	/// Cruder than a real `--flat` frame (it can't tell dust shadows from
+	/// the scene itself), but a reasonable one-tool approximation for casual
+	/// use. Applied after `--remove-gradient`, before `--white-balance`.
+	#[arg(long = "self-flat")]
+	selfFlat: bool,
+
+	/// Gaussian blur radius, in pixels, for `--self-flat`'s illumination
+	/// profile. Needs to be large enough to wash out stars/subjects and
+	/// leave only the smooth large-scale falloff; too small and it'll try
+	/// to flatten real detail instead of vignetting.
+	#[arg(long = "self-flat-radius", default_value_t = 50.0)]
+	selfFlatRadius: f32,
+
+	/// White-balance the stacked result, after `--remove-gradient` but
+	/// before `--normalize`. `auto` uses a gray-world assumption, scaling
+	/// each channel so its mean matches the other channels'; give an
+	/// explicit `r,g,b` triple of multipliers instead to dial it in by
+	/// hand. Applied scale factors are printed to stderr.
+	#[arg(long = "white-balance")]
+	whiteBalance: Option<WhiteBalance>,
+
+	/// Apply a 1D or 3D `.cube` LUT to the stacked result, after stacking
+	/// (and `--normalize`, if given) but before saving. 3D LUTs are sampled
+	/// with trilinear interpolation.
+	#[arg(long)]
+	lut: Option<PathBuf>,
+
+	/// Write a fast, low-resolution preview here before running the full
+	/// stack, using the same `--mode` but with every input downsampled by
+	/// `--preview-scale` first. Handy for checking framing and mode choice
+	/// on huge files before committing to a full-resolution run. The full
+	/// output is still produced afterwards unless `--preview-only` is given.
+	#[arg(long)]
+	preview: Option<PathBuf>,
+
+	/// How much to shrink each input before accumulating the `--preview`.
+	#[arg(long = "preview-scale", default_value_t = 0.25)]
+	previewScale: f32,
+
+	/// Stop after writing `--preview`, skipping the full-resolution stack.
+	/// Requires `--preview`.
+	#[arg(long = "preview-only")]
+	previewOnly: bool,
+
+	/// Write a side-by-side comparison image here: the first input next to
+	/// the final stacked result, divided by a vertical line, for quick
+	/// visual QA (e.g. eyeballing a denoise/stack's improvement) without
+	/// opening two files. Both halves are downscaled to `--compare-max-width`.
+	/// A convenience output alongside the normal one; doesn't affect it.
+	#[arg(long)]
+	compare: Option<PathBuf>,
+
+	/// Max width, in pixels, either half of `--compare`'s image is
+	/// downscaled to. Never upscales a half already narrower than this.
+	#[arg(long = "compare-max-width", default_value_t = 800)]
+	compareMaxWidth: u32,
+
+	/// Overwrite `--preview-every-path` with the accumulated output so far
+	/// every `N` inputs, so a long run can be watched in an image viewer
+	/// without interrupting it. Only meaningful for cumulative modes (the
+	/// same restriction as `--animate`); other modes get a warning since an
+	/// intermediate result wouldn't mean anything. Downscaled by
+	/// `--preview-scale`, same as `--preview`. The final output is written
+	/// separately once the run completes and is unaffected by this.
+	#[arg(long = "preview-every")]
+	previewEvery: Option<usize>,
+
+	/// Fixed path `--preview-every` repeatedly overwrites. Requires
+	/// `--preview-every`.
+	#[arg(long = "preview-every-path")]
+	previewEveryPath: Option<PathBuf>,
+
+	/// Serializes the running accumulator to this path every
+	/// `--checkpoint-every` inputs, so an interrupted stack can pick up where
+	/// it left off with `--resume` instead of restarting from scratch. Only
+	/// supported for the plain running-accumulator modes (`average`, `fade`,
+	/// `comet`, `std-dev`, `range`, `rms`, `geometric-mean`, `harmonic-mean`,
+	/// `screen`, `multiply`, `sum-scaled`) — every other mode either needs
+	/// every sample present at once or has its own dedicated pipeline
+	/// (streaming modes, `alpha-over`, `exposure-fusion`, `focus-stack`), and
+	/// rejects this. Doesn't support video inputs, whose frame count isn't
+	/// known without decoding them. Forces the same strictly-in-order
+	/// accumulation `--animate`/`--preview-every` use, so it can't fall back
+	/// to the faster bounded producer/consumer pipeline those modes would
+	/// otherwise get.
+	#[arg(long)]
+	checkpoint: Option<PathBuf>,
+
+	/// How often to write `--checkpoint`, in inputs processed. Ignored
+	/// without `--checkpoint`.
+	#[arg(long = "checkpoint-every", default_value_t = 50)]
+	checkpointEvery: usize,
+
+	/// Resumes a stack from a checkpoint written by a previous run's
+	/// `--checkpoint`, skipping the inputs it already folded in and
+	/// continuing accumulation from its saved state. The mode and
+	/// accumulated dimensions must match the checkpoint exactly.
+	#[arg(long)]
+	resume: Option<PathBuf>,
+
+	/// Split the inputs into windows of this many consecutive frames and
+	/// stack each window separately, instead of stacking everything into one
+	/// output. `--output` must contain a `{n}` placeholder, replaced with the
+	/// (0-based) chunk index. Useful for turning a long timelapse or video
+	/// into a sequence of denoised/averaged frames rather than one flat
+	/// stack. A trailing group of fewer than `--chunk-size` leftover inputs
+	/// is dropped, with a warning.
+	#[arg(long = "chunk-size")]
+	chunkSize: Option<usize>,
+
+	/// Step between the start of consecutive chunks, in inputs. Defaults to
+	/// `--chunk-size` itself (non-overlapping, "tumbling" windows); a smaller
+	/// value produces overlapping, "sliding" windows. Only meaningful
+	/// alongside `--chunk-size`.
+	#[arg(long = "chunk-stride")]
+	chunkStride: Option<usize>,
+
+	/// Gaussian standard deviation, in input positions, for weighting each
+	/// window toward its middle frame instead of a flat mean. Meant to pair
+	/// with `--chunk-size` so each output frame is a Gaussian-weighted blend
+	/// of its temporal neighbors, cutting flicker in timelapse/video denoise
+	/// versus a hard boxcar average while still tracking motion better than
+	/// a flat average across the whole window. Mutually exclusive with
+	/// `--weights`/`--weight-by-exposure`/`--weight-by-sharpness`.
+	#[arg(long = "temporal-sigma")]
+	temporalSigma: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PngCompression {
+	Fast,
+	Default,
+	Best,
+}
+
+impl From<PngCompression> for image::codecs::png::CompressionType {
+	fn from(value: PngCompression) -> Self {
+		match value {
+			PngCompression::Fast => image::codecs::png::CompressionType::Fast,
+			PngCompression::Default => image::codecs::png::CompressionType::Default,
+			PngCompression::Best => image::codecs::png::CompressionType::Best,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ResizeFilter {
+	Nearest,
+	Triangle,
+	Lanczos3,
+}
+
+impl From<ResizeFilter> for image::imageops::FilterType {
+	fn from(value: ResizeFilter) -> Self {
+		match value {
+			ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+			ResizeFilter::Triangle => image::imageops::FilterType::Triangle,
+			ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortOrder {
+	/// Current behavior: keep inputs in the order they're given (argument
+	/// order, then glob/directory expansion order).
+	None,
+
+	/// Sort by file name.
+	Name,
+
+	/// Sort by file modification time, so timelapse frames land in capture
+	/// order even if named oddly.
+	Mtime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SampleStrategy {
+	/// Evenly spaced frames across the sequence, including the first and
+	/// last.
+	Even,
+
+	/// A `--seed`-determined random subset.
+	Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Align {
+	/// Match brightest-point (star) centroids between frames and correct
+	/// for the translation between them.
+	Stars,
+
+	/// Estimate the translation between frames via FFT phase correlation on
+	/// a downscaled luminance version. Lighter-weight than `stars`, and
+	/// works on ordinary (non-astro) subjects too.
+	Phase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColorSpace {
+	/// Average gamma-encoded samples directly, matching this tool's
+	/// historical (mathematically incorrect) behavior.
+	Srgb,
+
+	/// Decode to linear light before averaging, and re-encode to sRGB
+	/// afterwards.
+	Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AccumPrecision {
+	/// Accumulate the running mean in `f32`, same as every other mode.
+	/// Default, for compatibility and performance.
+	F32,
+
+	/// Accumulate the running mean in `f64`, trading memory for accuracy on
+	/// very large or very precise stacks. Only affects `Average`/`Fade`,
+	/// whose running mean is the one accumulator that keeps refining its
+	/// value across every frame; other float modes are unaffected.
+	F64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum NormalizeMode {
+	/// Stretch each channel to its own min/max independently, maximizing
+	/// contrast per channel at the risk of shifting color balance.
+	PerChannel,
+
+	/// Stretch all channels together using one shared min/max, preserving
+	/// color balance.
+	Global,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ChromaSource {
+	/// Take Cb/Cr from the per-pixel median across frames, rejecting
+	/// outliers the same way `median` mode would.
+	Median,
+
+	/// Take Cb/Cr from the first frame outright, the cheapest option and
+	/// the one least likely to blend two different colors together.
+	First,
+}
+
+/// `--white-balance <auto|r,g,b>`'s argument: either `auto` for a gray-world
+/// correction, or an explicit `r,g,b` triple of per-channel multipliers.
+/// Not a [`ValueEnum`] since the manual variant carries data; clap picks up
+/// the [`std::str::FromStr`] impl below automatically instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WhiteBalance {
+	/// Scale each channel so its mean matches the average of all three
+	/// channel means (the gray-world assumption).
+	Auto,
+
+	/// Multiply the red, green, and blue channels by these factors.
+	Manual(f32, f32, f32),
+}
+
+impl std::str::FromStr for WhiteBalance {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.eq_ignore_ascii_case("auto") {
+			return Ok(WhiteBalance::Auto);
+		}
+		let parts: Vec<&str> = s.split(',').collect();
+		let [r, g, b] = parts.as_slice() else {
+			return Err(format!("expected `auto` or `r,g,b`, got {s:?}"));
+		};
+		let parseFactor = |s: &str| s.trim().parse::<f32>().map_err(|_| format!("expected a number, got {s:?}"));
+		Ok(WhiteBalance::Manual(parseFactor(r)?, parseFactor(g)?, parseFactor(b)?))
+	}
+}
+
+/// `--clamp-range`/`--clip-range`'s shared `lo,hi` argument. Each side is
+/// either an already-normalized fraction (`0.0..=1.0`) or a raw byte
+/// (`0..=255`); anything above `1.0` is assumed to be a byte and divided by
+/// 255, so `10,240` and `0.039,0.941` describe the same near-black-to-
+/// near-white band. Not a [`ValueEnum`] since it carries data; clap picks up
+/// the [`std::str::FromStr`] impl below automatically instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SampleRange {
+	lo: f32,
+	hi: f32,
+}
+
+impl std::str::FromStr for SampleRange {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (lo, hi) = s.split_once(',').ok_or_else(|| format!("expected `lo,hi`, got {s:?}"))?;
+		let parseChannel = |s: &str| -> Result<f32, String> {
+			let v: f32 = s.trim().parse().map_err(|_| format!("expected a number, got {s:?}"))?;
+			Ok(if v > 1.0 { v / 255.0 } else { v })
+		};
+		let (lo, hi) = (parseChannel(lo)?, parseChannel(hi)?);
+		if !(0.0..=1.0).contains(&lo) || !(0.0..=1.0).contains(&hi) {
+			return Err(format!("expected both sides within 0-255 or 0.0-1.0, got {s:?}"));
+		}
+		if lo > hi {
+			return Err(format!("lo must not exceed hi, got {s:?}"));
+		}
+		Ok(SampleRange { lo, hi })
+	}
+}
+
+/// `--range <start:end>`'s argument: a Python-slice-style window into the
+/// sorted input list. Either side may be omitted (`:100`, `100:`, `:`) to
+/// mean "from the start"/"through the end", and either side may be negative
+/// to count back from the end (`-10:` is the last ten frames). Not a
+/// [`ValueEnum`] since it carries data; clap picks up the
+/// [`std::str::FromStr`] impl below automatically instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FrameRange {
+	start: Option<i64>,
+	end: Option<i64>,
+}
+
+impl std::str::FromStr for FrameRange {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (start, end) = s.split_once(':').ok_or_else(|| format!("expected `start:end`, got {s:?}"))?;
+		let parseIndex = |s: &str| -> Result<Option<i64>, String> {
+			if s.is_empty() {
+				return Ok(None);
+			}
+			s.parse::<i64>().map(Some).map_err(|_| format!("expected an integer, got {s:?}"))
+		};
+		Ok(FrameRange { start: parseIndex(start)?, end: parseIndex(end)? })
+	}
+}
+
+/// Resolves a [`FrameRange`] against `len` inputs, Python-slice-style:
+/// negative indices count back from `len`, and both ends are clamped to
+/// `0..=len` so an out-of-bounds range degrades to an empty or truncated
+/// slice rather than panicking. Returns `start..end` (with `end >= start`).
+fn resolveFrameRange(range: FrameRange, len: usize) -> std::ops::Range<usize> {
+	let resolveIndex = |index: i64| -> usize {
+		let index = if index < 0 { index + len as i64 } else { index };
+		index.clamp(0, len as i64) as usize
+	};
+	let start = range.start.map_or(0, resolveIndex);
+	let end = range.end.map_or(len, resolveIndex);
+	start..end.max(start)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Dither {
+	/// No dithering; round to the nearest 8-bit value. Matches the output of
+	/// every release before `--dither` existed.
+	None,
+
+	/// 4x4 ordered (Bayer) dithering: cheap, no error propagation between
+	/// pixels, produces a mild fixed pattern instead of banding.
+	Bayer,
+
+	/// Floyd–Steinberg error diffusion: propagates each pixel's rounding
+	/// error onto its right/below neighbors. Smoother than Bayer, but
+	/// inherently sequential (each pixel depends on the ones before it), so
+	/// this path isn't parallelized.
+	#[value(alias = "fs")]
+	FloydSteinberg,
+}
+
+/// Frame type built by `--make-master`, picking the mode this kind of master
+/// frame is conventionally combined with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MasterKind {
+	/// Master dark: median, to reject any stray hot pixel or cosmic ray hit
+	/// in a single capture rather than blending it in.
+	Dark,
+
+	/// Master bias: same reasoning, and the same median mode, as `dark`.
+	Bias,
+
+	/// Master flat: average, since flats are deliberately overexposed and
+	/// smooth, and there's no outlier to reject.
+	Flat,
+}
+
+impl MasterKind {
+	fn mode(self) -> Mode {
+		match self {
+			MasterKind::Dark | MasterKind::Bias => Mode::Median,
+			MasterKind::Flat => Mode::Average,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Rounding {
+	/// Truncate toward zero, matching every release before `--rounding`
+	/// existed. Introduces a consistent downward bias (e.g. averaging 0 and
+	/// 1 gives 0, not 1).
+	Truncate,
+
+	/// Round to the nearest 8-bit value.
+	Round,
+
+	/// Round up or down randomly, weighted by the fractional part, so the
+	/// residual quantization error averages to zero across many pixels
+	/// instead of consistently rounding the same direction.
+	Stochastic,
+}
+
+/// How to compress a float result's highlights into displayable range before
+/// 8-bit conversion, applied in linear light. An alternative to the hard
+/// clamp `--rounding`/`--dither` otherwise do implicitly, for modes that can
+/// legitimately produce values above 1.0 (e.g. `sum-scaled`, or `average` in
+/// linear color space).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Tonemap {
+	/// Hard clamp to 0.0-1.0, same as before `--tonemap` existed.
+	None,
+
+	/// `v / (1.0 + v)`: cheap, rolls off highlights smoothly but desaturates
+	/// them toward white.
+	Reinhard,
+
+	/// Narkowicz's fitted approximation of the ACES filmic curve: a punchier
+	/// rolloff with more contrast in the midtones than Reinhard.
+	Aces,
+}
+
+impl Tonemap {
+	/// Maps a linear-light sample into 0.0-1.0. `None` still clamps, so every
+	/// variant produces a value safe to hand straight to 8-bit quantization.
+	fn apply(self, v: f32) -> f32 {
+		match self {
+			Tonemap::None => v.clamp(0.0, 1.0),
+			Tonemap::Reinhard => v / (1.0 + v),
+			Tonemap::Aces => {
+				let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+				((v * (a * v + b)) / (v * (c * v + d) + e)).clamp(0.0, 1.0)
+			},
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+	/// Infer the format from `--output`'s extension.
+	Auto,
+
+	Png,
+	Jpeg,
+	WebP,
+	Avif,
+	Tiff,
+	Bmp,
+	Hdr,
+	#[value(alias = "exr")]
+	OpenExr,
+}
+
+impl OutputFormat {
+	fn resolve(self, outFile: &Path) -> AResult<image::ImageFormat> {
+		match self {
+			OutputFormat::Auto => image::ImageFormat::from_path(outFile)
+				.with_context(|| format!("Guessing output format of {outFile:?}; pass --format explicitly")),
+			OutputFormat::Png => Ok(image::ImageFormat::Png),
+			OutputFormat::Jpeg => Ok(image::ImageFormat::Jpeg),
+			OutputFormat::WebP => Ok(image::ImageFormat::WebP),
+			OutputFormat::Avif => Ok(image::ImageFormat::Avif),
+			OutputFormat::Tiff => Ok(image::ImageFormat::Tiff),
+			OutputFormat::Bmp => Ok(image::ImageFormat::Bmp),
+			OutputFormat::Hdr => Ok(image::ImageFormat::Hdr),
+			OutputFormat::OpenExr => Ok(image::ImageFormat::OpenExr),
+		}
+	}
+
+	/// Whether this format stores samples as floating point, and so should be
+	/// fed the accumulated image directly instead of tonemapping it down to
+	/// 8 bits first.
+	fn isFloatingPoint(format: image::ImageFormat) -> bool {
+		matches!(format, image::ImageFormat::Hdr | image::ImageFormat::OpenExr)
+	}
+}
+
+/// Forces `--input-format` instead of guessing from each file's header via
+/// `with_guessed_format`, for files with wrong or missing extensions/magic
+/// bytes. Doesn't include `OutputFormat::Auto`, since forcing a guess makes
+/// no sense.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+	Png,
+	Jpeg,
+	WebP,
+	Tiff,
+	Bmp,
+	Gif,
+	Hdr,
+	#[value(alias = "exr")]
+	OpenExr,
+}
+
+impl From<InputFormat> for image::ImageFormat {
+	fn from(value: InputFormat) -> Self {
+		match value {
+			InputFormat::Png => image::ImageFormat::Png,
+			InputFormat::Jpeg => image::ImageFormat::Jpeg,
+			InputFormat::WebP => image::ImageFormat::WebP,
+			InputFormat::Tiff => image::ImageFormat::Tiff,
+			InputFormat::Bmp => image::ImageFormat::Bmp,
+			InputFormat::Gif => image::ImageFormat::Gif,
+			InputFormat::Hdr => image::ImageFormat::Hdr,
+			InputFormat::OpenExr => image::ImageFormat::OpenExr,
+		}
+	}
 }
 
-#[derive(Clone, Copy, Debug, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
 enum Mode {
 	/// Saturating sum.
 	Sum,
 
-	/// Overflowing sum.
+	/// Overflowing sum: wraps around on overflow instead of clipping, for the
+	/// psychedelic banding effect that produces. `--overflow-map` records how
+	/// many times each pixel/channel wrapped, which is otherwise thrown away.
 	SumOverflow,
 
+	/// Sum accumulated into a widening `u32` per channel instead of
+	/// saturating `u8`, then linearly rescaled back down to fit `u8` at the
+	/// end (by `--sum-divisor`, or by the observed max sample if that's not
+	/// given). Preserves relative brightness across the whole stack instead
+	/// of clipping highlights the way `Sum` does, at the cost of not being a
+	/// literal running total anymore.
+	SumScaled,
+
+	/// Sum accumulated into a widening `u32` per channel, same as
+	/// `SumScaled`, but never rescaled: the output holds the true absolute
+	/// per-pixel sum for downstream quantitative analysis, not a display
+	/// image. Needs an output format that can actually hold that (a
+	/// floating-point one, or a 16-bit-capable one with `--sum-shift`).
+	SumRaw,
+
 	/// Per-channel minimum.
 	Min,
 
 	/// Per-channel maximum.
 	Max,
 
+	/// Keeps the whole pixel (all three channels together) from whichever
+	/// frame has the highest luminance there, rather than `Max`'s per-channel
+	/// maximum. Avoids the false colors `Max` can create by mixing channels
+	/// from different frames; the classic "comet mode" for star trails where
+	/// color integrity matters.
+	LightenLuma,
+
+	/// Like `LightenLuma`, but keeps the whole pixel with the lowest
+	/// luminance instead.
+	DarkenLuma,
+
+	/// Photographic "screen" blend, folded across all frames: per channel,
+	/// in normalized float, `1 - (1-a)(1-b)`. Screening against black is a
+	/// no-op, so this gives much nicer highlight accumulation for light
+	/// painting than the saturating `Sum`.
+	Screen,
+
+	/// Photographic "multiply" blend, folded across all frames: per channel,
+	/// in normalized float, `a * b`. Multiplying by white is a no-op.
+	Multiply,
+
+	/// "Soft light" blend, folded across all frames in input order: the
+	/// standard per-channel formula (as used by Photoshop/the W3C compositing
+	/// spec), in normalized float. Blending against 50% gray is a no-op.
+	/// Relies on input order, so it pairs well with `--sort`.
+	SoftLight,
+
+	/// "Overlay" blend, folded across all frames in input order: per channel,
+	/// `Multiply` where the running base is dark and `Screen` where it's
+	/// light, with the split at 50% gray. Blending against 50% gray is a
+	/// no-op. Relies on input order, so it pairs well with `--sort`.
+	Overlay,
+
 	/// Per-channel average.
 	#[value(alias = "avg")]
 	Average,
-}
 
-fn main() -> AResult<()> {
-	let args = Args::parse();
-	#[cfg(debug_assertions)]
-	dbg!(&args);
+	/// Linear cross-fade: like `average`, but each frame's weight ramps
+	/// linearly with its position in the input order instead of being equal,
+	/// so the result morphs from the first frame towards the last (or the
+	/// reverse, with `--fade-reverse`). Relies on input order, so it pairs
+	/// well with `--sort`.
+	Fade,
 
-	let outFile = args.output;
-	if outFile.is_dir() {
-		return Err(anyhow!("Output file {outFile:?} is a directory"));
-	}
-	if outFile.exists() && !args.overwrite {
-		return Err(anyhow!(
-			"Output file {outFile:?} exists, refusing to overwrite"
-		));
-	}
+	/// Comet/star-trail look: a plain per-channel average for the background,
+	/// lightened wherever a per-frame highlight trail is brighter. Each
+	/// frame contributes to the trail scaled by `--comet-decay` raised to its
+	/// distance from the last input, so recent frames' highlights stay
+	/// brightest and older ones fade out gradually instead of building up
+	/// forever like `LightenLuma`. Relies on input order, so it pairs well
+	/// with `--sort`.
+	Comet,
 
-	let (width, height) = image_dimensions(args.inputs.first().unwrap())
-		.context("Querying initial image dimensions")?;
-	for file in args.inputs.iter().skip(1) {
-		if !file.exists() || !file.is_file() {
-			return Err(anyhow!("Input file {file:?} does not exist"));
-		}
+	/// Per-channel mean after rejecting outlier samples more than `--sigma`
+	/// standard deviations from the mean, useful for removing transient
+	/// objects or hot pixels from a stack.
+	SigmaClip,
 
-		let (otherWidth, otherHeight) =
-			image_dimensions(file).with_context(|| format!("Querying dimensions of {file:?}"))?;
-		if width != otherWidth || height != otherHeight {
-			return Err(anyhow!(
-				"Input image {file:?} has mismatched dimensions: expected {}x{} but got {}x{}",
-				width,
-				height,
-				otherWidth,
-				otherHeight
-			));
-		}
-	}
+	/// Like `SigmaClip`, but clamps (winsorizes) outlier samples to the
+	/// `--sigma` threshold instead of discarding them, then takes the mean.
+	/// Keeps more of each pixel's samples, giving lower variance than plain
+	/// clipping for moderate contamination at the cost of a slight bias.
+	/// Shares `SigmaClip`'s iteration loop, reusing `--sigma`/`--iterations`.
+	WinsorSigma,
 
-	let inputs = args.inputs.into_iter().map(|path| {
-		// for use in lazy error messages
-		let pathStr = format!("{path:?}");
-		let pathStr = &*Box::leak(pathStr.into_boxed_str());
+	/// Per-channel median, useful for removing transient objects without
+	/// `--sigma`/`--iterations` tuning.
+	Median,
 
-		eprintln!("Stacking {pathStr}");
-		let file = OpenOptions::new()
-			.read(true)
-			.open(path)
-			.with_context(|| format!("Opening {pathStr}"))?;
-		let file = BufReader::new(file);
-
-		let img = ImageReader::new(file)
-			.with_guessed_format()
-			.with_context(|| format!("Guessing format of {pathStr}"))?
-			.decode()
-			.with_context(|| format!("Decoding {pathStr}"))?;
-		match &img {
-			image::DynamicImage::ImageRgb8(_) => {},
-			image::DynamicImage::ImageRgba8(_) => {
-				eprintln!("Warning: alpha channel in {pathStr} will be discarded")
-			},
-			_ => return Err(anyhow!("Image {pathStr} has an unsupported pixel format")),
-		}
-		Ok(img)
-	});
+	/// Per-channel `--percentile`, of which `Median` is just the 50th.
+	Percentile,
 
-	let outImg = match args.mode {
-		Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max => {
-			let mut outImg = RgbImage::new(width, height);
-			let op = match args.mode {
-				Mode::Sum => |acc: u8, samp: u8| acc.saturating_add(samp),
-				Mode::SumOverflow => |acc: u8, samp: u8| acc.overflowing_add(samp).0,
-				Mode::Min => |acc: u8, samp: u8| acc.min(samp),
-				Mode::Max => |acc: u8, samp: u8| acc.max(samp),
-				Mode::Average => unreachable!(),
-			};
-			for img in inputs {
-				let img = img?;
-				for (acc, (_, _, sample)) in outImg.pixels_mut().zip(img.pixels()) {
-					let sample = sample.to_rgb();
-					acc.apply2(&sample, op);
-				}
-			}
-			outImg
-		},
-		Mode::Average => {
-			let mut outImg = Rgb32FImage::new(width, height);
-			let divisor = inputs.len() as f32;
-			for img in inputs {
-				let img = img?;
-				for (acc, (_, _, sample)) in outImg.pixels_mut().zip(img.pixels()) {
-					let sample = sample.to_rgb();
-					let sample = sample.0.map(|v| v as f32 / 255.0).into();
-					acc.apply2(&sample, |acc, sample| acc + sample);
-				}
-			}
-			outImg.pixels_mut().for_each(|p| p.apply(|v| v / divisor));
-			outImg.convert()
-		},
-	};
-	outImg.save(outFile).context("Saving output file")?;
+	/// Per-channel mean after rejecting samples farther than
+	/// `--mad-threshold` scaled median absolute deviations from the median.
+	/// More resistant to a handful of extreme outliers than `SigmaClip`,
+	/// whose mean/stddev bounds are themselves skewed by those outliers.
+	MadReject,
 
-	Ok(())
+	/// Per-channel most frequently occurring 8-bit sample value, via a
+	/// 256-bin histogram. Useful for extracting a clean background plate
+	/// from frames with moving foreground objects, which only ever cover a
+	/// given pixel a minority of the time. Ties resolve to the lower value.
+	#[value(alias = "mode")]
+	MostFrequent,
+
+	/// Per-channel mean after discarding `--trim-fraction` of the sorted
+	/// samples from each end, a robust compromise between `Average` (no
+	/// rejection) and `Median` (maximal rejection).
+	TrimmedMean,
+
+	/// Per-channel, per-pixel standard deviation across all input frames,
+	/// scaled by `--stddev-scale`. Useful as a noise map: a pixel that never
+	/// varies comes out black.
+	#[value(alias = "stddev")]
+	StdDev,
+
+	/// Per-channel range: tracks a running min and running max across all
+	/// input frames in a single pass and outputs their difference (saturating
+	/// at `0`). Cheaper than `StdDev` for the same rough purpose — spotting
+	/// pixels with a lot of motion or noise — since it only ever needs the
+	/// two running extremes, not every sample.
+	Range,
+
+	/// Per-channel root-mean-square: accumulates the sum of squares of each
+	/// channel (in float), divides by the count, and takes the square root.
+	/// Useful for combining noise realizations, where the arithmetic mean
+	/// (`Average`) would underestimate the noise's actual magnitude. Not
+	/// compatible with `--gamma`, since the accumulated sum of squares isn't
+	/// meaningful in a gamma-decoded space.
+	Rms,
+
+	/// Per-channel geometric mean: accumulates the sum of logs of each
+	/// normalized channel and exponentiates the mean at the end. Useful for
+	/// combining multiplicative/ratio images, where the arithmetic mean
+	/// (`Average`) would be skewed by outliers on the high end. Each sample
+	/// is floored to `--geomean-epsilon` before taking its log, since a
+	/// literal zero would otherwise force the whole pixel to `-inf`. Not
+	/// compatible with `--gamma`, for the same reason as `Rms`.
+	#[value(alias = "geomean")]
+	GeometricMean,
+
+	/// Per-channel harmonic mean: accumulates the sum of reciprocals of each
+	/// normalized channel and divides the count by that sum at the end.
+	/// Suited to ratio/rate values, where it weighs small values more heavily
+	/// than the arithmetic mean (`Average`) would. Each sample is floored to
+	/// `--harmonic-epsilon` before taking its reciprocal, since a literal
+	/// zero would otherwise force the whole pixel's reciprocal to infinity.
+	/// Not compatible with `--gamma`, for the same reason as `GeometricMean`.
+	#[value(alias = "harmonic")]
+	HarmonicMean,
+
+	/// Per-channel absolute difference from the first input, reduced across
+	/// the remaining inputs per `--difference-reduce`. Useful for spotting
+	/// what changed between shots, e.g. motion detection.
+	Difference,
+
+	/// Standard source-over alpha compositing, in input order, producing an
+	/// RGBA output. Unlike every other mode, this honors the alpha channel
+	/// instead of discarding it. Not compatible with `--animate`, `--dark`,
+	/// `--flat`, or `--weights`.
+	AlphaOver,
+
+	/// Blends same-size, differently-exposed inputs using the Mertens
+	/// exposure fusion algorithm: each frame is weighted per pixel by
+	/// contrast, saturation, and well-exposedness, then the weighted frames
+	/// are blended through a Laplacian/Gaussian pyramid to avoid visible
+	/// seams. Produces a natural-looking result directly in LDR, without a
+	/// separate HDR merge/tone-map step. Not compatible with `--animate`,
+	/// `--align`, `--weights`, `--weight-by-exposure`, or `--weight-by-sharpness`.
+	#[value(alias = "mertens")]
+	ExposureFusion,
+
+	/// Blends same-size inputs from a focus sweep by picking, at every pixel,
+	/// the whole RGB value from whichever frame is sharpest there (per
+	/// `--focus-radius`-smoothed local Laplacian energy on luminance).
+	/// Selecting whole pixels rather than per-channel keeps colors coherent
+	/// at focus boundaries. Not compatible with `--animate`, `--align`,
+	/// `--weights`, `--weight-by-exposure`, or `--weight-by-sharpness`.
+	FocusStack,
+
+	/// Linear opacity mix of exactly two inputs: `out = a*(1-o) + b*o`, where
+	/// `o` is `--opacity` and `a`/`b` are the first and second inputs. Errors
+	/// if given anything other than exactly two inputs. Simpler and more
+	/// direct than reaching for `--weights` on `average` mode for the common
+	/// two-layer composite (e.g. a star-trail over a foreground). Not
+	/// compatible with `--animate`, `--align`, `--weights`,
+	/// `--weight-by-exposure`, or `--weight-by-sharpness`.
+	Blend,
+}
+
+/// How `Difference` mode combines the per-frame differences from the base
+/// frame into a single output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DifferenceReduce {
+	/// Add up every frame's difference from the base.
+	Sum,
+	/// Keep only the largest difference from the base seen at each pixel.
+	Max,
+}
+
+impl Mode {
+	/// Whether combining partial accumulators for this mode pairwise, in any
+	/// order, produces the same result as combining them strictly
+	/// left-to-right. Associative modes can be combined via a parallel tree
+	/// reduction; others must fall back to a serial fold.
+	///
+	/// `SigmaClip`, `Median`, `Percentile`, `MadReject`, `MostFrequent`, and
+	/// `TrimmedMean` need every sample for a pixel at once (to compute
+	/// outlier bounds, sort, or bucket into a histogram), so they aren't
+	/// reducible to a pairwise-combinable accumulator at all; they're handled
+	/// by a shared streaming pipeline instead (see [`Mode::needsStreamingPipeline`]).
+	/// `SoftLight`/`Overlay` are a different kind of non-associative: they
+	/// fold through the ordinary `Accumulator` machinery one frame at a time
+	/// just like `Screen`/`Multiply`, but aren't symmetric in their two
+	/// operands (`f(a, b) != f(b, a)` in general), so a pairwise tree
+	/// reduction in arbitrary grouping wouldn't match the strict
+	/// left-to-right fold `--sort` promises.
+	fn isAssociative(self) -> bool {
+		match self {
+			// `AlphaOver`, `ExposureFusion`, `FocusStack`, and `Blend` never
+			// reach this split at all: `main` handles them via their own
+			// dedicated paths before checking this.
+			Mode::Sum | Mode::SumOverflow | Mode::SumScaled | Mode::SumRaw | Mode::Min | Mode::Max | Mode::LightenLuma | Mode::DarkenLuma | Mode::Screen | Mode::Multiply | Mode::Average | Mode::Fade | Mode::Comet | Mode::StdDev | Mode::Range | Mode::Rms | Mode::GeometricMean | Mode::HarmonicMean | Mode::Difference | Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend => true,
+			Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean | Mode::SoftLight | Mode::Overlay => false,
+		}
+	}
+
+	/// Whether this mode needs every sample for a pixel present at once
+	/// (to compute outlier bounds, sort, or bucket into a histogram) rather
+	/// than being foldable one frame at a time through `Accumulator::
+	/// fromImage`/`combine`. A strict subset of `!isAssociative()`:
+	/// `SoftLight`/`Overlay` are also non-associative, but still fold one
+	/// frame at a time, just not in a way that can be reordered.
+	fn needsStreamingPipeline(self) -> bool {
+		matches!(
+			self,
+			Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean
+		)
+	}
+
+	/// Whether this mode discards some samples per pixel rather than folding
+	/// every one in, i.e. whether `--count-map`/`--rejection-map` have
+	/// anything to report for it. `Median`, `Percentile`, and `MostFrequent`
+	/// are streaming modes
+	/// too, but every sample participates in their result; only these three
+	/// actually reject outliers. `WinsorSigma` also keeps every sample
+	/// (clamped, not dropped), so it's excluded for the same reason.
+	fn isRejectionMode(self) -> bool {
+		matches!(self, Mode::SigmaClip | Mode::MadReject | Mode::TrimmedMean)
+	}
+
+	/// Whether this mode's formula folds each channel independently, with no
+	/// coupling between R/G/B and no extra per-run state beyond a plain
+	/// fold over frames. `LightenLuma`/`DarkenLuma` decide what to keep from
+	/// a frame's luma across all three channels together, and `Fade`,
+	/// `Comet`, `Difference`, `AlphaOver`, `ExposureFusion`, and `FocusStack`
+	/// all need frame position, per-pixel selection, or blending beyond a
+	/// single running combine, so none of those qualify. This is exactly the set
+	/// `--mode-per-channel` can mix and match, since a plain per-channel fold
+	/// is all `runModePerChannel` implements.
+	fn isChannelIndependent(self) -> bool {
+		matches!(self, Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max | Mode::Screen | Mode::Multiply | Mode::Average)
+	}
+
+	/// Whether `--modes` can compute this mode alongside others from one
+	/// shared decode. Deliberately narrower than [`Mode::isAssociative`]:
+	/// `LightenLuma`/`DarkenLuma`/`Fade`/`Comet`/etc. are associative too, but
+	/// need frame position, weights, or per-pixel selection state that
+	/// `runMultiMode`'s plain per-mode fold doesn't thread through.
+	fn isSinglePassMode(self) -> bool {
+		matches!(self, Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max | Mode::Average | Mode::Range | Mode::StdDev)
+	}
+}
+
+/// A decoded input frame, still in whichever precision it was loaded at.
+/// Plain 8-bit frames stay on the cheap `U8` path; 16-bit and float/HDR
+/// frames are normalized to `Rgb32FImage` so they can be combined without
+/// clipping.
+#[derive(Clone)]
+enum DecodedFrame {
+	Ldr(RgbImage),
+	Hdr(Rgb32FImage),
+}
+
+impl DecodedFrame {
+	fn intoRgb8(self) -> RgbImage {
+		match self {
+			DecodedFrame::Ldr(img) => img,
+			DecodedFrame::Hdr(img) => img.convert(),
+		}
+	}
+
+	fn intoRgb32f(self) -> Rgb32FImage {
+		match self {
+			DecodedFrame::Ldr(img) => img.convert(),
+			DecodedFrame::Hdr(img) => img,
+		}
+	}
+
+	fn width(&self) -> u32 {
+		match self {
+			DecodedFrame::Ldr(img) => img.width(),
+			DecodedFrame::Hdr(img) => img.width(),
+		}
+	}
+
+	fn height(&self) -> u32 {
+		match self {
+			DecodedFrame::Ldr(img) => img.height(),
+			DecodedFrame::Hdr(img) => img.height(),
+		}
+	}
+}
+
+/// A width×height RGB buffer of `f64` samples, backing `Accumulator::
+/// AverageF64`. A plain raw `Vec<f64>` rather than an `image::ImageBuffer`,
+/// since the `image` crate's `Primitive`/`Pixel` traits (needed for
+/// `ImageBuffer` to accept a channel type) aren't implemented for `f64`, and
+/// can't be implemented here either — both the trait and the type are
+/// foreign to this crate.
+#[derive(Clone)]
+struct RgbF64Buffer {
+	width: u32,
+	height: u32,
+	/// Interleaved r,g,b samples, row-major, same layout as `image::
+	/// ImageBuffer::as_raw`.
+	data: Vec<f64>,
+}
+
+impl RgbF64Buffer {
+	fn fromRgb32f(img: &Rgb32FImage) -> Self {
+		RgbF64Buffer { width: img.width(), height: img.height(), data: img.as_raw().iter().map(|&v| v as f64).collect() }
+	}
+
+	fn intoRgb32f(self) -> Rgb32FImage {
+		let data: Vec<f32> = self.data.iter().map(|&v| v as f32).collect();
+		Rgb32FImage::from_raw(self.width, self.height, data).expect("RgbF64Buffer always holds width*height*3 samples")
+	}
+}
+
+/// A partial (or final) result of combining some number of input images.
+enum Accumulator {
+	/// Used by `Sum`/`Min`/`Max` on 8-bit-only input, which operate directly
+	/// on 8-bit samples.
+	U8(RgbImage),
+
+	/// Used by `Sum`/`SumOverflow`/`Min`/`Max` when any input is 16-bit or
+	/// float, so that, e.g., a `Sum` of very bright frames doesn't clip. Also
+	/// used at 8-bit precision when `--gamma` is anything but `1.0`, since
+	/// applying it needs float math. `gamma` records the value so `intoOutput`
+	/// /`preview` can undo it; it's `1.0` (a no-op) unless `--gamma` was given.
+	F32 { img: Rgb32FImage, gamma: f32 },
+
+	/// Used by `Average`, which keeps a running weighted mean per pixel
+	/// (Welford's online algorithm, generalized to weighted samples) rather
+	/// than a running sum, so precision doesn't degrade as more frames pile
+	/// on top of an already-large sum. `weight` is the total weight seen so
+	/// far, used to proportion each merge. Always float, regardless of input
+	/// precision. `colorSpace` records which space the mean is accumulated
+	/// in, so `intoOutput`/`preview` know whether to re-encode to sRGB.
+	/// Unweighted averaging is just every frame weighted `1.0`. `gamma` is
+	/// the same `--gamma` bookkeeping as `F32`.
+	Average { mean: Rgb32FImage, weight: f32, colorSpace: ColorSpace, gamma: f32 },
+
+	/// Same running weighted mean as `Average`, but accumulated in `f64`
+	/// instead of `f32`. Used when `--accum-precision f64` asks for it, on
+	/// very large or very precise stacks where `f32`'s mantissa can start
+	/// losing bits as the mean converges.
+	AverageF64 { mean: RgbF64Buffer, weight: f64, colorSpace: ColorSpace, gamma: f32 },
+
+	/// Used by `Comet`, which keeps an unweighted running mean (`mean`/
+	/// `weight`, same merge as `Average`) for the background alongside a
+	/// running per-channel maximum (`trail`) of each frame pre-scaled by its
+	/// `--comet-decay` falloff. The two are blended (per-channel max of
+	/// `mean` and `trail`) only once, in `intoOutput`/`preview`, so partial
+	/// accumulators stay cheap to merge. `colorSpace`/`gamma` are the same
+	/// bookkeeping as `Average`.
+	Comet { mean: Rgb32FImage, weight: f32, trail: Rgb32FImage, colorSpace: ColorSpace, gamma: f32 },
+
+	/// Used by `StdDev`, which accumulates a running sum and sum-of-squares
+	/// so that the variance (and hence standard deviation) of every pixel
+	/// can be derived at the end without keeping every sample resident.
+	StdDev { sum: Rgb32FImage, sumSq: Rgb32FImage, count: u32, scale: f32 },
+
+	/// Used by `Range`, which tracks a running per-channel min and max so
+	/// their difference can be taken at the end, without keeping every
+	/// sample resident.
+	Range { min: Rgb32FImage, max: Rgb32FImage },
+
+	/// Used by `SumScaled`, which sums into a widening `u32`-per-channel
+	/// buffer instead of `Sum`'s saturating `u8` add, so a long run of
+	/// frames doesn't clip its highlights away. Rescaled back down to `u8`
+	/// at the very end, by `divisor` if `--sum-divisor` was given or by the
+	/// observed per-run max otherwise.
+	SumScaled { sum: ImageBuffer<Rgb<u32>, Vec<u32>>, divisor: Option<u32> },
+
+	/// Used by `SumOverflow` on 8-bit-only input, which sums into the same
+	/// widening `u32`-per-channel buffer as `SumScaled`, so the wrapped-away
+	/// high bits stay available for `--overflow-map` even though the visible
+	/// output (`intoOutput`/`preview`) only ever shows their low byte
+	/// (`sum % 256`), matching `U8`'s wrapping-add output exactly. HDR/float
+	/// input, or `--gamma` other than `1.0`, still accumulates as `F32`
+	/// instead, same as `Sum`/`Min`/`Max`; `--overflow-map` has no effect on
+	/// that path.
+	SumOverflow { sum: ImageBuffer<Rgb<u32>, Vec<u32>> },
+
+	/// Used by `SumRaw`, for quantitative work that wants the true per-pixel
+	/// sum with no saturation, wrapping, or display rescaling at all. Shares
+	/// `SumScaled`'s widening `u32`-per-channel buffer, but `intoOutput`/
+	/// `preview` hand the raw counts straight to the output format (a
+	/// floating-point one, or 16-bit via `--sum-shift`) instead of
+	/// normalizing them to fit `u8`.
+	SumRaw { sum: ImageBuffer<Rgb<u32>, Vec<u32>>, shift: Option<u32> },
+
+	/// Used by `Rms`, which accumulates a running sum of squares and count,
+	/// dividing and taking the square root at the very end. Shares the
+	/// float-accumulator structure with `Average`, but sums squares instead
+	/// of raw samples.
+	Rms { sumSq: Rgb32FImage, count: u32 },
+
+	/// Used by `GeometricMean`, which accumulates a running sum of logs and
+	/// count, dividing and exponentiating at the very end. `epsilon` records
+	/// the `--geomean-epsilon` floor applied before each sample's log, for
+	/// documentation purposes only (it's already baked into `sumLog`).
+	GeometricMean { sumLog: Rgb32FImage, count: u32, epsilon: f32 },
+
+	/// Used by `HarmonicMean`, which accumulates a running sum of reciprocals
+	/// and count, dividing the count by the sum at the very end. `epsilon`
+	/// records the `--harmonic-epsilon` floor applied before each sample's
+	/// reciprocal, for documentation purposes only (it's already baked into
+	/// `sumRecip`).
+	HarmonicMean { sumRecip: Rgb32FImage, count: u32, epsilon: f32 },
+}
+
+impl Accumulator {
+	/// `hdr` indicates whether any frame in this run is 16-bit/float, in
+	/// which case `Sum`/`SumOverflow`/`Min`/`Max` accumulate as `F32` instead
+	/// of `U8` so that every frame is combined at full precision. `weight`
+	/// is only meaningful for `Average`.
+	fn fromImage(mode: Mode, frame: DecodedFrame, hdr: bool, colorSpace: ColorSpace, weight: f32, stddevScale: f32, gamma: f32, sumDivisor: Option<u32>, sumShift: Option<u32>, geomeanEpsilon: f32, harmonicEpsilon: f32, accumPrecision: AccumPrecision) -> Self {
+		match mode {
+			Mode::Sum | Mode::SumOverflow | Mode::Min | Mode::Max | Mode::LightenLuma | Mode::DarkenLuma if hdr || gamma != 1.0 => {
+				let mut img = frame.intoRgb32f();
+				img.pixels_mut().for_each(|p| p.apply(|v| gammaDecode(v, gamma)));
+				Accumulator::F32 { img, gamma }
+			},
+			Mode::Sum | Mode::Min | Mode::Max | Mode::LightenLuma | Mode::DarkenLuma => Accumulator::U8(frame.intoRgb8()),
+			Mode::SumOverflow => {
+				let img = frame.intoRgb8();
+				let sum = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+					let Rgb([r, g, b]) = *img.get_pixel(x, y);
+					Rgb([r as u32, g as u32, b as u32])
+				});
+				Accumulator::SumOverflow { sum }
+			},
+			Mode::Screen | Mode::Multiply | Mode::SoftLight | Mode::Overlay => {
+				let mut img = frame.intoRgb32f();
+				img.pixels_mut().for_each(|p| p.apply(|v| gammaDecode(v, gamma)));
+				Accumulator::F32 { img, gamma }
+			},
+			Mode::SumScaled => {
+				let img = frame.intoRgb8();
+				let sum = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+					let Rgb([r, g, b]) = *img.get_pixel(x, y);
+					Rgb([r as u32, g as u32, b as u32])
+				});
+				Accumulator::SumScaled { sum, divisor: sumDivisor }
+			},
+			Mode::SumRaw => {
+				let img = frame.intoRgb8();
+				let sum = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+					let Rgb([r, g, b]) = *img.get_pixel(x, y);
+					Rgb([r as u32, g as u32, b as u32])
+				});
+				Accumulator::SumRaw { sum, shift: sumShift }
+			},
+			// `Fade` accumulates exactly like `Average`, weighted average of
+			// every frame; the only difference is where its per-frame `weight`
+			// comes from (position in the sequence, computed by the caller,
+			// instead of `--weights`/`--weight-by-exposure`/`--weight-by-sharpness`).
+			Mode::Average | Mode::Fade => {
+				let mut mean = frame.intoRgb32f();
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaDecode(v, gamma)));
+				if colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(srgbToLinear));
+				}
+				match accumPrecision {
+					AccumPrecision::F32 => Accumulator::Average { mean, weight, colorSpace, gamma },
+					AccumPrecision::F64 => Accumulator::AverageF64 { mean: RgbF64Buffer::fromRgb32f(&mean), weight: weight as f64, colorSpace, gamma },
+				}
+			},
+			// `Comet` reuses `weight` for a different purpose than `Average`/
+			// `Fade`: instead of proportioning the mean merge, it's the
+			// per-frame `--comet-decay` falloff scale (also computed by the
+			// caller from position in the sequence), applied only to the
+			// trail. The mean itself always accumulates unweighted.
+			Mode::Comet => {
+				let mut mean = frame.intoRgb32f();
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaDecode(v, gamma)));
+				if colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(srgbToLinear));
+				}
+				let mut trail = mean.clone();
+				trail.pixels_mut().for_each(|p| p.apply(|v| v * weight));
+				Accumulator::Comet { mean, weight: 1.0, trail, colorSpace, gamma }
+			},
+			Mode::Range => {
+				let img = frame.intoRgb32f();
+				Accumulator::Range { min: img.clone(), max: img }
+			},
+			Mode::StdDev => {
+				let sum = frame.intoRgb32f();
+				let mut sumSq = sum.clone();
+				sumSq.pixels_mut().for_each(|p| p.apply(|v| v * v));
+				Accumulator::StdDev { sum, sumSq, count: 1, scale: stddevScale }
+			},
+			Mode::Rms => {
+				let mut sumSq = frame.intoRgb32f();
+				sumSq.pixels_mut().for_each(|p| p.apply(|v| v * v));
+				Accumulator::Rms { sumSq, count: 1 }
+			},
+			Mode::GeometricMean => {
+				let mut sumLog = frame.intoRgb32f();
+				sumLog.pixels_mut().for_each(|p| p.apply(|v| v.max(geomeanEpsilon).ln()));
+				Accumulator::GeometricMean { sumLog, count: 1, epsilon: geomeanEpsilon }
+			},
+			Mode::HarmonicMean => {
+				let mut sumRecip = frame.intoRgb32f();
+				sumRecip.pixels_mut().for_each(|p| p.apply(|v| 1.0 / v.max(harmonicEpsilon)));
+				Accumulator::HarmonicMean { sumRecip, count: 1, epsilon: harmonicEpsilon }
+			},
+			Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean | Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend => {
+				unreachable!("streaming modes use a dedicated pipeline; alpha-over/exposure-fusion/focus-stack/blend use their own dedicated paths")
+			},
+			Mode::Difference => unreachable!("difference mode builds its accumulators directly, see main()"),
+		}
+	}
+
+	fn combine(mode: Mode, mut a: Self, b: Self, lumaCoeffs: LumaCoeffs) -> Self {
+		match (&mut a, b) {
+			(Accumulator::U8(a), Accumulator::U8(b)) if matches!(mode, Mode::LightenLuma | Mode::DarkenLuma) => {
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					keepPixelByLumaU8(mode, acc, samp, lumaCoeffs);
+				}
+				drop(b);
+			},
+			(Accumulator::U8(a), Accumulator::U8(b)) => {
+				// Only Sum/Min/Max ever reach here: LightenLuma/DarkenLuma
+				// are peeled off by the arm above, and every other mode's
+				// `fromImage` never produces a `U8` accumulator to begin with.
+				combineU8Buffers(a, &b, mode);
+				drop(b);
+			},
+			(Accumulator::F32 { img: a, .. }, Accumulator::F32 { img: b, .. }) if matches!(mode, Mode::LightenLuma | Mode::DarkenLuma) => {
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					keepPixelByLumaF32(mode, acc, samp, lumaCoeffs);
+				}
+				drop(b);
+			},
+			(Accumulator::F32 { img: a, .. }, Accumulator::F32 { img: b, .. }) => {
+				let op = f32CombineOp(mode);
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					acc.apply2(samp, op);
+				}
+				drop(b);
+			},
+			(Accumulator::Average { mean: a, weight: weightA, .. }, Accumulator::Average { mean: b, weight: weightB, .. }) => {
+				// Chan et al.'s parallel merge of two weighted running means:
+				// the combined mean is `a` nudged toward `b` by `b`'s share of
+				// the combined weight. Associative and commutative, so it's
+				// safe to fold pairs in any order (rayon's tree reduce).
+				let totalWeight = *weightA + weightB;
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + (weightB / totalWeight) * (samp - acc));
+				}
+				*weightA = totalWeight;
+			},
+			(Accumulator::AverageF64 { mean: a, weight: weightA, .. }, Accumulator::AverageF64 { mean: b, weight: weightB, .. }) => {
+				// Same merge as `Average` above, just in `f64`.
+				let totalWeight = *weightA + weightB;
+				for (acc, samp) in a.data.iter_mut().zip(b.data.iter()) {
+					*acc += (weightB / totalWeight) * (samp - *acc);
+				}
+				*weightA = totalWeight;
+			},
+			(
+				Accumulator::Comet { mean: meanA, weight: weightA, trail: trailA, .. },
+				Accumulator::Comet { mean: meanB, weight: weightB, trail: trailB, .. },
+			) => {
+				// Mean merges exactly like `Average` above. The trail merges
+				// by elementwise max, which is associative and commutative
+				// regardless of how the per-frame decay was baked in, since
+				// that scaling already happened in `fromImage`.
+				let totalWeight = *weightA + weightB;
+				for (acc, samp) in meanA.pixels_mut().zip(meanB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + (weightB / totalWeight) * (samp - acc));
+				}
+				*weightA = totalWeight;
+				for (acc, samp) in trailA.pixels_mut().zip(trailB.pixels()) {
+					acc.apply2(samp, f32::max);
+				}
+			},
+			(Accumulator::Range { min: minA, max: maxA }, Accumulator::Range { min: minB, max: maxB }) => {
+				for (acc, samp) in minA.pixels_mut().zip(minB.pixels()) {
+					acc.apply2(samp, f32::min);
+				}
+				for (acc, samp) in maxA.pixels_mut().zip(maxB.pixels()) {
+					acc.apply2(samp, f32::max);
+				}
+			},
+			(
+				Accumulator::StdDev { sum: sumA, sumSq: sumSqA, count: countA, .. },
+				Accumulator::StdDev { sum: sumB, sumSq: sumSqB, count: countB, .. },
+			) => {
+				for (acc, samp) in sumA.pixels_mut().zip(sumB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				for (acc, samp) in sumSqA.pixels_mut().zip(sumSqB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				*countA += countB;
+			},
+			(Accumulator::SumScaled { sum: a, .. }, Accumulator::SumScaled { sum: b, .. }) => {
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				drop(b);
+			},
+			(Accumulator::SumOverflow { sum: a }, Accumulator::SumOverflow { sum: b }) => {
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				drop(b);
+			},
+			(Accumulator::SumRaw { sum: a, .. }, Accumulator::SumRaw { sum: b, .. }) => {
+				for (acc, samp) in a.pixels_mut().zip(b.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				drop(b);
+			},
+			(Accumulator::Rms { sumSq: sumSqA, count: countA }, Accumulator::Rms { sumSq: sumSqB, count: countB }) => {
+				for (acc, samp) in sumSqA.pixels_mut().zip(sumSqB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				*countA += countB;
+			},
+			(Accumulator::GeometricMean { sumLog: sumLogA, count: countA, .. }, Accumulator::GeometricMean { sumLog: sumLogB, count: countB, .. }) => {
+				for (acc, samp) in sumLogA.pixels_mut().zip(sumLogB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				*countA += countB;
+			},
+			(Accumulator::HarmonicMean { sumRecip: sumRecipA, count: countA, .. }, Accumulator::HarmonicMean { sumRecip: sumRecipB, count: countB, .. }) => {
+				for (acc, samp) in sumRecipA.pixels_mut().zip(sumRecipB.pixels()) {
+					acc.apply2(samp, |acc, samp| acc + samp);
+				}
+				*countA += countB;
+			},
+			_ => unreachable!("mismatched accumulator kinds"),
+		}
+		a
+	}
+
+	/// Produces the final combined image, still at full precision; the
+	/// caller decides whether to tonemap it down to 8 bits or save it as-is.
+	fn intoOutput(self) -> DecodedFrame {
+		match self {
+			Accumulator::U8(img) => DecodedFrame::Ldr(img),
+			Accumulator::F32 { mut img, gamma } => {
+				img.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, gamma)));
+				DecodedFrame::Hdr(img)
+			},
+			Accumulator::Average { mut mean, colorSpace, gamma, .. } => {
+				if colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::AverageF64 { mean, colorSpace, gamma, .. } => {
+				let mut mean = mean.intoRgb32f();
+				if colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::Comet { mut mean, trail, colorSpace, gamma, .. } => {
+				for (acc, samp) in mean.pixels_mut().zip(trail.pixels()) {
+					acc.apply2(samp, f32::max);
+				}
+				if colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::Range { min, max } => DecodedFrame::Hdr(rangeImage(min, max)),
+			Accumulator::StdDev { sum, sumSq, count, scale } => DecodedFrame::Hdr(stdDevImage(sum, sumSq, count, scale)),
+			Accumulator::SumScaled { sum, divisor } => DecodedFrame::Ldr(sumScaledImage(&sum, divisor)),
+			Accumulator::SumOverflow { sum } => DecodedFrame::Ldr(sumOverflowImage(&sum)),
+			Accumulator::SumRaw { sum, shift } => DecodedFrame::Hdr(sumRawImage(&sum, shift)),
+			Accumulator::Rms { mut sumSq, count } => {
+				sumSq.pixels_mut().for_each(|p| p.apply(|v| (v / count as f32).sqrt()));
+				DecodedFrame::Hdr(sumSq)
+			},
+			Accumulator::GeometricMean { mut sumLog, count, .. } => {
+				sumLog.pixels_mut().for_each(|p| p.apply(|v| (v / count as f32).exp()));
+				DecodedFrame::Hdr(sumLog)
+			},
+			Accumulator::HarmonicMean { mut sumRecip, count, .. } => {
+				sumRecip.pixels_mut().for_each(|p| p.apply(|v| count as f32 / v));
+				DecodedFrame::Hdr(sumRecip)
+			},
+		}
+	}
+
+	/// Like `intoOutput`, but without consuming the accumulator. Used by
+	/// `--animate` to snapshot the running accumulator after every input.
+	fn preview(&self) -> DecodedFrame {
+		match self {
+			Accumulator::U8(img) => DecodedFrame::Ldr(img.clone()),
+			Accumulator::F32 { img, gamma } => {
+				let mut img = img.clone();
+				img.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, *gamma)));
+				DecodedFrame::Hdr(img)
+			},
+			Accumulator::Average { mean, colorSpace, gamma, .. } => {
+				let mut mean = mean.clone();
+				if *colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, *gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::AverageF64 { mean, colorSpace, gamma, .. } => {
+				let mut mean = mean.clone().intoRgb32f();
+				if *colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, *gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::Comet { mean, trail, colorSpace, gamma, .. } => {
+				let mut mean = mean.clone();
+				for (acc, samp) in mean.pixels_mut().zip(trail.pixels()) {
+					acc.apply2(samp, f32::max);
+				}
+				if *colorSpace == ColorSpace::Linear {
+					mean.pixels_mut().for_each(|p| p.apply(linearToSrgb));
+				}
+				mean.pixels_mut().for_each(|p| p.apply(|v| gammaEncode(v, *gamma)));
+				DecodedFrame::Hdr(mean)
+			},
+			Accumulator::Range { min, max } => DecodedFrame::Hdr(rangeImage(min.clone(), max.clone())),
+			Accumulator::StdDev { sum, sumSq, count, scale } => {
+				DecodedFrame::Hdr(stdDevImage(sum.clone(), sumSq.clone(), *count, *scale))
+			},
+			Accumulator::SumScaled { sum, divisor } => DecodedFrame::Ldr(sumScaledImage(sum, *divisor)),
+			Accumulator::SumOverflow { sum } => DecodedFrame::Ldr(sumOverflowImage(sum)),
+			Accumulator::SumRaw { sum, shift } => DecodedFrame::Hdr(sumRawImage(sum, *shift)),
+			Accumulator::Rms { sumSq, count } => {
+				let mut sumSq = sumSq.clone();
+				sumSq.pixels_mut().for_each(|p| p.apply(|v| (v / *count as f32).sqrt()));
+				DecodedFrame::Hdr(sumSq)
+			},
+			Accumulator::GeometricMean { sumLog, count, .. } => {
+				let mut sumLog = sumLog.clone();
+				sumLog.pixels_mut().for_each(|p| p.apply(|v| (v / *count as f32).exp()));
+				DecodedFrame::Hdr(sumLog)
+			},
+			Accumulator::HarmonicMean { sumRecip, count, .. } => {
+				let mut sumRecip = sumRecip.clone();
+				sumRecip.pixels_mut().for_each(|p| p.apply(|v| *count as f32 / v));
+				DecodedFrame::Hdr(sumRecip)
+			},
+		}
+	}
+}
+
+/// Modes whose accumulator can fold frames one at a time, without needing
+/// every sample present at once — the same set `runPipelinedAssociative`
+/// targets. `--checkpoint`/`--resume` reuse this list, since a checkpoint is
+/// only meaningful for a plain running accumulator, not the
+/// streaming/all-samples/dedicated-pipeline modes. `SoftLight`/`Overlay` fold
+/// in strict input order rather than any order, but a checkpoint always
+/// resumes in that same order, so they qualify too.
+fn isCheckpointableMode(mode: Mode) -> bool {
+	matches!(
+		mode,
+		Mode::Average | Mode::Fade | Mode::Comet | Mode::StdDev | Mode::Range | Mode::Rms | Mode::GeometricMean | Mode::HarmonicMean | Mode::Screen | Mode::Multiply | Mode::SoftLight | Mode::Overlay | Mode::SumScaled
+	)
+}
+
+/// Magic bytes identifying a `--checkpoint` file, so `--resume` fails fast on
+/// an unrelated or corrupt file instead of misinterpreting its bytes.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"IMGCKPT1";
+
+fn writeF32Image(w: &mut impl Write, img: &Rgb32FImage) -> AResult<()> {
+	for v in img.as_raw() {
+		w.write_all(&v.to_le_bytes())?;
+	}
+	Ok(())
+}
+
+fn readF32Image(r: &mut impl Read, width: u32, height: u32) -> AResult<Rgb32FImage> {
+	let mut bytes = vec![0u8; width as usize * height as usize * 3 * 4];
+	r.read_exact(&mut bytes).context("Reading checkpoint image buffer")?;
+	let data: Vec<f32> = bytes.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+	Rgb32FImage::from_raw(width, height, data).ok_or_else(|| anyhow!("Corrupt checkpoint: wrong buffer size for {width}x{height}"))
+}
+
+fn writeU32Image(w: &mut impl Write, img: &ImageBuffer<Rgb<u32>, Vec<u32>>) -> AResult<()> {
+	for v in img.as_raw() {
+		w.write_all(&v.to_le_bytes())?;
+	}
+	Ok(())
+}
+
+fn readU32Image(r: &mut impl Read, width: u32, height: u32) -> AResult<ImageBuffer<Rgb<u32>, Vec<u32>>> {
+	let mut bytes = vec![0u8; width as usize * height as usize * 3 * 4];
+	r.read_exact(&mut bytes).context("Reading checkpoint image buffer")?;
+	let data: Vec<u32> = bytes.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+	ImageBuffer::from_raw(width, height, data).ok_or_else(|| anyhow!("Corrupt checkpoint: wrong buffer size for {width}x{height}"))
+}
+
+fn readU32LE(r: &mut impl Read) -> AResult<u32> {
+	let mut bytes = [0u8; 4];
+	r.read_exact(&mut bytes)?;
+	Ok(u32::from_le_bytes(bytes))
+}
+
+fn readU64LE(r: &mut impl Read) -> AResult<u64> {
+	let mut bytes = [0u8; 8];
+	r.read_exact(&mut bytes)?;
+	Ok(u64::from_le_bytes(bytes))
+}
+
+fn readF32LE(r: &mut impl Read) -> AResult<f32> {
+	let mut bytes = [0u8; 4];
+	r.read_exact(&mut bytes)?;
+	Ok(f32::from_le_bytes(bytes))
+}
+
+/// Errors if a checkpoint's saved dimensions don't match the current run's,
+/// since resuming with the wrong dimensions would silently corrupt the
+/// accumulator (`ImageBuffer::from_raw` only guards against a wrong byte
+/// count, not a wrong width/height that happens to multiply out the same).
+fn checkCheckpointDims(path: &Path, got: (u32, u32), want: (u32, u32)) -> AResult<()> {
+	if got != want {
+		return Err(anyhow!(
+			"--resume checkpoint {path:?} was saved at {}x{} but this run is {}x{}",
+			got.0,
+			got.1,
+			want.0,
+			want.1
+		));
+	}
+	Ok(())
+}
+
+/// Writes `acc` (and how many inputs it's folded in so far) to `path`, for
+/// `--resume` to pick back up from later. Overwrites any existing file at
+/// `path`, so the same path can be reused for every periodic write. Only
+/// ever called for an `isCheckpointableMode` mode, so every other
+/// `Accumulator` variant is unreachable here.
+fn saveCheckpoint(path: &Path, mode: Mode, inputCount: usize, acc: &Accumulator) -> AResult<()> {
+	let mut file = std::io::BufWriter::new(OpenOptions::new().write(true).create(true).truncate(true).open(path).with_context(|| format!("Opening checkpoint file {path:?}"))?);
+	file.write_all(CHECKPOINT_MAGIC)?;
+	let modeName = mode.to_possible_value().expect("checkpointable modes always have a possible value").get_name().to_owned();
+	file.write_all(&(modeName.len() as u32).to_le_bytes())?;
+	file.write_all(modeName.as_bytes())?;
+	file.write_all(&(inputCount as u64).to_le_bytes())?;
+	match acc {
+		Accumulator::F32 { img, gamma } => {
+			file.write_all(&[0u8])?;
+			file.write_all(&img.width().to_le_bytes())?;
+			file.write_all(&img.height().to_le_bytes())?;
+			file.write_all(&gamma.to_le_bytes())?;
+			writeF32Image(&mut file, img)?;
+		},
+		Accumulator::Average { mean, weight, colorSpace, gamma } => {
+			file.write_all(&[1u8])?;
+			file.write_all(&mean.width().to_le_bytes())?;
+			file.write_all(&mean.height().to_le_bytes())?;
+			file.write_all(&weight.to_le_bytes())?;
+			file.write_all(&[*colorSpace as u8])?;
+			file.write_all(&gamma.to_le_bytes())?;
+			writeF32Image(&mut file, mean)?;
+		},
+		Accumulator::Comet { mean, weight, trail, colorSpace, gamma } => {
+			file.write_all(&[2u8])?;
+			file.write_all(&mean.width().to_le_bytes())?;
+			file.write_all(&mean.height().to_le_bytes())?;
+			file.write_all(&weight.to_le_bytes())?;
+			file.write_all(&[*colorSpace as u8])?;
+			file.write_all(&gamma.to_le_bytes())?;
+			writeF32Image(&mut file, mean)?;
+			writeF32Image(&mut file, trail)?;
+		},
+		Accumulator::StdDev { sum, sumSq, count, scale } => {
+			file.write_all(&[3u8])?;
+			file.write_all(&sum.width().to_le_bytes())?;
+			file.write_all(&sum.height().to_le_bytes())?;
+			file.write_all(&count.to_le_bytes())?;
+			file.write_all(&scale.to_le_bytes())?;
+			writeF32Image(&mut file, sum)?;
+			writeF32Image(&mut file, sumSq)?;
+		},
+		Accumulator::Range { min, max } => {
+			file.write_all(&[4u8])?;
+			file.write_all(&min.width().to_le_bytes())?;
+			file.write_all(&min.height().to_le_bytes())?;
+			writeF32Image(&mut file, min)?;
+			writeF32Image(&mut file, max)?;
+		},
+		Accumulator::SumScaled { sum, divisor } => {
+			file.write_all(&[5u8])?;
+			file.write_all(&sum.width().to_le_bytes())?;
+			file.write_all(&sum.height().to_le_bytes())?;
+			file.write_all(&divisor.unwrap_or(0).to_le_bytes())?;
+			file.write_all(&[divisor.is_some() as u8])?;
+			writeU32Image(&mut file, sum)?;
+		},
+		Accumulator::Rms { sumSq, count } => {
+			file.write_all(&[6u8])?;
+			file.write_all(&sumSq.width().to_le_bytes())?;
+			file.write_all(&sumSq.height().to_le_bytes())?;
+			file.write_all(&count.to_le_bytes())?;
+			writeF32Image(&mut file, sumSq)?;
+		},
+		Accumulator::GeometricMean { sumLog, count, epsilon } => {
+			file.write_all(&[7u8])?;
+			file.write_all(&sumLog.width().to_le_bytes())?;
+			file.write_all(&sumLog.height().to_le_bytes())?;
+			file.write_all(&count.to_le_bytes())?;
+			file.write_all(&epsilon.to_le_bytes())?;
+			writeF32Image(&mut file, sumLog)?;
+		},
+		Accumulator::HarmonicMean { sumRecip, count, epsilon } => {
+			file.write_all(&[8u8])?;
+			file.write_all(&sumRecip.width().to_le_bytes())?;
+			file.write_all(&sumRecip.height().to_le_bytes())?;
+			file.write_all(&count.to_le_bytes())?;
+			file.write_all(&epsilon.to_le_bytes())?;
+			writeF32Image(&mut file, sumRecip)?;
+		},
+		Accumulator::U8(_) | Accumulator::SumOverflow { .. } | Accumulator::SumRaw { .. } => unreachable!("not produced by an isCheckpointableMode mode"),
+		Accumulator::AverageF64 { .. } => unreachable!("--checkpoint/--resume are rejected together with --accum-precision f64"),
+	}
+	file.flush().context("Flushing checkpoint file")?;
+	Ok(())
+}
+
+/// Reads back a checkpoint written by `saveCheckpoint`, returning how many
+/// inputs it already folded in and the accumulator to resume from. Errors if
+/// `path` isn't a checkpoint file at all, or if its mode/dimensions disagree
+/// with the current run's.
+fn loadCheckpoint(path: &Path, mode: Mode, targetDims: (u32, u32)) -> AResult<(usize, Accumulator)> {
+	let mut file = std::io::BufReader::new(OpenOptions::new().read(true).open(path).with_context(|| format!("Opening checkpoint file {path:?}"))?);
+	let mut magic = [0u8; 8];
+	file.read_exact(&mut magic).with_context(|| format!("Reading {path:?} as a checkpoint file"))?;
+	if &magic != CHECKPOINT_MAGIC {
+		return Err(anyhow!("{path:?} isn't an imgstack checkpoint file"));
+	}
+	let modeNameLen = readU32LE(&mut file)? as usize;
+	let mut modeNameBytes = vec![0u8; modeNameLen];
+	file.read_exact(&mut modeNameBytes).context("Reading checkpoint mode name")?;
+	let modeName = String::from_utf8(modeNameBytes).context("Checkpoint mode name isn't valid UTF-8")?;
+	let savedMode = Mode::from_str(&modeName, false).map_err(|err| anyhow!("Checkpoint {path:?} has an unrecognized mode {modeName:?}: {err}"))?;
+	if savedMode != mode {
+		return Err(anyhow!("--resume checkpoint {path:?} was saved with {savedMode:?} mode, but this run is {mode:?}"));
+	}
+	let inputCount = readU64LE(&mut file)? as usize;
+	let mut tag = [0u8; 1];
+	file.read_exact(&mut tag).context("Reading checkpoint accumulator kind")?;
+	let acc = match tag[0] {
+		0 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let gamma = readF32LE(&mut file)?;
+			Accumulator::F32 { img: readF32Image(&mut file, width, height)?, gamma }
+		},
+		1 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let weight = readF32LE(&mut file)?;
+			let mut colorSpaceByte = [0u8; 1];
+			file.read_exact(&mut colorSpaceByte)?;
+			let colorSpace = if colorSpaceByte[0] == 0 { ColorSpace::Srgb } else { ColorSpace::Linear };
+			let gamma = readF32LE(&mut file)?;
+			Accumulator::Average { mean: readF32Image(&mut file, width, height)?, weight, colorSpace, gamma }
+		},
+		2 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let weight = readF32LE(&mut file)?;
+			let mut colorSpaceByte = [0u8; 1];
+			file.read_exact(&mut colorSpaceByte)?;
+			let colorSpace = if colorSpaceByte[0] == 0 { ColorSpace::Srgb } else { ColorSpace::Linear };
+			let gamma = readF32LE(&mut file)?;
+			let mean = readF32Image(&mut file, width, height)?;
+			let trail = readF32Image(&mut file, width, height)?;
+			Accumulator::Comet { mean, weight, trail, colorSpace, gamma }
+		},
+		3 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let count = readU32LE(&mut file)?;
+			let scale = readF32LE(&mut file)?;
+			let sum = readF32Image(&mut file, width, height)?;
+			let sumSq = readF32Image(&mut file, width, height)?;
+			Accumulator::StdDev { sum, sumSq, count, scale }
+		},
+		4 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let min = readF32Image(&mut file, width, height)?;
+			let max = readF32Image(&mut file, width, height)?;
+			Accumulator::Range { min, max }
+		},
+		5 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let divisorValue = readU32LE(&mut file)?;
+			let mut hasDivisorByte = [0u8; 1];
+			file.read_exact(&mut hasDivisorByte)?;
+			let divisor = (hasDivisorByte[0] != 0).then_some(divisorValue);
+			Accumulator::SumScaled { sum: readU32Image(&mut file, width, height)?, divisor }
+		},
+		6 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let count = readU32LE(&mut file)?;
+			Accumulator::Rms { sumSq: readF32Image(&mut file, width, height)?, count }
+		},
+		7 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let count = readU32LE(&mut file)?;
+			let epsilon = readF32LE(&mut file)?;
+			Accumulator::GeometricMean { sumLog: readF32Image(&mut file, width, height)?, count, epsilon }
+		},
+		8 => {
+			let (width, height) = (readU32LE(&mut file)?, readU32LE(&mut file)?);
+			checkCheckpointDims(path, (width, height), targetDims)?;
+			let count = readU32LE(&mut file)?;
+			let epsilon = readF32LE(&mut file)?;
+			Accumulator::HarmonicMean { sumRecip: readF32Image(&mut file, width, height)?, count, epsilon }
+		},
+		other => return Err(anyhow!("Checkpoint {path:?} has an unrecognized accumulator kind {other}")),
+	};
+	Ok((inputCount, acc))
+}
+
+/// Derives the per-pixel, per-channel standard deviation from a running sum
+/// and sum-of-squares: `variance = mean(x^2) - mean(x)^2`. Clamped to zero
+/// before the square root to guard against a tiny negative result from
+/// floating-point rounding when the true variance is exactly zero.
+fn stdDevImage(mut sum: Rgb32FImage, sumSq: Rgb32FImage, count: u32, scale: f32) -> Rgb32FImage {
+	let n = count as f32;
+	for (sum, sumSq) in sum.pixels_mut().zip(sumSq.pixels()) {
+		sum.apply2(sumSq, |sum, sumSq| {
+			let mean = sum / n;
+			let variance = (sumSq / n - mean * mean).max(0.0);
+			variance.sqrt() * scale
+		});
+	}
+	sum
+}
+
+/// Derives the per-pixel, per-channel range from a running min and max:
+/// `max - min`, saturating at `0` (already guaranteed since `max >= min` by
+/// construction, but the `.max(0.0)` guards against future callers breaking
+/// that invariant).
+fn rangeImage(mut min: Rgb32FImage, max: Rgb32FImage) -> Rgb32FImage {
+	for (min, max) in min.pixels_mut().zip(max.pixels()) {
+		min.apply2(max, |min, max| (max - min).max(0.0));
+	}
+	min
+}
+
+/// Rescales a `SumScaled` accumulator's `u32` sum down to `u8`: by
+/// `divisor` if `--sum-divisor` was given, or by the observed max sample
+/// across every channel and pixel otherwise (so the brightest point in the
+/// stack lands at 255 without clipping anywhere else). An all-zero `sum`
+/// (a single all-black frame) is left as all-zero rather than dividing by
+/// zero.
+fn sumScaledImage(sum: &ImageBuffer<Rgb<u32>, Vec<u32>>, divisor: Option<u32>) -> RgbImage {
+	let scale = divisor.unwrap_or_else(|| sum.pixels().flat_map(|p| p.0).max().unwrap_or(0).max(1)) as f32 / 255.0;
+	RgbImage::from_fn(sum.width(), sum.height(), |x, y| {
+		let Rgb([r, g, b]) = *sum.get_pixel(x, y);
+		Rgb([
+			(r as f32 / scale).round().clamp(0.0, 255.0) as u8,
+			(g as f32 / scale).round().clamp(0.0, 255.0) as u8,
+			(b as f32 / scale).round().clamp(0.0, 255.0) as u8,
+		])
+	})
+}
+
+/// Extracts a `SumOverflow` accumulator's visible output: the low byte of
+/// each channel's `u32` running sum (`sum % 256`), which is byte-identical
+/// to what repeatedly `u8::wrapping_add`-ing every sample would have
+/// produced, since modular addition doesn't care how the terms were grouped.
+fn sumOverflowImage(sum: &ImageBuffer<Rgb<u32>, Vec<u32>>) -> RgbImage {
+	RgbImage::from_fn(sum.width(), sum.height(), |x, y| {
+		let Rgb([r, g, b]) = *sum.get_pixel(x, y);
+		Rgb([(r % 256) as u8, (g % 256) as u8, (b % 256) as u8])
+	})
+}
+
+/// Extracts a `SumRaw` accumulator's output: the true per-pixel sum, with no
+/// saturation, wrapping, or rescaling to fit a display range. Without
+/// `--sum-shift`, every sample is handed straight through as an `f32` for a
+/// floating-point output format to write verbatim. With `--sum-shift n`, the
+/// sum is right-shifted by `n` bits to fit a 16-bit output, then divided by
+/// `u16::MAX` so `saveOutput`'s existing HDR-to-16-bit path (which multiplies
+/// back up by `u16::MAX`) reconstructs the shifted integer exactly.
+fn sumRawImage(sum: &ImageBuffer<Rgb<u32>, Vec<u32>>, shift: Option<u32>) -> Rgb32FImage {
+	Rgb32FImage::from_fn(sum.width(), sum.height(), |x, y| {
+		let Rgb([r, g, b]) = *sum.get_pixel(x, y);
+		match shift {
+			Some(shift) => Rgb([(r >> shift) as f32 / u16::MAX as f32, (g >> shift) as f32 / u16::MAX as f32, (b >> shift) as f32 / u16::MAX as f32]),
+			None => Rgb([r as f32, g as f32, b as f32]),
+		}
+	})
+}
+
+/// Per-channel `(min, max, mean)` of `img`, for `--stats-json`.
+fn channelStats(img: &RgbImage) -> [(u8, u8, f64); 3] {
+	let mut mins = [u8::MAX; 3];
+	let mut maxes = [0u8; 3];
+	let mut sums = [0f64; 3];
+	for pixel in img.pixels() {
+		for channel in 0..3 {
+			let value = pixel.0[channel];
+			mins[channel] = mins[channel].min(value);
+			maxes[channel] = maxes[channel].max(value);
+			sums[channel] += value as f64;
+		}
+	}
+	let pixelCount = (img.width() * img.height()).max(1) as f64;
+	std::array::from_fn(|channel| (mins[channel], maxes[channel], sums[channel] / pixelCount))
+}
+
+/// Fraction of `img`'s channel samples sitting at the 0 or 255 extremes, for
+/// `--clip-warn-threshold`/`--error-on-clip`. A stack that's mostly clipped
+/// is usually a sign of a mode/parameter mismatch (e.g. `sum` over too many
+/// frames) rather than a deliberate choice.
+fn clippedFraction(img: &RgbImage) -> f32 {
+	let mut clipped = 0u64;
+	let mut total = 0u64;
+	for pixel in img.pixels() {
+		for &sample in &pixel.0 {
+			if sample == 0 || sample == 255 {
+				clipped += 1;
+			}
+			total += 1;
+		}
+	}
+	if total == 0 {
+		0.0
+	} else {
+		clipped as f32 / total as f32
+	}
+}
+
+/// Per-channel absolute difference of `frame` from `base`, used by
+/// `Difference` mode to compare every non-base input against the base frame.
+fn absoluteDifference(mut frame: Rgb32FImage, base: &Rgb32FImage) -> Rgb32FImage {
+	frame.pixels_mut().zip(base.pixels()).for_each(|(d, b)| d.apply2(b, |v, base| (v - base).abs()));
+	frame
+}
+
+/// `Blend` mode's linear mix of two same-size frames: `a*(1-opacity) +
+/// b*opacity`. Works in `f32` so it applies equally to LDR and HDR inputs;
+/// the caller converts back to `Ldr` if both inputs were LDR.
+fn blendFrames(mut a: Rgb32FImage, b: &Rgb32FImage, opacity: f32) -> Rgb32FImage {
+	a.pixels_mut().zip(b.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a * (1.0 - opacity) + b * opacity));
+	a
+}
+
+/// For `--subtract-background`: computes the per-pixel minimum across every
+/// frame (the static background, assuming highlights only ever brighten a
+/// pixel relative to it), then subtracts that background from each frame,
+/// saturating at black. Needs all frames decoded and in memory at once,
+/// which is only true for the associative-mode path, hence no streaming
+/// equivalent.
+fn subtractBackground(frames: Vec<DecodedFrame>) -> Vec<DecodedFrame> {
+	let mut background: Option<Rgb32FImage> = None;
+	for frame in &frames {
+		let img = frame.clone().intoRgb32f();
+		background = Some(match background {
+			Some(mut bg) => {
+				bg.pixels_mut().zip(img.pixels()).for_each(|(a, b)| a.apply2(b, f32::min));
+				bg
+			},
+			None => img,
+		});
+	}
+	let background = background.expect("caller checked frames is non-empty");
+	frames
+		.into_iter()
+		.map(|frame| {
+			let isLdr = matches!(frame, DecodedFrame::Ldr(_));
+			let mut img = frame.intoRgb32f();
+			img.pixels_mut().zip(background.pixels()).for_each(|(a, b)| a.apply2(b, |v, bg| (v - bg).max(0.0)));
+			if isLdr {
+				DecodedFrame::Ldr(DynamicImage::ImageRgb32F(img).into_rgb8())
+			} else {
+				DecodedFrame::Hdr(img)
+			}
+		})
+		.collect()
+}
+
+/// A single-channel float image, used for `ExposureFusion`'s per-pixel blend
+/// weights: they need finer precision than `GrayImage`'s `u8` gives, since
+/// they're multiplied together and normalized before ever being clamped to a
+/// displayable range.
+type WeightMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Blends `frames` (same-size, differently-exposed `Rgb32FImage`s) using the
+/// Mertens exposure fusion algorithm: each frame is weighted per pixel by
+/// contrast, saturation, and well-exposedness ([`fusionWeights`]), the
+/// weights are normalized to sum to 1 across frames at every pixel, and the
+/// weighted frames are blended through a Laplacian/Gaussian pyramid
+/// ([`gaussianPyramid`]/[`laplacianPyramid`]) rather than a flat per-pixel
+/// average, so that sharp weight transitions (e.g. a bright window against a
+/// dark room) don't produce visible blend seams.
+fn exposureFusion(frames: Vec<Rgb32FImage>, lumaCoeffs: LumaCoeffs, ignoreClipped: Option<(f32, f32)>) -> AResult<Rgb32FImage> {
+	let (width, height) = frames.first().ok_or_else(|| anyhow!("No frames decoded from inputs"))?.dimensions();
+	let levels = fusionPyramidLevels(width, height);
+
+	let mut weights: Vec<WeightMap> = frames.iter().map(|frame| fusionWeights(frame, lumaCoeffs)).collect();
+	if let Some((clipLow, clipHigh)) = ignoreClipped {
+		maskClippedFusionWeights(&mut weights, &frames, lumaCoeffs, clipLow, clipHigh);
+	}
+	let weights = normalizeFusionWeights(weights);
+	let weightPyramids: Vec<_> = weights.iter().map(|w| gaussianPyramid(w, levels)).collect();
+	let laplacianPyramids: Vec<_> = frames.iter().map(|f| laplacianPyramid(f, levels)).collect();
+
+	let mut blendedPyramid = Vec::with_capacity(levels as usize + 1);
+	for level in 0..=levels as usize {
+		let (levelWidth, levelHeight) = laplacianPyramids[0][level].dimensions();
+		let mut blended = Rgb32FImage::new(levelWidth, levelHeight);
+		for frameIndex in 0..frames.len() {
+			let laplacian = &laplacianPyramids[frameIndex][level];
+			let weight = &weightPyramids[frameIndex][level];
+			for (x, y, pixel) in laplacian.enumerate_pixels() {
+				let w = weight.get_pixel(x, y).0[0];
+				let out = blended.get_pixel_mut(x, y);
+				for c in 0..3 {
+					out.0[c] += pixel.0[c] * w;
+				}
+			}
+		}
+		blendedPyramid.push(blended);
+	}
+
+	// Collapse the blended pyramid back into a single image, smallest level
+	// first: each level is the previous (smaller) level's residual detail,
+	// so upsampling and adding rebuilds the full-resolution result.
+	let mut result = blendedPyramid.pop().ok_or_else(|| anyhow!("Fusion pyramid has no levels"))?;
+	while let Some(next) = blendedPyramid.pop() {
+		let (levelWidth, levelHeight) = next.dimensions();
+		let mut upsampled = image::imageops::resize(&result, levelWidth, levelHeight, image::imageops::FilterType::Triangle);
+		upsampled.pixels_mut().zip(next.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a + b));
+		result = upsampled;
+	}
+	Ok(result)
+}
+
+/// Number of Laplacian/Gaussian pyramid levels to build for `exposureFusion`,
+/// halving the smaller dimension at each level down to roughly 32px, capped
+/// at 5 to bound memory/time on very large inputs.
+fn fusionPyramidLevels(width: u32, height: u32) -> u32 {
+	let smallestDimension = width.min(height).max(1) as f32;
+	(smallestDimension / 32.0).log2().floor().clamp(0.0, 5.0) as u32
+}
+
+/// Per-pixel Mertens blend weight for `frame`: the product of local
+/// contrast, color saturation, and well-exposedness, each in roughly
+/// `0.0..=1.0`. Higher is a better contribution to the fused result. A small
+/// epsilon keeps every pixel's weight strictly positive, so a frame that's
+/// locally flat, gray, and mid-exposed everywhere still gets *some* weight
+/// rather than being entirely excluded by [`normalizeFusionWeights`].
+fn fusionWeights(frame: &Rgb32FImage, lumaCoeffs: LumaCoeffs) -> WeightMap {
+	const WELL_EXPOSEDNESS_SIGMA: f32 = 0.2;
+	const WEIGHT_EPSILON: f32 = 1e-6;
+
+	let gray = WeightMap::from_fn(frame.width(), frame.height(), |x, y| {
+		let Rgb([r, g, b]) = *frame.get_pixel(x, y);
+		Luma([luminanceOf(r, g, b, lumaCoeffs)])
+	});
+	let contrast = laplacianEnergyMap(&gray);
+
+	WeightMap::from_fn(frame.width(), frame.height(), |x, y| {
+		let Rgb([r, g, b]) = *frame.get_pixel(x, y);
+		let mean = (r + g + b) / 3.0;
+		let saturation = (((r - mean).powi(2) + (g - mean).powi(2) + (b - mean).powi(2)) / 3.0).sqrt();
+		let wellExposedness = [r, g, b]
+			.into_iter()
+			.map(|v| (-(v - 0.5).powi(2) / (2.0 * WELL_EXPOSEDNESS_SIGMA * WELL_EXPOSEDNESS_SIGMA)).exp())
+			.product::<f32>();
+		Luma([contrast.get_pixel(x, y).0[0] * saturation * wellExposedness + WEIGHT_EPSILON])
+	})
+}
+
+/// Absolute Laplacian response of `gray` at every pixel, edge pixels reusing
+/// their nearest interior neighbor rather than needing separate border
+/// handling. Used as `fusionWeights`'s contrast term: a flat, blurry region
+/// has a near-zero response, while a sharp edge or fine detail spikes it.
+fn laplacianEnergyMap(gray: &WeightMap) -> WeightMap {
+	let (width, height) = gray.dimensions();
+	let at = |x: i64, y: i64| gray.get_pixel(x.clamp(0, width as i64 - 1) as u32, y.clamp(0, height as i64 - 1) as u32).0[0];
+	WeightMap::from_fn(width, height, |x, y| {
+		let (x, y) = (x as i64, y as i64);
+		let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+		Luma([laplacian.abs()])
+	})
+}
+
+/// Zeroes each frame's fusion weight at pixels where that frame's luminance
+/// is at or beyond `clipLow`/`clipHigh` (normalized `0.0..=1.0`), for
+/// `--ignore-clipped`: a pixel that's blown out or crushed black in a
+/// particular frame carries no real information there and shouldn't win any
+/// share of the blend. If every frame is clipped at a pixel, leaves that
+/// pixel's weights untouched instead of letting `normalizeFusionWeights`
+/// divide an all-zero sum, the same fallback the streaming-pipeline modes
+/// use.
+fn maskClippedFusionWeights(weights: &mut [WeightMap], frames: &[Rgb32FImage], lumaCoeffs: LumaCoeffs, clipLow: f32, clipHigh: f32) {
+	let (width, height) = frames[0].dimensions();
+	for y in 0..height {
+		for x in 0..width {
+			let clipped: Vec<bool> = frames
+				.iter()
+				.map(|frame| {
+					let Rgb([r, g, b]) = *frame.get_pixel(x, y);
+					let luma = luminanceOf(r, g, b, lumaCoeffs);
+					luma <= clipLow || luma >= clipHigh
+				})
+				.collect();
+			if clipped.iter().all(|&isClipped| isClipped) {
+				continue;
+			}
+			for (weight, &isClipped) in weights.iter_mut().zip(&clipped) {
+				if isClipped {
+					weight.get_pixel_mut(x, y).0[0] = 0.0;
+				}
+			}
+		}
+	}
+}
+
+/// Normalizes `weights` (one map per frame) so that at every pixel, the
+/// weights across all frames sum to 1 — the fused result is then a proper
+/// weighted average rather than scaled up or down by how "good" every frame
+/// happened to be at that pixel.
+fn normalizeFusionWeights(mut weights: Vec<WeightMap>) -> Vec<WeightMap> {
+	let (width, height) = weights[0].dimensions();
+	for y in 0..height {
+		for x in 0..width {
+			let sum: f32 = weights.iter().map(|w| w.get_pixel(x, y).0[0]).sum();
+			for weight in weights.iter_mut() {
+				weight.get_pixel_mut(x, y).0[0] /= sum;
+			}
+		}
+	}
+	weights
+}
+
+/// Builds a Gaussian pyramid of `levels + 1` images: `pyramid[0]` is `img`
+/// itself, and each subsequent level is a blurred, half-resolution copy of
+/// the previous one. Generic over any pixel type with `f32` subpixels, so it
+/// serves both `Rgb32FImage` frames and `WeightMap` weights.
+fn gaussianPyramid<P>(img: &ImageBuffer<P, Vec<f32>>, levels: u32) -> Vec<ImageBuffer<P, Vec<f32>>>
+where
+	P: Pixel<Subpixel = f32> + 'static,
+{
+	let mut pyramid = Vec::with_capacity(levels as usize + 1);
+	let mut current = img.clone();
+	pyramid.push(current.clone());
+	for _ in 0..levels {
+		let blurred = image::imageops::blur(&current, 1.0);
+		let (width, height) = current.dimensions();
+		let (nextWidth, nextHeight) = ((width / 2).max(1), (height / 2).max(1));
+		current = image::imageops::resize(&blurred, nextWidth, nextHeight, image::imageops::FilterType::Triangle);
+		pyramid.push(current.clone());
+	}
+	pyramid
+}
+
+/// Builds a Laplacian pyramid from `img`'s Gaussian pyramid: every level but
+/// the last holds the detail lost between it and the next (smaller) Gaussian
+/// level, and the last level is that smallest Gaussian image outright. Levels
+/// sum back to `img` by upsampling and adding from the smallest level up, as
+/// [`exposureFusion`] does when collapsing the blended pyramid.
+fn laplacianPyramid(img: &Rgb32FImage, levels: u32) -> Vec<Rgb32FImage> {
+	let gaussian = gaussianPyramid(img, levels);
+	let mut laplacian = Vec::with_capacity(gaussian.len());
+	for i in 0..gaussian.len() - 1 {
+		let (width, height) = gaussian[i].dimensions();
+		let upsampled = image::imageops::resize(&gaussian[i + 1], width, height, image::imageops::FilterType::Triangle);
+		let mut diff = gaussian[i].clone();
+		diff.pixels_mut().zip(upsampled.pixels()).for_each(|(a, b)| a.apply2(b, |a, b| a - b));
+		laplacian.push(diff);
+	}
+	laplacian.push(gaussian.last().unwrap().clone());
+	laplacian
+}
+
+/// Combines `frames` (a focus sweep of same-size shots) by picking, at every
+/// pixel, the whole RGB value from whichever frame has the highest local
+/// sharpness there, per [`smoothedSharpnessMap`]. Selecting whole pixels
+/// (rather than per-channel, as a naive per-channel argmax would) keeps
+/// colors coherent across a focus boundary instead of mixing channels from
+/// different frames.
+/// Also returns which frame won at each pixel (its index into `frames`), for
+/// `--source-map`; free to track alongside the winner search this already
+/// does.
+fn focusStack(frames: Vec<Rgb32FImage>, focusRadius: u32, lumaCoeffs: LumaCoeffs) -> AResult<(Rgb32FImage, ImageBuffer<Luma<u32>, Vec<u32>>)> {
+	let (width, height) = frames.first().ok_or_else(|| anyhow!("No frames decoded from inputs"))?.dimensions();
+	let sharpnessMaps: Vec<WeightMap> = frames.iter().map(|frame| smoothedSharpnessMap(frame, focusRadius, lumaCoeffs)).collect();
+
+	let mut result = Rgb32FImage::new(width, height);
+	let mut sourceMap = ImageBuffer::new(width, height);
+	for y in 0..height {
+		for x in 0..width {
+			let mut bestFrame = 0;
+			for frameIndex in 1..frames.len() {
+				if sharpnessMaps[frameIndex].get_pixel(x, y).0[0] > sharpnessMaps[bestFrame].get_pixel(x, y).0[0] {
+					bestFrame = frameIndex;
+				}
+			}
+			result.put_pixel(x, y, *frames[bestFrame].get_pixel(x, y));
+			sourceMap.put_pixel(x, y, Luma([bestFrame as u32]));
+		}
+	}
+	Ok((result, sourceMap))
+}
+
+/// `frame`'s per-pixel local Laplacian energy on luminance ([`laplacianEnergyMap`]),
+/// blurred by `focusRadius` to favor coherent, contiguous regions over
+/// pixel-by-pixel noise in the sharpness estimate. `focusRadius` of `0`
+/// skips the blur entirely.
+fn smoothedSharpnessMap(frame: &Rgb32FImage, focusRadius: u32, lumaCoeffs: LumaCoeffs) -> WeightMap {
+	let gray = WeightMap::from_fn(frame.width(), frame.height(), |x, y| {
+		let Rgb([r, g, b]) = *frame.get_pixel(x, y);
+		Luma([luminanceOf(r, g, b, lumaCoeffs)])
+	});
+	let energy = laplacianEnergyMap(&gray);
+	if focusRadius == 0 { energy } else { image::imageops::blur(&energy, focusRadius as f32) }
+}
+
+/// `frame`'s planes under a reversible luma/chroma transform: `y` is
+/// [`luminanceOf`]'s weighted sum, `cb`/`cr` are how far the blue/red
+/// samples sit from it. [`fromYCbCr`] inverts this exactly (up to float
+/// rounding), which is what makes `--luma-chroma-split` a lossless
+/// round-trip when every frame agrees.
+struct YCbCrPlanes {
+	y: Vec<f32>,
+	cb: Vec<f32>,
+	cr: Vec<f32>,
+}
+
+fn toYCbCr(img: &Rgb32FImage, lumaCoeffs: LumaCoeffs) -> YCbCrPlanes {
+	let mut planes = YCbCrPlanes { y: Vec::new(), cb: Vec::new(), cr: Vec::new() };
+	for pixel in img.pixels() {
+		let Rgb([r, g, b]) = *pixel;
+		let y = luminanceOf(r, g, b, lumaCoeffs);
+		planes.y.push(y);
+		planes.cb.push(b - y);
+		planes.cr.push(r - y);
+	}
+	planes
+}
+
+/// Inverts [`toYCbCr`]: `r`/`b` fall straight out of `cr`/`cb`, and `g` is
+/// recovered by solving `luminanceOf`'s weighted sum for the one unknown.
+fn fromYCbCr(planes: &YCbCrPlanes, width: u32, height: u32, lumaCoeffs: LumaCoeffs) -> Rgb32FImage {
+	Rgb32FImage::from_fn(width, height, |x, y| {
+		let i = (y * width + x) as usize;
+		let (y, cb, cr) = (planes.y[i], planes.cb[i], planes.cr[i]);
+		let (r, b) = (cr + y, cb + y);
+		let g = (y - lumaCoeffs.0 * r - lumaCoeffs.2 * b) / lumaCoeffs.1;
+		Rgb([r, g, b])
+	})
+}
+
+/// `--luma-chroma-split`: averages luma across every frame for low noise,
+/// while chroma comes from `chromaSource` instead of also being averaged,
+/// which would otherwise smear a moving subject's color across frames.
+fn lumaChromaSplitStack(frames: Vec<Rgb32FImage>, chromaSource: ChromaSource, lumaCoeffs: LumaCoeffs) -> AResult<Rgb32FImage> {
+	let (width, height) = frames.first().ok_or_else(|| anyhow!("No frames decoded from inputs"))?.dimensions();
+	let planes: Vec<YCbCrPlanes> = frames.iter().map(|frame| toYCbCr(frame, lumaCoeffs)).collect();
+	let pixelCount = (width as usize) * (height as usize);
+
+	let mut y = vec![0.0f32; pixelCount];
+	for plane in &planes {
+		for i in 0..pixelCount {
+			y[i] += plane.y[i];
+		}
+	}
+	y.iter_mut().for_each(|v| *v /= planes.len() as f32);
+
+	let (cb, cr) = match chromaSource {
+		ChromaSource::First => (planes[0].cb.clone(), planes[0].cr.clone()),
+		ChromaSource::Median => {
+			let mut cb = vec![0.0f32; pixelCount];
+			let mut cr = vec![0.0f32; pixelCount];
+			for i in 0..pixelCount {
+				cb[i] = medianReduce(&planes.iter().map(|plane| plane.cb[i]).collect::<Vec<_>>());
+				cr[i] = medianReduce(&planes.iter().map(|plane| plane.cr[i]).collect::<Vec<_>>());
+			}
+			(cb, cr)
+		},
+	};
+
+	Ok(fromYCbCr(&YCbCrPlanes { y, cb, cr }, width, height, lumaCoeffs))
+}
+
+/// `--normalize`: linearly rescales `frame` in float so its darkest sample
+/// maps to 0.0 and its brightest maps to 1.0 (the `image` crate's normalized
+/// float range, i.e. what will land on 0/255 once an `Ldr` frame is
+/// re-encoded to 8-bit). Runs after stacking but before saving.
+fn normalizeFrame(frame: DecodedFrame, mode: NormalizeMode) -> DecodedFrame {
+	let wasLdr = matches!(frame, DecodedFrame::Ldr(_));
+	let mut img = frame.intoRgb32f();
+	normalizeInPlace(&mut img, mode);
+	if wasLdr { DecodedFrame::Ldr(img.convert()) } else { DecodedFrame::Hdr(img) }
+}
+
+/// Does the actual min/max detection and rescale for `normalizeFrame`,
+/// either per channel or with one shared range across all channels. Reports
+/// the detected range to stderr so the applied stretch is visible. Leaves a
+/// degenerate (zero-width) range untouched rather than dividing by zero.
+fn normalizeInPlace(img: &mut Rgb32FImage, mode: NormalizeMode) {
+	match mode {
+		NormalizeMode::Global => {
+			let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+			for pixel in img.pixels() {
+				for &v in pixel.0.iter() {
+					min = min.min(v);
+					max = max.max(v);
+				}
+			}
+			eprintln!("--normalize: detected range [{min:.4}, {max:.4}] (of 1.0), stretching to fill it");
+			if max > min {
+				img.pixels_mut().for_each(|p| p.apply(|v| (v - min) / (max - min)));
+			}
+		},
+		NormalizeMode::PerChannel => {
+			let mut mins = [f32::INFINITY; 3];
+			let mut maxes = [f32::NEG_INFINITY; 3];
+			for pixel in img.pixels() {
+				for c in 0..3 {
+					mins[c] = mins[c].min(pixel.0[c]);
+					maxes[c] = maxes[c].max(pixel.0[c]);
+				}
+			}
+			eprintln!(
+				"--normalize: detected per-channel range R[{:.4}, {:.4}] G[{:.4}, {:.4}] B[{:.4}, {:.4}] (of 1.0), stretching to fill it",
+				mins[0], maxes[0], mins[1], maxes[1], mins[2], maxes[2]
+			);
+			img.pixels_mut().for_each(|p| {
+				for c in 0..3 {
+					if maxes[c] > mins[c] {
+						p.0[c] = (p.0[c] - mins[c]) / (maxes[c] - mins[c]);
+					}
+				}
+			});
+		},
+	}
+}
+
+/// `--white-balance`: scales `frame`'s channels in float, either by an
+/// explicit `r,g,b` multiplier triple or, for `auto`, by a gray-world
+/// correction (see [`whiteBalanceScales`]). Runs after `--remove-gradient`,
+/// before `--normalize`, so the color cast is corrected before the range
+/// gets stretched.
+fn whiteBalanceFrame(frame: DecodedFrame, whiteBalance: WhiteBalance) -> DecodedFrame {
+	let wasLdr = matches!(frame, DecodedFrame::Ldr(_));
+	let mut img = frame.intoRgb32f();
+	let (rScale, gScale, bScale) = whiteBalanceScales(&img, whiteBalance);
+	eprintln!("--white-balance: applying R×{rScale:.4} G×{gScale:.4} B×{bScale:.4}");
+	img.pixels_mut().for_each(|p| {
+		p.0[0] = (p.0[0] * rScale).max(0.0);
+		p.0[1] = (p.0[1] * gScale).max(0.0);
+		p.0[2] = (p.0[2] * bScale).max(0.0);
+	});
+	if wasLdr { DecodedFrame::Ldr(img.convert()) } else { DecodedFrame::Hdr(img) }
+}
+
+/// Resolves `whiteBalance` to a concrete `(r, g, b)` multiplier triple.
+/// `Manual` passes its factors straight through; `Auto` computes each
+/// channel's mean over `img` and scales it to match the average of all
+/// three channel means (the gray-world assumption), leaving a degenerate
+/// (all-zero) channel untouched rather than dividing by zero.
+fn whiteBalanceScales(img: &Rgb32FImage, whiteBalance: WhiteBalance) -> (f32, f32, f32) {
+	if let WhiteBalance::Manual(r, g, b) = whiteBalance {
+		return (r, g, b);
+	}
+
+	let mut sums = [0f64; 3];
+	for pixel in img.pixels() {
+		for c in 0..3 {
+			sums[c] += pixel.0[c] as f64;
+		}
+	}
+	let pixelCount = (img.width() as u64 * img.height() as u64).max(1) as f64;
+	let means: Vec<f64> = sums.iter().map(|sum| sum / pixelCount).collect();
+	let target = means.iter().sum::<f64>() / 3.0;
+	let scaleOf = |mean: f64| if mean == 0.0 { 1.0 } else { (target / mean) as f32 };
+	(scaleOf(means[0]), scaleOf(means[1]), scaleOf(means[2]))
+}
+
+/// `--remove-gradient`: fits a low-order 2D polynomial per channel to
+/// `frame`'s background and subtracts it, flattening light pollution or
+/// vignetting gradients. Runs after stacking, before `--normalize`.
+fn removeGradient(frame: DecodedFrame, degree: u32, lumaCoeffs: LumaCoeffs) -> DecodedFrame {
+	let wasLdr = matches!(frame, DecodedFrame::Ldr(_));
+	let mut img = frame.intoRgb32f();
+	removeGradientInPlace(&mut img, degree, lumaCoeffs);
+	if wasLdr { DecodedFrame::Ldr(img.convert()) } else { DecodedFrame::Hdr(img) }
+}
+
+/// Does the actual fit-and-subtract for `removeGradient`. Excludes any pixel
+/// more than 2 standard deviations above the mean luminance from the fit
+/// (assumed to be a star, not background), fits `x^i * y^j` (`i + j <=
+/// degree`) independently per channel over normalized `0.0..=1.0` image
+/// coordinates via least squares, then subtracts the fitted surface and adds
+/// back that channel's background mean so overall brightness is preserved.
+/// A channel whose fit turns out singular (e.g. every background pixel
+/// landed in a line) is left untouched rather than corrupted.
+fn removeGradientInPlace(img: &mut Rgb32FImage, degree: u32, lumaCoeffs: LumaCoeffs) {
+	let (width, height) = img.dimensions();
+	if width == 0 || height == 0 {
+		return;
+	}
+
+	let luminance: Vec<f32> = img.pixels().map(|p| luminanceOf(p.0[0], p.0[1], p.0[2], lumaCoeffs)).collect();
+	let mean = luminance.iter().sum::<f32>() / luminance.len() as f32;
+	let variance = luminance.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / luminance.len() as f32;
+	let threshold = mean + 2.0 * variance.sqrt();
+
+	let terms = polynomialTerms(degree);
+	let backgroundPixels: Vec<(u32, u32)> = (0..height)
+		.flat_map(|y| (0..width).map(move |x| (x, y)))
+		.filter(|&(x, y)| luminance[(y * width + x) as usize] <= threshold)
+		.collect();
+	if backgroundPixels.len() < terms.len() {
+		eprintln!("--remove-gradient: only {} background pixel(s) survived star rejection, too few to fit a degree-{degree} polynomial, skipping", backgroundPixels.len());
+		return;
+	}
+
+	for channel in 0..3 {
+		let samples: Vec<(f32, f32, f32)> = backgroundPixels
+			.iter()
+			.map(|&(x, y)| (x as f32 / width as f32, y as f32 / height as f32, img.get_pixel(x, y).0[channel]))
+			.collect();
+		let Some(coeffs) = fitPolynomial(&samples, &terms) else {
+			eprintln!("--remove-gradient: channel {channel}'s background fit is singular, leaving it untouched");
+			continue;
+		};
+		let channelMean = samples.iter().map(|&(_, _, v)| v).sum::<f32>() / samples.len() as f32;
+		for y in 0..height {
+			for x in 0..width {
+				let fitted = evalPolynomial(&coeffs, &terms, x as f32 / width as f32, y as f32 / height as f32);
+				img.get_pixel_mut(x, y).0[channel] = (img.get_pixel(x, y).0[channel] - fitted + channelMean).max(0.0);
+			}
+		}
+	}
+}
+
+/// `--self-flat`: approximates flat-fielding without dedicated flat frames
+/// by blurring the stack's own result with a large Gaussian (`radius`) to
+/// estimate its own vignetting/illumination profile, then dividing by that
+/// profile normalized to its own mean so overall brightness is preserved.
+/// Like [`divideFlat`], a profile pixel too close to zero is left
+/// uncorrected rather than blown out by the divide.
+fn applySelfFlat(frame: DecodedFrame, radius: f32) -> DecodedFrame {
+	let wasLdr = matches!(frame, DecodedFrame::Ldr(_));
+	let mut img = frame.intoRgb32f();
+	let profile = image::imageops::blur(&img, radius);
+	let mean = imageMeanSampleF32(&profile);
+	if mean > f32::EPSILON {
+		img.pixels_mut().zip(profile.pixels()).for_each(|(p, f)| p.apply2(f, |v, &f| if f <= f32::EPSILON { v } else { v * mean / f }));
+	}
+	if wasLdr { DecodedFrame::Ldr(img.convert()) } else { DecodedFrame::Hdr(img) }
+}
+
+/// Every `(i, j)` exponent pair for a 2D polynomial term `x^i * y^j` with
+/// `i + j <= degree`, in a fixed order shared between fitting and
+/// evaluation.
+fn polynomialTerms(degree: u32) -> Vec<(u32, u32)> {
+	(0..=degree).flat_map(|total| (0..=total).map(move |i| (i, total - i))).collect()
+}
+
+/// Evaluates the polynomial defined by `coeffs`/`terms` (see
+/// [`polynomialTerms`]) at `(x, y)`.
+fn evalPolynomial(coeffs: &[f32], terms: &[(u32, u32)], x: f32, y: f32) -> f32 {
+	coeffs.iter().zip(terms).map(|(&c, &(i, j))| c * x.powi(i as i32) * y.powi(j as i32)).sum()
+}
+
+/// Least-squares fit of the 2D polynomial defined by `terms` to `samples`
+/// (`(x, y, value)` triples), via the normal equations solved by Gaussian
+/// elimination. Returns `None` if the resulting system is singular.
+fn fitPolynomial(samples: &[(f32, f32, f32)], terms: &[(u32, u32)]) -> Option<Vec<f32>> {
+	let n = terms.len();
+	let mut normalMatrix = vec![0f64; n * n];
+	let mut rhs = vec![0f64; n];
+	for &(x, y, value) in samples {
+		let basis: Vec<f64> = terms.iter().map(|&(i, j)| (x as f64).powi(i as i32) * (y as f64).powi(j as i32)).collect();
+		for row in 0..n {
+			for col in 0..n {
+				normalMatrix[row * n + col] += basis[row] * basis[col];
+			}
+			rhs[row] += basis[row] * value as f64;
+		}
+	}
+	solveLinearSystem(&mut normalMatrix, &mut rhs, n).map(|solution| solution.into_iter().map(|v| v as f32).collect())
+}
+
+/// Solves `matrix * x = rhs` (row-major, `n x n`) via Gaussian elimination
+/// with partial pivoting, in place. Returns `None` if `matrix` is singular
+/// (a zero, or numerically negligible, pivot column).
+fn solveLinearSystem(matrix: &mut [f64], rhs: &mut [f64], n: usize) -> Option<Vec<f64>> {
+	for col in 0..n {
+		let pivotRow = (col..n).max_by(|&a, &b| matrix[a * n + col].abs().partial_cmp(&matrix[b * n + col].abs()).unwrap())?;
+		if matrix[pivotRow * n + col].abs() < 1e-10 {
+			return None;
+		}
+		if pivotRow != col {
+			for c in 0..n {
+				matrix.swap(col * n + c, pivotRow * n + c);
+			}
+			rhs.swap(col, pivotRow);
+		}
+		let pivot = matrix[col * n + col];
+		for row in (col + 1)..n {
+			let factor = matrix[row * n + col] / pivot;
+			if factor == 0.0 {
+				continue;
+			}
+			for c in col..n {
+				matrix[row * n + c] -= factor * matrix[col * n + c];
+			}
+			rhs[row] -= factor * rhs[col];
+		}
+	}
+
+	let mut solution = vec![0f64; n];
+	for row in (0..n).rev() {
+		let sum: f64 = (row + 1..n).map(|c| matrix[row * n + c] * solution[c]).sum();
+		solution[row] = (rhs[row] - sum) / matrix[row * n + row];
+	}
+	Some(solution)
+}
+
+/// A parsed `.cube` LUT (`--lut`): either a per-channel 1D curve or a full
+/// 3D color cube, sampled with linear/trilinear interpolation respectively.
+enum Lut {
+	OneD(Vec<[f32; 3]>),
+	ThreeD { size: usize, data: Vec<[f32; 3]> },
+}
+
+impl Lut {
+	/// Maps one normalized (`0.0..=1.0`) RGB sample through the LUT.
+	fn sample(&self, r: f32, g: f32, b: f32) -> [f32; 3] {
+		match self {
+			Lut::OneD(table) => [sample1D(table, r, 0), sample1D(table, g, 1), sample1D(table, b, 2)],
+			Lut::ThreeD { size, data } => sample3DTrilinear(*size, data, r, g, b),
+		}
+	}
+}
+
+/// Linearly interpolates channel `channel` of a 1D LUT at `v`.
+fn sample1D(table: &[[f32; 3]], v: f32, channel: usize) -> f32 {
+	let steps = table.len() - 1;
+	let pos = v.clamp(0.0, 1.0) * steps as f32;
+	let i0 = pos.floor() as usize;
+	let i1 = (i0 + 1).min(steps);
+	let t = pos - i0 as f32;
+	table[i0][channel] * (1.0 - t) + table[i1][channel] * t
+}
+
+/// Trilinearly interpolates a 3D LUT at `(r, g, b)`. `data` is indexed with
+/// red moving fastest, then green, then blue, matching the `.cube` format's
+/// row order.
+fn sample3DTrilinear(size: usize, data: &[[f32; 3]], r: f32, g: f32, b: f32) -> [f32; 3] {
+	let steps = size - 1;
+	let (rf, gf, bf) = (r.clamp(0.0, 1.0) * steps as f32, g.clamp(0.0, 1.0) * steps as f32, b.clamp(0.0, 1.0) * steps as f32);
+	let (r0, g0, b0) = (rf.floor() as usize, gf.floor() as usize, bf.floor() as usize);
+	let (r1, g1, b1) = ((r0 + 1).min(steps), (g0 + 1).min(steps), (b0 + 1).min(steps));
+	let (tr, tg, tb) = (rf - r0 as f32, gf - g0 as f32, bf - b0 as f32);
+	let at = |ri: usize, gi: usize, bi: usize| data[ri + gi * size + bi * size * size];
+
+	let mut out = [0.0f32; 3];
+	for (c, out) in out.iter_mut().enumerate() {
+		let c00 = at(r0, g0, b0)[c] * (1.0 - tr) + at(r1, g0, b0)[c] * tr;
+		let c10 = at(r0, g1, b0)[c] * (1.0 - tr) + at(r1, g1, b0)[c] * tr;
+		let c01 = at(r0, g0, b1)[c] * (1.0 - tr) + at(r1, g0, b1)[c] * tr;
+		let c11 = at(r0, g1, b1)[c] * (1.0 - tr) + at(r1, g1, b1)[c] * tr;
+		let c0 = c00 * (1.0 - tg) + c10 * tg;
+		let c1 = c01 * (1.0 - tg) + c11 * tg;
+		*out = c0 * (1.0 - tb) + c1 * tb;
+	}
+	out
+}
+
+/// Parses a `.cube` LUT file (either 1D or 3D). `DOMAIN_MIN`/`DOMAIN_MAX`
+/// and `TITLE` headers are recognized and ignored; every input is assumed
+/// to already be normalized `0.0..=1.0`.
+fn parseCubeLut(path: &Path) -> AResult<Lut> {
+	let contents = std::fs::read_to_string(path).with_context(|| format!("Reading LUT {path:?}"))?;
+	let mut size1D = None;
+	let mut size3D = None;
+	let mut values = Vec::new();
+	for line in contents.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") || line.starts_with("DOMAIN_MIN") || line.starts_with("DOMAIN_MAX") {
+			continue;
+		}
+		if let Some(rest) = line.strip_prefix("LUT_1D_SIZE") {
+			size1D = Some(rest.trim().parse::<usize>().with_context(|| format!("Parsing LUT_1D_SIZE in {path:?}"))?);
+			continue;
+		}
+		if let Some(rest) = line.strip_prefix("LUT_3D_SIZE") {
+			size3D = Some(rest.trim().parse::<usize>().with_context(|| format!("Parsing LUT_3D_SIZE in {path:?}"))?);
+			continue;
+		}
+		let mut parts = line.split_whitespace().map(|part| part.parse::<f32>());
+		match (parts.next(), parts.next(), parts.next()) {
+			(Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => values.push([r, g, b]),
+			_ => return Err(anyhow!("Unrecognized line in LUT {path:?}: {line:?}")),
+		}
+	}
+	match (size1D, size3D) {
+		(Some(size), None) if size < 2 => Err(anyhow!("LUT {path:?} declares LUT_1D_SIZE {size}, but a LUT needs at least 2 entries to interpolate between")),
+		(Some(size), None) if values.len() == size => Ok(Lut::OneD(values)),
+		(Some(size), None) => Err(anyhow!("LUT {path:?} declares LUT_1D_SIZE {size} but has {} entries", values.len())),
+		(None, Some(size)) if size < 2 => Err(anyhow!("LUT {path:?} declares LUT_3D_SIZE {size}, but a LUT needs at least 2 entries per axis to interpolate between")),
+		(None, Some(size)) if values.len() == size * size * size => Ok(Lut::ThreeD { size, data: values }),
+		(None, Some(size)) => Err(anyhow!("LUT {path:?} declares LUT_3D_SIZE {size} but has {} entries", values.len())),
+		(None, None) => Err(anyhow!("LUT {path:?} has no LUT_1D_SIZE or LUT_3D_SIZE header")),
+		(Some(_), Some(_)) => Err(anyhow!("LUT {path:?} declares both LUT_1D_SIZE and LUT_3D_SIZE")),
+	}
+}
+
+/// `--lut`: maps every pixel of `frame` through `lut`. Runs after stacking
+/// (and `--normalize`, if given) but before saving.
+fn applyLut(frame: DecodedFrame, lut: &Lut) -> DecodedFrame {
+	let wasLdr = matches!(frame, DecodedFrame::Ldr(_));
+	let mut img = frame.intoRgb32f();
+	img.pixels_mut().for_each(|p| {
+		let Rgb([r, g, b]) = *p;
+		p.0 = lut.sample(r, g, b);
+	});
+	if wasLdr { DecodedFrame::Ldr(img.convert()) } else { DecodedFrame::Hdr(img) }
+}
+
+/// Keeps whichever of `acc`/`samp` has the higher (`LightenLuma`) or lower
+/// (`DarkenLuma`) luminance, replacing `acc` wholesale rather than comparing
+/// channel-by-channel like `u8CombineOp`'s other ops. Ties keep `acc`.
+fn keepPixelByLumaU8(mode: Mode, acc: &mut Rgb<u8>, samp: &Rgb<u8>, lumaCoeffs: LumaCoeffs) {
+	let accLuma = luminanceOf(acc.0[0] as f32, acc.0[1] as f32, acc.0[2] as f32, lumaCoeffs);
+	let sampLuma = luminanceOf(samp.0[0] as f32, samp.0[1] as f32, samp.0[2] as f32, lumaCoeffs);
+	let keepSamp = match mode {
+		Mode::LightenLuma => sampLuma > accLuma,
+		Mode::DarkenLuma => sampLuma < accLuma,
+		_ => unreachable!(),
+	};
+	if keepSamp {
+		*acc = *samp;
+	}
+}
+
+/// Float equivalent of `keepPixelByLumaU8`.
+fn keepPixelByLumaF32(mode: Mode, acc: &mut Rgb<f32>, samp: &Rgb<f32>, lumaCoeffs: LumaCoeffs) {
+	let accLuma = luminanceOf(acc.0[0], acc.0[1], acc.0[2], lumaCoeffs);
+	let sampLuma = luminanceOf(samp.0[0], samp.0[1], samp.0[2], lumaCoeffs);
+	let keepSamp = match mode {
+		Mode::LightenLuma => sampLuma > accLuma,
+		Mode::DarkenLuma => sampLuma < accLuma,
+		_ => unreachable!(),
+	};
+	if keepSamp {
+		*acc = *samp;
+	}
+}
+
+/// `--source-map` for `LightenLuma`/`DarkenLuma`: which frame won at each
+/// pixel, by the same higher/lower-luminance rule as [`keepPixelByLumaF32`]
+/// (ties keep the earliest frame). Recomputes the winner directly across
+/// every frame at once rather than threading an index through the pairwise
+/// `Accumulator` fold, since only `--source-map` needs it.
+fn lumaSourceMap(frames: &[Rgb32FImage], mode: Mode, lumaCoeffs: LumaCoeffs) -> ImageBuffer<Luma<u32>, Vec<u32>> {
+	let (width, height) = frames[0].dimensions();
+	ImageBuffer::from_fn(width, height, |x, y| {
+		let mut bestFrame = 0;
+		let mut bestLuma = {
+			let Rgb([r, g, b]) = *frames[0].get_pixel(x, y);
+			luminanceOf(r, g, b, lumaCoeffs)
+		};
+		for (frameIndex, frame) in frames.iter().enumerate().skip(1) {
+			let Rgb([r, g, b]) = *frame.get_pixel(x, y);
+			let luma = luminanceOf(r, g, b, lumaCoeffs);
+			let isBetter = match mode {
+				Mode::LightenLuma => luma > bestLuma,
+				Mode::DarkenLuma => luma < bestLuma,
+				_ => unreachable!(),
+			};
+			if isBetter {
+				bestFrame = frameIndex;
+				bestLuma = luma;
+			}
+		}
+		Luma([bestFrame as u32])
+	})
+}
+
+fn u8CombineOp(mode: Mode) -> fn(u8, u8) -> u8 {
+	match mode {
+		Mode::Sum => |acc, samp| acc.saturating_add(samp),
+		Mode::Min => |acc, samp| acc.min(samp),
+		Mode::Max => |acc, samp| acc.max(samp),
+		Mode::SumOverflow | Mode::SumScaled | Mode::SumRaw | Mode::LightenLuma | Mode::DarkenLuma | Mode::Screen | Mode::Multiply | Mode::SoftLight | Mode::Overlay | Mode::Average | Mode::Fade | Mode::Comet | Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean | Mode::StdDev | Mode::Range | Mode::Rms | Mode::GeometricMean | Mode::HarmonicMean | Mode::Difference | Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend => unreachable!(),
+	}
+}
+
+/// Folds `b` into `a` in place using `mode`'s [`u8CombineOp`]. Only ever
+/// called for `Sum`/`Min`/`Max`, whose combine op is applied identically to
+/// every byte regardless of which channel it belongs to, so this treats both
+/// buffers as flat bytes rather than walking pixel by pixel — same result,
+/// less per-pixel overhead.
+#[cfg(not(feature = "simd"))]
+fn combineU8Buffers(a: &mut RgbImage, b: &RgbImage, mode: Mode) {
+	let op = u8CombineOp(mode);
+	for (acc, samp) in a.as_mut().iter_mut().zip(b.as_raw().iter()) {
+		*acc = op(*acc, *samp);
+	}
+}
+
+/// SIMD-accelerated version of [`combineU8Buffers`] (behind the `simd`
+/// feature, backed by the `wide` crate so it works on stable). Processes 16
+/// bytes at a time via the same flat-byte view; whatever doesn't fill a full
+/// 16-byte lane falls back to the scalar op. `saturating_add`/`min`/`max` on
+/// `u8x16` match `u8::saturating_add`/`min`/`max` lane-for-lane, so the
+/// result is byte-identical to the scalar path — this is purely a
+/// throughput optimization for large frames.
+#[cfg(feature = "simd")]
+fn combineU8Buffers(a: &mut RgbImage, b: &RgbImage, mode: Mode) {
+	use wide::u8x16;
+
+	const LANES: usize = 16;
+
+	let aBytes = a.as_mut();
+	let bBytes = b.as_raw();
+	let chunkCount = aBytes.len() / LANES;
+
+	for i in 0..chunkCount {
+		let start = i * LANES;
+		let aLane = u8x16::new(aBytes[start..start + LANES].try_into().unwrap());
+		let bLane = u8x16::new(bBytes[start..start + LANES].try_into().unwrap());
+		let combined = match mode {
+			Mode::Sum => aLane.saturating_add(bLane),
+			Mode::Min => aLane.min(bLane),
+			Mode::Max => aLane.max(bLane),
+			_ => unreachable!("combineU8Buffers is only called for Sum/Min/Max"),
+		};
+		aBytes[start..start + LANES].copy_from_slice(&combined.to_array());
+	}
+
+	let op = u8CombineOp(mode);
+	for i in (chunkCount * LANES)..aBytes.len() {
+		aBytes[i] = op(aBytes[i], bBytes[i]);
+	}
+}
+
+/// Float equivalent of `u8CombineOp`. `Sum`/`SumOverflow` both accumulate as
+/// a plain (non-saturating) addition here: 8-bit-style saturation would
+/// defeat the point of the HDR working path, and wraparound has no useful
+/// meaning for continuous float samples.
+/// The W3C/Photoshop soft-light "D" function: a smoothed version of `sqrt`
+/// used to keep [`f32CombineOp`]'s `SoftLight` case continuous at `x ==
+/// 0.25`, where it switches from the polynomial approximation to the real
+/// square root.
+fn softLightD(x: f32) -> f32 {
+	if x <= 0.25 { ((16.0 * x - 12.0) * x + 4.0) * x } else { x.sqrt() }
+}
+
+fn f32CombineOp(mode: Mode) -> fn(f32, f32) -> f32 {
+	match mode {
+		Mode::Sum | Mode::SumOverflow => |acc, samp| acc + samp,
+		Mode::Min => f32::min,
+		Mode::Max => f32::max,
+		Mode::Screen => |acc, samp| 1.0 - (1.0 - acc) * (1.0 - samp),
+		Mode::Multiply => |acc, samp| acc * samp,
+		// Standard soft-light formula (as used by Photoshop/the W3C
+		// compositing spec), `acc` as the running base and `samp` as the
+		// blend layer. Exactly `acc` when `samp == 0.5`, in both branches.
+		Mode::SoftLight => |acc, samp| {
+			if samp <= 0.5 { acc - (1.0 - 2.0 * samp) * acc * (1.0 - acc) } else { acc + (2.0 * samp - 1.0) * (softLightD(acc) - acc) }
+		},
+		// `Multiply` where the running base is dark, `Screen` where it's
+		// light, split at 50% gray. Exactly `acc` when `samp == 0.5`, in
+		// both branches.
+		Mode::Overlay => |acc, samp| {
+			if acc <= 0.5 { 2.0 * acc * samp } else { 1.0 - 2.0 * (1.0 - acc) * (1.0 - samp) }
+		},
+		Mode::SumScaled | Mode::SumRaw | Mode::LightenLuma | Mode::DarkenLuma | Mode::Average | Mode::Fade | Mode::Comet | Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean | Mode::StdDev | Mode::Range | Mode::Rms | Mode::GeometricMean | Mode::HarmonicMean | Mode::Difference | Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend => unreachable!(),
+	}
+}
+
+/// Applies `--gamma`'s forward transform to a normalized sample, decoding it
+/// by the chosen gamma before accumulation. Distinct from `--color-space
+/// linear`'s sRGB decode (`srgbToLinear`): that one always uses the sRGB
+/// transfer function to correct averaging specifically, while this one is an
+/// arbitrary exponent the caller opts into for any accumulating mode, and the
+/// two compose (both are applied, in the order shown in `Accumulator::
+/// fromImage`) if given together. Exactly `v` at `gamma == 1.0`, so callers
+/// can apply it unconditionally without changing behavior when unset.
+fn gammaDecode(v: f32, gamma: f32) -> f32 {
+	if gamma == 1.0 { v } else { v.powf(1.0 / gamma) }
+}
+
+/// Inverse of `gammaDecode`, re-applied to the final accumulated result.
+fn gammaEncode(v: f32, gamma: f32) -> f32 {
+	if gamma == 1.0 { v } else { v.powf(gamma) }
+}
+
+/// Decodes a normalized (0.0–1.0) sRGB-gamma-encoded sample into linear
+/// light, per the IEC 61966-2-1 transfer function.
+fn srgbToLinear(v: f32) -> f32 {
+	if v <= 0.04045 {
+		v / 12.92
+	} else {
+		((v + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+/// Inverse of `srgbToLinear`: re-encodes a normalized linear-light sample
+/// back into gamma-encoded sRGB.
+fn linearToSrgb(v: f32) -> f32 {
+	if v <= 0.0031308 {
+		v * 12.92
+	} else {
+		1.055 * v.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+/// Extensions recognized as video containers rather than still images.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mkv", "mov", "avi", "webm", "m4v", "flv", "wmv"];
+
+fn isVideoFile(path: &Path) -> bool {
+	path.extension()
+		.and_then(|ext| ext.to_str())
+		.map(|ext| ext.to_ascii_lowercase())
+		.is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Initializes ffmpeg's global state; a no-op unless the `video` feature is
+/// compiled in, since without it there's nothing ffmpeg-backed to run.
+#[cfg(feature = "video")]
+fn initVideoBackend() -> AResult<()> {
+	ffmpeg::init().context("Initializing ffmpeg")
+}
+
+#[cfg(not(feature = "video"))]
+fn initVideoBackend() -> AResult<()> {
+	Ok(())
+}
+
+/// Extensions recognized as RAW camera formats, decoded via `rawloader` +
+/// `imagepipe` (behind the `raw` cargo feature) instead of the `image`
+/// crate's normal format-guessing decode path.
+const RAW_EXTENSIONS: &[&str] = &["cr2", "arw", "nef", "dng", "raf", "orf", "rw2"];
+
+fn isRawFile(path: &Path) -> bool {
+	path.extension()
+		.and_then(|ext| ext.to_str())
+		.map(|ext| ext.to_ascii_lowercase())
+		.is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Demosaics a RAW camera file (e.g. Canon `.CR2`, Sony `.ARW`) into an RGB
+/// image so it can flow through the same pipeline as any other still image.
+/// Width, height, and bit depth all come from the demosaiced result, not the
+/// RAW header, since `imagepipe`'s pipeline can crop/scale along the way.
+#[cfg(feature = "raw")]
+fn decodeRawImage(path: &Path) -> AResult<DynamicImage> {
+	let pathStr = format!("{path:?}");
+	let decoded = imagepipe::simple_decode_8bit(path, 0, 0).map_err(|err| anyhow!("Demosaicing RAW {pathStr}: {err}"))?;
+	let img = RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+		.ok_or_else(|| anyhow!("Demosaiced RAW {pathStr} produced an unexpected buffer size"))?;
+	Ok(DynamicImage::ImageRgb8(img))
+}
+
+#[cfg(not(feature = "raw"))]
+fn decodeRawImage(path: &Path) -> AResult<DynamicImage> {
+	Err(anyhow!("{path:?} is a RAW file; rebuild imgstack with `--features raw` to decode it"))
+}
+
+fn inputDimensions(path: &Path, inputFormat: Option<image::ImageFormat>, ignoreOrientation: bool) -> AResult<(u32, u32)> {
+	if isVideoFile(path) {
+		return videoDimensions(path);
+	} else if isRawFile(path) {
+		return Ok(decodeRawImage(path)?.dimensions());
+	}
+	let (width, height) = if let Some(format) = inputFormat {
+		let file = BufReader::new(OpenOptions::new().read(true).open(path).with_context(|| format!("Opening {path:?}"))?);
+		ImageReader::with_format(file, format).into_dimensions().with_context(|| format!("Querying dimensions of {path:?}"))?
+	} else {
+		image_dimensions(path).with_context(|| format!("Querying dimensions of {path:?}"))?
+	};
+	// The header reports sensor/raw-scan-order dimensions; a 90°/270° EXIF
+	// orientation swaps width and height once `apply_orientation` runs at
+	// decode time, so the dimension check below has to account for that too,
+	// or a portrait-tagged landscape capture would be rejected as mismatched.
+	if !ignoreOrientation && orientationSwapsDimensions(readOrientation(path).unwrap_or(1)) {
+		Ok((height, width))
+	} else {
+		Ok((width, height))
+	}
+}
+
+/// Whether an EXIF `Orientation` value (`1..=8`) implies a 90°/270° rotation,
+/// which swaps width and height once applied. `5..=8` are the four
+/// transpose/rotate variants; `1..=4` are identity/180°/mirror, which don't.
+fn orientationSwapsDimensions(orientation: u8) -> bool {
+	(5..=8).contains(&orientation)
+}
+
+#[cfg(feature = "video")]
+fn videoDimensions(path: &Path) -> AResult<(u32, u32)> {
+	let inputCtx =
+		ffmpeg::format::input(&path).with_context(|| format!("Opening video {path:?}"))?;
+	let stream = inputCtx
+		.streams()
+		.best(ffmpeg::media::Type::Video)
+		.ok_or_else(|| anyhow!("Video {path:?} has no video stream"))?;
+	let decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?
+		.decoder()
+		.video()
+		.with_context(|| format!("Opening video decoder for {path:?}"))?;
+	Ok((decoder.width(), decoder.height()))
+}
+
+#[cfg(not(feature = "video"))]
+fn videoDimensions(path: &Path) -> AResult<(u32, u32)> {
+	Err(anyhow!("{path:?} is a video file; rebuild imgstack with `--features video` to stack it"))
+}
+
+/// Reports per-input decoding progress as a single bar with count,
+/// percentage, and ETA, replacing the old one-line-per-file `eprintln!`.
+/// Falls back to plain `eprintln!` lines (still one per stacking message)
+/// when there's no bar to print above, so `--quiet` and non-terminal output
+/// keep working exactly as before.
+struct Progress {
+	bar: Option<indicatif::ProgressBar>,
+	warnings: std::sync::Mutex<Vec<String>>,
+}
+
+impl Progress {
+	fn new(total: u64, quiet: bool) -> Self {
+		let bar = (!quiet && std::io::stderr().is_terminal()).then(|| {
+			let bar = indicatif::ProgressBar::new(total);
+			bar.set_style(
+				indicatif::ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} ({percent}%) ETA {eta}")
+					.unwrap(),
+			);
+			bar
+		});
+		Progress { bar, warnings: std::sync::Mutex::new(Vec::new()) }
+	}
+
+	/// Prints a message above the bar, or straight to stderr if there's no
+	/// bar to draw over.
+	fn println(&self, message: &str) {
+		match &self.bar {
+			Some(bar) => bar.println(message),
+			None => eprintln!("{message}"),
+		}
+	}
+
+	/// Prints `message` prefixed with `Warning: `, same as `println`, and
+	/// also records it so `--stats-json` can report every warning from the
+	/// run.
+	fn warn(&self, message: &str) {
+		let message = format!("Warning: {message}");
+		self.println(&message);
+		self.warnings.lock().unwrap().push(message);
+	}
+
+	/// Returns every warning recorded via `warn` so far.
+	fn warnings(&self) -> Vec<String> {
+		self.warnings.lock().unwrap().clone()
+	}
+
+	/// Advances the bar by one input processed. No-op without a bar.
+	fn inc(&self) {
+		if let Some(bar) = &self.bar {
+			bar.inc(1);
+		}
+	}
+
+	fn finish(&self) {
+		if let Some(bar) = &self.bar {
+			bar.finish_and_clear();
+		}
+	}
+}
+
+/// Decodes `reader` (either a buffered file or a memory-mapped one, see
+/// [`openImageReader`]) into a [`DynamicImage`], guessing the format unless
+/// `inputFormat` overrides it.
+fn decodeFromReader<R: BufRead + Seek>(reader: R, pathStr: &str, inputFormat: Option<image::ImageFormat>) -> AResult<DynamicImage> {
+	let reader = match inputFormat {
+		Some(format) => ImageReader::with_format(reader, format),
+		None => ImageReader::new(reader).with_guessed_format().with_context(|| format!("Guessing format of {pathStr}"))?,
+	};
+	reader
+		.decode()
+		.with_context(|| format!("Decoding {pathStr} (it may be empty or truncated; pass --skip-errors to skip bad inputs instead of aborting)"))
+}
+
+/// Rejects an empty file before it ever reaches the decoder, which would
+/// otherwise fail with an opaque "unsupported format" or EOF error that
+/// gives no hint the file itself is the problem.
+fn checkFileNotEmpty(file: &std::fs::File, pathStr: &str) -> AResult<()> {
+	let length = file.metadata().with_context(|| format!("Reading metadata of {pathStr}"))?.len();
+	if length == 0 {
+		return Err(anyhow!("{pathStr} is empty (0 bytes); pass --skip-errors to skip bad inputs instead of aborting"));
+	}
+	Ok(())
+}
+
+/// Applies `path`'s EXIF `Orientation` tag to `img` in place, rotating/
+/// flipping it into display orientation, unless `ignoreOrientation` (from
+/// `--ignore-orientation`) opts out. A missing EXIF block, missing tag, or
+/// `--ignore-orientation` all leave `img` untouched.
+fn applyExifOrientation(img: &mut DynamicImage, path: &Path, ignoreOrientation: bool) {
+	if ignoreOrientation {
+		return;
+	}
+	if let Some(orientation) = readOrientation(path).and_then(Orientation::from_exif) {
+		img.apply_orientation(orientation);
+	}
+}
+
+fn decodeImage(path: &Path, progress: &Progress, inputFormat: Option<image::ImageFormat>, useMmap: bool, ignoreOrientation: bool) -> AResult<DecodedFrame> {
+	// Owned, not leaked: this lives only as long as the closures below need it.
+	let pathStr = format!("{path:?}");
+
+	progress.println(&format!("Stacking {pathStr}"));
+	if isRawFile(path) {
+		return Ok(DecodedFrame::Ldr(decodeRawImage(path)?.into_rgb8()));
+	}
+	let file = OpenOptions::new()
+		.read(true)
+		.open(path)
+		.with_context(|| format!("Opening {pathStr}"))?;
+	checkFileNotEmpty(&file, &pathStr)?;
+	let mut img = if useMmap {
+		// SAFETY: mapping a file that another process truncates or rewrites
+		// out from under us is technically UB, same caveat as any other
+		// mmap-based tool; --mmap is opt-in for exactly that tradeoff against
+		// skipping a full buffered read of large, uncompressed inputs.
+		let mapped = unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("Memory-mapping {pathStr}"))?;
+		decodeFromReader(std::io::Cursor::new(mapped), &pathStr, inputFormat)?
+	} else {
+		decodeFromReader(BufReader::new(file), &pathStr, inputFormat)?
+	};
+	applyExifOrientation(&mut img, path, ignoreOrientation);
+	let isHdr = match &img {
+		DynamicImage::ImageRgb8(_) => false,
+		DynamicImage::ImageRgba8(_) => {
+			progress.warn(&format!("alpha channel in {pathStr} will be discarded"));
+			false
+		},
+		// Grayscale inputs are promoted to RGB by channel replication so
+		// they can flow through the same accumulation pipeline as everything
+		// else, rather than requiring a dedicated single-channel path.
+		DynamicImage::ImageLuma8(_) => {
+			progress.warn(&format!("promoting grayscale image {pathStr} to RGB"));
+			false
+		},
+		DynamicImage::ImageLumaA8(_) => {
+			progress.warn(&format!("promoting grayscale image {pathStr} to RGB, alpha channel will be discarded"));
+			false
+		},
+		DynamicImage::ImageLuma16(_) => {
+			progress.println(&format!("promoting 16-bit grayscale image {pathStr} to RGB"));
+			true
+		},
+		DynamicImage::ImageLumaA16(_) => {
+			progress.println(&format!("promoting 16-bit grayscale image {pathStr} to RGB, alpha channel will be discarded"));
+			true
+		},
+		DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgb32F(_) => true,
+		DynamicImage::ImageRgba16(_) | DynamicImage::ImageRgba32F(_) => {
+			progress.warn(&format!("alpha channel in {pathStr} will be discarded"));
+			true
+		},
+		// Every pixel format `image` currently produces is matched above; this
+		// only exists because `DynamicImage` is non-exhaustive, for whatever
+		// format a future `image` upgrade might add. Rather than rejecting it
+		// outright, fall back to an 8-bit RGB conversion (`into_rgb8` handles
+		// any variant) and say so, so only genuinely undecodable files fail.
+		_ => {
+			progress.println(&format!("{pathStr} decoded to an unrecognized pixel format; converting to 8-bit RGB"));
+			false
+		},
+	};
+	if isHdr {
+		Ok(DecodedFrame::Hdr(img.into_rgb32f()))
+	} else {
+		Ok(DecodedFrame::Ldr(img.into_rgb8()))
+	}
+}
+
+/// Like `decodeImage`, but keeps the alpha channel instead of discarding it.
+/// Used only by `alpha-over` mode, which is the only mode that cares about
+/// alpha; every other format quirk (16-bit, grayscale) is out of scope here.
+fn decodeImageRgba(path: &Path, progress: &Progress, inputFormat: Option<image::ImageFormat>, useMmap: bool, ignoreOrientation: bool) -> AResult<RgbaImage> {
+	// Owned, not leaked: this lives only as long as the closures below need it.
+	let pathStr = format!("{path:?}");
+
+	progress.println(&format!("Stacking {pathStr}"));
+	if isRawFile(path) {
+		return Ok(decodeRawImage(path)?.into_rgba8());
+	}
+	let file = OpenOptions::new()
+		.read(true)
+		.open(path)
+		.with_context(|| format!("Opening {pathStr}"))?;
+	checkFileNotEmpty(&file, &pathStr)?;
+	let mut img = if useMmap {
+		// SAFETY: same caveat as decodeImage's mmap path.
+		let mapped = unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("Memory-mapping {pathStr}"))?;
+		decodeFromReader(std::io::Cursor::new(mapped), &pathStr, inputFormat)?
+	} else {
+		decodeFromReader(BufReader::new(file), &pathStr, inputFormat)?
+	};
+	applyExifOrientation(&mut img, path, ignoreOrientation);
+	Ok(img.into_rgba8())
+}
+
+/// Composites `top` over `bottom` using standard (straight-alpha) source-over
+/// blending, per the Porter-Duff "over" operator.
+fn compositeOver(mut bottom: RgbaImage, top: RgbaImage) -> RgbaImage {
+	for (b, t) in bottom.pixels_mut().zip(top.pixels()) {
+		let Rgba([br, bg, bb, ba]) = *b;
+		let Rgba([tr, tg, tb, ta]) = *t;
+		let (ba, ta) = (ba as f32 / 255.0, ta as f32 / 255.0);
+		let outAlpha = ta + ba * (1.0 - ta);
+
+		let blend = |bc: u8, tc: u8| -> u8 {
+			if outAlpha <= 0.0 {
+				return 0;
+			}
+			let outPremultiplied = (tc as f32 / 255.0) * ta + (bc as f32 / 255.0) * ba * (1.0 - ta);
+			((outPremultiplied / outAlpha).clamp(0.0, 1.0) * 255.0).round() as u8
+		};
+		*b = Rgba([blend(br, tr), blend(bg, tg), blend(bb, tb), (outAlpha * 255.0).round() as u8]);
+	}
+	bottom
+}
+
+/// Converts a decoded RGB24 ffmpeg frame into an `RgbImage`.
+#[cfg(feature = "video")]
+fn videoFrameToImage(frame: &ffmpeg::util::frame::Video) -> RgbImage {
+	let (width, height) = (frame.width(), frame.height());
+	let stride = frame.stride(0);
+	let data = frame.data(0);
+
+	let mut img = RgbImage::new(width, height);
+	for y in 0..height {
+		let row = &data[y as usize * stride..];
+		for x in 0..width {
+			let i = x as usize * 3;
+			img.put_pixel(x, y, Rgb([row[i], row[i + 1], row[i + 2]]));
+		}
+	}
+	img
+}
+
+/// Decodes the frames of a video input, subsampling and bounding the range
+/// per `args.fps`/`args.frameStep`/`args.start`/`args.end`.
+#[cfg(feature = "video")]
+fn decodeVideoFrames(path: &Path, args: &Args, progress: &Progress) -> AResult<Vec<RgbImage>> {
+	progress.println(&format!("Stacking frames from {path:?}"));
+
+	let mut inputCtx =
+		ffmpeg::format::input(&path).with_context(|| format!("Opening video {path:?}"))?;
+	let stream = inputCtx
+		.streams()
+		.best(ffmpeg::media::Type::Video)
+		.ok_or_else(|| anyhow!("Video {path:?} has no video stream"))?;
+	let streamIndex = stream.index();
+	let timeBase = stream.time_base();
+	let nativeFps = f64::from(stream.avg_frame_rate());
+
+	let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?
+		.decoder()
+		.video()
+		.with_context(|| format!("Opening video decoder for {path:?}"))?;
+	let mut scaler = ffmpeg::software::scaling::Context::get(
+		decoder.format(),
+		decoder.width(),
+		decoder.height(),
+		ffmpeg::format::Pixel::RGB24,
+		decoder.width(),
+		decoder.height(),
+		ffmpeg::software::scaling::Flags::BILINEAR,
+	)
+	.with_context(|| format!("Building scaler for {path:?}"))?;
+
+	let step = match (args.fps, args.frameStep) {
+		(Some(fps), _) if fps > 0.0 && nativeFps > 0.0 => (nativeFps / fps).round().max(1.0) as u64,
+		(_, Some(step)) => step.max(1),
+		_ => 1,
+	};
+
+	let mut frames = Vec::new();
+	let mut decodedCount: u64 = 0;
+	let mut receive = |decoder: &mut ffmpeg::codec::decoder::Video,
+	                    scaler: &mut ffmpeg::software::scaling::Context,
+	                    frames: &mut Vec<RgbImage>|
+	 -> AResult<bool> {
+		let mut decoded = ffmpeg::util::frame::Video::empty();
+		while decoder.receive_frame(&mut decoded).is_ok() {
+			let seconds = decoded
+				.timestamp()
+				.map(|pts| pts as f64 * f64::from(timeBase))
+				.unwrap_or(0.0);
+			let idx = decodedCount;
+			decodedCount += 1;
+
+			if let Some(end) = args.end {
+				if seconds > end {
+					return Ok(true);
+				}
+			}
+			if args.start.is_some_and(|start| seconds < start) || idx % step != 0 {
+				continue;
+			}
+
+			let mut rgbFrame = ffmpeg::util::frame::Video::empty();
+			scaler.run(&decoded, &mut rgbFrame)?;
+			frames.push(videoFrameToImage(&rgbFrame));
+		}
+		Ok(false)
+	};
+
+	'decode: for (stream, packet) in inputCtx.packets() {
+		if stream.index() != streamIndex {
+			continue;
+		}
+		decoder.send_packet(&packet)?;
+		if receive(&mut decoder, &mut scaler, &mut frames)? {
+			break 'decode;
+		}
+	}
+	decoder.send_eof()?;
+	receive(&mut decoder, &mut scaler, &mut frames)?;
+
+	Ok(frames)
+}
+
+#[cfg(not(feature = "video"))]
+fn decodeVideoFrames(path: &Path, _args: &Args, _progress: &Progress) -> AResult<Vec<RgbImage>> {
+	Err(anyhow!("{path:?} is a video file; rebuild imgstack with `--features video` to stack it"))
+}
+
+/// Master bias/dark/flat frames and known hot/dead pixel coordinates applied
+/// to every input before stacking, decoded/loaded once up front so per-input
+/// decoding only pays for the input itself. Applied in that order: bias,
+/// then dark, then flat, then bad-pixel correction.
+#[derive(Default)]
+struct Calibration {
+	bias: Option<RgbImage>,
+	dark: Option<RgbImage>,
+	/// The flat frame, alongside its own mean sample level (used to
+	/// normalize it before dividing inputs by it).
+	flat: Option<(RgbImage, f32)>,
+	badPixels: Vec<(u32, u32)>,
+}
+
+fn decodeInputFrames(
+	path: &Path,
+	args: &Args,
+	calibration: &Calibration,
+	progress: &Progress,
+	targetDims: (u32, u32),
+	roi: Option<(u32, u32, u32, u32)>,
+	offset: (i64, i64),
+) -> AResult<Vec<DecodedFrame>> {
+	let decodeStart = Instant::now();
+	let frames: Vec<DecodedFrame> = if isVideoFile(path) {
+		decodeVideoFrames(path, args, progress)?
+			.into_iter()
+			.map(DecodedFrame::Ldr)
+			.collect()
+	} else {
+		vec![decodeImage(path, progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)?]
+	};
+	if args.verbose && !args.quiet {
+		reportVerboseFrameStats(path, &frames, decodeStart.elapsed(), args, progress)?;
+	}
+	progress.inc();
+	frames
+		.into_iter()
+		.map(|mut frame| {
+			// `image_dimensions` in validateInputs only reads the header; a
+			// malformed file can decode to a buffer that disagrees with it,
+			// which would otherwise let the accumulation loop's `zip` silently
+			// stop early on the shorter frame instead of erroring.
+			if args.resize.is_none() && (frame.width(), frame.height()) != targetDims {
+				return Err(anyhow!(
+					"{path:?} decoded to {}x{} but the header (or an earlier input) said {}x{} (pass --resize to allow this)",
+					frame.width(),
+					frame.height(),
+					targetDims.0,
+					targetDims.1
+				));
+			}
+			frame = resizeFrameIfNeeded(frame, args.resize, targetDims);
+			if let Some(clampRange) = args.clampRange {
+				frame = clampFrameRange(frame, clampRange.lo, clampRange.hi);
+			}
+			if let Some(bias) = &calibration.bias {
+				frame = subtractDark(frame, bias);
+			}
+			if let Some(dark) = &calibration.dark {
+				frame = subtractDark(frame, dark);
+			}
+			if let Some((flat, flatMean)) = &calibration.flat {
+				frame = divideFlat(frame, flat, *flatMean);
+			}
+			if !calibration.badPixels.is_empty() {
+				frame = correctBadPixels(frame, &calibration.badPixels);
+			}
+			if offset != (0, 0) {
+				frame = shiftFrame(frame, offset.0, offset.1);
+			}
+			Ok(cropFrame(frame, roi))
+		})
+		.collect()
+}
+
+/// `--verbose`'s label for the kind of file `path` decoded as. Not a
+/// substitute for `inputDimensions`/`decodeImage`'s own format handling,
+/// just a human-readable summary of what those already decided.
+fn detectedFormatLabel(path: &Path, inputFormat: Option<image::ImageFormat>) -> AResult<String> {
+	if isVideoFile(path) {
+		return Ok("video".to_owned());
+	} else if isRawFile(path) {
+		return Ok("raw".to_owned());
+	}
+	let format = match inputFormat {
+		Some(format) => format,
+		None => {
+			let file = BufReader::new(OpenOptions::new().read(true).open(path).with_context(|| format!("Opening {path:?}"))?);
+			ImageReader::new(file)
+				.with_guessed_format()
+				.with_context(|| format!("Guessing format of {path:?}"))?
+				.format()
+				.ok_or_else(|| anyhow!("Could not detect the format of {path:?}"))?
+		},
+	};
+	Ok(format!("{format:?}").to_lowercase())
+}
+
+/// Prints one `--verbose` line per frame in `frames` (more than one only for
+/// a video input), reporting the dimensions, detected format, min/max/mean
+/// luminance, and how long `path` took to decode. Called right after
+/// decoding, before resize/calibration/crop reshape the frame further, so
+/// the numbers describe the file on disk rather than this run's processing
+/// of it.
+fn reportVerboseFrameStats(path: &Path, frames: &[DecodedFrame], decodeElapsed: Duration, args: &Args, progress: &Progress) -> AResult<()> {
+	let format = detectedFormatLabel(path, args.inputFormat.map(Into::into))?;
+	let lumaCoeffs = resolveLumaCoeffs(&args.lumaCoeffs)?;
+	for (i, frame) in frames.iter().enumerate() {
+		let suffix = if frames.len() > 1 { format!(" frame {i}") } else { String::new() };
+		let (lo, hi, mean) = luminanceStats(frame, lumaCoeffs);
+		progress.println(&format!(
+			"  {path:?}{suffix}: {}x{} {format}, luminance min={lo:.1} max={hi:.1} mean={mean:.1}, decoded in {:.2?}",
+			frame.width(),
+			frame.height(),
+			decodeElapsed
+		));
+	}
+	Ok(())
+}
+
+/// Reads `--offsets` from `path`. See `parseOffsets` for the format.
+fn readOffsets(path: &Path) -> AResult<Vec<(i64, i64)>> {
+	parseOffsets(&std::fs::read_to_string(path)?)
+}
+
+/// Reads `--bad-pixels` from `path`. See `parseBadPixels` for the format.
+fn readBadPixels(path: &Path) -> AResult<Vec<(u32, u32)>> {
+	parseBadPixels(&std::fs::read_to_string(path)?)
+}
+
+/// Parses one `x y` pixel coordinate per line. Blank lines are skipped so a
+/// trailing newline doesn't count as a malformed entry.
+fn parseBadPixels(text: &str) -> AResult<Vec<(u32, u32)>> {
+	text.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let mut parts = line.split_whitespace();
+			let (Some(x), Some(y), None) = (parts.next(), parts.next(), parts.next()) else {
+				return Err(anyhow!("Malformed bad-pixels line {line:?}: expected `x y`"));
+			};
+			let x = x.parse::<u32>().with_context(|| format!("Parsing x in bad-pixels line {line:?}"))?;
+			let y = y.parse::<u32>().with_context(|| format!("Parsing y in bad-pixels line {line:?}"))?;
+			Ok((x, y))
+		})
+		.collect()
+}
+
+/// Parses one `dx dy` pixel-shift pair per line, in input order. Blank
+/// lines are skipped so a trailing newline doesn't count as a mismatched
+/// entry.
+fn parseOffsets(text: &str) -> AResult<Vec<(i64, i64)>> {
+	text.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let mut parts = line.split_whitespace();
+			let (Some(dx), Some(dy), None) = (parts.next(), parts.next(), parts.next()) else {
+				return Err(anyhow!("Malformed offsets line {line:?}: expected `dx dy`"));
+			};
+			let dx = dx.parse::<i64>().with_context(|| format!("Parsing dx in offsets line {line:?}"))?;
+			let dy = dy.parse::<i64>().with_context(|| format!("Parsing dy in offsets line {line:?}"))?;
+			Ok((dx, dy))
+		})
+		.collect()
+}
+
+/// Crops `frame` to `roi` (`x, y, w, h`), if given. Applied after
+/// resize/calibration and right before accumulation, so the accumulation
+/// loop only ever touches the region of interest.
+fn cropFrame(frame: DecodedFrame, roi: Option<(u32, u32, u32, u32)>) -> DecodedFrame {
+	let Some((x, y, w, h)) = roi else { return frame };
+	match frame {
+		DecodedFrame::Ldr(img) => DecodedFrame::Ldr(image::imageops::crop_imm(&img, x, y, w, h).to_image()),
+		DecodedFrame::Hdr(img) => DecodedFrame::Hdr(image::imageops::crop_imm(&img, x, y, w, h).to_image()),
+	}
+}
+
+/// The rectangle (`x, y, w, h`) of a `width` x `height` canvas that every
+/// frame shifted by `shiftFrame` with one of `offsets` still actually
+/// covers, i.e. the intersection of each frame's valid (non-vacated) region.
+/// `shiftFrame` fills anything shifted off-canvas with black, so this is
+/// what `--crop-overlap` crops the stacked output down to, dropping the
+/// ragged, partially-covered borders instead of leaving them darkened. An
+/// empty `offsets`, or every entry being `(0, 0)`, covers the whole canvas.
+fn overlapRegion(offsets: &[(i64, i64)], width: u32, height: u32) -> AResult<(u32, u32, u32, u32)> {
+	let (minDx, maxDx) = offsets.iter().fold((0i64, 0i64), |(lo, hi), &(dx, _)| (lo.min(dx), hi.max(dx)));
+	let (minDy, maxDy) = offsets.iter().fold((0i64, 0i64), |(lo, hi), &(_, dy)| (lo.min(dy), hi.max(dy)));
+	let x0 = maxDx.max(0) as u32;
+	let y0 = maxDy.max(0) as u32;
+	let x1 = (width as i64 + minDx.min(0)).max(0) as u32;
+	let y1 = (height as i64 + minDy.min(0)).max(0) as u32;
+	if x1 <= x0 || y1 <= y0 {
+		return Err(anyhow!(
+			"--crop-overlap: the detected shifts leave no region covered by every frame"
+		));
+	}
+	Ok((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Intersects `rect` with `existing` (if given), used to fold
+/// `--crop-overlap`'s region into an already-requested `--roi`.
+fn intersectRects(existing: Option<(u32, u32, u32, u32)>, rect: (u32, u32, u32, u32)) -> AResult<(u32, u32, u32, u32)> {
+	let Some((ex, ey, ew, eh)) = existing else { return Ok(rect) };
+	let (rx, ry, rw, rh) = rect;
+	let x0 = ex.max(rx);
+	let y0 = ey.max(ry);
+	let x1 = (ex + ew).min(rx + rw);
+	let y1 = (ey + eh).min(ry + rh);
+	if x1 <= x0 || y1 <= y0 {
+		return Err(anyhow!("--crop-overlap: the detected shifts leave no region covered by both every frame and --roi"));
+	}
+	Ok((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Applies `--crop-overlap` right after `--align` detects `offsets`: crops
+/// every `(frame, weight)` pair to the region every frame covers (see
+/// `overlapRegion`) and reports the resulting dimensions.
+fn cropFramesToOverlap(
+	frames: Vec<(DecodedFrame, f32)>,
+	offsets: &[(i64, i64)],
+	width: u32,
+	height: u32,
+	progress: &Progress,
+) -> AResult<Vec<(DecodedFrame, f32)>> {
+	let rect = overlapRegion(offsets, width, height)?;
+	progress.println(&format!("--crop-overlap: cropped to the {}x{} region covered by every frame", rect.2, rect.3));
+	Ok(frames.into_iter().map(|(frame, weight)| (cropFrame(frame, Some(rect)), weight)).collect())
+}
+
+/// Overlays every pixel of `frame` where `mask` is at or below `threshold`
+/// with the corresponding pixel of `firstFrame`, so `--mask`-excluded
+/// regions keep the first input's value untouched instead of the mode's
+/// combined result. Applied once to the final image, rather than gating
+/// every per-pixel combine step during accumulation: combine calls run
+/// through a parallel tree reduction whose pairing order isn't guaranteed
+/// to be left-to-right, so skipping combines pixel-by-pixel wouldn't
+/// reliably preserve "the first frame's value" through re-ordering, while
+/// overlaying the known first frame afterward always does.
+fn applyMask(frame: DecodedFrame, firstFrame: &DecodedFrame, mask: &GrayImage, threshold: u8) -> DecodedFrame {
+	match frame {
+		DecodedFrame::Ldr(mut img) => {
+			let first = firstFrame.clone().intoRgb8();
+			for (x, y, pixel) in img.enumerate_pixels_mut() {
+				if mask.get_pixel(x, y).0[0] <= threshold {
+					*pixel = *first.get_pixel(x, y);
+				}
+			}
+			DecodedFrame::Ldr(img)
+		},
+		DecodedFrame::Hdr(mut img) => {
+			let first = firstFrame.clone().intoRgb32f();
+			for (x, y, pixel) in img.enumerate_pixels_mut() {
+				if mask.get_pixel(x, y).0[0] <= threshold {
+					*pixel = *first.get_pixel(x, y);
+				}
+			}
+			DecodedFrame::Hdr(img)
+		},
+	}
+}
+
+/// Resizes `frame` to `targetDims` with `filter`, if given and the frame
+/// doesn't already match. Applied before calibration/accumulation so every
+/// mode sees uniformly-sized frames regardless of `--resize`.
+fn resizeFrameIfNeeded(frame: DecodedFrame, filter: Option<ResizeFilter>, targetDims: (u32, u32)) -> DecodedFrame {
+	let Some(filter) = filter else { return frame };
+	let (targetWidth, targetHeight) = targetDims;
+	let filter = filter.into();
+	match frame {
+		DecodedFrame::Ldr(img) if (img.width(), img.height()) != targetDims => {
+			DecodedFrame::Ldr(image::imageops::resize(&img, targetWidth, targetHeight, filter))
+		},
+		DecodedFrame::Hdr(img) if (img.width(), img.height()) != targetDims => {
+			DecodedFrame::Hdr(image::imageops::resize(&img, targetWidth, targetHeight, filter))
+		},
+		frame => frame,
+	}
+}
+
+/// Shrinks `frame` by `scale` (e.g. `0.25` for a quarter-size preview),
+/// rounding dimensions down but never below `1`.
+fn downscaleFrame(frame: DecodedFrame, scale: f32) -> DecodedFrame {
+	let targetWidth = ((frame.width() as f32 * scale) as u32).max(1);
+	let targetHeight = ((frame.height() as f32 * scale) as u32).max(1);
+	match frame {
+		DecodedFrame::Ldr(img) => DecodedFrame::Ldr(image::imageops::resize(&img, targetWidth, targetHeight, image::imageops::FilterType::Triangle)),
+		DecodedFrame::Hdr(img) => DecodedFrame::Hdr(image::imageops::resize(&img, targetWidth, targetHeight, image::imageops::FilterType::Triangle)),
+	}
+}
+
+/// Reports how many decoded `frames` are 8-bit versus 16-bit/float, right
+/// after the caller has computed its own `hdr` flag from the same slice
+/// (`frames.iter().any(|frame| matches!(frame, DecodedFrame::Hdr(_)))`), so
+/// a capture set that accidentally mixes bit depths says so instead of
+/// silently promoting everything to match the highest one present. A no-op
+/// when every frame agrees.
+fn printBitDepthSummary<'a>(frames: impl IntoIterator<Item = &'a DecodedFrame>, progress: &Progress) {
+	let (mut ldrCount, mut hdrCount) = (0u32, 0u32);
+	for frame in frames {
+		match frame {
+			DecodedFrame::Ldr(_) => ldrCount += 1,
+			DecodedFrame::Hdr(_) => hdrCount += 1,
+		}
+	}
+	if hdrCount > 0 && ldrCount > 0 {
+		progress.println(&format!("mixed bit depths detected: {ldrCount} 8-bit frame(s) promoted to match {hdrCount} 16-bit/float frame(s)"));
+	}
+}
+
+/// `--preview`: decodes and downscales every input by `--preview-scale`,
+/// stacks them with `mode` (an associative mode, same restriction as
+/// `--animate`), and saves the result to `previewPath`. Runs before the
+/// full-resolution stack so framing/mode choice can be sanity-checked on
+/// huge inputs without waiting for (or allocating memory for) the real run.
+fn generatePreview(inputs: &[PathBuf], args: &Args, progress: &Progress, previewPath: &Path) -> AResult<()> {
+	let format = image::ImageFormat::from_path(previewPath).with_context(|| format!("Guessing preview format of {previewPath:?}; pass a recognized extension"))?;
+	let mode = args.mode;
+	let lumaCoeffs = resolveLumaCoeffs(&args.lumaCoeffs)?;
+	let frames = inputs
+		.par_iter()
+		.map(|path| decodeImage(path, progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation).map(|frame| downscaleFrame(frame, args.previewScale)))
+		.collect::<AResult<Vec<_>>>()?;
+	let hdr = frames.iter().any(|frame| matches!(frame, DecodedFrame::Hdr(_)));
+	let combined = frames
+		.into_par_iter()
+		.map(|frame| Accumulator::fromImage(mode, frame, hdr, args.colorSpace, 1.0, args.stddevScale, args.gamma, args.sumDivisor, args.sumShift, args.geomeanEpsilon, args.harmonicEpsilon, args.accumPrecision))
+		.reduce_with(|a, b| Accumulator::combine(mode, a, b, lumaCoeffs))
+		.ok_or_else(|| anyhow!("No frames decoded from inputs"))?
+		.intoOutput();
+	saveOutput(combined, previewPath, format, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, None).context("Saving preview file")
+}
+
+/// Downscales `img` so its width is at most `maxWidth`, preserving aspect
+/// ratio. Never upscales an image already narrower than `maxWidth`, so a
+/// small input isn't blown up just to match a wide stacked result.
+fn downscaleToMaxWidth(img: RgbImage, maxWidth: u32) -> RgbImage {
+	if img.width() <= maxWidth {
+		return img;
+	}
+	let targetHeight = ((img.height() as f32 * maxWidth as f32 / img.width() as f32) as u32).max(1);
+	image::imageops::resize(&img, maxWidth, targetHeight, image::imageops::FilterType::Triangle)
+}
+
+/// Background/divider color for `--compare`'s canvas: dark gray, so a thin
+/// vertical rule is visible between the two halves regardless of what colors
+/// either image happens to have at the seam.
+const COMPARE_BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+
+/// Width, in pixels, of the vertical divider `--compare` draws between the
+/// input and output halves.
+const COMPARE_DIVIDER_WIDTH: u32 = 4;
+
+/// `--compare`: builds a side-by-side comparison of `firstInput` against
+/// `outImg`, both downscaled to at most `compareMaxWidth` pixels wide and
+/// joined by a vertical divider, and writes it to `comparePath`. A
+/// convenience output alongside the normal one; doesn't affect it. Decodes
+/// `firstInput` fresh rather than reusing anything from the main pipeline,
+/// since calibration/alignment/resize would otherwise defeat the point of
+/// showing what the raw input actually looked like.
+fn saveComparisonImage(comparePath: &Path, firstInput: &Path, outImg: &DecodedFrame, args: &Args, progress: &Progress) -> AResult<()> {
+	let format = image::ImageFormat::from_path(comparePath).with_context(|| format!("Guessing format for --compare {comparePath:?}; pass a recognized extension"))?;
+	let before = decodeImage(firstInput, progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)?.intoRgb8();
+	let before = downscaleToMaxWidth(before, args.compareMaxWidth);
+	let after = downscaleToMaxWidth(outImg.clone().intoRgb8(), args.compareMaxWidth);
+
+	let width = before.width() + COMPARE_DIVIDER_WIDTH + after.width();
+	let height = before.height().max(after.height());
+	let mut canvas = RgbImage::from_pixel(width, height, COMPARE_BACKGROUND);
+	image::imageops::overlay(&mut canvas, &before, 0, 0);
+	image::imageops::overlay(&mut canvas, &after, (before.width() + COMPARE_DIVIDER_WIDTH) as i64, 0);
+
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(comparePath)
+		.with_context(|| format!("Creating --compare file {comparePath:?}"))?;
+	canvas
+		.write_to(&mut std::io::BufWriter::new(file), format)
+		.with_context(|| format!("Encoding --compare output {comparePath:?}"))
+}
+
+/// `--mode-per-channel`: decodes every input once, then runs the ordinary
+/// associative accumulate pipeline three times, once per `(channel, mode)`
+/// pair in `modes` (red, green, blue in that order), each time keeping only
+/// that pass's result for its own channel. Merges the three single-channel
+/// results into one RGB image at the end. This reuses `Accumulator` exactly
+/// as every other associative mode does, rather than teaching it to run a
+/// different formula per channel within a single pass; the tradeoff is
+/// redoing the fold three times instead of once, which is cheap next to the
+/// decode cost this already shares with the normal pipeline.
+fn runModePerChannel(
+	inputs: &[PathBuf],
+	args: &Args,
+	calibration: &Calibration,
+	progress: &Progress,
+	modes: [Mode; 3],
+	targetDims: (u32, u32),
+	roi: Option<(u32, u32, u32, u32)>,
+) -> AResult<DecodedFrame> {
+	let lumaCoeffs = resolveLumaCoeffs(&args.lumaCoeffs)?;
+	let frames: Vec<DecodedFrame> = inputs
+		.par_iter()
+		.map(|path| decodeInputFrames(path, args, calibration, progress, targetDims, roi, (0, 0)))
+		.collect::<AResult<Vec<_>>>()?
+		.into_iter()
+		.flatten()
+		.collect();
+	if frames.is_empty() {
+		return Err(anyhow!("No frames decoded from inputs"));
+	}
+	let hdr = frames.iter().any(|frame| matches!(frame, DecodedFrame::Hdr(_)));
+	printBitDepthSummary(&frames, progress);
+
+	let channelResults = modes.map(|mode| {
+		frames
+			.clone()
+			.into_par_iter()
+			.map(|frame| Accumulator::fromImage(mode, frame, hdr, args.colorSpace, 1.0, args.stddevScale, args.gamma, args.sumDivisor, args.sumShift, args.geomeanEpsilon, args.harmonicEpsilon, args.accumPrecision))
+			.reduce_with(|a, b| Accumulator::combine(mode, a, b, lumaCoeffs))
+			.ok_or_else(|| anyhow!("No frames decoded from inputs"))
+			.map(|combined| combined.intoOutput().intoRgb32f())
+	});
+	let [rResult, gResult, bResult] = channelResults;
+	let (r, g, b) = (rResult?, gResult?, bResult?);
+
+	let mut out = Rgb32FImage::new(r.width(), r.height());
+	for (channel, channelImg) in [&r, &g, &b].into_iter().enumerate() {
+		for (outPixel, srcPixel) in out.pixels_mut().zip(channelImg.pixels()) {
+			outPixel.0[channel] = srcPixel.0[channel];
+		}
+	}
+	Ok(DecodedFrame::Hdr(out))
+}
+
+/// Set by [`installSigintHandler`]'s Ctrl-C callback; the two per-input
+/// incremental fold loops (below, and `runStack`'s `--animate`/
+/// `--preview-every`/`--checkpoint` fold) check this between inputs and, if
+/// set, save whatever's accumulated so far instead of continuing.
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Installs a Ctrl-C handler that requests a graceful shutdown: the first
+/// SIGINT sets [`INTERRUPTED`] so an in-progress incremental fold can save a
+/// partial result and exit on its own terms; a second SIGINT (the user's
+/// been waiting, or the current mode has no meaningful partial result to
+/// save) exits immediately. `ctrlc` runs the callback from its own thread
+/// rather than actual signal context, so it's safe to print and allocate
+/// here.
+fn installSigintHandler() -> AResult<()> {
+	let sigintCount = std::sync::atomic::AtomicUsize::new(0);
+	ctrlc::set_handler(move || {
+		if sigintCount.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+			INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+			eprintln!("\nInterrupted: finishing the current frame and saving a partial result if the mode supports one. Press Ctrl-C again to exit immediately.");
+		} else {
+			eprintln!("\nInterrupted again, exiting immediately without saving.");
+			std::process::exit(130);
+		}
+	})
+	.context("Installing Ctrl-C handler")
+}
+
+/// Called by an incremental fold loop once [`INTERRUPTED`] is seen: saves
+/// `acc`'s current contents to `outFile` with `.partial` appended (so it
+/// never collides with, or is mistaken for, a complete run's output),
+/// reports how many of `total` inputs made it in, and ends the process.
+/// There's no meaningful way to hand a partial result back to the rest of
+/// `runStack`'s pipeline (gradient removal, LUT, `--stats-json`, and so on
+/// all assume a finished stack), so this is the one place in the tool that
+/// exits directly instead of returning an error for `main` to report.
+fn saveInterruptedPartial(acc: Accumulator, mask: &Option<GrayImage>, firstFrame: Option<DecodedFrame>, processed: usize, total: usize, outFile: &Path, outFormat: image::ImageFormat, args: &Args) -> ! {
+	let outImg = acc.intoOutput();
+	let outImg = match (mask, firstFrame) {
+		(Some(mask), Some(firstFrame)) => applyMask(outImg, &firstFrame, mask, args.maskThreshold),
+		_ => outImg,
+	};
+	if isStdout(outFile) {
+		eprintln!("Interrupted after {processed}/{total} input(s): can't save a partial result to stdout, exiting without saving");
+		std::process::exit(0);
+	}
+	let partialPath = PathBuf::from(format!("{}.partial", outFile.display()));
+	match saveOutput(outImg, &partialPath, outFormat, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, args.bitDepth) {
+		Ok(()) => eprintln!("Interrupted after {processed}/{total} input(s): partial result saved to {partialPath:?}"),
+		Err(err) => eprintln!("Interrupted after {processed}/{total} input(s): failed to save partial result: {err:#}"),
+	}
+	std::process::exit(0);
+}
+
+/// Decodes and accumulates `inputs` for `mode` through a bounded producer/
+/// consumer pipeline: a background thread decodes each input in turn and
+/// sends it down a `sync_channel` of capacity 2, while this thread folds
+/// whatever's already arrived into a running [`Accumulator`], overlapping
+/// I/O with compute instead of collecting every decoded frame in memory
+/// before accumulating any of them. Frames are still folded in input order,
+/// so the result is identical to decoding everything up front and reducing;
+/// only throughput and peak memory differ.
+///
+/// Restricted to modes whose [`Accumulator::fromImage`] doesn't need to know
+/// up front whether any frame in the run is HDR (`Average`, `Fade`,
+/// `StdDev`, `Range`, `Rms`, `GeometricMean`, `HarmonicMean`, `Screen`,
+/// `Multiply`, `SumScaled`) —
+/// every other mode picks a `U8` vs `F32` representation based on that,
+/// which would mean knowing every frame's type before accumulating the
+/// first one, defeating the point of a bounded pipeline. Also not used for
+/// `--align`, `--subtract-background`, `--animate`, or `--preview-every`,
+/// which need the full frame set at once regardless. `runStack` only calls
+/// this once those conditions are checked.
+fn runPipelinedAssociative(
+	inputs: &[PathBuf],
+	args: &Args,
+	calibration: &Calibration,
+	progress: &Progress,
+	mode: Mode,
+	weights: &[f32],
+	offsets: &[(i64, i64)],
+	gains: &[f32],
+	targetDims: (u32, u32),
+	roi: Option<(u32, u32, u32, u32)>,
+	mask: &Option<GrayImage>,
+	decodeNanos: &std::sync::atomic::AtomicU64,
+	accumulateNanos: &std::sync::atomic::AtomicU64,
+	lumaCoeffs: LumaCoeffs,
+	outFile: &Path,
+	outFormat: image::ImageFormat,
+) -> AResult<DecodedFrame> {
+	let (tx, rx) = std::sync::mpsc::sync_channel::<AResult<Vec<(DecodedFrame, f32)>>>(2);
+	let outcome: AResult<(Option<Accumulator>, Option<DecodedFrame>)> = std::thread::scope(|scope| {
+		// `tx` is moved into the decoder thread so it's dropped (closing the
+		// channel) as soon as decoding finishes, which is what lets the
+		// `rx.recv()` loop below terminate instead of blocking forever.
+		scope.spawn(move || {
+			for (((path, &weight), &offset), &gain) in inputs.iter().zip(weights.iter()).zip(offsets.iter()).zip(gains.iter()) {
+				let decodeStart = Instant::now();
+				let result = decodeInputFrames(path, args, calibration, progress, targetDims, roi, offset).map(|frames| {
+					frames
+						.into_iter()
+						.map(|frame| {
+							let frame = if gain != 1.0 { scaleFrameBrightness(frame, gain) } else { frame };
+							let weight = if args.weightBySharpness {
+								let sharpness = sharpnessOf(&frame, lumaCoeffs);
+								progress.println(&format!("{path:?}: sharpness {sharpness:.4}"));
+								sharpness
+							} else {
+								weight
+							};
+							(frame, weight)
+						})
+						.collect::<Vec<_>>()
+				});
+				decodeNanos.fetch_add(decodeStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+				if tx.send(result).is_err() {
+					// The consumer below already gave up (hard decode error
+					// upstream); stop decoding rather than filling a channel
+					// nobody's draining.
+					break;
+				}
+			}
+		});
+
+		let mut acc: Option<Accumulator> = None;
+		let mut firstFrame: Option<DecodedFrame> = None;
+		let mut processedCount = 0;
+		while let Ok(result) = rx.recv() {
+			let accumulateStart = Instant::now();
+			for (frame, weight) in result? {
+				if firstFrame.is_none() {
+					firstFrame = Some(frame.clone());
+				}
+				let next = Accumulator::fromImage(mode, frame, false, args.colorSpace, weight, args.stddevScale, args.gamma, args.sumDivisor, args.sumShift, args.geomeanEpsilon, args.harmonicEpsilon, args.accumPrecision);
+				acc = Some(match acc {
+					Some(current) => Accumulator::combine(mode, current, next, lumaCoeffs),
+					None => next,
+				});
+			}
+			processedCount += 1;
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			if INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+				// Dropping `rx` here (by returning) makes the decoder thread's
+				// next `tx.send` fail and break out on its own, so the scope
+				// still exits promptly instead of blocking on a full channel.
+				if let Some(acc) = acc {
+					saveInterruptedPartial(acc, mask, firstFrame, processedCount, inputs.len(), outFile, outFormat, args);
+				}
+				eprintln!("Interrupted after 0/{} input(s): nothing decoded yet, exiting without saving", inputs.len());
+				std::process::exit(0);
+			}
+		}
+		Ok((acc, firstFrame))
+	});
+	let (acc, firstFrame) = outcome?;
+	let acc = acc.ok_or_else(|| anyhow!("No frames decoded from inputs"))?;
+	let outImg = acc.intoOutput();
+	Ok(match (mask, firstFrame) {
+		(Some(mask), Some(firstFrame)) => applyMask(outImg, &firstFrame, mask, args.maskThreshold),
+		_ => outImg,
+	})
+}
+
+/// A detected bright point, in sub-pixel image coordinates.
+type Star = (f32, f32);
+
+/// Finds up to `maxStars` of the brightest local-maximum points in `frame`'s
+/// luminance, each refined to a sub-pixel centroid. Used by `--align stars`
+/// to find landmarks to match between frames.
+fn detectStars(frame: &DecodedFrame, maxStars: usize, lumaCoeffs: LumaCoeffs) -> Vec<Star> {
+	let (width, height, luminance) = frameLuminance(frame, lumaCoeffs);
+	if width < 3 || height < 3 {
+		return Vec::new();
+	}
+
+	let mean = luminance.iter().sum::<f32>() / luminance.len() as f32;
+	let variance = luminance.iter().map(|&v| (v - mean).powi(2)).sum::<f32>() / luminance.len() as f32;
+	let threshold = mean + 3.0 * variance.sqrt();
+
+	let mut peaks = Vec::new();
+	for y in 1..height - 1 {
+		for x in 1..width - 1 {
+			let value = luminance[(y * width + x) as usize];
+			if value < threshold {
+				continue;
+			}
+			let isLocalMax = (-1i32..=1).all(|dy| {
+				(-1i32..=1).all(|dx| {
+					(dx == 0 && dy == 0)
+						|| value >= luminance[((y as i32 + dy) as u32 * width + (x as i32 + dx) as u32) as usize]
+				})
+			});
+			if isLocalMax {
+				peaks.push((x, y, value));
+			}
+		}
+	}
+	peaks.sort_unstable_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+	peaks.truncate(maxStars);
+
+	peaks
+		.into_iter()
+		.map(|(x, y, _)| centroidAround(&luminance, width, height, x, y))
+		.collect()
+}
+
+/// Refines a detected peak at `(x, y)` to a sub-pixel centroid, using a 5x5
+/// window of luminance values as weights.
+fn centroidAround(luminance: &[f32], width: u32, height: u32, x: u32, y: u32) -> Star {
+	const RADIUS: i32 = 2;
+	let (mut weightedX, mut weightedY, mut totalWeight) = (0.0, 0.0, 0.0);
+	for dy in -RADIUS..=RADIUS {
+		for dx in -RADIUS..=RADIUS {
+			let (sampleX, sampleY) = (x as i32 + dx, y as i32 + dy);
+			if sampleX < 0 || sampleY < 0 || sampleX as u32 >= width || sampleY as u32 >= height {
+				continue;
+			}
+			let weight = luminance[(sampleY as u32 * width + sampleX as u32) as usize];
+			weightedX += sampleX as f32 * weight;
+			weightedY += sampleY as f32 * weight;
+			totalWeight += weight;
+		}
+	}
+	if totalWeight == 0.0 {
+		(x as f32, y as f32)
+	} else {
+		(weightedX / totalWeight, weightedY / totalWeight)
+	}
+}
+
+/// `frame`'s dimensions and per-pixel luminance (`Rec. 709` weights),
+/// normalized to roughly the 0.0-255.0 range regardless of whether `frame`
+/// is 8-bit or float, so `detectStars`'s threshold behaves the same either
+/// way.
+fn frameLuminance(frame: &DecodedFrame, lumaCoeffs: LumaCoeffs) -> (u32, u32, Vec<f32>) {
+	match frame {
+		DecodedFrame::Ldr(img) => {
+			let luminance = img.pixels().map(|p| luminanceOf(p.0[0] as f32, p.0[1] as f32, p.0[2] as f32, lumaCoeffs)).collect();
+			(img.width(), img.height(), luminance)
+		},
+		DecodedFrame::Hdr(img) => {
+			let luminance = img
+				.pixels()
+				.map(|p| luminanceOf(p.0[0] * 255.0, p.0[1] * 255.0, p.0[2] * 255.0, lumaCoeffs))
+				.collect();
+			(img.width(), img.height(), luminance)
+		},
+	}
+}
+
+/// Per-channel weights for [`luminanceOf`], as `(r, g, b)`. Always normalized
+/// (via [`resolveLumaCoeffs`]) so the three sum to `1.0`.
+type LumaCoeffs = (f32, f32, f32);
+
+/// Rec. 709 weights, the default for `--luma-coeffs` and every luminance
+/// calculation in the tool unless overridden.
+const REC709_LUMA_COEFFS: LumaCoeffs = (0.2126, 0.7152, 0.0722);
+
+/// Validates and normalizes `--luma-coeffs` so its three weights always sum
+/// to `1.0`, regardless of what the user passed in (e.g. equal `1,1,1`
+/// weighting for scientific monochrome-from-RGB use).
+fn resolveLumaCoeffs(coeffs: &[f32]) -> AResult<LumaCoeffs> {
+	let &[r, g, b] = coeffs else {
+		return Err(anyhow!("--luma-coeffs expects exactly 3 values (r,g,b), got {}", coeffs.len()));
+	};
+	let sum = r + g + b;
+	if sum <= 0.0 {
+		return Err(anyhow!("--luma-coeffs must sum to a positive value, got {r},{g},{b}"));
+	}
+	Ok((r / sum, g / sum, b / sum))
+}
+
+fn luminanceOf(r: f32, g: f32, b: f32, coeffs: LumaCoeffs) -> f32 {
+	coeffs.0 * r + coeffs.1 * g + coeffs.2 * b
+}
+
+/// Focus metric for `--weight-by-sharpness`: the variance of the Laplacian of
+/// `frame`'s luminance. Blurry frames have a flat Laplacian response (low
+/// variance); sharp, detailed frames have edges that spike strongly in
+/// either direction (high variance). This is the standard "variance of
+/// Laplacian" focus measure used for autofocus and focus stacking.
+fn sharpnessOf(frame: &DecodedFrame, lumaCoeffs: LumaCoeffs) -> f32 {
+	let (width, height, luminance) = frameLuminance(frame, lumaCoeffs);
+	if width < 3 || height < 3 {
+		return 0.0;
+	}
+
+	let at = |x: u32, y: u32| luminance[(y * width + x) as usize];
+	let mut laplacians = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+	for y in 1..height - 1 {
+		for x in 1..width - 1 {
+			let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+			laplacians.push(laplacian);
+		}
+	}
+
+	let mean = laplacians.iter().sum::<f32>() / laplacians.len() as f32;
+	laplacians.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / laplacians.len() as f32
+}
+
+/// `frame`'s mean luminance across every pixel, roughly `0.0..=255.0`
+/// regardless of whether `frame` is 8-bit or float, same normalization as
+/// [`frameLuminance`]. Used by `--match-exposure` to compare overall
+/// brightness between frames.
+fn meanLuminance(frame: &DecodedFrame, lumaCoeffs: LumaCoeffs) -> f32 {
+	let (_, _, luminance) = frameLuminance(frame, lumaCoeffs);
+	if luminance.is_empty() {
+		return 0.0;
+	}
+	luminance.iter().sum::<f32>() / luminance.len() as f32
+}
+
+/// `frame`'s min, max, and mean luminance across every pixel, in one pass
+/// over the same normalization [`frameLuminance`] uses. Used by
+/// `--verbose` to summarize each frame as it's decoded.
+fn luminanceStats(frame: &DecodedFrame, lumaCoeffs: LumaCoeffs) -> (f32, f32, f32) {
+	let (_, _, luminance) = frameLuminance(frame, lumaCoeffs);
+	if luminance.is_empty() {
+		return (0.0, 0.0, 0.0);
+	}
+	let (mut lo, mut hi, mut sum) = (f32::INFINITY, f32::NEG_INFINITY, 0.0);
+	for &l in &luminance {
+		lo = lo.min(l);
+		hi = hi.max(l);
+		sum += l;
+	}
+	(lo, hi, sum / luminance.len() as f32)
+}
+
+/// Clamps every sample of `frame` into `[lo, hi]` (normalized `0.0..=1.0`),
+/// for `--clamp-range`. Applied per input, before calibration, so an
+/// out-of-band sample (a sensor's saturated highlights, or below its
+/// black-level offset) is folded in at the boundary rather than skewing
+/// every mode's result the same way an unclamped outlier would.
+fn clampFrameRange(mut frame: DecodedFrame, lo: f32, hi: f32) -> DecodedFrame {
+	match &mut frame {
+		DecodedFrame::Ldr(img) => {
+			let (loByte, hiByte) = (lo * 255.0, hi * 255.0);
+			img.pixels_mut().for_each(|p| p.apply(|v| (v as f32).clamp(loByte, hiByte).round() as u8));
+		},
+		DecodedFrame::Hdr(img) => img.pixels_mut().for_each(|p| p.apply(|v| v.clamp(lo, hi))),
+	}
+	frame
+}
+
+/// Multiplies every sample of `frame` by `scale`, clamping to each
+/// representation's valid range. Used by `--match-exposure` to equalize
+/// brightness between frames before accumulation, and by `--input-gain` to
+/// apply a user-specified per-input scale factor.
+fn scaleFrameBrightness(mut frame: DecodedFrame, scale: f32) -> DecodedFrame {
+	match &mut frame {
+		DecodedFrame::Ldr(img) => img.pixels_mut().for_each(|p| p.apply(|v| ((v as f32) * scale).clamp(0.0, 255.0) as u8)),
+		DecodedFrame::Hdr(img) => img.pixels_mut().for_each(|p| p.apply(|v| (v * scale).max(0.0))),
+	}
+	frame
+}
+
+/// Scale factors are clamped to this range so a frame that's near-black
+/// (whose mean luminance denominator is close to zero) doesn't get amplified
+/// into noise by `--match-exposure`.
+const MATCH_EXPOSURE_SCALE_RANGE: (f32, f32) = (0.1, 10.0);
+
+/// `--match-exposure`: scales every frame's brightness so its mean luminance
+/// matches the first frame's (or `--exposure-reference`'s, if given), so
+/// bracketed frames whose auto-exposure drifted a little don't pull a plain
+/// average toward whichever frame happened to be brightest. Applied after
+/// `--align`/`--subtract-background`, right before accumulation. Prints each
+/// frame's applied scale to stderr.
+fn matchExposure(frames: Vec<(DecodedFrame, f32)>, exposureReference: Option<f32>, progress: &Progress, lumaCoeffs: LumaCoeffs) -> Vec<(DecodedFrame, f32)> {
+	let referenceLuminance = match exposureReference {
+		Some(luminance) => luminance,
+		None => {
+			let Some(luminance) = frames.first().map(|(frame, _)| meanLuminance(frame, lumaCoeffs)) else {
+				return frames;
+			};
+			luminance
+		},
+	};
+	frames
+		.into_iter()
+		.enumerate()
+		.map(|(index, (frame, weight))| {
+			let scale = if referenceLuminance == 0.0 {
+				1.0
+			} else {
+				(referenceLuminance / meanLuminance(&frame, lumaCoeffs)).clamp(MATCH_EXPOSURE_SCALE_RANGE.0, MATCH_EXPOSURE_SCALE_RANGE.1)
+			};
+			progress.println(&format!("--match-exposure: frame {index} scaled by {scale:.4}"));
+			(scaleFrameBrightness(frame, scale), weight)
+		})
+		.collect()
+}
+
+/// `--reject-outlier-frames`: downscales every frame's luminance to a cheap
+/// thumbnail (via `downscaleLuminance`, the same helper `--align phase` uses
+/// for its correlation), takes the per-pixel median across all of them, and
+/// drops whole frames whose mean absolute difference from that median
+/// thumbnail exceeds `threshold`, as a fraction of full scale. Cheap because
+/// it only ever touches thumbnails, unlike the per-pixel rejection modes.
+/// Excluded frames are reported to stderr. A no-op below 3 frames, since a
+/// meaningful median (and the outlier it's supposed to expose) needs at
+/// least that many.
+fn rejectOutlierFrames(frames: Vec<(DecodedFrame, f32)>, threshold: f32, progress: &Progress, lumaCoeffs: LumaCoeffs) -> Vec<(DecodedFrame, f32)> {
+	if frames.len() < 3 {
+		return frames;
+	}
+	let thumbnails: Vec<GrayImage> = frames.iter().map(|(frame, _)| downscaleLuminance(frame, lumaCoeffs)).collect();
+	let (width, height) = thumbnails[0].dimensions();
+	let median = GrayImage::from_fn(width, height, |x, y| {
+		let samples: Vec<f32> = thumbnails.iter().map(|thumb| thumb.get_pixel(x, y).0[0] as f32).collect();
+		Luma([medianReduce(&samples).round() as u8])
+	});
+	let pixelCount = (width * height) as f32;
+	frames
+		.into_iter()
+		.zip(thumbnails)
+		.enumerate()
+		.filter_map(|(index, ((frame, weight), thumb))| {
+			let totalDiff: f32 = thumb.pixels().zip(median.pixels()).map(|(a, b)| (a.0[0] as f32 - b.0[0] as f32).abs()).sum();
+			let meanDiff = (totalDiff / pixelCount) / 255.0;
+			if meanDiff > threshold {
+				progress.warn(&format!(
+					"--reject-outlier-frames: excluding frame {index}, mean absolute difference {meanDiff:.4} exceeds threshold {threshold}"
+				));
+				None
+			} else {
+				Some((frame, weight))
+			}
+		})
+		.collect()
+}
+
+/// Estimates the integer `(dx, dy)` translation that best maps
+/// `referenceStars` onto `candidateStars`, by voting: the displacement
+/// between every reference/candidate pair is rounded and tallied, and the
+/// most common displacement wins. A true frame shift produces one big spike
+/// (every real star votes for it); mismatched pairs scatter across many
+/// different displacements and rarely agree, so the vote is robust without
+/// needing to solve correspondence explicitly.
+fn estimateStarShift(referenceStars: &[Star], candidateStars: &[Star]) -> (i64, i64) {
+	let mut votes: HashMap<(i64, i64), u32> = HashMap::new();
+	for &(refX, refY) in referenceStars {
+		for &(candX, candY) in candidateStars {
+			let offset = ((refX - candX).round() as i64, (refY - candY).round() as i64);
+			*votes.entry(offset).or_insert(0) += 1;
+		}
+	}
+	votes.into_iter().max_by_key(|&(_, count)| count).map(|(offset, _)| offset).unwrap_or((0, 0))
+}
+
+/// Shifts `frame` by `(dx, dy)` pixels, so a positive `dx`/`dy` moves content
+/// right/down. Pixels shifted in from outside the original frame are filled
+/// with black rather than, e.g., clamping to the edge, since that's least
+/// likely to be mistaken for real signal in a stack.
+fn shiftFrame(frame: DecodedFrame, dx: i64, dy: i64) -> DecodedFrame {
+	match frame {
+		DecodedFrame::Ldr(img) => DecodedFrame::Ldr(shiftImage(img, dx, dy)),
+		DecodedFrame::Hdr(img) => DecodedFrame::Hdr(shiftImage(img, dx, dy)),
+	}
+}
+
+fn shiftImage<T: image::Primitive>(img: image::ImageBuffer<Rgb<T>, Vec<T>>, dx: i64, dy: i64) -> image::ImageBuffer<Rgb<T>, Vec<T>> {
+	let (width, height) = img.dimensions();
+	image::ImageBuffer::from_fn(width, height, |x, y| {
+		let (srcX, srcY) = (x as i64 - dx, y as i64 - dy);
+		if srcX >= 0 && srcY >= 0 && (srcX as u32) < width && (srcY as u32) < height {
+			*img.get_pixel(srcX as u32, srcY as u32)
+		} else {
+			Rgb([T::DEFAULT_MIN_VALUE; 3])
+		}
+	})
+}
+
+/// Aligns every frame after the first onto the first via `--align stars`:
+/// detects star centroids in each, votes on the translation that best
+/// matches them to the first frame's stars, and shifts accordingly. Logs
+/// each detected shift to stderr so drift can be sanity-checked. The first
+/// frame is always the reference and is returned unchanged. Also returns the
+/// `(dx, dy)` applied to each frame (the reference's is always `(0, 0)`), so
+/// `--crop-overlap` can crop to the region every frame actually covers.
+fn alignFrames(frames: Vec<DecodedFrame>, starCount: usize, lumaCoeffs: LumaCoeffs) -> (Vec<DecodedFrame>, Vec<(i64, i64)>) {
+	let mut frames = frames.into_iter();
+	let Some(reference) = frames.next() else { return (Vec::new(), Vec::new()) };
+	let referenceStars = detectStars(&reference, starCount, lumaCoeffs);
+
+	let mut aligned = vec![reference];
+	let mut offsets = vec![(0, 0)];
+	for (index, frame) in frames.enumerate() {
+		let candidateStars = detectStars(&frame, starCount, lumaCoeffs);
+		let (dx, dy) = estimateStarShift(&referenceStars, &candidateStars);
+		eprintln!("--align stars: frame {} shifted by ({dx}, {dy})", index + 1);
+		aligned.push(shiftFrame(frame, dx, dy));
+		offsets.push((dx, dy));
+	}
+	(aligned, offsets)
+}
+
+/// Longest side that `--align phase` downscales luminance to before running
+/// the FFT. Big enough to localize a several-pixel shift after scaling back
+/// up, small enough that the FFT stays cheap regardless of input resolution.
+const PHASE_CORRELATION_MAX_DIM: u32 = 128;
+
+/// Aligns every frame after the first onto the first via `--align phase`:
+/// estimates the translation between each frame and the first using FFT
+/// phase correlation on downscaled luminance, then shifts accordingly. Logs
+/// each detected shift to stderr, and errors out if `maxShift` is given and
+/// exceeded, since that means the detection latched onto noise rather than a
+/// real shift. The first frame is always the reference and is returned
+/// unchanged. Also returns the `(dx, dy)` applied to each frame (the
+/// reference's is always `(0, 0)`), so `--crop-overlap` can crop to the
+/// region every frame actually covers.
+fn alignFramesPhase(frames: Vec<DecodedFrame>, maxShift: Option<u32>, lumaCoeffs: LumaCoeffs) -> AResult<(Vec<DecodedFrame>, Vec<(i64, i64)>)> {
+	let mut frames = frames.into_iter();
+	let Some(reference) = frames.next() else { return Ok((Vec::new(), Vec::new())) };
+	let referenceLuma = downscaleLuminance(&reference, lumaCoeffs);
+
+	let mut aligned = vec![reference];
+	let mut offsets = vec![(0, 0)];
+	for (index, frame) in frames.enumerate() {
+		let candidateLuma = downscaleLuminance(&frame, lumaCoeffs);
+		let (dx, dy) = phaseCorrelationShift(&referenceLuma, &candidateLuma, frame.width(), frame.height());
+		eprintln!("--align phase: frame {} shifted by ({dx}, {dy})", index + 1);
+		if let Some(maxShift) = maxShift {
+			if dx.unsigned_abs() > maxShift as u64 || dy.unsigned_abs() > maxShift as u64 {
+				return Err(anyhow!(
+					"--align phase detected an implausible shift of ({dx}, {dy}) on frame {} (limit is {maxShift}); \
+					 this usually means the frame has too little texture to align reliably",
+					index + 1
+				));
+			}
+		}
+		aligned.push(shiftFrame(frame, dx, dy));
+		offsets.push((dx, dy));
+	}
+	Ok((aligned, offsets))
+}
+
+/// Downscales `frame`'s luminance to at most `PHASE_CORRELATION_MAX_DIM` on
+/// its longest side (preserving aspect ratio), for cheap FFT-based
+/// correlation. Frames already smaller than that are left at their own size.
+fn downscaleLuminance(frame: &DecodedFrame, lumaCoeffs: LumaCoeffs) -> GrayImage {
+	let (width, height, luminance) = frameLuminance(frame, lumaCoeffs);
+	let gray = GrayImage::from_fn(width, height, |x, y| Luma([luminance[(y * width + x) as usize].clamp(0.0, 255.0) as u8]));
+
+	let longestSide = width.max(height);
+	if longestSide <= PHASE_CORRELATION_MAX_DIM {
+		return gray;
+	}
+	let scale = PHASE_CORRELATION_MAX_DIM as f32 / longestSide as f32;
+	let (targetWidth, targetHeight) = (((width as f32 * scale).round() as u32).max(1), ((height as f32 * scale).round() as u32).max(1));
+	image::imageops::resize(&gray, targetWidth, targetHeight, image::imageops::FilterType::Triangle)
+}
+
+/// Estimates the integer `(dx, dy)` translation, in `originalWidth` x
+/// `originalHeight` pixels, that best maps `reference` onto `candidate` (both
+/// already downscaled to the same size), via FFT phase correlation: the
+/// cross-power spectrum of the two images is flattened to unit magnitude at
+/// every frequency and inverse-transformed, which concentrates almost all of
+/// its energy at the spatial offset between them.
+fn phaseCorrelationShift(reference: &GrayImage, candidate: &GrayImage, originalWidth: u32, originalHeight: u32) -> (i64, i64) {
+	let (width, height) = (reference.width() as usize, reference.height() as usize);
+
+	let mut referenceFreq: Vec<Complex32> = reference.pixels().map(|p| Complex32::new(p.0[0] as f32, 0.0)).collect();
+	let mut candidateFreq: Vec<Complex32> = candidate.pixels().map(|p| Complex32::new(p.0[0] as f32, 0.0)).collect();
+	fft2d(&mut referenceFreq, width, height, false);
+	fft2d(&mut candidateFreq, width, height, false);
+
+	let mut crossPower: Vec<Complex32> = referenceFreq
+		.iter()
+		.zip(candidateFreq.iter())
+		.map(|(&a, &b)| {
+			let product = a * b.conj();
+			let magnitude = product.norm();
+			if magnitude > 1e-6 { product / magnitude } else { Complex32::new(0.0, 0.0) }
+		})
+		.collect();
+	fft2d(&mut crossPower, width, height, true);
+
+	let (peakIndex, _) = crossPower
+		.iter()
+		.map(|c| c.norm())
+		.enumerate()
+		.fold((0, f32::MIN), |best, (i, v)| if v > best.1 { (i, v) } else { best });
+	let (peakX, peakY) = (peakIndex % width, peakIndex / width);
+
+	// The FFT places a shift of `d` at bin `d` for `d < size/2` and at bin
+	// `size - d` for negative shifts, so unwrap bins past the midpoint back
+	// to negative offsets. This `d` is `candidate`'s offset *from*
+	// `reference` (i.e. `candidate(x) == reference(x - d)`), so the shift
+	// that aligns `candidate` back onto `reference` is its negation.
+	let unwrap = |bin: usize, size: usize| -> i64 {
+		if bin > size / 2 { bin as i64 - size as i64 } else { bin as i64 }
+	};
+	let (candidateOffsetX, candidateOffsetY) = (unwrap(peakX, width), unwrap(peakY, height));
+
+	let scaleX = originalWidth as f64 / width as f64;
+	let scaleY = originalHeight as f64 / height as f64;
+	(
+		(-candidateOffsetX as f64 * scaleX).round() as i64,
+		(-candidateOffsetY as f64 * scaleY).round() as i64,
+	)
+}
+
+/// In-place 2D FFT (or inverse, if `inverse`) of `data`, laid out row-major
+/// as `width` x `height`, done as two passes of 1D FFTs (rows, then columns)
+/// since the 2D DFT is separable. `rustfft`'s inverse doesn't normalize, so
+/// the inverse pass scales by `1 / (width * height)` itself.
+fn fft2d(data: &mut [Complex32], width: usize, height: usize, inverse: bool) {
+	let mut planner = FftPlanner::new();
+	let rowFft = if inverse { planner.plan_fft_inverse(width) } else { planner.plan_fft_forward(width) };
+	for row in data.chunks_mut(width) {
+		rowFft.process(row);
+	}
+
+	let columnFft = if inverse { planner.plan_fft_inverse(height) } else { planner.plan_fft_forward(height) };
+	let mut column = vec![Complex32::new(0.0, 0.0); height];
+	for x in 0..width {
+		for y in 0..height {
+			column[y] = data[y * width + x];
+		}
+		columnFft.process(&mut column);
+		for y in 0..height {
+			data[y * width + x] = column[y];
+		}
+	}
+
+	if inverse {
+		let scale = 1.0 / (width * height) as f32;
+		data.iter_mut().for_each(|v| *v *= scale);
+	}
+}
+
+/// Subtracts a master calibration frame (`--bias` or `--dark`) from `frame`,
+/// saturating at zero rather than wrapping, to remove sensor read noise or
+/// thermal noise and hot pixels before stacking.
+fn subtractDark(mut frame: DecodedFrame, dark: &RgbImage) -> DecodedFrame {
+	match &mut frame {
+		DecodedFrame::Ldr(img) => {
+			img.pixels_mut().zip(dark.pixels()).for_each(|(p, d)| p.apply2(d, |v, d| v.saturating_sub(d)));
+		},
+		DecodedFrame::Hdr(img) => {
+			img.pixels_mut()
+				.zip(dark.pixels())
+				.for_each(|(p, d)| p.apply2(d, |v, &d| (v - d as f32 / 255.0).max(0.0)));
+		},
+	}
+	frame
+}
+
+/// Average sample value across every channel of `img`, used to normalize a
+/// master flat frame before dividing inputs by it.
+fn imageMeanSample(img: &RgbImage) -> f32 {
+	let mut sum = 0u64;
+	let mut count = 0u64;
+	for pixel in img.pixels() {
+		for &sample in &pixel.0 {
+			sum += sample as u64;
+			count += 1;
+		}
+	}
+	sum as f32 / count as f32
+}
+
+/// Like [`imageMeanSample`], but for an already-f32 image; used by
+/// `--self-flat` to normalize its blurred profile without a wasteful
+/// roundtrip through 8-bit.
+fn imageMeanSampleF32(img: &Rgb32FImage) -> f32 {
+	let mut sum = 0.0f64;
+	let mut count = 0u64;
+	for pixel in img.pixels() {
+		for &sample in &pixel.0 {
+			sum += sample as f64;
+			count += 1;
+		}
+	}
+	(sum / count as f64) as f32
+}
+
+/// Divides `frame` by a master flat frame normalized to its own mean level,
+/// to correct lens vignetting and dust shadows. Flat pixels of zero are
+/// treated as the mean instead, i.e. left uncorrected, to avoid a
+/// divide-by-zero from dead/masked pixels in the flat.
+fn divideFlat(mut frame: DecodedFrame, flat: &RgbImage, flatMean: f32) -> DecodedFrame {
+	let normalize = |sample: u8| if sample == 0 { 1.0 } else { sample as f32 / flatMean };
+	match &mut frame {
+		DecodedFrame::Ldr(img) => {
+			img.pixels_mut()
+				.zip(flat.pixels())
+				.for_each(|(p, f)| p.apply2(f, |v, f| ((v as f32) / normalize(f)).clamp(0.0, 255.0) as u8));
+		},
+		DecodedFrame::Hdr(img) => {
+			img.pixels_mut()
+				.zip(flat.pixels())
+				.for_each(|(p, f)| p.apply2(f, |v, &f| v / normalize(f)));
+		},
+	}
+	frame
+}
+
+/// Replaces each `badPixels` coordinate in `frame` with the per-channel
+/// median of its in-bounds neighbors (up to 8, fewer at an edge or corner),
+/// to correct known sensor defects before stacking. Cheaper and more
+/// targeted than a statistical rejection mode when the defect coordinates
+/// are already known. A pixel with no in-bounds neighbors (a 1x1 frame) is
+/// left untouched.
+fn correctBadPixels(mut frame: DecodedFrame, badPixels: &[(u32, u32)]) -> DecodedFrame {
+	let (width, height) = (frame.width(), frame.height());
+	let neighborsOf = |x: u32, y: u32| {
+		(-1i64..=1)
+			.flat_map(|dy| (-1i64..=1).map(move |dx| (dx, dy)))
+			.filter(|&(dx, dy)| (dx, dy) != (0, 0))
+			.filter_map(|(dx, dy)| {
+				let nx = x as i64 + dx;
+				let ny = y as i64 + dy;
+				(nx >= 0 && ny >= 0 && (nx as u32) < width && (ny as u32) < height).then_some((nx as u32, ny as u32))
+			})
+			.collect::<Vec<_>>()
+	};
+	match &mut frame {
+		DecodedFrame::Ldr(img) => {
+			for &(x, y) in badPixels {
+				let neighbors = neighborsOf(x, y);
+				if neighbors.is_empty() {
+					continue;
+				}
+				let mut channels = [0u8; 3];
+				for (c, channel) in channels.iter_mut().enumerate() {
+					let samples: Vec<f32> = neighbors.iter().map(|&(nx, ny)| img.get_pixel(nx, ny).0[c] as f32).collect();
+					*channel = medianReduce(&samples).round() as u8;
+				}
+				*img.get_pixel_mut(x, y) = Rgb(channels);
+			}
+		},
+		DecodedFrame::Hdr(img) => {
+			for &(x, y) in badPixels {
+				let neighbors = neighborsOf(x, y);
+				if neighbors.is_empty() {
+					continue;
+				}
+				let mut channels = [0f32; 3];
+				for (c, channel) in channels.iter_mut().enumerate() {
+					let samples: Vec<f32> = neighbors.iter().map(|&(nx, ny)| img.get_pixel(nx, ny).0[c]).collect();
+					*channel = medianReduce(&samples);
+				}
+				*img.get_pixel_mut(x, y) = Rgb(channels);
+			}
+		},
+	}
+	frame
+}
+
+/// Rows of output processed at once by streaming modes (`sigma-clip`,
+/// `median`, `percentile`), which need every sample for a pixel at once and
+/// so can't use the pairwise-combinable `Accumulator` path. Each input frame
+/// is decoded exactly once, up front, into a temp file; bands are then read
+/// back a row-range at a time, so only `inputs.len()` bands (not full
+/// frames) are ever resident in memory during the reduce pass. Configurable
+/// via `--tile-height`; this default is used when that flag is absent.
+const DEFAULT_TILE_HEIGHT: u32 = 256;
+
+/// One decoded frame for a streaming mode, spilled to disk immediately after
+/// decoding so that bands can be read back without keeping every frame
+/// resident at once. Streaming modes only operate at 8-bit precision, so HDR
+/// frames are tonemapped down before being spilled.
+struct StreamingSource {
+	file: NamedTempFile,
+	width: u32,
+}
+
+impl StreamingSource {
+	/// Decodes every frame of `path` and spills each one to its own temp
+	/// file, which is deleted once the returned sources are dropped.
+	fn decode(
+		path: &Path,
+		args: &Args,
+		calibration: &Calibration,
+		progress: &Progress,
+		targetDims: (u32, u32),
+		roi: Option<(u32, u32, u32, u32)>,
+		offset: (i64, i64),
+	) -> AResult<Vec<StreamingSource>> {
+		decodeInputFrames(path, args, calibration, progress, targetDims, roi, offset)?
+			.into_iter()
+			.map(DecodedFrame::intoRgb8)
+			.map(|frame| {
+				let width = frame.width();
+				let mut file = NamedTempFile::new().context("Creating streaming-mode temp file")?;
+				file.write_all(&frame).context("Spilling decoded frame to temp file")?;
+				Ok(StreamingSource { file, width })
+			})
+			.collect()
+	}
+
+	/// Reads back the rows `bandTop..bandTop + bandHeight` without touching
+	/// any other row of the frame.
+	fn readBand(&self, bandTop: u32, bandHeight: u32) -> AResult<RgbImage> {
+		let rowBytes = self.width as usize * 3;
+		let mut file = self.file.reopen().context("Reopening streaming-mode temp file")?;
+		file.seek(SeekFrom::Start(bandTop as u64 * rowBytes as u64))
+			.context("Seeking streaming-mode temp file")?;
+
+		let mut bytes = vec![0u8; bandHeight as usize * rowBytes];
+		file.read_exact(&mut bytes).context("Reading streaming-mode band")?;
+		RgbImage::from_raw(self.width, bandHeight, bytes).ok_or_else(|| anyhow!("Corrupt streaming-mode temp buffer"))
+	}
+}
+
+/// Per pixel and channel: reject samples more than `sigma` standard
+/// deviations from the mean, recompute the mean from the survivors, and
+/// repeat for `iterations` passes. Falls back to the previous pass's mean if
+/// a pass has zero standard deviation or rejects every sample.
+fn sigmaClipReduce(samples: &[f32], sigma: f32, iterations: u32) -> f32 {
+	sigmaClipReduceDetailed(samples, sigma, iterations, false).0
+}
+
+/// Same rejection loop as [`sigmaClipReduce`], also returning how many
+/// samples survived (to feed `--count-map`) and how many iterations actually
+/// ran. If `converge` is set, the loop stops as soon as a pass rejects zero
+/// samples instead of always running the full `iterations` count, for
+/// `--sigma-converge`.
+fn sigmaClipReduceDetailed(samples: &[f32], sigma: f32, iterations: u32, converge: bool) -> (f32, usize, u32) {
+	let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+
+	let mut survivors = samples.to_vec();
+	let mut mu = mean(&survivors);
+	let mut ran = 0;
+	for _ in 0..iterations {
+		let variance = survivors.iter().map(|v| (v - mu).powi(2)).sum::<f32>() / survivors.len() as f32;
+		let stdDev = variance.sqrt();
+		if stdDev == 0.0 {
+			break;
+		}
+
+		let next: Vec<f32> = survivors
+			.iter()
+			.copied()
+			.filter(|v| (v - mu).abs() <= sigma * stdDev)
+			.collect();
+		if next.is_empty() {
+			break;
+		}
+		ran += 1;
+		let converged = next.len() == survivors.len();
+		survivors = next;
+		mu = mean(&survivors);
+		if converge && converged {
+			break;
+		}
+	}
+	(mu, survivors.len(), ran)
+}
+
+/// Like [`sigmaClipReduceDetailed`], but clamps (winsorizes) samples beyond
+/// `sigma` standard deviations of the running mean to that threshold instead
+/// of discarding them, then keeps iterating on the clamped values. Every
+/// sample always participates, so there's no survivor count to report.
+fn winsorSigmaReduce(samples: &[f32], sigma: f32, iterations: u32) -> f32 {
+	winsorSigmaReduceDetailed(samples, sigma, iterations, false).0
+}
+
+/// Same clamping loop as [`winsorSigmaReduce`], also returning how many
+/// iterations actually ran. If `converge` is set, the loop stops as soon as
+/// a pass clamps zero samples instead of always running the full
+/// `iterations` count, for `--sigma-converge`.
+fn winsorSigmaReduceDetailed(samples: &[f32], sigma: f32, iterations: u32, converge: bool) -> (f32, u32) {
+	let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+
+	let mut values = samples.to_vec();
+	let mut mu = mean(&values);
+	let mut ran = 0;
+	for _ in 0..iterations {
+		let variance = values.iter().map(|v| (v - mu).powi(2)).sum::<f32>() / values.len() as f32;
+		let stdDev = variance.sqrt();
+		if stdDev == 0.0 {
+			break;
+		}
+
+		let threshold = sigma * stdDev;
+		let mut anyClamped = false;
+		for v in values.iter_mut() {
+			let clamped = (*v - mu).clamp(-threshold, threshold);
+			if clamped != *v - mu {
+				anyClamped = true;
+			}
+			*v = mu + clamped;
+		}
+		mu = mean(&values);
+		ran += 1;
+		if converge && !anyClamped {
+			break;
+		}
+	}
+	(mu, ran)
+}
+
+/// Per pixel and channel: the median sample, i.e. the middle element once
+/// sorted, or the average of the two middle elements for an even count.
+fn medianReduce(samples: &[f32]) -> f32 {
+	let mut sorted = samples.to_vec();
+	sorted.sort_by(f32::total_cmp);
+	let mid = sorted.len() / 2;
+	if sorted.len() % 2 == 0 {
+		(sorted[mid - 1] + sorted[mid]) / 2.0
+	} else {
+		sorted[mid]
+	}
+}
+
+/// Finds the same value [`medianReduce`] would from a 256-bin histogram of
+/// `total` 8-bit samples instead of a sorted list, by walking the buckets in
+/// order and tracking where the cumulative count passes each middle rank.
+/// `total` must equal the histogram's sample count.
+fn medianFromHistogram(histogram: &[u32; 256], total: usize) -> u8 {
+	let lowRank = (total - 1) / 2;
+	let highRank = total / 2;
+	let mut cumulative = 0usize;
+	let mut low = None;
+	for (value, &count) in histogram.iter().enumerate() {
+		cumulative += count as usize;
+		if low.is_none() && cumulative > lowRank {
+			low = Some(value as u8);
+		}
+		if cumulative > highRank {
+			let low = low.expect("lowRank <= highRank, so low is always set by the time this is reached");
+			let high = value as u8;
+			return ((low as u16 + high as u16) / 2) as u8;
+		}
+	}
+	unreachable!("cumulative reaches `total` by the last bucket, which is > highRank for any non-empty histogram")
+}
+
+/// Computes `Median` mode's output the same way [`runStreamingReduce`] does
+/// (band by band, so peak memory stays bounded on very large stacks), but
+/// reduces each band through a per-pixel 256-bin histogram built by
+/// streaming `sources` one at a time, instead of gathering every source's
+/// band into memory before reducing. Peak memory per band is `256 * width *
+/// bandHeight` counters, independent of `sources.len()`. See
+/// `Args::medianExact` for the sorted-samples fallback.
+fn runMedianHistogram(width: u32, height: u32, sources: &[StreamingSource], tileHeight: u32) -> AResult<RgbImage> {
+	let mut outImg = RgbImage::new(width, height);
+	let mut bandTop = 0;
+	while bandTop < height {
+		let bandHeight = tileHeight.min(height - bandTop);
+		eprintln!("Reducing rows {bandTop}..{}", bandTop + bandHeight);
+		medianHistogramBandInto(sources, &mut outImg, bandTop, width, bandHeight)?;
+		bandTop += bandHeight;
+	}
+	Ok(outImg)
+}
+
+/// Reduces one band of `sources` into `outImg` at `bandTop`, for
+/// [`runMedianHistogram`].
+fn medianHistogramBandInto(sources: &[StreamingSource], outImg: &mut RgbImage, bandTop: u32, width: u32, bandHeight: u32) -> AResult<()> {
+	let mut histograms = vec![[0u32; 256]; width as usize * bandHeight as usize * 3];
+	for source in sources {
+		let band = source.readBand(bandTop, bandHeight)?;
+		for (pixel, hist) in band.pixels().zip(histograms.chunks_exact_mut(3)) {
+			for (&sample, bucket) in pixel.0.iter().zip(hist.iter_mut()) {
+				bucket[sample as usize] += 1;
+			}
+		}
+	}
+	let total = sources.len();
+	for y in 0..bandHeight {
+		for x in 0..width {
+			let hist = &histograms[(y as usize * width as usize + x as usize) * 3..][..3];
+			let pixel = Rgb([medianFromHistogram(&hist[0], total), medianFromHistogram(&hist[1], total), medianFromHistogram(&hist[2], total)]);
+			outImg.put_pixel(x, bandTop + y, pixel);
+		}
+	}
+	Ok(())
+}
+
+/// Per pixel and channel: the value at `percentile` (0.0–100.0) of the
+/// sorted samples, linearly interpolated between the two nearest ranks so
+/// non-integer percentiles are meaningful. `percentile == 50.0` matches
+/// `medianReduce` for an odd sample count, but the two disagree on rounding
+/// for an even one; `Median` is kept as its own mode for that reason.
+fn percentileReduce(samples: &[f32], percentile: f32) -> f32 {
+	let mut sorted = samples.to_vec();
+	sorted.sort_by(f32::total_cmp);
+
+	let rank = (percentile / 100.0) * (sorted.len() - 1) as f32;
+	let lower = rank.floor() as usize;
+	let upper = rank.ceil() as usize;
+	let frac = rank - lower as f32;
+	sorted[lower] + (sorted[upper] - sorted[lower]) * frac
+}
+
+/// Per pixel and channel: the mean of samples within `threshold` scaled
+/// median absolute deviations of the median, i.e. `median(|x - median(x)|)`.
+/// Falls back to the median itself if every sample is rejected (which only
+/// happens when the MAD is zero and `threshold` is zero too).
+fn madRejectReduce(samples: &[f32], threshold: f32) -> f32 {
+	madRejectReduceDetailed(samples, threshold).0
+}
+
+/// Same rejection as [`madRejectReduce`], also returning how many samples
+/// survived to feed `--count-map`.
+fn madRejectReduceDetailed(samples: &[f32], threshold: f32) -> (f32, usize) {
+	let median = medianReduce(samples);
+	let absoluteDeviations: Vec<f32> = samples.iter().map(|&v| (v - median).abs()).collect();
+	let mad = medianReduce(&absoluteDeviations);
+
+	let survivors: Vec<f32> = samples.iter().copied().filter(|&v| (v - median).abs() <= threshold * mad).collect();
+	if survivors.is_empty() {
+		return (median, 0);
+	}
+	(survivors.iter().sum::<f32>() / survivors.len() as f32, survivors.len())
+}
+
+/// Per pixel and channel: the most frequently occurring 8-bit sample value,
+/// via a 256-bin histogram, so a pixel that's covered by a moving foreground
+/// object only a minority of the time still resolves to its background
+/// value. Ties resolve to the lower value.
+fn mostFrequentReduce(samples: &[f32]) -> f32 {
+	let mut histogram = [0u32; 256];
+	for &v in samples {
+		histogram[v.round().clamp(0.0, 255.0) as usize] += 1;
+	}
+	let mut bestBucket = 0;
+	for (bucket, &count) in histogram.iter().enumerate().skip(1) {
+		if count > histogram[bestBucket] {
+			bestBucket = bucket;
+		}
+	}
+	bestBucket as f32
+}
+
+/// Per pixel and channel: the mean of the sorted samples after discarding
+/// `trimFraction` from each end, reusing `medianReduce`'s sort. Trimming is
+/// capped at whatever leaves one survivor (odd count) or two (even count),
+/// the same floor `medianReduce` itself sits at, so `trimFraction == 0.5`
+/// degenerates to exactly `medianReduce`'s result and `trimFraction == 0.0`
+/// is a plain average.
+fn trimmedMeanReduce(samples: &[f32], trimFraction: f32) -> f32 {
+	trimmedMeanReduceDetailed(samples, trimFraction).0
+}
+
+/// Same trimming as [`trimmedMeanReduce`], also returning how many samples
+/// survived to feed `--count-map`.
+fn trimmedMeanReduceDetailed(samples: &[f32], trimFraction: f32) -> (f32, usize) {
+	let mut sorted = samples.to_vec();
+	sorted.sort_by(f32::total_cmp);
+	let n = sorted.len();
+	let trimCount = ((n as f32 * trimFraction).floor() as usize).min((n - 1) / 2);
+	let survivors = &sorted[trimCount..n - trimCount];
+	(survivors.iter().sum::<f32>() / survivors.len() as f32, survivors.len())
+}
+
+/// Returns the per-pixel reducer for a streaming mode, closing over whatever
+/// parameters it needs from `args`.
+fn streamingReducer(mode: Mode, args: &Args) -> Box<dyn Fn(&[f32]) -> f32 + '_> {
+	match mode {
+		Mode::SigmaClip => Box::new(|samples| sigmaClipReduceDetailed(samples, args.sigma, args.iterations, args.sigmaConverge).0),
+		Mode::WinsorSigma => Box::new(|samples| winsorSigmaReduceDetailed(samples, args.sigma, args.iterations, args.sigmaConverge).0),
+		Mode::Median => Box::new(medianReduce),
+		Mode::Percentile => Box::new(|samples| percentileReduce(samples, args.percentile)),
+		Mode::Max => Box::new(|samples| percentileReduce(samples, args.maxPercentile.expect("only reached when --max-percentile is set"))),
+		Mode::Min => Box::new(|samples| percentileReduce(samples, args.minPercentile.expect("only reached when --min-percentile is set"))),
+		Mode::MadReject => Box::new(|samples| madRejectReduce(samples, args.madThreshold)),
+		Mode::MostFrequent => Box::new(mostFrequentReduce),
+		Mode::TrimmedMean => Box::new(|samples| trimmedMeanReduce(samples, args.trimFraction)),
+		_ => unreachable!("only streaming modes have a streaming reducer"),
+	}
+}
+
+/// Returns the per-pixel survivor-count reducer for a rejection mode, for
+/// `--count-map`/`--rejection-map`. Mirrors `streamingReducer`, but only the
+/// three modes that actually reject samples (see [`Mode::isRejectionMode`])
+/// have one.
+fn survivorCountReducer(mode: Mode, args: &Args) -> Box<dyn Fn(&[f32]) -> usize + '_> {
+	match mode {
+		Mode::SigmaClip => Box::new(|samples| sigmaClipReduceDetailed(samples, args.sigma, args.iterations, args.sigmaConverge).1),
+		Mode::MadReject => Box::new(|samples| madRejectReduceDetailed(samples, args.madThreshold).1),
+		Mode::TrimmedMean => Box::new(|samples| trimmedMeanReduceDetailed(samples, args.trimFraction).1),
+		_ => unreachable!("only rejection modes have a survivor-count reducer"),
+	}
+}
+
+/// Returns the per-pixel iteration-count reducer for `--sigma-converge`, for
+/// the two modes with an iterative rejection/clamping loop. Mirrors
+/// `survivorCountReducer`, but reports how many passes actually ran instead
+/// of how many samples survived.
+fn iterationsReducer(mode: Mode, args: &Args) -> Box<dyn Fn(&[f32]) -> u32 + '_> {
+	match mode {
+		Mode::SigmaClip => Box::new(|samples| sigmaClipReduceDetailed(samples, args.sigma, args.iterations, args.sigmaConverge).2),
+		Mode::WinsorSigma => Box::new(|samples| winsorSigmaReduceDetailed(samples, args.sigma, args.iterations, args.sigmaConverge).1),
+		_ => unreachable!("only sigma-clip/winsor-sigma modes have an iteration-count reducer"),
+	}
+}
+
+/// Filters `samples` down to those inside `clipRange` (given as bytes,
+/// `0.0..=255.0`, matching the streaming pipeline's own sample scale), for
+/// `--clip-range`. Falls back to the full, unfiltered set if every sample
+/// would be excluded, the same way `madRejectReduceDetailed` falls back to
+/// the median rather than reducing an empty slice.
+fn clipSamples(samples: &[f32], clipRange: Option<(f32, f32)>) -> Cow<'_, [f32]> {
+	let Some((lo, hi)) = clipRange else { return Cow::Borrowed(samples) };
+	let filtered: Vec<f32> = samples.iter().copied().filter(|v| (lo..=hi).contains(v)).collect();
+	if filtered.is_empty() { Cow::Borrowed(samples) } else { Cow::Owned(filtered) }
+}
+
+/// Wraps `reducer` so it only ever sees samples surviving `--clip-range`,
+/// still in byte scale. A thin adapter so `streamingReducer`/
+/// `survivorCountReducer`/`iterationsReducer` don't each need their own
+/// clip-range-aware copy.
+fn withClipRange<'a, T>(reducer: Box<dyn Fn(&[f32]) -> T + 'a>, clipRange: Option<(f32, f32)>) -> Box<dyn Fn(&[f32]) -> T + 'a> {
+	match clipRange {
+		None => reducer,
+		Some(_) => Box::new(move |samples| reducer(&clipSamples(samples, clipRange))),
+	}
+}
+
+/// Filters `samples` down to those strictly inside `(clipLow, clipHigh)`
+/// (bytes, `0.0..=255.0`), for `--ignore-clipped`. Falls back to the full,
+/// unfiltered set if every sample is at or beyond one of the bounds, the
+/// same fallback `clipSamples` uses.
+fn excludeClippedSamples(samples: &[f32], bounds: Option<(f32, f32)>) -> Cow<'_, [f32]> {
+	let Some((clipLow, clipHigh)) = bounds else { return Cow::Borrowed(samples) };
+	let filtered: Vec<f32> = samples.iter().copied().filter(|&v| v > clipLow && v < clipHigh).collect();
+	if filtered.is_empty() { Cow::Borrowed(samples) } else { Cow::Owned(filtered) }
+}
+
+/// Wraps `reducer` so it only ever sees samples surviving `--ignore-clipped`,
+/// same shape as `withClipRange`; the two compose since each wraps whatever
+/// it's given.
+fn withIgnoreClipped<'a, T>(reducer: Box<dyn Fn(&[f32]) -> T + 'a>, bounds: Option<(f32, f32)>) -> Box<dyn Fn(&[f32]) -> T + 'a> {
+	match bounds {
+		None => reducer,
+		Some(_) => Box::new(move |samples| reducer(&excludeClippedSamples(samples, bounds))),
+	}
+}
+
+/// Reduces one band's worth of same-sized frames into `outImg` at `bandTop`.
+fn reduceBandInto(bands: &[RgbImage], outImg: &mut RgbImage, bandTop: u32, width: u32, bandHeight: u32, reducer: &dyn Fn(&[f32]) -> f32) {
+	let mut samples = vec![0f32; bands.len()];
+	for y in 0..bandHeight {
+		for x in 0..width {
+			let mut pixel = [0u8; 3];
+			for (channel, out) in pixel.iter_mut().enumerate() {
+				for (sample, band) in samples.iter_mut().zip(bands) {
+					*sample = band.get_pixel(x, y)[channel] as f32;
+				}
+				*out = reducer(&samples).round().clamp(0.0, 255.0) as u8;
+			}
+			outImg.put_pixel(x, bandTop + y, Rgb(pixel));
+		}
+	}
+}
+
+/// Reduces the same band of frames used by `reduceBandInto` into `counts` at
+/// `bandTop`, recording how many samples survived rejection instead of the
+/// reduced value, averaged across channels. Runs alongside `reduceBandInto`
+/// on the bands it already decoded, so `--count-map` doesn't cost a second
+/// decode pass over the inputs.
+fn countBandInto(bands: &[RgbImage], counts: &mut ImageBuffer<Luma<u32>, Vec<u32>>, bandTop: u32, width: u32, bandHeight: u32, countReducer: &dyn Fn(&[f32]) -> usize) {
+	let mut samples = vec![0f32; bands.len()];
+	for y in 0..bandHeight {
+		for x in 0..width {
+			let mut total = 0usize;
+			for channel in 0..3 {
+				for (sample, band) in samples.iter_mut().zip(bands) {
+					*sample = band.get_pixel(x, y)[channel] as f32;
+				}
+				total += countReducer(&samples);
+			}
+			counts.put_pixel(x, bandTop + y, Luma([(total as f32 / 3.0).round() as u32]));
+		}
+	}
+}
+
+/// Reduces the same band of frames used by `reduceBandInto`, but instead of
+/// writing an output image, accumulates how many iterations the per-pixel
+/// rejection/clamping loop actually ran into `totalIterations`/`totalCells`,
+/// for `--sigma-converge`'s average-iterations report. Runs alongside
+/// `reduceBandInto` on the bands it already decoded, same as `countBandInto`.
+fn iterationsBandInto(
+	bands: &[RgbImage],
+	width: u32,
+	bandHeight: u32,
+	iterationsReducer: &dyn Fn(&[f32]) -> u32,
+	totalIterations: &mut u64,
+	totalCells: &mut u64,
+) {
+	let mut samples = vec![0f32; bands.len()];
+	for y in 0..bandHeight {
+		for x in 0..width {
+			for channel in 0..3 {
+				for (sample, band) in samples.iter_mut().zip(bands) {
+					*sample = band.get_pixel(x, y)[channel] as f32;
+				}
+				*totalIterations += iterationsReducer(&samples) as u64;
+				*totalCells += 1;
+			}
+		}
+	}
+}
+
+/// Decodes every input exactly once, spilling each frame to a temp file so
+/// that `runStreamingReduce` can stream bands back out without re-running the
+/// decoder (or, for video inputs, ffmpeg) once per band.
+fn prepareStreamingSources(
+	args: &Args,
+	calibration: &Calibration,
+	progress: &Progress,
+	targetDims: (u32, u32),
+	roi: Option<(u32, u32, u32, u32)>,
+	offsets: &[(i64, i64)],
+) -> AResult<Vec<StreamingSource>> {
+	Ok(args
+		.inputs
+		.par_iter()
+		.zip(offsets.par_iter())
+		.map(|(path, &offset)| StreamingSource::decode(path, args, calibration, progress, targetDims, roi, offset))
+		.collect::<AResult<Vec<_>>>()?
+		.into_iter()
+		.flatten()
+		.collect())
+}
+
+/// Runs a streaming mode, reducing `sources` band by band so that memory use
+/// stays bounded regardless of how many frames are stacked. `tileHeight`
+/// controls how many rows are held in memory per input at once (`inputs.len()
+/// * tileHeight` pixels total); smaller values trade throughput for a lower
+/// peak. When `countReducer` is given (for `--count-map`), also builds a raw
+/// per-pixel survivor-count image from the same decoded bands. When
+/// `iterationsReducer` is given (for `--sigma-converge`), also returns the
+/// average number of iterations run per pixel/channel across the whole
+/// image.
+fn runStreamingReduce(
+	width: u32,
+	height: u32,
+	sources: &[StreamingSource],
+	reducer: &dyn Fn(&[f32]) -> f32,
+	countReducer: Option<&dyn Fn(&[f32]) -> usize>,
+	iterationsReducer: Option<&dyn Fn(&[f32]) -> u32>,
+	tileHeight: u32,
+) -> AResult<(RgbImage, Option<ImageBuffer<Luma<u32>, Vec<u32>>>, Option<f64>)> {
+	let mut outImg = RgbImage::new(width, height);
+	let mut counts = countReducer.map(|_| ImageBuffer::new(width, height));
+	let mut totalIterations = 0u64;
+	let mut totalCells = 0u64;
+
+	let mut bandTop = 0;
+	while bandTop < height {
+		let bandHeight = tileHeight.min(height - bandTop);
+		eprintln!("Reducing rows {bandTop}..{}", bandTop + bandHeight);
+
+		let bands = sources
+			.par_iter()
+			.map(|source| source.readBand(bandTop, bandHeight))
+			.collect::<AResult<Vec<_>>>()?;
+		reduceBandInto(&bands, &mut outImg, bandTop, width, bandHeight, reducer);
+		if let (Some(countReducer), Some(counts)) = (countReducer, counts.as_mut()) {
+			countBandInto(&bands, counts, bandTop, width, bandHeight, countReducer);
+		}
+		if let Some(iterationsReducer) = iterationsReducer {
+			iterationsBandInto(&bands, width, bandHeight, iterationsReducer, &mut totalIterations, &mut totalCells);
+		}
+
+		bandTop += bandHeight;
+	}
+
+	let averageIterations = iterationsReducer.map(|_| if totalCells == 0 { 0.0 } else { totalIterations as f64 / totalCells as f64 });
+	Ok((outImg, counts, averageIterations))
+}
+
+/// Rescales `counts` so its max observed value maps to 255 and writes it as
+/// a grayscale image to `path`, for `--count-map`. A fully-zero `counts` (no
+/// pixels reduced at all, which shouldn't happen in practice) is written as
+/// solid black rather than dividing by zero.
+fn saveCountMap(counts: &ImageBuffer<Luma<u32>, Vec<u32>>, path: &Path) -> AResult<()> {
+	let maxCount = counts.pixels().map(|p| p.0[0]).max().unwrap_or(0);
+	let scaled = GrayImage::from_fn(counts.width(), counts.height(), |x, y| {
+		let count = counts.get_pixel(x, y).0[0];
+		let value = if maxCount == 0 { 0.0 } else { count as f32 / maxCount as f32 * 255.0 };
+		Luma([value.round().clamp(0.0, 255.0) as u8])
+	});
+	let format = image::ImageFormat::from_path(path).with_context(|| format!("Guessing format for --count-map {path:?}"))?;
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating --count-map file {path:?}"))?;
+	scaled
+		.write_to(&mut std::io::BufWriter::new(file), format)
+		.with_context(|| format!("Encoding --count-map output {path:?}"))
+}
+
+/// Colorizes `counts` (survivor counts, same as `--count-map`) into a
+/// rejected-count heatmap for `--rejection-map`: blue where nothing was
+/// rejected, ramping through to red where the most samples were rejected
+/// anywhere in the image. A fully-zero rejection count (nothing ever
+/// rejected) is written as solid blue rather than dividing by zero.
+fn saveRejectionMap(counts: &ImageBuffer<Luma<u32>, Vec<u32>>, frameCount: u32, path: &Path) -> AResult<()> {
+	let maxRejected = counts.pixels().map(|p| frameCount.saturating_sub(p.0[0])).max().unwrap_or(0);
+	let colorized = RgbImage::from_fn(counts.width(), counts.height(), |x, y| {
+		let rejected = frameCount.saturating_sub(counts.get_pixel(x, y).0[0]);
+		let t = if maxRejected == 0 { 0.0 } else { rejected as f32 / maxRejected as f32 };
+		Rgb([(t * 255.0).round() as u8, 0, ((1.0 - t) * 255.0).round() as u8])
+	});
+	let format = image::ImageFormat::from_path(path).with_context(|| format!("Guessing format for --rejection-map {path:?}"))?;
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating --rejection-map file {path:?}"))?;
+	colorized
+		.write_to(&mut std::io::BufWriter::new(file), format)
+		.with_context(|| format!("Encoding --rejection-map output {path:?}"))
+}
+
+/// Writes `sum`'s per-channel high bits (`sum >> 8`, clamped to 255) to
+/// `path`, for `--overflow-map`. This is the information `sum-overflow`
+/// mode's visible output throws away by only keeping the low byte: `0`
+/// means that pixel/channel never wrapped, higher values mean it wrapped
+/// around more times.
+fn saveOverflowMap(sum: &ImageBuffer<Rgb<u32>, Vec<u32>>, path: &Path) -> AResult<()> {
+	let overflow = RgbImage::from_fn(sum.width(), sum.height(), |x, y| {
+		let Rgb([r, g, b]) = *sum.get_pixel(x, y);
+		Rgb([(r >> 8).min(255) as u8, (g >> 8).min(255) as u8, (b >> 8).min(255) as u8])
+	});
+	let format = image::ImageFormat::from_path(path).with_context(|| format!("Guessing format for --overflow-map {path:?}"))?;
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating --overflow-map file {path:?}"))?;
+	overflow
+		.write_to(&mut std::io::BufWriter::new(file), format)
+		.with_context(|| format!("Encoding --overflow-map output {path:?}"))
+}
+
+/// Writes `indices`' raw frame indices to `path`, for `--source-map`. Unlike
+/// `saveCountMap`/`saveRejectionMap`, values are not rescaled: pixel value N
+/// literally means "input frame N won here". Up to 256 frames fits in 8-bit
+/// grayscale; beyond that we widen to 16-bit rather than lossily rescale.
+fn saveSourceMap(indices: &ImageBuffer<Luma<u32>, Vec<u32>>, frameCount: usize, path: &Path) -> AResult<()> {
+	let format = image::ImageFormat::from_path(path).with_context(|| format!("Guessing format for --source-map {path:?}"))?;
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating --source-map file {path:?}"))?;
+	if frameCount <= 256 {
+		let narrowed = GrayImage::from_fn(indices.width(), indices.height(), |x, y| Luma([indices.get_pixel(x, y).0[0] as u8]));
+		narrowed
+			.write_to(&mut std::io::BufWriter::new(file), format)
+			.with_context(|| format!("Encoding --source-map output {path:?}"))
+	} else {
+		let widened = ImageBuffer::<Luma<u16>, Vec<u16>>::from_fn(indices.width(), indices.height(), |x, y| Luma([indices.get_pixel(x, y).0[0] as u16]));
+		widened
+			.write_to(&mut std::io::BufWriter::new(file), format)
+			.with_context(|| format!("Encoding --source-map output {path:?}"))
+	}
+}
+
+/// Writes the `--stats-json` sidecar report: input count, resolution, mode,
+/// per-channel min/max/mean of the output, elapsed time, and any warnings
+/// printed during the run. Written to stdout for the `-` placeholder, same
+/// as `--output`.
+fn saveStatsJson(
+	path: &Path,
+	inputCount: usize,
+	width: u32,
+	height: u32,
+	mode: Mode,
+	stats: [(u8, u8, f64); 3],
+	elapsed: Duration,
+	warnings: &[String],
+) -> AResult<()> {
+	let report = serde_json::json!({
+		"inputCount": inputCount,
+		"width": width,
+		"height": height,
+		"mode": format!("{mode:?}"),
+		"channels": {
+			"r": {"min": stats[0].0, "max": stats[0].1, "mean": stats[0].2},
+			"g": {"min": stats[1].0, "max": stats[1].1, "mean": stats[1].2},
+			"b": {"min": stats[2].0, "max": stats[2].1, "mean": stats[2].2},
+		},
+		"elapsedSeconds": elapsed.as_secs_f64(),
+		"warnings": warnings,
+	});
+	if isStdout(path) {
+		println!("{report}");
+	} else {
+		let file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(path)
+			.with_context(|| format!("Creating --stats-json file {path:?}"))?;
+		serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report)
+			.with_context(|| format!("Encoding --stats-json output {path:?}"))?;
+	}
+	Ok(())
+}
+
+/// One `--log` row: everything known about a single input once it's been
+/// decoded (or failed to decode).
+struct LogEntry {
+	path: PathBuf,
+	dimensions: Option<(u32, u32)>,
+	format: String,
+	exposureTime: Option<f32>,
+	weight: f32,
+	gain: f32,
+	status: String,
+}
+
+/// Guesses `--log`'s per-input "format" column from `path`'s extension:
+/// `image::ImageFormat` for stills, `"video"` for `isVideoFile` paths (which
+/// `image::ImageFormat` doesn't know about), `"unknown"` otherwise. A guess
+/// rather than a content sniff, same tradeoff `--format auto` already makes.
+fn guessInputFormat(path: &Path) -> String {
+	match image::ImageFormat::from_path(path) {
+		Ok(format) => format!("{format:?}"),
+		Err(_) if isVideoFile(path) => "video".to_string(),
+		Err(_) => "unknown".to_string(),
+	}
+}
+
+/// Escapes one CSV field per RFC 4180: wrapped in quotes (with embedded
+/// quotes doubled) only if it contains a comma, quote, or newline, so the
+/// common case of a plain path or number stays easy to skim unquoted.
+fn csvField(field: &str) -> String {
+	if field.contains(',') || field.contains('"') || field.contains('\n') {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+/// Formats one [`LogEntry`] as a CSV row: path, resolution, format, EXIF
+/// exposure time, applied weight, applied gain, and keep/skip status.
+fn formatLogRow(entry: &LogEntry) -> String {
+	let resolution = match entry.dimensions {
+		Some((width, height)) => format!("{width}x{height}"),
+		None => String::new(),
+	};
+	let exposureTime = entry.exposureTime.map(|t| t.to_string()).unwrap_or_default();
+	[
+		csvField(&entry.path.to_string_lossy()),
+		resolution,
+		csvField(&entry.format),
+		exposureTime,
+		entry.weight.to_string(),
+		entry.gain.to_string(),
+		csvField(&entry.status),
+	]
+	.join(",")
+}
+
+/// Writes `--log`'s per-input CSV report to `path` (or to stdout, if `-`):
+/// a header row followed by one [`formatLogRow`] row per input, in input
+/// order. Unlike `--stats-json`, this is per-frame and meant to be read by
+/// a person, not just machine-parsed.
+fn saveFrameLog(entries: &[LogEntry], path: &Path) -> AResult<()> {
+	let mut report = String::from("path,resolution,format,exposureTime,weight,gain,status\n");
+	for entry in entries {
+		report.push_str(&formatLogRow(entry));
+		report.push('\n');
+	}
+	if isStdout(path) {
+		print!("{report}");
+	} else {
+		let file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(path)
+			.with_context(|| format!("Creating --log file {path:?}"))?;
+		std::io::BufWriter::new(file).write_all(report.as_bytes()).with_context(|| format!("Writing --log output {path:?}"))?;
+	}
+	Ok(())
+}
+
+/// Prints `--timings`'s report to stderr: wall-clock time, the decode/
+/// accumulate/save breakdown, and effective megapixels/second across every
+/// input frame processed. Purely diagnostic, doesn't touch the image output.
+fn printTimings(total: Duration, decode: Duration, accumulate: Duration, save: Duration, width: u32, height: u32, inputCount: usize) {
+	let megapixels = (width as f64 * height as f64 * inputCount as f64) / 1e6;
+	let mpPerSecond = megapixels / total.as_secs_f64();
+	eprintln!(
+		"Timings: total {:.3}s (decode {:.3}s, accumulate {:.3}s, save {:.3}s), {:.2} MP/s",
+		total.as_secs_f64(),
+		decode.as_secs_f64(),
+		accumulate.as_secs_f64(),
+		save.as_secs_f64(),
+		mpPerSecond
+	);
+}
+
+/// Speed/quality tradeoff for the AVIF encoder, from 0 (slowest, smallest)
+/// to 10 (fastest, largest). Not exposed as a flag: `--quality` already
+/// covers the tradeoff users actually care about, and this only affects
+/// how hard the encoder works to hit that target.
+const AVIF_DEFAULT_SPEED: u8 = 4;
+
+/// Maps `--quality` onto a PNG compression level. Unlike JPEG quality, this
+/// trades encode time and file size, not fidelity: PNG is always lossless.
+fn pngCompressionFromQuality(quality: Option<u8>) -> image::codecs::png::CompressionType {
+	match quality {
+		None => image::codecs::png::CompressionType::Default,
+		Some(quality) if quality >= 90 => image::codecs::png::CompressionType::Best,
+		Some(quality) if quality <= 30 => image::codecs::png::CompressionType::Fast,
+		Some(_) => image::codecs::png::CompressionType::Default,
+	}
+}
+
+/// Whether `path` is the `-` placeholder for standard output, rather than a
+/// literal file named `-`.
+fn isStdout(path: &Path) -> bool {
+	path == Path::new("-")
+}
+
+/// Whether `path` is the `-` placeholder for standard input, rather than a
+/// literal file named `-`.
+fn isStdin(path: &Path) -> bool {
+	path == Path::new("-")
+}
+
+/// Opens the writer `saveOutput`/`saveRgbaOutput` encode into: stdout for the
+/// `-` placeholder, or the named file otherwise. Buffered either way, so the
+/// encoders never see more syscalls than a single real file would cause.
+fn openOutputWriter(outFile: &Path) -> AResult<Box<dyn Write>> {
+	if isStdout(outFile) {
+		return Ok(Box::new(std::io::BufWriter::new(std::io::stdout())));
+	}
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(outFile)
+		.with_context(|| format!("Creating output file {outFile:?}"))?;
+	Ok(Box::new(std::io::BufWriter::new(file)))
+}
+
+/// 4x4 Bayer threshold matrix, scaled to `0..16`.
+const BAYER_4X4: [[f32; 4]; 4] = [
+	[0.0, 8.0, 2.0, 10.0],
+	[12.0, 4.0, 14.0, 6.0],
+	[3.0, 11.0, 1.0, 9.0],
+	[15.0, 7.0, 13.0, 5.0],
+];
+
+/// Compresses `img`'s highlights into 0.0-1.0 in place, ahead of 8-bit
+/// quantization. A no-op for `Tonemap::None`, which leaves the implicit hard
+/// clamp the quantization functions below already do. The saved result is
+/// always gamma-encoded sRGB by this point (`Accumulator::intoOutput` does
+/// that unconditionally), so this round-trips through linear light itself
+/// rather than needing the original `--color-space` choice threaded through.
+fn applyTonemap(img: &mut Rgb32FImage, tonemap: Tonemap) {
+	if matches!(tonemap, Tonemap::None) {
+		return;
+	}
+	img.pixels_mut().for_each(|p| p.apply(|v| linearToSrgb(tonemap.apply(srgbToLinear(v)))));
+}
+
+/// Quantizes a float-precision image down to 8-bit, optionally dithering to
+/// break up banding. `Dither::None` falls back to `rounding` instead of
+/// dithering spatially; the other modes do their own rounding as part of
+/// diffusing the error, so `rounding` has no effect on them.
+fn ditherToRgb8(img: &Rgb32FImage, dither: Dither, rounding: Rounding) -> RgbImage {
+	match dither {
+		Dither::None => quantizeRoundingRgb8(img, rounding),
+		Dither::Bayer => {
+			let mut out = RgbImage::new(img.width(), img.height());
+			for (x, y, pixel) in img.enumerate_pixels() {
+				// Centered on zero so the threshold nudges the rounding up or
+				// down rather than only ever up.
+				let threshold = BAYER_4X4[(y % 4) as usize][(x % 4) as usize] / 16.0 - 0.5;
+				let Rgb([r, g, b]) = *pixel;
+				out.put_pixel(
+					x,
+					y,
+					Rgb([quantizeChannel(r, threshold), quantizeChannel(g, threshold), quantizeChannel(b, threshold)]),
+				);
+			}
+			out
+		},
+		Dither::FloydSteinberg => floydSteinbergDither(img),
+	}
+}
+
+fn quantizeChannel(value: f32, thresholdOffset: f32) -> u8 {
+	(value * 255.0 + thresholdOffset).round().clamp(0.0, 255.0) as u8
+}
+
+/// Quantizes a float image down to 8-bit per `rounding`, with no spatial
+/// dithering. `Rounding::Truncate` matches `ConvertBuffer`'s own behavior,
+/// preserved for anyone who depended on the exact old numbers.
+fn quantizeRoundingRgb8(img: &Rgb32FImage, rounding: Rounding) -> RgbImage {
+	match rounding {
+		Rounding::Truncate => img.convert(),
+		Rounding::Round => mapChannels(img, |v| (v * 255.0).round().clamp(0.0, 255.0) as u8),
+		Rounding::Stochastic => mapChannels(img, |v| {
+			let scaled = (v * 255.0).clamp(0.0, 255.0);
+			let floor = scaled.floor();
+			let bumped = if rand::random::<f32>() < scaled - floor { floor + 1.0 } else { floor };
+			bumped.clamp(0.0, 255.0) as u8
+		}),
+	}
+}
+
+fn mapChannels(img: &Rgb32FImage, f: impl Fn(f32) -> u8) -> RgbImage {
+	let mut out = RgbImage::new(img.width(), img.height());
+	for (x, y, pixel) in img.enumerate_pixels() {
+		let Rgb([r, g, b]) = *pixel;
+		out.put_pixel(x, y, Rgb([f(r), f(g), f(b)]));
+	}
+	out
+}
+
+/// Floyd–Steinberg error diffusion, applied independently per channel.
+/// Strictly sequential (each pixel's error feeds its neighbors), so unlike
+/// the rest of the accumulation pipeline this doesn't parallelize.
+fn floydSteinbergDither(img: &Rgb32FImage) -> RgbImage {
+	let (width, height) = img.dimensions();
+	let mut errors = vec![[0.0f32; 3]; (width * height) as usize];
+	let mut out = RgbImage::new(width, height);
+	for y in 0..height {
+		for x in 0..width {
+			let idx = (y * width + x) as usize;
+			let Rgb(sample) = *img.get_pixel(x, y);
+			let mut quantized = [0u8; 3];
+			for c in 0..3 {
+				let value = (sample[c] * 255.0 + errors[idx][c]).clamp(0.0, 255.0);
+				let rounded = value.round();
+				quantized[c] = rounded as u8;
+				let err = value - rounded;
+				let mut spread = |dx: i64, dy: i64, weight: f32| {
+					let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+					if nx >= 0 && (nx as u32) < width && ny >= 0 && (ny as u32) < height {
+						errors[(ny as u32 * width + nx as u32) as usize][c] += err * weight;
+					}
+				};
+				spread(1, 0, 7.0 / 16.0);
+				spread(-1, 1, 3.0 / 16.0);
+				spread(0, 1, 5.0 / 16.0);
+				spread(1, 1, 1.0 / 16.0);
+			}
+			out.put_pixel(x, y, Rgb(quantized));
+		}
+	}
+	out
+}
+
+/// Saves `img` to `outFile` in `format`, applying `quality` for formats that
+/// support it. Floating-point formats are written directly at full
+/// precision, skipping `tonemap` entirely; other formats get `tonemap`
+/// applied and are then quantized down to 8 bits (or 16, see below).
+/// `pngCompression`, if given, overrides the compression level `quality`
+/// would otherwise imply for PNG output. `bitDepth`, if given, overrides the
+/// usual "16 bits only if the result is already float-precision" rule below
+/// with an explicit `8` or `16`; validated by the caller, so any other value
+/// is a programmer error.
+fn saveOutput(
+	img: DecodedFrame,
+	outFile: &Path,
+	format: image::ImageFormat,
+	quality: Option<u8>,
+	pngCompression: Option<PngCompression>,
+	floatOutput: bool,
+	dither: Dither,
+	rounding: Rounding,
+	tonemap: Tonemap,
+	bitDepth: Option<u8>,
+) -> AResult<()> {
+	let mut writer = openOutputWriter(outFile)?;
+
+	if floatOutput && format == image::ImageFormat::Tiff {
+		let img = img.intoRgb32f();
+		DynamicImage::ImageRgb32F(img)
+			.write_to(&mut writer, format)
+			.context("Encoding float TIFF output")?;
+		return Ok(());
+	}
+
+	if OutputFormat::isFloatingPoint(format) {
+		let img = img.intoRgb32f();
+		if format == image::ImageFormat::Hdr {
+			// `image`'s generic `write_to` has no Radiance HDR arm, so this
+			// format needs its encoder driven directly.
+			let pixels: Vec<Rgb<f32>> = img.pixels().copied().collect();
+			image::codecs::hdr::HdrEncoder::new(writer)
+				.encode(&pixels, img.width() as usize, img.height() as usize)
+				.context("Encoding HDR output")?;
+		} else {
+			DynamicImage::ImageRgb32F(img)
+				.write_to(&mut writer, format)
+				.context("Encoding HDR output")?;
+		}
+		return Ok(());
+	}
+
+	// `--bit-depth` overrides the auto-detection below outright: `8` forces
+	// the truncated path even for a float-precision result, `16` forces the
+	// widened path even for an already-8-bit one. The caller has already
+	// checked the chosen format can actually store 16 bits before getting
+	// here.
+	let img = match bitDepth {
+		Some(8) => DecodedFrame::Ldr(match img {
+			DecodedFrame::Ldr(img) => img,
+			DecodedFrame::Hdr(mut img) => {
+				applyTonemap(&mut img, tonemap);
+				ditherToRgb8(&img, dither, rounding)
+			},
+		}),
+		Some(16) => DecodedFrame::Hdr(img.intoRgb32f()),
+		_ => img,
+	};
+
+	// PNG and TIFF can both store 16-bit-per-channel samples. If the image
+	// is still float-precision here (i.e. it came from a 16-bit or HDR
+	// input, or `--bit-depth 16` forced it), write it out at 16 bits instead
+	// of truncating to 8, so stacking 16-bit inputs doesn't lose precision
+	// in the output.
+	if let DecodedFrame::Hdr(hdrImg) = &img {
+		if matches!(format, image::ImageFormat::Png | image::ImageFormat::Tiff) {
+			let img16: image::ImageBuffer<image::Rgb<u16>, Vec<u16>> = hdrImg.convert();
+			DynamicImage::ImageRgb16(img16)
+				.write_to(&mut writer, format)
+				.context("Encoding 16-bit output")?;
+			return Ok(());
+		}
+	}
+
+	let img = match img {
+		DecodedFrame::Hdr(mut hdrImg) => {
+			applyTonemap(&mut hdrImg, tonemap);
+			ditherToRgb8(&hdrImg, dither, rounding)
+		},
+		DecodedFrame::Ldr(img) => img,
+	};
+	match format {
+		image::ImageFormat::Jpeg => {
+			let quality = quality.unwrap_or(90);
+			image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, quality)
+				.encode_image(&img)
+				.context("Encoding JPEG output")?;
+		},
+		image::ImageFormat::Png => {
+			let compression = pngCompression.map_or_else(|| pngCompressionFromQuality(quality), Into::into);
+			image::codecs::png::PngEncoder::new_with_quality(&mut writer, compression, image::codecs::png::FilterType::Adaptive)
+				.write_image(img.as_raw(), img.width(), img.height(), image::ColorType::Rgb8)
+				.context("Encoding PNG output")?;
+		},
+		image::ImageFormat::WebP => {
+			#[cfg(feature = "webp")]
+			{
+				let quality = quality.unwrap_or(90) as f32;
+				image::codecs::webp::WebPEncoder::new_with_quality(&mut writer, quality)
+					.write_image(img.as_raw(), img.width(), img.height(), image::ColorType::Rgb8)
+					.context("Encoding WebP output")?;
+			}
+			#[cfg(not(feature = "webp"))]
+			{
+				return Err(anyhow!("WebP output requires imgstack to be built with `--features webp`"));
+			}
+		},
+		image::ImageFormat::Avif => {
+			#[cfg(feature = "avif")]
+			{
+				let quality = quality.unwrap_or(80);
+				image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut writer, AVIF_DEFAULT_SPEED, quality)
+					.write_image(img.as_raw(), img.width(), img.height(), image::ColorType::Rgb8)
+					.context("Encoding AVIF output")?;
+			}
+			#[cfg(not(feature = "avif"))]
+			{
+				return Err(anyhow!("AVIF output requires imgstack to be built with `--features avif`"));
+			}
+		},
+		_ => {
+			img.write_to(&mut writer, format).context("Encoding output")?;
+		},
+	}
+	Ok(())
+}
+
+/// Writes an RGBA image, for `alpha-over` mode's composited output. Unlike
+/// `saveOutput`, there's no HDR/16-bit precision to worry about here since
+/// `alpha-over` only ever works at 8-bit precision.
+fn saveRgbaOutput(
+	img: RgbaImage,
+	outFile: &Path,
+	format: image::ImageFormat,
+	quality: Option<u8>,
+	pngCompression: Option<PngCompression>,
+) -> AResult<()> {
+	let mut writer = openOutputWriter(outFile)?;
+
+	match format {
+		image::ImageFormat::Png => {
+			let compression = pngCompression.map_or_else(|| pngCompressionFromQuality(quality), Into::into);
+			image::codecs::png::PngEncoder::new_with_quality(&mut writer, compression, image::codecs::png::FilterType::Adaptive)
+				.write_image(img.as_raw(), img.width(), img.height(), image::ColorType::Rgba8)
+				.context("Encoding PNG output")?;
+		},
+		_ => {
+			img.write_to(&mut writer, format).context("Encoding output")?;
+		},
+	}
+	Ok(())
+}
+
+/// Writes `frames` as pages of one multi-page TIFF at `path`, for
+/// `--stack-tiff`. `image`'s generic `write_to` only knows how to encode a
+/// single image per file, so this drives the `tiff` crate's encoder
+/// directly (same reasoning as [`saveOutput`]'s Radiance HDR arm) to append
+/// each frame as its own IFD instead. LDR frames are written at 8 bits;
+/// HDR frames are widened to 16 bits the same way `saveOutput` does for a
+/// float-precision result, rather than tonemapped, since the point is a
+/// lossless-as-received archive, not the same picture the main output is.
+/// Unlike `saveOutput`, this always opens `path` as a plain file rather than
+/// going through `openOutputWriter`: the `tiff` crate needs a `Seek`able
+/// writer to back-patch each page's IFD offsets, which rules out `-`/stdout.
+fn saveStackTiff<'a>(path: &Path, frames: impl IntoIterator<Item = &'a DecodedFrame>) -> AResult<()> {
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating --stack-tiff file {path:?}"))?;
+	let mut encoder = tiff::encoder::TiffEncoder::new(file).with_context(|| format!("Creating TIFF encoder for {path:?}"))?;
+	for frame in frames {
+		match frame {
+			DecodedFrame::Ldr(img) => encoder
+				.write_image::<tiff::encoder::colortype::RGB8>(img.width(), img.height(), img.as_raw())
+				.with_context(|| format!("Writing a page to {path:?}"))?,
+			DecodedFrame::Hdr(img) => {
+				let img16: image::ImageBuffer<image::Rgb<u16>, Vec<u16>> = img.convert();
+				encoder
+					.write_image::<tiff::encoder::colortype::RGB16>(img16.width(), img16.height(), img16.as_raw())
+					.with_context(|| format!("Writing a page to {path:?}"))?
+			},
+		}
+	}
+	Ok(())
+}
+
+/// Writes a progressive-stacking animation, one frame per snapshot.
+/// Animation frames are downscaled to at most this many pixels along their
+/// longer side before being encoded, since one full-resolution frame per
+/// input can otherwise make the GIF enormous for large stacks.
+const ANIMATION_MAX_DIMENSION: u32 = 480;
+
+fn saveAnimation(path: &Path, frames: Vec<DecodedFrame>, delay: Delay) -> AResult<()> {
+	let file = OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+		.with_context(|| format!("Creating animation file {path:?}"))?;
+
+	let frames = frames
+		.into_iter()
+		.map(|frame| Frame::from_parts(downscaleForAnimation(frame.intoRgb8()).convert(), 0, 0, delay));
+	GifEncoder::new(file)
+		.encode_frames(frames)
+		.context("Encoding animation")?;
+	Ok(())
+}
+
+/// Shrinks `img` to fit within `ANIMATION_MAX_DIMENSION` on its longer side,
+/// preserving aspect ratio. Leaves already-small frames untouched.
+fn downscaleForAnimation(img: RgbImage) -> RgbImage {
+	let (width, height) = img.dimensions();
+	let longestSide = width.max(height);
+	if longestSide <= ANIMATION_MAX_DIMENSION {
+		return img;
+	}
+
+	let scale = ANIMATION_MAX_DIMENSION as f32 / longestSide as f32;
+	let newWidth = ((width as f32 * scale).round() as u32).max(1);
+	let newHeight = ((height as f32 * scale).round() as u32).max(1);
+	image::imageops::resize(&img, newWidth, newHeight, image::imageops::FilterType::Triangle)
+}
+
+/// Reads the EXIF `ExposureTime` tag from `path`, in seconds. Returns `None`
+/// if the file has no EXIF block or no exposure time recorded, rather than
+/// erroring, since a missing tag is expected for e.g. screenshots or
+/// synthetic frames and just falls back to equal weighting.
+fn readExposureTime(path: &Path) -> Option<f32> {
+	let file = std::fs::File::open(path).ok()?;
+	let mut reader = BufReader::new(&file);
+	let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+	let field = exif.get_field(exif::Tag::ExposureTime, exif::In::PRIMARY)?;
+	match &field.value {
+		exif::Value::Rational(values) => values.first().map(|v| v.to_f32()),
+		_ => None,
+	}
+}
+
+/// Reads the EXIF `Orientation` tag from `path`, as its raw `1..=8` value.
+/// Returns `None` if the file has no EXIF block or no orientation recorded,
+/// which callers should treat the same as `1` (no rotation/flip needed).
+fn readOrientation(path: &Path) -> Option<u8> {
+	let file = std::fs::File::open(path).ok()?;
+	let mut reader = BufReader::new(&file);
+	let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+	let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+	field.value.get_uint(0).map(|v| v as u8)
+}
+
+/// Copies the EXIF block from `sourcePath` into `outFile`, refreshing the
+/// `DateTime` tag and adding a `Software` tag naming this tool. TIFF's
+/// encoder has no metadata-write hook to splice EXIF into, unlike JPEG's
+/// segment-based layout (see `Args::quality`'s doc comment for a similar
+/// TIFF-encoder limitation), so this only supports jpeg output for now.
+fn copyExifMetadata(sourcePath: &Path, outFile: &Path, format: image::ImageFormat, progress: &Progress) -> AResult<()> {
+	if format != image::ImageFormat::Jpeg {
+		progress.warn(&format!("--copy-exif is only supported for jpeg output, ignoring for {format:?}"));
+		return Ok(());
+	}
+
+	let file = std::fs::File::open(sourcePath).with_context(|| format!("Opening {sourcePath:?} for EXIF metadata"))?;
+	let mut reader = BufReader::new(&file);
+	let exif = match exif::Reader::new().read_from_container(&mut reader) {
+		Ok(exif) => exif,
+		Err(_) => {
+			progress.warn(&format!("{sourcePath:?} has no EXIF metadata to copy"));
+			return Ok(());
+		},
+	};
+
+	let now = chrono::Local::now().format("%Y:%m:%d %H:%M:%S").to_string();
+	let mut fields: Vec<exif::Field> = exif
+		.fields()
+		.filter(|field| field.tag != exif::Tag::DateTime && field.tag != exif::Tag::Software)
+		.cloned()
+		.collect();
+	fields.push(exif::Field {
+		tag: exif::Tag::DateTime,
+		ifd_num: exif::In::PRIMARY,
+		value: exif::Value::Ascii(vec![now.into_bytes()]),
+	});
+	fields.push(exif::Field {
+		tag: exif::Tag::Software,
+		ifd_num: exif::In::PRIMARY,
+		value: exif::Value::Ascii(vec![b"imgstack".to_vec()]),
+	});
+
+	let mut writer = exif::experimental::Writer::new();
+	for field in &fields {
+		writer.push_field(field);
+	}
+	let mut exifBuf = std::io::Cursor::new(Vec::new());
+	writer.write(&mut exifBuf, false).context("Encoding EXIF block")?;
+	let exifBytes = exifBuf.into_inner();
+
+	// Splice an APP1 "Exif" segment in right after the JPEG's SOI marker.
+	let mut jpegBytes = std::fs::read(outFile).with_context(|| format!("Reading {outFile:?} to embed EXIF"))?;
+	if jpegBytes.len() < 2 || jpegBytes[0..2] != [0xFF, 0xD8] {
+		return Err(anyhow!("{outFile:?} is not a valid JPEG file"));
+	}
+	let mut segment = Vec::with_capacity(exifBytes.len() + 6);
+	segment.extend_from_slice(b"Exif\0\0");
+	segment.extend_from_slice(&exifBytes);
+	let segmentLen = (segment.len() + 2) as u16;
+	let mut app1 = vec![0xFF, 0xE1];
+	app1.extend_from_slice(&segmentLen.to_be_bytes());
+	app1.extend_from_slice(&segment);
+
+	jpegBytes.splice(2..2, app1);
+	std::fs::write(outFile, jpegBytes).with_context(|| format!("Writing EXIF metadata into {outFile:?}"))?;
+	Ok(())
+}
+
+/// Reads newline-separated input paths from `path` (or stdin, if `path` is
+/// `-`), skipping blank lines and `#`-comments. Relative paths are resolved
+/// against `path`'s own directory rather than the current working directory,
+/// since a list of frames is usually kept alongside them; paths read from
+/// stdin are left as-is since there's no file location to resolve against.
+fn readInputsFromFile(path: &Path) -> AResult<Vec<PathBuf>> {
+	let (contents, base) = if path == Path::new("-") {
+		let mut buf = String::new();
+		std::io::stdin()
+			.read_to_string(&mut buf)
+			.context("Reading input list from stdin")?;
+		(buf, None)
+	} else {
+		let contents = std::fs::read_to_string(path)
+			.with_context(|| format!("Reading input list {path:?}"))?;
+		let base = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+		(contents, base)
+	};
+
+	Ok(contents
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(|line| match base {
+			Some(dir) => dir.join(line),
+			None => PathBuf::from(line),
+		})
+		.collect())
+}
+
+/// Expands any entry of `inputs` that contains glob metacharacters
+/// (`*`, `?`, `[`) into the files it matches, sorted lexicographically for
+/// deterministic ordering. Entries without metacharacters pass through
+/// unchanged, so plain paths behave exactly as before.
+fn expandInputGlobs(inputs: Vec<PathBuf>) -> AResult<Vec<PathBuf>> {
+	let mut expanded = Vec::with_capacity(inputs.len());
+	for input in inputs {
+		let pattern = match input.to_str() {
+			Some(pattern) if pattern.contains(['*', '?', '[']) => pattern,
+			_ => {
+				expanded.push(input);
+				continue;
+			},
+		};
+
+		let mut matches = glob::glob(pattern)
+			.with_context(|| format!("Invalid glob pattern {pattern:?}"))?
+			.collect::<Result<Vec<_>, _>>()
+			.with_context(|| format!("Reading glob matches for {pattern:?}"))?;
+		if matches.is_empty() {
+			return Err(anyhow!("Glob pattern {pattern:?} matched no files"));
+		}
+		matches.sort();
+		expanded.extend(matches);
+	}
+	Ok(expanded)
+}
+
+/// Extensions recognized as still images when expanding directory inputs.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "tif", "tiff", "bmp", "hdr", "exr", "gif"];
+
+fn isStackableFile(path: &Path) -> bool {
+	if isVideoFile(path) {
+		return true;
+	}
+	path.extension()
+		.and_then(|ext| ext.to_str())
+		.map(|ext| ext.to_ascii_lowercase())
+		.is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Recursively (if `recursive`) collects the stackable files directly inside
+/// `dir`, skipping dotfiles and non-image/video extensions.
+fn collectDirectoryEntries(dir: &Path, recursive: bool) -> AResult<Vec<PathBuf>> {
+	let mut entries = Vec::new();
+	for entry in std::fs::read_dir(dir).with_context(|| format!("Reading directory {dir:?}"))? {
+		let path = entry.with_context(|| format!("Reading directory {dir:?}"))?.path();
+		let isHidden = path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.is_some_and(|name| name.starts_with('.'));
+		if isHidden {
+			continue;
+		}
+		if path.is_dir() {
+			if recursive {
+				entries.extend(collectDirectoryEntries(&path, recursive)?);
+			}
+			continue;
+		}
+		if isStackableFile(&path) {
+			entries.push(path);
+		}
+	}
+	Ok(entries)
+}
+
+/// Expands any entry of `inputs` that's a directory into the stackable files
+/// inside it, sorted lexicographically. Non-directory entries pass through
+/// unchanged, so mixing explicit files and directories works as expected.
+fn expandInputDirectories(inputs: Vec<PathBuf>, recursive: bool) -> AResult<Vec<PathBuf>> {
+	let mut expanded = Vec::with_capacity(inputs.len());
+	for input in inputs {
+		if input.is_dir() {
+			let mut entries = collectDirectoryEntries(&input, recursive)?;
+			entries.sort();
+			expanded.extend(entries);
+		} else {
+			expanded.push(input);
+		}
+	}
+	Ok(expanded)
+}
+
+/// Sorts `inputs` per `--sort`. `--sort mtime` breaks ties between
+/// same-timestamp files by path name, for determinism.
+fn sortInputs(mut inputs: Vec<PathBuf>, order: SortOrder) -> AResult<Vec<PathBuf>> {
+	match order {
+		SortOrder::None => {},
+		SortOrder::Name => inputs.sort(),
+		SortOrder::Mtime => {
+			let mut keyed = inputs
+				.into_iter()
+				.map(|path| {
+					let mtime = std::fs::metadata(&path).and_then(|meta| meta.modified()).with_context(|| format!("Reading modification time of {path:?}"))?;
+					Ok((mtime, path))
+				})
+				.collect::<AResult<Vec<_>>>()?;
+			keyed.sort_by(|(aTime, aPath), (bTime, bPath)| aTime.cmp(bTime).then_with(|| aPath.cmp(bPath)));
+			inputs = keyed.into_iter().map(|(_, path)| path).collect();
+		},
+	}
+	Ok(inputs)
+}
+
+/// Implements `--range`/`--step`: slices `inputs` down to `range`'s window
+/// (or leaves it untouched if `range` is `None`), then keeps every `step`th
+/// survivor. Prints the resolved frame count either flag narrowed the list.
+fn rangeInputs(inputs: Vec<PathBuf>, range: Option<FrameRange>, step: usize) -> AResult<Vec<PathBuf>> {
+	if step == 0 {
+		return Err(anyhow!("--step must be non-zero"));
+	}
+	let originalCount = inputs.len();
+	let windowed = match range {
+		Some(range) => {
+			let bounds = resolveFrameRange(range, inputs.len());
+			inputs[bounds].to_vec()
+		},
+		None => inputs,
+	};
+	let stepped: Vec<PathBuf> = windowed.into_iter().step_by(step).collect();
+	if range.is_some() || step != 1 {
+		eprintln!("--range/--step: {} of {originalCount} input(s) selected", stepped.len());
+	}
+	Ok(stepped)
+}
+
+/// Implements `--max-frames`: picks `count` of `inputs`, preserving their
+/// relative order, and prints which ones were kept. A no-op (returns
+/// `inputs` untouched) if there aren't more than `count` to begin with.
+fn sampleInputs(inputs: Vec<PathBuf>, count: usize, strategy: SampleStrategy, seed: u64) -> Vec<PathBuf> {
+	if inputs.len() <= count {
+		return inputs;
+	}
+	let mut indices = match strategy {
+		SampleStrategy::Even => sampleIndicesEvenly(inputs.len(), count),
+		SampleStrategy::Random => sampleIndicesRandomly(inputs.len(), count, seed),
+	};
+	indices.sort_unstable();
+	eprintln!(
+		"--max-frames: sampling {count} of {} input(s) ({strategy:?}): {}",
+		inputs.len(),
+		indices.iter().map(|&i| inputs[i].display().to_string()).collect::<Vec<_>>().join(", ")
+	);
+	indices.into_iter().map(|i| inputs[i].clone()).collect()
+}
+
+/// `count` indices, evenly spaced across `0..len`, always including `0` and
+/// `len - 1`. `count` is assumed `<= len` and non-zero (checked by
+/// `sampleInputs`'s caller).
+fn sampleIndicesEvenly(len: usize, count: usize) -> Vec<usize> {
+	if count == 1 {
+		return vec![0];
+	}
+	(0..count).map(|i| i * (len - 1) / (count - 1)).collect()
+}
+
+/// `count` distinct indices into `0..len`, chosen uniformly at random from a
+/// `seed`-derived RNG so the same seed always picks the same frames.
+fn sampleIndicesRandomly(len: usize, count: usize, seed: u64) -> Vec<usize> {
+	use rand::seq::SliceRandom;
+	use rand::SeedableRng;
+
+	let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+	let mut indices: Vec<usize> = (0..len).collect();
+	indices.shuffle(&mut rng);
+	indices.truncate(count);
+	indices
+}
+
+/// Detects an image input's format without fully decoding it, by peeking at
+/// its header. Returns `None` for video inputs, which don't have an
+/// `image::ImageFormat` counterpart.
+fn detectImageFormat(path: &Path) -> AResult<Option<image::ImageFormat>> {
+	Ok(ImageReader::open(path)
+		.with_context(|| format!("Opening {path:?}"))?
+		.with_guessed_format()
+		.with_context(|| format!("Guessing format of {path:?}"))?
+		.format())
+}
+
+/// Implements `--check`: runs through every input gathering warnings instead
+/// of failing on the first one, prints a summary, and returns an error if
+/// anything was wrong so the process exits nonzero. `width`/`height` are the
+/// dimensions `main` already validated every input against.
+fn printCheckReport(inputs: &[PathBuf], width: u32, height: u32) -> AResult<()> {
+	let mut formats = std::collections::BTreeSet::new();
+	let mut warnings = Vec::new();
+	for path in inputs {
+		if isVideoFile(path) {
+			formats.insert("video".to_string());
+			continue;
+		}
+		if isRawFile(path) {
+			formats.insert("raw".to_string());
+			continue;
+		}
+		match detectImageFormat(path) {
+			Ok(Some(format)) => {
+				formats.insert(format!("{format:?}"));
+			},
+			Ok(None) => warnings.push(format!("Could not determine the format of {path:?}")),
+			Err(err) => warnings.push(format!("{path:?}: {err:#}")),
+		}
+	}
+
+	println!("{} input(s), {width}x{height}", inputs.len());
+	println!("Formats: {}", formats.into_iter().collect::<Vec<_>>().join(", "));
+	if warnings.is_empty() {
+		println!("No problems found.");
+	} else {
+		for warning in &warnings {
+			println!("Warning: {warning}");
+		}
+	}
+
+	if warnings.is_empty() {
+		Ok(())
+	} else {
+		Err(anyhow!("--check found {} problem(s)", warnings.len()))
+	}
+}
+
+fn main() -> AResult<()> {
+	installSigintHandler()?;
+	let mut args = Args::parse();
+	if let Some(inputsFrom) = &args.inputsFrom {
+		args.inputs.extend(readInputsFromFile(inputsFrom)?);
+	}
+	args.inputs = expandInputGlobs(args.inputs)?;
+	args.inputs = expandInputDirectories(args.inputs, args.recursive)?;
+	args.inputs = sortInputs(args.inputs, args.sort)?;
+	args.inputs = rangeInputs(args.inputs, args.range, args.step)?;
+	if let Some(maxFrames) = args.maxFrames {
+		if maxFrames == 0 {
+			return Err(anyhow!("--max-frames must be non-zero"));
+		}
+		args.inputs = sampleInputs(args.inputs, maxFrames, args.sample, args.seed);
+	} else if args.sample != SampleStrategy::Even || args.seed != 0 {
+		eprintln!("--sample/--seed have no effect without --max-frames");
+	}
+	if args.inputs.is_empty() {
+		return Err(anyhow!("No inputs given, either as arguments or via --inputs-from"));
+	}
+	if args.inputs.iter().filter(|path| isStdin(path)).count() > 1 {
+		return Err(anyhow!("`-` (read one input from stdin) may only be given once"));
+	}
+	// Stdin can't be rewound, but every input gets probed for its dimensions
+	// and decoded again later, so it's spilled to a temp file up front and
+	// treated as an ordinary path from there on, same as any other input.
+	// `_stdinInput` just needs to outlive `main` so the temp file isn't
+	// deleted before it's read.
+	let _stdinInput = match args.inputs.iter().position(|path| isStdin(path)) {
+		Some(index) => {
+			let mut bytes = Vec::new();
+			std::io::stdin().lock().read_to_end(&mut bytes).context("Reading image from stdin")?;
+			let mut file = NamedTempFile::new().context("Creating temp file for stdin input")?;
+			file.write_all(&bytes).context("Spilling stdin input to temp file")?;
+			args.inputs[index] = file.path().to_path_buf();
+			Some(file)
+		},
+		None => None,
+	};
+	if let Some(kind) = args.makeMaster {
+		args.mode = kind.mode();
+	}
+	#[cfg(debug_assertions)]
+	dbg!(&args);
+	initVideoBackend()?;
+
+	if let Some(modes) = args.modes.clone() {
+		return runMultiMode(&args, &modes);
+	}
+	if args.chunkSize.is_none() {
+		return runStack(&args, &args.inputs, &args.output);
+	}
+	runChunked(&args)
+}
+
+/// Splits `args.inputs` into windows per `--chunk-size`/`--chunk-stride` and
+/// calls [`runStack`] once per window, substituting the chunk index into
+/// `--output`'s `{n}` placeholder. Only reached when `--chunk-size` is set;
+/// the unchunked path in `main` calls `runStack` directly instead.
+fn runChunked(args: &Args) -> AResult<()> {
+	let chunkSize = args.chunkSize.unwrap();
+	if chunkSize == 0 {
+		return Err(anyhow!("--chunk-size must be non-zero"));
+	}
+	if args.log.is_some() {
+		return Err(anyhow!("--log is not supported with --chunk-size, which would overwrite it once per chunk"));
+	}
+	let chunkStride = args.chunkStride.unwrap_or(chunkSize);
+	if chunkStride == 0 {
+		return Err(anyhow!("--chunk-stride must be non-zero"));
+	}
+	let outputTemplate = args
+		.output
+		.to_str()
+		.ok_or_else(|| anyhow!("--output must be valid UTF-8 when used with --chunk-size"))?;
+	if !outputTemplate.contains("{n}") {
+		return Err(anyhow!("--output must contain a `{{n}}` placeholder when used with --chunk-size"));
+	}
+
+	let mut chunkIndex = 0;
+	let mut start = 0;
+	while start + chunkSize <= args.inputs.len() {
+		let chunk = &args.inputs[start..start + chunkSize];
+		let outFile = PathBuf::from(outputTemplate.replace("{n}", &chunkIndex.to_string()));
+		runStack(args, chunk, &outFile)?;
+		chunkIndex += 1;
+		start += chunkStride;
+	}
+	if chunkIndex == 0 {
+		return Err(anyhow!(
+			"--chunk-size {chunkSize} is larger than the {} input(s) given: no chunks to stack",
+			args.inputs.len()
+		));
+	}
+	if start < args.inputs.len() {
+		eprintln!(
+			"Warning: {} trailing input(s) left over, not enough for another full --chunk-size {chunkSize} window",
+			args.inputs.len() - start
+		);
+	}
+	Ok(())
+}
+
+/// `--modes`: decodes every input once, then folds the decoded frames into
+/// each of `modes` in turn, saving one output per mode. Only single-pass
+/// modes are accepted (see [`Mode::isSinglePassMode`]) since every other mode
+/// either needs its own dedicated code path or the all-samples streaming
+/// pipeline, neither of which can share a decode with anything else.
+/// Deliberately much simpler than [`runStack`]: no calibration frames,
+/// `--align`, `--roi`, weighting, or checkpointing, since none of those
+/// compose with running several modes off one decode.
+fn runMultiMode(args: &Args, modes: &[Mode]) -> AResult<()> {
+	if modes.is_empty() {
+		return Err(anyhow!("--modes requires at least one mode"));
+	}
+	for &mode in modes {
+		if !mode.isSinglePassMode() {
+			return Err(anyhow!(
+				"--modes does not support {mode:?} mode; only single-pass modes (sum, sum-overflow, min, max, average, range, stddev) can share a decode"
+			));
+		}
+	}
+	if args.chunkSize.is_some() {
+		return Err(anyhow!("--modes is not supported with --chunk-size"));
+	}
+	if args.log.is_some() {
+		return Err(anyhow!("--modes is not supported with --log, which would overwrite itself once per mode"));
+	}
+	if args.align.is_some() {
+		return Err(anyhow!("--modes is not supported with --align"));
+	}
+	if args.dark.is_some() || args.bias.is_some() || args.flat.is_some() || args.badPixels.is_some() || args.mask.is_some() {
+		return Err(anyhow!("--modes is not supported with calibration frames (--dark, --bias, --flat, --bad-pixels, --mask)"));
+	}
+	let outputTemplate = args
+		.output
+		.to_str()
+		.ok_or_else(|| anyhow!("--output must be valid UTF-8 when used with --modes"))?;
+	if !outputTemplate.contains("{mode}") {
+		return Err(anyhow!("--output must contain a `{{mode}}` placeholder when used with --modes"));
+	}
+
+	let progress = Progress::new(args.inputs.len() as u64, args.quiet);
+	let targetDims = validateInputs(&args.inputs, args, &progress)?;
+	let calibration = Calibration { bias: None, dark: None, flat: None, badPixels: Vec::new() };
+	let lumaCoeffs = resolveLumaCoeffs(&args.lumaCoeffs)?;
+	let frames: Vec<DecodedFrame> = args
+		.inputs
+		.par_iter()
+		.map(|path| decodeInputFrames(path, args, &calibration, &progress, targetDims, None, (0, 0)))
+		.collect::<AResult<Vec<_>>>()?
+		.into_iter()
+		.flatten()
+		.collect();
+	if frames.is_empty() {
+		return Err(anyhow!("No frames decoded from inputs"));
+	}
+	let hdr = frames.iter().any(|frame| matches!(frame, DecodedFrame::Hdr(_)));
+	printBitDepthSummary(&frames, &progress);
+
+	for &mode in modes {
+		let combined = frames
+			.clone()
+			.into_par_iter()
+			.map(|frame| Accumulator::fromImage(mode, frame, hdr, args.colorSpace, 1.0, args.stddevScale, args.gamma, args.sumDivisor, args.sumShift, args.geomeanEpsilon, args.harmonicEpsilon, args.accumPrecision))
+			.reduce_with(|a, b| Accumulator::combine(mode, a, b, lumaCoeffs))
+			.ok_or_else(|| anyhow!("No frames decoded from inputs"))?
+			.intoOutput();
+		let modeName = mode.to_possible_value().expect("single-pass modes always have a possible value").get_name().to_owned();
+		let outFile = PathBuf::from(outputTemplate.replace("{mode}", &modeName));
+		let outFormat = args.format.resolve(&outFile)?;
+		saveOutput(combined, &outFile, outFormat, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, args.bitDepth)
+			.with_context(|| format!("Saving {mode:?} output to {outFile:?}"))?;
+		progress.println(&format!("Wrote {outFile:?}"));
+	}
+	Ok(())
+}
+
+/// Checks every input (including the first) exists, is a file, and can be
+/// dimension-probed, and that every input agrees on dimensions unless
+/// `--resize` is given. Also canonicalizes every input to catch the same
+/// file appearing twice under different spellings (a relative path and a
+/// symlink to it, say), warning about it or, with `--no-duplicates`, treating
+/// it as a problem like any other. Collects every problem across all inputs
+/// instead of bailing on the first one, so a bad batch is one re-run to fix
+/// instead of one re-run per bad file. Returns the agreed-on (width, height)
+/// on success.
+fn validateInputs(inputs: &[PathBuf], args: &Args, progress: &Progress) -> AResult<(u32, u32)> {
+	let mut inputProblems = Vec::new();
+	let mut dims = None;
+	let mut seen: HashMap<PathBuf, &Path> = HashMap::new();
+	for file in inputs {
+		if !file.exists() || !file.is_file() {
+			inputProblems.push(format!("Input file {file:?} does not exist"));
+			continue;
+		}
+		let (fileWidth, fileHeight) = match inputDimensions(file, args.inputFormat.map(Into::into), args.ignoreOrientation) {
+			Ok(queried) => queried,
+			Err(err) => {
+				inputProblems.push(format!("{file:?}: {err:#}"));
+				continue;
+			},
+		};
+		match dims {
+			None => dims = Some((fileWidth, fileHeight)),
+			Some((width, height)) if (width != fileWidth || height != fileHeight) && args.resize.is_none() => {
+				inputProblems.push(format!(
+					"Input {file:?} has mismatched dimensions: expected {width}x{height} but got {fileWidth}x{fileHeight} (pass --resize to allow this)"
+				));
+			},
+			Some(_) => {},
+		}
+		match file.canonicalize() {
+			Ok(canonical) => match seen.entry(canonical) {
+				std::collections::hash_map::Entry::Occupied(entry) => {
+					let message = format!("{file:?} is a duplicate of {:?} (the same file once symlinks and relative paths are resolved)", entry.get());
+					if args.noDuplicates {
+						inputProblems.push(message);
+					} else {
+						progress.warn(&message);
+					}
+				},
+				std::collections::hash_map::Entry::Vacant(entry) => {
+					entry.insert(file);
+				},
+			},
+			Err(err) => inputProblems.push(format!("{file:?}: {err:#}")),
+		}
+	}
+	if !inputProblems.is_empty() {
+		return Err(anyhow!("Found {} problem(s) with inputs:\n{}", inputProblems.len(), inputProblems.join("\n")));
+	}
+	dims.ok_or_else(|| anyhow!("No inputs given"))
+}
+
+/// Checks `outFile`'s parent directory up front, alongside `runStack`'s
+/// other is-dir/exists checks, so a missing output directory fails fast
+/// before any decoding or stacking work happens instead of surfacing as a
+/// filesystem error only once the run is otherwise done. With
+/// `createDirs`, creates the parent (and any missing ancestors) instead of
+/// erroring. A relative `outFile` with no directory component (an empty
+/// parent) is always fine, matching the current working directory.
+fn ensureOutputDirExists(outFile: &Path, createDirs: bool) -> AResult<()> {
+	let Some(parent) = outFile.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+		return Ok(());
+	};
+	if parent.exists() {
+		return Ok(());
+	}
+	if createDirs {
+		std::fs::create_dir_all(parent).with_context(|| format!("Creating output directory {parent:?}"))
+	} else {
+		Err(anyhow!("Output directory {parent:?} does not exist (pass --create-dirs to create it)"))
+	}
+}
+
+/// Validates, decodes, and stacks `inputs` into `outFile`, using every other
+/// setting from `args`. `inputs`/`outFile` are taken separately from `args`
+/// (rather than reading `args.inputs`/`args.output` directly) so `--chunk-size`
+/// can invoke this once per chunk with a different slice and numbered output
+/// path each time; the unchunked case just calls it once with `args.inputs`
+/// and `args.output` directly.
+fn runStack(args: &Args, inputs: &[PathBuf], outFile: &Path) -> AResult<()> {
+	let startTime = Instant::now();
+	let outputIsStdout = isStdout(outFile);
+	if !outputIsStdout {
+		if outFile.is_dir() {
+			return Err(anyhow!("Output file {outFile:?} is a directory"));
+		}
+		if outFile.exists() && !args.overwrite {
+			return Err(anyhow!(
+				"Output file {outFile:?} exists, refusing to overwrite"
+			));
+		}
+		ensureOutputDirExists(outFile, args.createDirs)?;
+	}
+
+	if outputIsStdout && matches!(args.format, OutputFormat::Auto) {
+		return Err(anyhow!("--output - has no extension to guess a format from; pass --format explicitly"));
+	}
+	if outputIsStdout && args.copyExif {
+		return Err(anyhow!("--copy-exif is not supported with --output -, since it re-reads the written file"));
+	}
+	let outFormat = args.format.resolve(outFile)?;
+	let intermediateFormat = match &args.intermediate {
+		Some(path) => {
+			let format = image::ImageFormat::from_path(path).with_context(|| format!("Guessing --intermediate format of {path:?}"))?;
+			if !matches!(format, image::ImageFormat::OpenExr | image::ImageFormat::Tiff) {
+				return Err(anyhow!("--intermediate only supports .exr or float .tiff output (to stay lossless), got {path:?}"));
+			}
+			Some(format)
+		},
+		None => None,
+	};
+	let progress = Progress::new(inputs.len() as u64, args.quiet || outputIsStdout);
+	if let Some(quality) = args.quality {
+		if !(1..=100).contains(&quality) {
+			return Err(anyhow!("--quality must be between 1 and 100, got {quality}"));
+		}
+		if outFormat != image::ImageFormat::Jpeg && outFormat != image::ImageFormat::Png {
+			progress.warn(&format!("--quality is ignored for {outFormat:?} output"));
+		}
+	}
+	if args.pngCompression.is_some() && outFormat != image::ImageFormat::Png {
+		progress.warn(&format!("--png-compression is ignored for {outFormat:?} output"));
+	}
+	if args.floatOutput && outFormat != image::ImageFormat::Tiff {
+		progress.warn(&format!("--float-output has no effect for {outFormat:?} output, falling back to normal precision"));
+	}
+	if let Some(depth) = args.bitDepth {
+		if depth != 8 && depth != 16 {
+			return Err(anyhow!("--bit-depth must be 8 or 16, got {depth}"));
+		}
+		if depth == 16 && !matches!(outFormat, image::ImageFormat::Png | image::ImageFormat::Tiff) && !OutputFormat::isFloatingPoint(outFormat) {
+			return Err(anyhow!("--bit-depth 16 requires a PNG or TIFF output format, got {outFormat:?}"));
+		}
+	}
+	if args.tileHeight == 0 {
+		return Err(anyhow!("--tile-height must be non-zero"));
+	}
+	if args.gamma <= 0.0 {
+		return Err(anyhow!("--gamma must be positive, got {}", args.gamma));
+	}
+	if args.selfFlat && args.selfFlatRadius <= 0.0 {
+		return Err(anyhow!("--self-flat-radius must be positive, got {}", args.selfFlatRadius));
+	}
+	if args.gamma != 1.0
+		&& matches!(
+			args.mode,
+			Mode::StdDev | Mode::Range | Mode::Rms | Mode::GeometricMean | Mode::HarmonicMean | Mode::Difference | Mode::SumScaled | Mode::SumRaw | Mode::SigmaClip | Mode::WinsorSigma | Mode::Median | Mode::Percentile | Mode::MadReject | Mode::MostFrequent | Mode::TrimmedMean | Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend
+		)
+	{
+		return Err(anyhow!("--gamma is not supported with {:?} mode", args.mode));
+	}
+	if args.sumDivisor.is_some() && !matches!(args.mode, Mode::SumScaled) {
+		return Err(anyhow!("--sum-divisor is only supported with sum-scaled mode"));
+	}
+	if matches!(args.mode, Mode::SumScaled) && args.sumDivisor == Some(0) {
+		return Err(anyhow!("--sum-divisor must be non-zero"));
+	}
+	if args.sumShift.is_some() && !matches!(args.mode, Mode::SumRaw) {
+		return Err(anyhow!("--sum-shift is only supported with sum-raw mode"));
+	}
+	if matches!(args.mode, Mode::SumRaw) {
+		let isFloatFormat = OutputFormat::isFloatingPoint(outFormat) || (outFormat == image::ImageFormat::Tiff && args.floatOutput);
+		match args.sumShift {
+			None if !isFloatFormat => {
+				return Err(anyhow!(
+					"sum-raw mode without --sum-shift needs a floating-point output format (OpenEXR, Radiance HDR, or --float-output .tiff), got {outFormat:?}"
+				));
+			},
+			Some(_) if !isFloatFormat && !matches!(outFormat, image::ImageFormat::Png | image::ImageFormat::Tiff) => {
+				return Err(anyhow!("--sum-shift needs a PNG, TIFF, or floating-point output format, got {outFormat:?}"));
+			},
+			_ => {},
+		}
+	}
+	if matches!(args.mode, Mode::Percentile) && !(0.0..=100.0).contains(&args.percentile) {
+		return Err(anyhow!(
+			"--percentile must be between 0.0 and 100.0, got {}",
+			args.percentile
+		));
+	}
+	if let Some(maxPercentile) = args.maxPercentile {
+		if !matches!(args.mode, Mode::Max) {
+			progress.warn(&format!("--max-percentile has no effect with {:?} mode", args.mode));
+		}
+		if !(0.0..=100.0).contains(&maxPercentile) {
+			return Err(anyhow!("--max-percentile must be between 0.0 and 100.0, got {maxPercentile}"));
+		}
+		if args.animate.is_some() {
+			return Err(anyhow!("--animate is not supported with --max-percentile, which needs every sample for a pixel at once"));
+		}
+	}
+	if let Some(minPercentile) = args.minPercentile {
+		if !matches!(args.mode, Mode::Min) {
+			progress.warn(&format!("--min-percentile has no effect with {:?} mode", args.mode));
+		}
+		if !(0.0..=100.0).contains(&minPercentile) {
+			return Err(anyhow!("--min-percentile must be between 0.0 and 100.0, got {minPercentile}"));
+		}
+		if args.animate.is_some() {
+			return Err(anyhow!("--animate is not supported with --min-percentile, which needs every sample for a pixel at once"));
+		}
+	}
+	if args.clipRange.is_some() {
+		let usesPercentileExtreme = (args.mode == Mode::Max && args.maxPercentile.is_some()) || (args.mode == Mode::Min && args.minPercentile.is_some());
+		if !args.mode.needsStreamingPipeline() && !usesPercentileExtreme {
+			progress.warn(&format!("--clip-range has no effect with {:?} mode, which doesn't keep a per-pixel sample buffer", args.mode));
+		}
+	}
+	if args.ignoreClipped {
+		if args.clipLow >= args.clipHigh {
+			return Err(anyhow!(
+				"--clip-low ({}) must be less than --clip-high ({})",
+				args.clipLow,
+				args.clipHigh
+			));
+		}
+		let usesPercentileExtreme = (args.mode == Mode::Max && args.maxPercentile.is_some()) || (args.mode == Mode::Min && args.minPercentile.is_some());
+		if !args.mode.needsStreamingPipeline() && !usesPercentileExtreme && !matches!(args.mode, Mode::ExposureFusion) {
+			progress.warn(&format!(
+				"--ignore-clipped has no effect with {:?} mode, which doesn't keep a per-pixel sample buffer to exclude clipped samples from",
+				args.mode
+			));
+		}
+	} else if args.clipLow != 0 || args.clipHigh != 255 {
+		progress.warn("--clip-low/--clip-high have no effect without --ignore-clipped");
+	}
+	if args.stackTiff.is_some() {
+		let usesPercentileExtreme = (args.mode == Mode::Max && args.maxPercentile.is_some()) || (args.mode == Mode::Min && args.minPercentile.is_some());
+		if args.mode.needsStreamingPipeline() || usesPercentileExtreme {
+			progress.warn(&format!(
+				"--stack-tiff has no effect with {:?} mode, which streams samples instead of materializing the aligned frames",
+				args.mode
+			));
+		}
+	}
+	if matches!(args.mode, Mode::MadReject) && args.madThreshold < 0.0 {
+		return Err(anyhow!("--mad-threshold must be non-negative, got {}", args.madThreshold));
+	}
+	if matches!(args.mode, Mode::TrimmedMean) && !(0.0..=0.5).contains(&args.trimFraction) {
+		return Err(anyhow!(
+			"--trim-fraction must be between 0.0 and 0.5, got {}",
+			args.trimFraction
+		));
+	}
+	if args.countMap.is_some() && !args.mode.isRejectionMode() {
+		progress.warn(&format!("--count-map has no effect with {:?} mode, which doesn't reject samples", args.mode));
+	}
+	if args.rejectionMap.is_some() && !args.mode.isRejectionMode() {
+		progress.warn(&format!("--rejection-map has no effect with {:?} mode, which doesn't reject samples", args.mode));
+	}
+	if args.overflowMap.is_some() && !matches!(args.mode, Mode::SumOverflow) {
+		progress.warn(&format!("--overflow-map has no effect with {:?} mode", args.mode));
+	}
+	if args.sourceMap.is_some() && !matches!(args.mode, Mode::FocusStack | Mode::LightenLuma | Mode::DarkenLuma) {
+		progress.warn(&format!("--source-map has no effect with {:?} mode, which doesn't select whole frames per pixel", args.mode));
+	}
+	if args.sigmaConverge && !matches!(args.mode, Mode::SigmaClip | Mode::WinsorSigma) {
+		progress.warn(&format!("--sigma-converge has no effect with {:?} mode, which has no iteration loop", args.mode));
+	}
+	if args.exposureReference.is_some() && !args.matchExposure {
+		progress.warn("--exposure-reference has no effect without --match-exposure");
+	}
+	if args.medianExact && !matches!(args.mode, Mode::Median) {
+		progress.warn(&format!("--median-exact has no effect with {:?} mode", args.mode));
+	}
+	if (args.checkpoint.is_some() || args.resume.is_some()) && !isCheckpointableMode(args.mode) {
+		return Err(anyhow!(
+			"--checkpoint/--resume are not supported with {:?} mode, which either needs every sample present at once or has its own dedicated pipeline",
+			args.mode
+		));
+	}
+	if (args.checkpoint.is_some() || args.resume.is_some()) && inputs.iter().any(|path| isVideoFile(path)) {
+		return Err(anyhow!("--checkpoint/--resume don't support video inputs, whose frame count isn't known without decoding them"));
+	}
+	if (args.checkpoint.is_some() || args.resume.is_some()) && matches!(args.accumPrecision, AccumPrecision::F64) {
+		return Err(anyhow!("--checkpoint/--resume are not supported with --accum-precision f64"));
+	}
+	if matches!(args.accumPrecision, AccumPrecision::F64) && !matches!(args.mode, Mode::Average | Mode::Fade) {
+		progress.warn(&format!("--accum-precision has no effect with {:?} mode, which doesn't keep a running mean", args.mode));
+	}
+	if matches!(args.accumPrecision, AccumPrecision::F64) && matches!(args.mode, Mode::Average | Mode::Fade) {
+		progress.println("Accumulating the running mean in f64 (--accum-precision f64)");
+	}
+	if args.checkpointEvery == 0 {
+		return Err(anyhow!("--checkpoint-every must be non-zero"));
+	}
+	if let Some(modes) = &args.modePerChannel {
+		let &[r, g, b] = modes.as_slice() else {
+			return Err(anyhow!("--mode-per-channel expects exactly 3 modes (r,g,b), got {}", modes.len()));
+		};
+		for mode in [r, g, b] {
+			if !mode.isChannelIndependent() {
+				return Err(anyhow!("--mode-per-channel does not support {mode:?} mode, which isn't a plain per-channel fold"));
+			}
+		}
+		if args.animate.is_some()
+			|| args.align.is_some()
+			|| args.weights.is_some()
+			|| args.weightByExposure
+			|| args.weightBySharpness
+			|| args.temporalSigma.is_some()
+			|| args.dark.is_some()
+			|| args.flat.is_some()
+			|| args.badPixels.is_some()
+			|| args.mask.is_some()
+			|| args.offsets.is_some()
+			|| args.gamma != 1.0
+			|| args.inputGain.is_some()
+		{
+			return Err(anyhow!(
+				"--animate, --align, --weights, --weight-by-exposure, --weight-by-sharpness, --temporal-sigma, --dark, --flat, --bad-pixels, --mask, --offsets, --gamma, and --input-gain are not supported with --mode-per-channel"
+			));
+		}
+	}
+	if args.animate.is_some() && !args.mode.isAssociative() {
+		return Err(anyhow!("--animate is not supported with {:?} mode", args.mode));
+	}
+	if args.subtractBackground && !args.mode.isAssociative() {
+		return Err(anyhow!("--subtract-background is not supported with {:?} mode", args.mode));
+	}
+	if args.preview.is_some() && !args.mode.isAssociative() {
+		return Err(anyhow!("--preview is not supported with {:?} mode", args.mode));
+	}
+	if args.previewOnly && args.preview.is_none() {
+		return Err(anyhow!("--preview-only requires --preview"));
+	}
+	if !(0.0..=1.0).contains(&args.clipWarnThreshold) {
+		return Err(anyhow!("--clip-warn-threshold must be between 0.0 and 1.0, got {}", args.clipWarnThreshold));
+	}
+	if let Some(errorOnClip) = args.errorOnClip {
+		if !(0.0..=1.0).contains(&errorOnClip) {
+			return Err(anyhow!("--error-on-clip must be between 0.0 and 1.0, got {errorOnClip}"));
+		}
+	}
+	if let Some(rejectOutlierFrames) = args.rejectOutlierFrames {
+		if !(0.0..=1.0).contains(&rejectOutlierFrames) {
+			return Err(anyhow!("--reject-outlier-frames must be between 0.0 and 1.0, got {rejectOutlierFrames}"));
+		}
+	}
+	if args.preview.is_some() && !(args.previewScale > 0.0 && args.previewScale <= 1.0) {
+		return Err(anyhow!("--preview-scale must be greater than 0.0 and at most 1.0, got {}", args.previewScale));
+	}
+	if args.previewEvery.is_some() != args.previewEveryPath.is_some() {
+		return Err(anyhow!("--preview-every and --preview-every-path must be given together"));
+	}
+	if args.previewEvery == Some(0) {
+		return Err(anyhow!("--preview-every must be greater than 0"));
+	}
+	if args.previewEvery.is_some() && (!args.mode.isAssociative() || matches!(args.mode, Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend)) {
+		progress.warn(&format!("--preview-every has no meaningful intermediate result with {:?} mode", args.mode));
+	}
+	if args.align.is_some() && !args.mode.isAssociative() {
+		return Err(anyhow!("--align is not supported with {:?} mode", args.mode));
+	}
+	if args.align.is_some() && args.alignStarCount == 0 {
+		return Err(anyhow!("--align-star-count must be non-zero"));
+	}
+	if args.alignMaxShift.is_some() && !matches!(args.align, Some(Align::Phase)) {
+		return Err(anyhow!("--align-max-shift is only supported with --align phase"));
+	}
+	if args.offsets.is_some() && args.align.is_some() {
+		return Err(anyhow!("--offsets and --align are mutually exclusive"));
+	}
+	if args.animate.is_some() && matches!(args.mode, Mode::Min) {
+		// Every pixel in a running minimum only ever gets darker, so the
+		// animation just shows more and more black rather than anything
+		// building up; not useful enough to bother supporting.
+		return Err(anyhow!("--animate is not supported with min mode"));
+	}
+	if matches!(args.mode, Mode::Difference) && inputs.len() < 2 {
+		return Err(anyhow!("difference mode needs a base frame plus at least one more input"));
+	}
+	if matches!(args.mode, Mode::ExposureFusion) {
+		if args.animate.is_some() || args.align.is_some() || args.weights.is_some() || args.weightByExposure || args.weightBySharpness || args.temporalSigma.is_some() {
+			return Err(anyhow!(
+				"--animate, --align, --weights, --weight-by-exposure, --weight-by-sharpness, and --temporal-sigma are not supported with exposure-fusion mode"
+			));
+		}
+	}
+	if matches!(args.mode, Mode::FocusStack) {
+		if args.animate.is_some() || args.align.is_some() || args.weights.is_some() || args.weightByExposure || args.weightBySharpness || args.temporalSigma.is_some() {
+			return Err(anyhow!(
+				"--animate, --align, --weights, --weight-by-exposure, --weight-by-sharpness, and --temporal-sigma are not supported with focus-stack mode"
+			));
+		}
+	}
+	if matches!(args.mode, Mode::Blend) {
+		if inputs.len() != 2 {
+			return Err(anyhow!("blend mode takes exactly two inputs, got {}", inputs.len()));
+		}
+		if args.dark.is_some()
+			|| args.flat.is_some()
+			|| args.badPixels.is_some()
+			|| args.weights.is_some()
+			|| args.weightByExposure
+			|| args.weightBySharpness
+			|| args.temporalSigma.is_some()
+			|| args.copyExif
+			|| args.roi.is_some()
+			|| args.mask.is_some()
+			|| args.offsets.is_some()
+			|| args.normalize
+			|| args.selfFlat
+			|| args.intermediate.is_some()
+			|| args.stackTiff.is_some()
+			|| args.animate.is_some()
+			|| args.align.is_some()
+		{
+			return Err(anyhow!(
+				"--dark, --flat, --bad-pixels, --weights, --weight-by-exposure, --weight-by-sharpness, --temporal-sigma, --copy-exif, --roi, --mask, --offsets, --normalize, --self-flat, --intermediate, --stack-tiff, --animate, and --align are not supported with blend mode"
+			));
+		}
+	}
+	if !matches!(args.mode, Mode::Blend) && args.opacity != 0.5 {
+		progress.warn("--opacity has no effect without blend mode");
+	}
+	if matches!(args.mode, Mode::Fade) && (args.weights.is_some() || args.weightByExposure || args.weightBySharpness || args.temporalSigma.is_some()) {
+		return Err(anyhow!("--weights, --weight-by-exposure, --weight-by-sharpness, and --temporal-sigma are not supported with fade mode, which derives its own weight from input order"));
+	}
+	if matches!(args.mode, Mode::Comet) && (args.weights.is_some() || args.weightByExposure || args.weightBySharpness || args.temporalSigma.is_some()) {
+		return Err(anyhow!("--weights, --weight-by-exposure, --weight-by-sharpness, and --temporal-sigma are not supported with comet mode, which derives its own trail weight from input order"));
+	}
+	if matches!(args.mode, Mode::Comet) && !(args.cometDecay > 0.0 && args.cometDecay <= 1.0) {
+		return Err(anyhow!("--comet-decay must be greater than 0.0 and at most 1.0, got {}", args.cometDecay));
+	}
+	if matches!(args.mode, Mode::AlphaOver) {
+		if args.dark.is_some()
+			|| args.flat.is_some()
+			|| args.badPixels.is_some()
+			|| args.weights.is_some()
+			|| args.weightByExposure
+			|| args.weightBySharpness
+			|| args.temporalSigma.is_some()
+			|| args.copyExif
+			|| args.roi.is_some()
+			|| args.mask.is_some()
+			|| args.offsets.is_some()
+			|| args.normalize
+			|| args.selfFlat
+			|| args.intermediate.is_some()
+			|| args.stackTiff.is_some()
+		{
+			return Err(anyhow!(
+				"--dark, --flat, --bad-pixels, --weights, --weight-by-exposure, --weight-by-sharpness, --temporal-sigma, --copy-exif, --roi, --mask, --offsets, --normalize, --self-flat, --intermediate, and --stack-tiff are not supported with alpha-over mode"
+			));
+		}
+		if args.animate.is_some() {
+			return Err(anyhow!("--animate is not supported with alpha-over mode"));
+		}
+	}
+	if [args.weights.is_some(), args.weightByExposure, args.weightBySharpness, args.temporalSigma.is_some()]
+		.iter()
+		.filter(|&&set| set)
+		.count()
+		> 1
+	{
+		return Err(anyhow!(
+			"--weights, --weight-by-exposure, --weight-by-sharpness, and --temporal-sigma are mutually exclusive"
+		));
+	}
+	if let Some(sigma) = args.temporalSigma {
+		if sigma <= 0.0 {
+			return Err(anyhow!("--temporal-sigma must be greater than 0.0, got {sigma}"));
+		}
+	}
+	if let Some(weights) = &args.weights {
+		if weights.len() != inputs.len() {
+			return Err(anyhow!(
+				"--weights has {} entries but there are {} inputs",
+				weights.len(),
+				inputs.len()
+			));
+		}
+	}
+	let gains: Vec<f32> = match &args.inputGain {
+		Some(gains) if gains.len() == 1 => vec![gains[0]; inputs.len()],
+		Some(gains) if gains.len() == inputs.len() => gains.clone(),
+		Some(gains) => {
+			return Err(anyhow!(
+				"--input-gain has {} entries but there are {} inputs (pass exactly 1 to apply it to every input)",
+				gains.len(),
+				inputs.len()
+			))
+		},
+		None => vec![1.0; inputs.len()],
+	};
+	let offsets = match &args.offsets {
+		Some(path) => {
+			let offsets = readOffsets(path).with_context(|| format!("Reading offsets file {path:?}"))?;
+			if offsets.len() != inputs.len() {
+				return Err(anyhow!(
+					"--offsets has {} entries but there are {} inputs",
+					offsets.len(),
+					inputs.len()
+				));
+			}
+			offsets
+		},
+		None => vec![(0, 0); inputs.len()],
+	};
+
+	let (width, height) = validateInputs(inputs, args, &progress)?;
+	let lumaCoeffs = resolveLumaCoeffs(&args.lumaCoeffs)?;
+
+	let roi = match &args.roi {
+		Some(values) => {
+			let &[x, y, w, h] = values.as_slice() else {
+				return Err(anyhow!("--roi expects exactly 4 values (x,y,w,h), got {}", values.len()));
+			};
+			if w == 0 || h == 0 {
+				return Err(anyhow!("--roi width and height must be non-zero"));
+			}
+			if x.saturating_add(w) > width || y.saturating_add(h) > height {
+				return Err(anyhow!(
+					"--roi {x},{y},{w},{h} doesn't fit within the {width}x{height} input"
+				));
+			}
+			Some((x, y, w, h))
+		},
+		None => None,
+	};
+	// `--align` detects its own per-frame shifts later, well after decoding,
+	// so it applies `--crop-overlap` itself right after alignment. This only
+	// has to handle the `--offsets` file, whose shifts are already known:
+	// fold the region every frame covers into `roi` up front, so it flows
+	// through `decodeInputFrames` like any other crop.
+	let roi = if args.cropOverlap && args.offsets.is_some() {
+		let rect = intersectRects(roi, overlapRegion(&offsets, width, height)?)?;
+		progress.println(&format!("--crop-overlap: cropped to the {}x{} region covered by every frame", rect.2, rect.3));
+		Some(rect)
+	} else {
+		roi
+	};
+	let (accumWidth, accumHeight) = roi.map_or((width, height), |(_, _, w, h)| (w, h));
+
+	if args.check {
+		return printCheckReport(inputs, width, height);
+	}
+
+	let bias = match &args.bias {
+		Some(path) => {
+			let img = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)
+				.with_context(|| format!("Decoding bias frame {path:?}"))?
+				.intoRgb8();
+			if img.width() != width || img.height() != height {
+				return Err(anyhow!(
+					"--bias frame has mismatched dimensions: expected {}x{} but got {}x{}",
+					width,
+					height,
+					img.width(),
+					img.height()
+				));
+			}
+			Some(img)
+		},
+		None => None,
+	};
+	let dark = match &args.dark {
+		Some(path) => {
+			let img = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)
+				.with_context(|| format!("Decoding dark frame {path:?}"))?
+				.intoRgb8();
+			if img.width() != width || img.height() != height {
+				return Err(anyhow!(
+					"--dark frame has mismatched dimensions: expected {}x{} but got {}x{}",
+					width,
+					height,
+					img.width(),
+					img.height()
+				));
+			}
+			Some(img)
+		},
+		None => None,
+	};
+	let flat = match &args.flat {
+		Some(path) => {
+			let img = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)
+				.with_context(|| format!("Decoding flat frame {path:?}"))?
+				.intoRgb8();
+			if img.width() != width || img.height() != height {
+				return Err(anyhow!(
+					"--flat frame has mismatched dimensions: expected {}x{} but got {}x{}",
+					width,
+					height,
+					img.width(),
+					img.height()
+				));
+			}
+			let mean = imageMeanSample(&img);
+			Some((img, mean))
+		},
+		None => None,
+	};
+	let badPixels = match &args.badPixels {
+		Some(path) => {
+			let coords = readBadPixels(path).with_context(|| format!("Reading --bad-pixels file {path:?}"))?;
+			coords
+				.into_iter()
+				.filter(|&(x, y)| {
+					let inBounds = x < width && y < height;
+					if !inBounds {
+						progress.warn(&format!("--bad-pixels coordinate ({x}, {y}) is outside the {width}x{height} input, skipping"));
+					}
+					inBounds
+				})
+				.collect()
+		},
+		None => Vec::new(),
+	};
+	let calibration = Calibration { bias, dark, flat, badPixels };
+
+	let exposureReferenceLuminance = match &args.exposureReference {
+		Some(path) => {
+			let img = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation).with_context(|| format!("Decoding --exposure-reference {path:?}"))?;
+			Some(meanLuminance(&img, lumaCoeffs))
+		},
+		None => None,
+	};
+
+	let lut = args.lut.as_ref().map(|path| parseCubeLut(path)).transpose()?;
+
+	let mask = match &args.mask {
+		Some(path) => {
+			let img = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation).with_context(|| format!("Decoding mask {path:?}"))?.intoRgb8();
+			if img.width() != width || img.height() != height {
+				return Err(anyhow!(
+					"--mask has mismatched dimensions: expected {}x{} but got {}x{}",
+					width,
+					height,
+					img.width(),
+					img.height()
+				));
+			}
+			let mask = GrayImage::from_fn(width, height, |x, y| {
+				let [r, g, b] = img.get_pixel(x, y).0;
+				Luma([luminanceOf(r as f32, g as f32, b as f32, lumaCoeffs).round().clamp(0.0, 255.0) as u8])
+			});
+			Some(match roi {
+				Some((x, y, w, h)) => image::imageops::crop_imm(&mask, x, y, w, h).to_image(),
+				None => mask,
+			})
+		},
+		None => None,
+	};
+
+	let threads = args.threads.unwrap_or_else(|| {
+		std::thread::available_parallelism()
+			.map(|n| n.get())
+			.unwrap_or(1)
+	});
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.context("Building thread pool")?;
+
+	let mode = args.mode;
+	if let Some(previewPath) = &args.preview {
+		pool.install(|| generatePreview(inputs, args, &progress, previewPath)).context("Generating preview")?;
+		if args.previewOnly {
+			return Ok(());
+		}
+	}
+	if matches!(mode, Mode::AlphaOver) {
+		if args.log.is_some() {
+			progress.warn("--log has no effect with alpha-over mode, which doesn't go through the weighted decode loop");
+		}
+		let alphaOverStart = Instant::now();
+		let result = pool.install(|| -> AResult<()> {
+			let composited = inputs
+				.par_iter()
+				.map(|path| {
+					let mut frame = decodeImageRgba(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)?;
+					if let Some(filter) = args.resize {
+						if (frame.width(), frame.height()) != (width, height) {
+							frame = image::imageops::resize(&frame, width, height, filter.into());
+						}
+					}
+					progress.inc();
+					Ok(frame)
+				})
+				.collect::<AResult<Vec<_>>>()?
+				.into_par_iter()
+				.reduce_with(compositeOver)
+				.ok_or_else(|| anyhow!("No frames decoded from inputs"))?;
+			saveRgbaOutput(composited, outFile, outFormat, args.quality, args.pngCompression).context("Saving output file")
+		});
+		progress.finish();
+		if args.timings {
+			// `alpha-over` fuses decode, composite, and save into one pass, so
+			// there's no meaningful split; report it all as decode time.
+			printTimings(startTime.elapsed(), alphaOverStart.elapsed(), Duration::ZERO, Duration::ZERO, width, height, inputs.len());
+		}
+		return result;
+	}
+	if matches!(mode, Mode::Blend) {
+		if args.log.is_some() {
+			progress.warn("--log has no effect with blend mode, which doesn't go through the weighted decode loop");
+		}
+		let blendStart = Instant::now();
+		let result = pool.install(|| -> AResult<()> {
+			let [a, b] = inputs else { unreachable!("validated above to have exactly 2 inputs") };
+			let decode = |path: &Path| -> AResult<DecodedFrame> {
+				let frame = decodeImage(path, &progress, args.inputFormat.map(Into::into), args.mmap, args.ignoreOrientation)?;
+				let frame = resizeFrameIfNeeded(frame, args.resize, (width, height));
+				progress.inc();
+				Ok(frame)
+			};
+			let (a, b) = (decode(a)?, decode(b)?);
+			let hdr = matches!((&a, &b), (DecodedFrame::Hdr(_), _) | (_, DecodedFrame::Hdr(_)));
+			let blended = blendFrames(a.intoRgb32f(), &b.intoRgb32f(), args.opacity);
+			let blended = if hdr { DecodedFrame::Hdr(blended) } else { DecodedFrame::Ldr(blended.convert()) };
+			saveOutput(blended, outFile, outFormat, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, args.bitDepth).context("Saving output file")
+		});
+		progress.finish();
+		if args.timings {
+			// Like `alpha-over`, this fuses decode, blend, and save into one
+			// pass with no meaningful split, so it's all counted as decode time.
+			printTimings(startTime.elapsed(), blendStart.elapsed(), Duration::ZERO, Duration::ZERO, width, height, inputs.len());
+		}
+		return result;
+	}
+	let decodeNanos = std::sync::atomic::AtomicU64::new(0);
+	let accumulateNanos = std::sync::atomic::AtomicU64::new(0);
+	let logEntries: std::sync::Mutex<Vec<LogEntry>> = std::sync::Mutex::new(Vec::new());
+	let outImg = pool.install(|| -> AResult<DecodedFrame> {
+		if let Some(modes) = &args.modePerChannel {
+			if args.log.is_some() {
+				progress.warn("--log has no effect with --mode-per-channel, which doesn't go through the weighted decode loop");
+			}
+			let &[r, g, b] = modes.as_slice() else { unreachable!("validated above to have exactly 3 entries") };
+			// `--mode-per-channel` fuses decode into its own per-channel
+			// accumulation; there's no meaningful split, so it's all counted
+			// as accumulate time.
+			let accumulateStart = Instant::now();
+			let result = runModePerChannel(inputs, args, &calibration, &progress, [r, g, b], (width, height), roi);
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			return result;
+		}
+		// `max`/`min` normally fold pairwise through the cheap `Accumulator`
+		// path below, but `--max-percentile`/`--min-percentile` need every
+		// sample for a pixel present at once (same as `percentile` mode), so
+		// route them through the streaming reduce instead when set.
+		let usesPercentileExtreme = (mode == Mode::Max && args.maxPercentile.is_some()) || (mode == Mode::Min && args.minPercentile.is_some());
+		if mode.needsStreamingPipeline() || usesPercentileExtreme {
+			if args.log.is_some() {
+				progress.warn(&format!("--log has no effect with {mode:?} mode, which streams samples instead of going through the weighted decode loop"));
+			}
+			let decodeStart = Instant::now();
+			let sources = prepareStreamingSources(args, &calibration, &progress, (width, height), roi, &offsets)?;
+			decodeNanos.fetch_add(decodeStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			let accumulateStart = Instant::now();
+			let ignoreClippedBounds = args.ignoreClipped.then(|| (args.clipLow as f32, args.clipHigh as f32));
+			// The histogram fast path can't apply --clip-range/--ignore-clipped's
+			// per-pixel sample filtering (it never holds a sample buffer to filter
+			// in the first place, only running bucket counts), so fall back to the
+			// sorted-samples reducer, same as --median-exact, whenever either is set.
+			let usesHistogramMedian = mode == Mode::Median && !args.medianExact && args.clipRange.is_none() && ignoreClippedBounds.is_none();
+			let (stackedImg, counts, averageIterations) = if usesHistogramMedian {
+				(runMedianHistogram(accumWidth, accumHeight, &sources, args.tileHeight)?, None, None)
+			} else {
+				let clipRangeBytes = args.clipRange.map(|range| (range.lo * 255.0, range.hi * 255.0));
+				let reducer = withIgnoreClipped(withClipRange(streamingReducer(mode, args), clipRangeBytes), ignoreClippedBounds);
+				let countReducer = ((args.countMap.is_some() || args.rejectionMap.is_some()) && mode.isRejectionMode())
+					.then(|| withIgnoreClipped(withClipRange(survivorCountReducer(mode, args), clipRangeBytes), ignoreClippedBounds));
+				let iterReducer = (args.sigmaConverge && matches!(mode, Mode::SigmaClip | Mode::WinsorSigma))
+					.then(|| withIgnoreClipped(withClipRange(iterationsReducer(mode, args), clipRangeBytes), ignoreClippedBounds));
+				runStreamingReduce(accumWidth, accumHeight, &sources, &reducer, countReducer.as_deref(), iterReducer.as_deref(), args.tileHeight)?
+			};
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			if let Some(counts) = &counts {
+				if let Some(countMap) = &args.countMap {
+					saveCountMap(counts, countMap)?;
+				}
+				if let Some(rejectionMap) = &args.rejectionMap {
+					saveRejectionMap(counts, sources.len() as u32, rejectionMap)?;
+				}
+			}
+			if let Some(averageIterations) = averageIterations {
+				progress.println(&format!("Average sigma-clip iterations run: {averageIterations:.2}"));
+			}
+			let stacked = DecodedFrame::Ldr(stackedImg);
+			return Ok(match &mask {
+				Some(mask) => {
+					let firstFrame = DecodedFrame::Ldr(sources.first().ok_or_else(|| anyhow!("No frames decoded from inputs"))?.readBand(0, accumHeight)?);
+					applyMask(stacked, &firstFrame, mask, args.maskThreshold)
+				},
+				None => stacked,
+			});
+		}
+
+		let weights = if mode == Mode::Fade {
+			// Linear ramp by position, 1-indexed so the first frame still
+			// contributes rather than being weighted to zero. For two frames
+			// this is a 1:2 ratio, not an even split, since a true 0-weighted
+			// endpoint would make `fade` with two inputs just return the last
+			// one untouched.
+			let n = inputs.len();
+			(0..n)
+				.map(|i| {
+					let position = if args.fadeReverse { n - 1 - i } else { i };
+					(position + 1) as f32
+				})
+				.collect()
+		} else if mode == Mode::Comet {
+			// Each frame's trail contribution is scaled by `--comet-decay`
+			// raised to its distance from the last input, so the most recent
+			// frame is always full strength (`decay^0 == 1.0`) and earlier
+			// ones fade out geometrically.
+			let n = inputs.len();
+			(0..n).map(|i| args.cometDecay.powi((n - 1 - i) as i32)).collect()
+		} else if args.weightByExposure {
+			inputs
+				.iter()
+				.map(|path| {
+					readExposureTime(path).unwrap_or_else(|| {
+						progress.warn(&format!(
+							"no ExposureTime EXIF tag in {path:?}, defaulting to weight 1.0"
+						));
+						1.0
+					})
+				})
+				.collect()
+		} else if let Some(sigma) = args.temporalSigma {
+			// Centered on the middle of this window (the whole input list,
+			// or one `--chunk-size` window when chunked), so denoising a
+			// timelapse/video weights each output frame toward its nearest
+			// temporal neighbors instead of a flat boxcar average.
+			let n = inputs.len();
+			let center = (n - 1) as f32 / 2.0;
+			(0..n).map(|i| (-0.5 * ((i as f32 - center) / sigma).powi(2)).exp()).collect()
+		} else {
+			args.weights.clone().unwrap_or_else(|| vec![1.0; inputs.len()])
+		};
+
+		let (initialAcc, resumeCount) = match &args.resume {
+			Some(path) => {
+				let (count, acc) = loadCheckpoint(path, mode, (accumWidth, accumHeight))?;
+				if count > inputs.len() {
+					return Err(anyhow!(
+						"--resume checkpoint {path:?} already processed {count} input(s), but only {} were given",
+						inputs.len()
+					));
+				}
+				progress.println(&format!("--resume: continuing from {path:?}, skipping the first {count} already-folded input(s)"));
+				for _ in 0..count {
+					progress.inc();
+				}
+				(Some(acc), count)
+			},
+			None => (None, 0),
+		};
+		let inputs = &inputs[resumeCount..];
+		let weights = &weights[resumeCount..];
+		let offsets = &offsets[resumeCount..];
+		let gains = &gains[resumeCount..];
+
+		let pipelineEligible = isCheckpointableMode(mode)
+			&& args.align.is_none()
+			&& !args.subtractBackground
+			&& !args.matchExposure
+			&& args.rejectOutlierFrames.is_none()
+			&& !args.lumaChromaSplit
+			&& args.animate.is_none()
+			&& args.previewEvery.is_none()
+			&& args.checkpoint.is_none()
+			&& args.resume.is_none()
+			// `--source-map` on `LightenLuma`/`DarkenLuma` needs every frame
+			// materialized at once to recover which one won per pixel; the
+			// streamlined pipeline below only ever sees pairwise combines.
+			&& !(matches!(mode, Mode::LightenLuma | Mode::DarkenLuma) && args.sourceMap.is_some())
+			// `--log` needs a per-input keep/skip record, which only the
+			// weighted decode loop below tracks.
+			&& args.log.is_none()
+			// `--stack-tiff` archives the aligned frames themselves, which only
+			// the weighted decode loop below materializes; the streamlined
+			// pipeline never holds more than one tile band at a time.
+			&& args.stackTiff.is_none();
+		if pipelineEligible {
+			return runPipelinedAssociative(inputs, args, &calibration, &progress, mode, &weights, &offsets, &gains, (width, height), roi, &mask, &decodeNanos, &accumulateNanos, lumaCoeffs, outFile, outFormat);
+		}
+
+		let decodeStart = Instant::now();
+		let decoded = inputs
+			.par_iter()
+			.zip(weights.par_iter())
+			.zip(offsets.par_iter())
+			.zip(gains.par_iter())
+			.map(|(((path, &weight), &offset), &gain)| {
+				let result = decodeInputFrames(path, args, &calibration, &progress, (width, height), roi, offset).map(
+					|frames| {
+						frames
+							.into_iter()
+							.map(|frame| {
+								let frame = if gain != 1.0 { scaleFrameBrightness(frame, gain) } else { frame };
+								let weight = if args.weightBySharpness {
+									let sharpness = sharpnessOf(&frame, lumaCoeffs);
+									progress.println(&format!("{path:?}: sharpness {sharpness:.4}"));
+									sharpness
+								} else {
+									weight
+								};
+								(frame, weight)
+							})
+							.collect::<Vec<_>>()
+					},
+				);
+				(path, weight, gain, result)
+			})
+			.collect::<Vec<_>>();
+		let mut frames = Vec::new();
+		for (path, weight, gain, result) in decoded {
+			if args.log.is_some() {
+				let dimensions = result.as_ref().ok().and_then(|f| f.first()).map(|(frame, _)| (frame.width(), frame.height()));
+				let appliedWeight = result.as_ref().ok().and_then(|f| f.first()).map(|(_, weight)| *weight).unwrap_or(weight);
+				let status = match &result {
+					Ok(_) => "kept".to_string(),
+					Err(err) if args.skipErrors => format!("skipped: {err:#}"),
+					Err(err) => format!("failed: {err:#}"),
+				};
+				logEntries.lock().unwrap().push(LogEntry {
+					path: path.clone(),
+					dimensions,
+					format: guessInputFormat(path),
+					exposureTime: readExposureTime(path),
+					weight: appliedWeight,
+					gain,
+					status,
+				});
+			}
+			match result {
+				Ok(decodedFrames) => frames.extend(decodedFrames),
+				Err(err) if args.skipErrors => {
+					progress.warn(&format!("skipping {path:?}, failed to decode: {err:#}"));
+					progress.inc();
+				},
+				Err(err) => return Err(err),
+			}
+		}
+		if frames.is_empty() && initialAcc.is_none() {
+			return Err(anyhow!("No frames decoded from inputs"));
+		}
+		decodeNanos.fetch_add(decodeStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+		let accumulateStart = Instant::now();
+		let frames = match args.rejectOutlierFrames {
+			Some(threshold) => rejectOutlierFrames(frames, threshold, &progress, lumaCoeffs),
+			None => frames,
+		};
+		if frames.is_empty() && initialAcc.is_none() {
+			return Err(anyhow!("No frames left after --reject-outlier-frames excluded every one"));
+		}
+		let frames = match args.align {
+			Some(Align::Stars) => {
+				let (frames, weights): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+				let (frames, alignOffsets) = alignFrames(frames, args.alignStarCount, lumaCoeffs);
+				let frames: Vec<_> = frames.into_iter().zip(weights).collect();
+				if args.cropOverlap {
+					cropFramesToOverlap(frames, &alignOffsets, accumWidth, accumHeight, &progress)?
+				} else {
+					frames
+				}
+			},
+			Some(Align::Phase) => {
+				let (frames, weights): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+				let (frames, alignOffsets) = alignFramesPhase(frames, args.alignMaxShift, lumaCoeffs)?;
+				let frames: Vec<_> = frames.into_iter().zip(weights).collect();
+				if args.cropOverlap {
+					cropFramesToOverlap(frames, &alignOffsets, accumWidth, accumHeight, &progress)?
+				} else {
+					frames
+				}
+			},
+			None => frames,
+		};
+		let frames = if args.subtractBackground {
+			let (frames, weights): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+			subtractBackground(frames).into_iter().zip(weights).collect()
+		} else {
+			frames
+		};
+		let frames = if args.matchExposure { matchExposure(frames, exposureReferenceLuminance, &progress, lumaCoeffs) } else { frames };
+		let hdr = frames.iter().any(|(frame, _)| matches!(frame, DecodedFrame::Hdr(_)));
+		printBitDepthSummary(frames.iter().map(|(frame, _)| frame), &progress);
+		let firstFrame = match &mask {
+			Some(_) => Some(frames.first().map(|(frame, _)| frame.clone()).ok_or_else(|| anyhow!("No frames decoded from inputs"))?),
+			None => None,
+		};
+		if let Some(stackTiffPath) = &args.stackTiff {
+			saveStackTiff(stackTiffPath, frames.iter().map(|(frame, _)| frame)).context("Writing --stack-tiff output")?;
+		}
+
+		// Like `Difference`, `ExposureFusion` isn't a per-frame accumulator
+		// kind: it needs every frame present at once to compute per-pixel
+		// blend weights and build the Laplacian/Gaussian pyramids, so it's
+		// computed directly here instead of through `Accumulator::combine`.
+		if mode == Mode::ExposureFusion {
+			let fusionFrames = frames.into_iter().map(|(frame, _)| frame.intoRgb32f()).collect::<Vec<_>>();
+			let ignoreClippedBounds = args.ignoreClipped.then(|| (args.clipLow as f32 / 255.0, args.clipHigh as f32 / 255.0));
+			let fused = DecodedFrame::Ldr(exposureFusion(fusionFrames, lumaCoeffs, ignoreClippedBounds)?.convert());
+			let result = match (&mask, firstFrame) {
+				(Some(mask), Some(firstFrame)) => applyMask(fused, &firstFrame, mask, args.maskThreshold),
+				_ => fused,
+			};
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			return Ok(result);
+		}
+
+		// Same reasoning as `ExposureFusion` above: `FocusStack` needs every
+		// frame's sharpness map at once to pick the winner per pixel.
+		if mode == Mode::FocusStack {
+			let stackFrames = frames.into_iter().map(|(frame, _)| frame.intoRgb32f()).collect::<Vec<_>>();
+			let frameCount = stackFrames.len();
+			let (stacked, sourceMap) = focusStack(stackFrames, args.focusRadius, lumaCoeffs)?;
+			if let Some(sourceMapPath) = &args.sourceMap {
+				saveSourceMap(&sourceMap, frameCount, sourceMapPath)?;
+			}
+			let stacked = DecodedFrame::Ldr(stacked.convert());
+			let result = match (&mask, firstFrame) {
+				(Some(mask), Some(firstFrame)) => applyMask(stacked, &firstFrame, mask, args.maskThreshold),
+				_ => stacked,
+			};
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			return Ok(result);
+		}
+
+		// `LightenLuma`/`DarkenLuma` stay on the ordinary pairwise
+		// `Accumulator` fold below, same as ever, unless `--source-map` was
+		// asked for: recovering which frame won at each pixel from that fold
+		// would mean threading an index through every accumulator variant,
+		// so instead this recomputes the winner directly from every frame at
+		// once, the same way `ExposureFusion`/`FocusStack` do above.
+		if matches!(mode, Mode::LightenLuma | Mode::DarkenLuma) && args.sourceMap.is_some() {
+			let stackFrames = frames.into_iter().map(|(frame, _)| frame.intoRgb32f()).collect::<Vec<_>>();
+			let sourceMap = lumaSourceMap(&stackFrames, mode, lumaCoeffs);
+			if let Some(sourceMapPath) = &args.sourceMap {
+				saveSourceMap(&sourceMap, stackFrames.len(), sourceMapPath)?;
+			}
+			let stacked = stackFrames
+				.into_iter()
+				.reduce(|mut acc, samp| {
+					for (accPixel, sampPixel) in acc.pixels_mut().zip(samp.pixels()) {
+						keepPixelByLumaF32(mode, accPixel, sampPixel, lumaCoeffs);
+					}
+					acc
+				})
+				.ok_or_else(|| anyhow!("No frames decoded from inputs"))?;
+			let stacked = DecodedFrame::Ldr(stacked.convert());
+			let result = match (&mask, firstFrame) {
+				(Some(mask), Some(firstFrame)) => applyMask(stacked, &firstFrame, mask, args.maskThreshold),
+				_ => stacked,
+			};
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			return Ok(result);
+		}
+
+		// `--luma-chroma-split` also isn't a per-frame accumulator kind: it
+		// needs every frame's chroma at once (to pick the median, when
+		// that's the configured source), so it's computed directly here
+		// too, same as `ExposureFusion`/`FocusStack` above.
+		if mode == Mode::Average && args.lumaChromaSplit {
+			let splitFrames = frames.into_iter().map(|(frame, _)| frame.intoRgb32f()).collect::<Vec<_>>();
+			let stacked = DecodedFrame::Ldr(lumaChromaSplitStack(splitFrames, args.chromaSource, lumaCoeffs)?.convert());
+			let result = match (&mask, firstFrame) {
+				(Some(mask), Some(firstFrame)) => applyMask(stacked, &firstFrame, mask, args.maskThreshold),
+				_ => stacked,
+			};
+			accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+			return Ok(result);
+		}
+
+		// `Difference` isn't a real per-frame accumulator kind: the first
+		// frame is a fixed base, and every other frame contributes its
+		// per-channel absolute difference from that base. Once the base is
+		// peeled off, those differences reduce like `Sum`/`Max` would, so
+		// `combineMode` is what actually drives `Accumulator::combine` below.
+		let combineMode = match mode {
+			Mode::Difference => match args.differenceReduce {
+				DifferenceReduce::Sum => Mode::Sum,
+				DifferenceReduce::Max => Mode::Max,
+			},
+			_ => mode,
+		};
+		let accumulators = if mode == Mode::Difference {
+			let mut frames = frames.into_iter().map(|(frame, _)| frame);
+			let base = frames
+				.next()
+				.ok_or_else(|| anyhow!("No frames decoded from inputs"))?
+				.intoRgb32f();
+			frames
+				.map(|frame| Accumulator::F32 { img: absoluteDifference(frame.intoRgb32f(), &base), gamma: 1.0 })
+				.collect::<Vec<_>>()
+		} else {
+			frames
+				.into_par_iter()
+				.map(|(frame, weight)| Accumulator::fromImage(mode, frame, hdr, args.colorSpace, weight, args.stddevScale, args.gamma, args.sumDivisor, args.sumShift, args.geomeanEpsilon, args.harmonicEpsilon, args.accumPrecision))
+				.collect::<Vec<_>>()
+		};
+
+		let previewEveryActive = args.previewEvery.is_some() && mode.isAssociative() && !matches!(mode, Mode::AlphaOver | Mode::ExposureFusion | Mode::FocusStack | Mode::Blend);
+		let checkpointActive = args.checkpoint.is_some() || args.resume.is_some();
+		let combined = if args.animate.is_some() || previewEveryActive || checkpointActive {
+			// Snapshotting the running accumulator (for `--animate`,
+			// `--preview-every`, and/or `--checkpoint`) requires folding
+			// strictly in input order, so this bypasses the tree reduction
+			// above. `--resume` seeds `acc` with the checkpoint's accumulator
+			// instead of starting from scratch.
+			let delay = Delay::from_saturating_duration(Duration::from_millis(args.delay.into()));
+			let previewEveryFormat = args
+				.previewEveryPath
+				.as_deref()
+				.filter(|_| previewEveryActive)
+				.map(|path| image::ImageFormat::from_path(path).with_context(|| format!("Guessing --preview-every-path format of {path:?}")))
+				.transpose()?;
+			let mut acc: Option<Accumulator> = initialAcc;
+			let mut animFrames = Vec::with_capacity(if args.animate.is_some() { accumulators.len() } else { 0 });
+			for (i, next) in accumulators.into_iter().enumerate() {
+				acc = Some(match acc {
+					Some(acc) => Accumulator::combine(combineMode, acc, next, lumaCoeffs),
+					None => next,
+				});
+				if args.animate.is_some() {
+					animFrames.push(acc.as_ref().unwrap().preview());
+				}
+				if let (Some(every), Some(path), Some(format)) = (args.previewEvery, &args.previewEveryPath, previewEveryFormat) {
+					if (i + 1) % every == 0 {
+						let snapshot = downscaleFrame(acc.as_ref().unwrap().preview(), args.previewScale);
+						saveOutput(snapshot, path, format, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, None).context("Saving --preview-every snapshot")?;
+					}
+				}
+				if let Some(checkpointPath) = &args.checkpoint {
+					let processedCount = resumeCount + i + 1;
+					if processedCount % args.checkpointEvery == 0 {
+						saveCheckpoint(checkpointPath, mode, processedCount, acc.as_ref().unwrap()).context("Writing --checkpoint")?;
+					}
+				}
+				if INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+					if let Some(acc) = acc.take() {
+						saveInterruptedPartial(acc, &mask, firstFrame.clone(), resumeCount + i + 1, resumeCount + inputs.len(), outFile, outFormat, args);
+					}
+				}
+			}
+			let acc = acc.ok_or_else(|| anyhow!("No frames decoded from inputs"))?;
+			if let Some(animatePath) = &args.animate {
+				saveAnimation(animatePath, animFrames, delay).context("Saving animation")?;
+			}
+			acc
+		} else if mode.isAssociative() {
+			accumulators
+				.into_par_iter()
+				.reduce_with(|a, b| Accumulator::combine(combineMode, a, b, lumaCoeffs))
+				.ok_or_else(|| anyhow!("No frames decoded from inputs"))?
+		} else {
+			accumulators
+				.into_iter()
+				.reduce(|a, b| Accumulator::combine(combineMode, a, b, lumaCoeffs))
+				.ok_or_else(|| anyhow!("No frames decoded from inputs"))?
+		};
+
+		if let Some(overflowMap) = &args.overflowMap {
+			match &combined {
+				Accumulator::SumOverflow { sum } => saveOverflowMap(sum, overflowMap)?,
+				_ if mode == Mode::SumOverflow => progress.warn(
+					"--overflow-map has no effect when sum-overflow mode falls back to its HDR/float accumulator (any 16-bit/float input, or --gamma other than 1.0)",
+				),
+				_ => {},
+			}
+		}
+		let outImg = combined.intoOutput();
+		let result = match (&mask, firstFrame) {
+			(Some(mask), Some(firstFrame)) => applyMask(outImg, &firstFrame, mask, args.maskThreshold),
+			_ => outImg,
+		};
+		accumulateNanos.fetch_add(accumulateStart.elapsed().as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+		Ok(result)
+	})?;
+	progress.finish();
+	let outImg = if args.removeGradient { removeGradient(outImg, args.gradientDegree, lumaCoeffs) } else { outImg };
+	let outImg = if args.selfFlat { applySelfFlat(outImg, args.selfFlatRadius) } else { outImg };
+	let outImg = match args.whiteBalance {
+		Some(whiteBalance) => whiteBalanceFrame(outImg, whiteBalance),
+		None => outImg,
+	};
+	let outImg = if args.normalize { normalizeFrame(outImg, args.normalizeMode) } else { outImg };
+	let outImg = match &lut {
+		Some(lut) => applyLut(outImg, lut),
+		None => outImg,
+	};
+	let outRgb8ForChecks = outImg.clone().intoRgb8();
+	let stats = args.statsJson.as_ref().map(|_| channelStats(&outRgb8ForChecks));
+	let clipFraction = clippedFraction(&outRgb8ForChecks);
+	if let Some(errorOnClip) = args.errorOnClip {
+		if clipFraction > errorOnClip {
+			return Err(anyhow!(
+				"{:.1}% of output channel samples are clipped at 0 or 255, exceeding --error-on-clip {:.1}% ({:?} mode over many frames often causes this; try sum-scaled or average instead)",
+				clipFraction * 100.0,
+				errorOnClip * 100.0,
+				args.mode
+			));
+		}
+	}
+	if clipFraction > args.clipWarnThreshold {
+		progress.warn(&format!(
+			"{:.1}% of output channel samples are clipped at 0 or 255 ({:?} mode over many frames often causes this; try sum-scaled or average instead)",
+			clipFraction * 100.0,
+			args.mode
+		));
+	}
+	if let (Some(intermediatePath), Some(intermediateFormat)) = (&args.intermediate, intermediateFormat) {
+		match &outImg {
+			DecodedFrame::Hdr(img) => {
+				saveOutput(DecodedFrame::Hdr(img.clone()), intermediatePath, intermediateFormat, None, None, true, args.dither, args.rounding, args.tonemap, None)
+					.context("Writing --intermediate output")?;
+			},
+			DecodedFrame::Ldr(_) => {
+				progress.warn("--intermediate has no effect: this mode's output has no float data to preserve");
+			},
+		}
+	}
+	if let Some(comparePath) = &args.compare {
+		saveComparisonImage(comparePath, &inputs[0], &outImg, args, &progress).context("Writing --compare output")?;
+	}
+	let saveStart = Instant::now();
+	saveOutput(outImg, outFile, outFormat, args.quality, args.pngCompression, args.floatOutput, args.dither, args.rounding, args.tonemap, args.bitDepth).context("Saving output file")?;
+	let saveElapsed = saveStart.elapsed();
+	if args.copyExif {
+		copyExifMetadata(&inputs[0], outFile, outFormat, &progress).context("Copying EXIF metadata")?;
+	}
+	if let Some(statsPath) = &args.statsJson {
+		saveStatsJson(
+			statsPath,
+			inputs.len(),
+			width,
+			height,
+			args.mode,
+			stats.unwrap(),
+			startTime.elapsed(),
+			&progress.warnings(),
+		)
+		.context("Writing --stats-json output")?;
+	}
+	if let Some(logPath) = &args.log {
+		saveFrameLog(&logEntries.into_inner().unwrap(), logPath).context("Writing --log output")?;
+	}
+	if args.timings {
+		let decodeElapsed = Duration::from_nanos(decodeNanos.load(std::sync::atomic::Ordering::Relaxed));
+		let accumulateElapsed = Duration::from_nanos(accumulateNanos.load(std::sync::atomic::Ordering::Relaxed));
+		printTimings(startTime.elapsed(), decodeElapsed, accumulateElapsed, saveElapsed, width, height, inputs.len());
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sigmaClipReduceAveragesWithoutOutliers() {
+		let mu = sigmaClipReduce(&[10.0, 11.0, 9.0, 10.0], 3.0, 2);
+		assert!((mu - 10.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn sigmaClipReduceRejectsAnOutlier() {
+		// One wildly bright sample among a tight cluster should get clipped
+		// out, leaving the mean close to the cluster rather than dragged
+		// toward the outlier.
+		let mu = sigmaClipReduce(&[10.0, 11.0, 9.0, 10.0, 255.0], 1.0, 3);
+		assert!((mu - 10.0).abs() < 1.0, "expected mean near 10.0, got {mu}");
+	}
+
+	#[test]
+	fn sigmaClipReduceHandlesZeroStdDev() {
+		// All samples identical: variance is zero on the first pass, so the
+		// loop must break instead of dividing by zero.
+		let mu = sigmaClipReduce(&[42.0, 42.0, 42.0], 1.0, 5);
+		assert_eq!(mu, 42.0);
+	}
+
+	#[test]
+	fn sigmaClipReduceFallsBackWhenEverySampleIsRejected() {
+		// sigma = 0.0 only keeps samples exactly equal to the running mean;
+		// with no such sample the pass rejects everything and must fall back
+		// to the previous iteration's mean instead of going empty.
+		let samples = [1.0, 2.0, 3.0, 4.0];
+		let expectedMean = samples.iter().sum::<f32>() / samples.len() as f32;
+		let mu = sigmaClipReduce(&samples, 0.0, 3);
+		assert_eq!(mu, expectedMean);
+	}
+
+	#[test]
+	fn sigmaClipReduceWithZeroIterationsReturnsPlainMean() {
+		let samples = [1.0, 2.0, 3.0, 100.0];
+		let expectedMean = samples.iter().sum::<f32>() / samples.len() as f32;
+		assert_eq!(sigmaClipReduce(&samples, 1.0, 0), expectedMean);
+	}
+
+	#[test]
+	fn sigmaClipReduceDetailedReportsFewerSurvivorsWhenAnOutlierIsRejected() {
+		let (_, survivorCount, _) = sigmaClipReduceDetailed(&[10.0, 11.0, 9.0, 10.0, 255.0], 1.0, 3, false);
+		assert_eq!(survivorCount, 4);
+	}
+
+	#[test]
+	fn sigmaClipReduceDetailedStopsEarlyWhenConverged() {
+		// No sample here is ever rejected at sigma=3.0, so the first pass
+		// already converges; with `converge` set the loop should stop there
+		// instead of burning the remaining nine passes.
+		let samples = [10.0, 11.0, 9.0, 10.0];
+		let (_, _, ran) = sigmaClipReduceDetailed(&samples, 3.0, 10, true);
+		assert_eq!(ran, 1);
+	}
+
+	#[test]
+	fn sigmaClipReduceDetailedWithoutConvergeAlwaysRunsUpToTheIterationCap() {
+		let samples = [10.0, 11.0, 9.0, 10.0];
+		let (_, _, ran) = sigmaClipReduceDetailed(&samples, 3.0, 4, false);
+		assert_eq!(ran, 4, "without --sigma-converge every iteration runs, even once nothing more is rejected");
+	}
+
+	#[test]
+	fn winsorSigmaReduceClampsAnOutlierInsteadOfDroppingIt() {
+		// Same cluster-plus-outlier setup as sigmaClipReduceRejectsAnOutlier,
+		// but every sample (including the clamped outlier) still contributes
+		// to the mean, so the result is pulled slightly above the cluster
+		// instead of landing exactly on it.
+		let mu = winsorSigmaReduce(&[10.0, 11.0, 9.0, 10.0, 255.0], 1.0, 3);
+		assert!(mu > 10.0 && mu < 20.0, "expected mean slightly above the cluster, got {mu}");
+	}
+
+	#[test]
+	fn winsorSigmaReduceHandlesZeroStdDev() {
+		let mu = winsorSigmaReduce(&[42.0, 42.0, 42.0], 1.0, 5);
+		assert_eq!(mu, 42.0);
+	}
+
+	#[test]
+	fn winsorSigmaReduceWithZeroIterationsReturnsPlainMean() {
+		let samples = [1.0, 2.0, 3.0, 100.0];
+		let expectedMean = samples.iter().sum::<f32>() / samples.len() as f32;
+		assert_eq!(winsorSigmaReduce(&samples, 1.0, 0), expectedMean);
+	}
+
+	#[test]
+	fn winsorSigmaReduceDetailedStopsEarlyWhenConverged() {
+		// Same reasoning as sigmaClipReduceDetailedStopsEarlyWhenConverged:
+		// nothing here needs clamping at sigma=3.0, so the first pass already
+		// converges.
+		let samples = [10.0, 11.0, 9.0, 10.0];
+		let (_, ran) = winsorSigmaReduceDetailed(&samples, 3.0, 10, true);
+		assert_eq!(ran, 1);
+	}
+
+	#[test]
+	fn medianReduceWithOddCountReturnsMiddleSample() {
+		assert_eq!(medianReduce(&[5.0, 1.0, 3.0]), 3.0);
+	}
+
+	#[test]
+	fn medianReduceWithEvenCountAveragesMiddleTwo() {
+		assert_eq!(medianReduce(&[1.0, 10.0, 2.0, 9.0]), 5.5);
+	}
+
+	#[test]
+	fn percentileReduceMatchesMinAndMaxAtExtremes() {
+		let samples = [5.0, 1.0, 9.0, 3.0];
+		assert_eq!(percentileReduce(&samples, 0.0), 1.0);
+		assert_eq!(percentileReduce(&samples, 100.0), 9.0);
+	}
+
+	#[test]
+	fn maxPercentileAtOneHundredMatchesPlainMax() {
+		let args = Args::parse_from(["imgstack", "-o", "out.png", "--max-percentile", "100"]);
+		let samples = [5.0, 1.0, 9.0, 3.0];
+		let reduced = streamingReducer(Mode::Max, &args)(&samples);
+		assert_eq!(reduced, samples.iter().cloned().fold(f32::MIN, f32::max));
+	}
+
+	#[test]
+	fn minPercentileAtZeroMatchesPlainMin() {
+		let args = Args::parse_from(["imgstack", "-o", "out.png", "--min-percentile", "0"]);
+		let samples = [5.0, 1.0, 9.0, 3.0];
+		let reduced = streamingReducer(Mode::Min, &args)(&samples);
+		assert_eq!(reduced, samples.iter().cloned().fold(f32::MAX, f32::min));
+	}
+
+	#[test]
+	fn percentileReduceInterpolatesBetweenRanks() {
+		// Four samples span ranks 0..3; the 50th percentile falls exactly
+		// between ranks 1 and 2.
+		let mu = percentileReduce(&[10.0, 20.0, 30.0, 40.0], 50.0);
+		assert!((mu - 25.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn medianReduceIgnoresAnOutlier() {
+		// Unlike a plain mean, an outlier shouldn't drag the median far from
+		// the tight cluster of the other samples.
+		let mu = medianReduce(&[10.0, 11.0, 9.0, 10.0, 255.0]);
+		assert!((mu - 10.0).abs() < 1.0, "expected median near 10.0, got {mu}");
+	}
+
+	#[test]
+	fn medianFromHistogramMatchesMedianReduceForOddCounts() {
+		let samples = [5.0, 1.0, 3.0];
+		let mut histogram = [0u32; 256];
+		for &v in &samples {
+			histogram[v as usize] += 1;
+		}
+		assert_eq!(medianFromHistogram(&histogram, samples.len()) as f32, medianReduce(&samples));
+	}
+
+	#[test]
+	fn medianFromHistogramMatchesMedianReduceForEvenCounts() {
+		let samples = [1.0, 10.0, 2.0, 9.0];
+		let mut histogram = [0u32; 256];
+		for &v in &samples {
+			histogram[v as usize] += 1;
+		}
+		// `medianReduce` averages the middle two samples as floats; rounding
+		// that average to the nearest `u8` is what `medianFromHistogram`
+		// (which only ever deals in whole 8-bit buckets) is expected to match.
+		assert_eq!(medianFromHistogram(&histogram, samples.len()), medianReduce(&samples).round() as u8);
+	}
+
+	#[test]
+	fn medianFromHistogramIgnoresAnOutlier() {
+		let samples = [10.0, 11.0, 9.0, 10.0, 255.0];
+		let mut histogram = [0u32; 256];
+		for &v in &samples {
+			histogram[v as usize] += 1;
+		}
+		assert_eq!(medianFromHistogram(&histogram, samples.len()), 10);
+	}
+
+	#[test]
+	fn madRejectReduceRejectsAnOutlier() {
+		// A single wildly bright sample should get excluded from the mean
+		// entirely, unlike sigma-clip which is still influenced by it while
+		// computing the bounds.
+		let mu = madRejectReduce(&[10.0, 11.0, 9.0, 10.0, 255.0], 3.0);
+		assert!((mu - 10.0).abs() < 1.0, "expected mean near 10.0, got {mu}");
+	}
+
+	#[test]
+	fn madRejectReduceFallsBackToMedianWhenEverySampleIsRejected() {
+		// All samples identical: the MAD is zero, so a threshold of zero
+		// rejects everything and the reducer must fall back to the median
+		// instead of dividing by an empty survivor count.
+		let mu = madRejectReduce(&[42.0, 42.0, 42.0], 0.0);
+		assert_eq!(mu, 42.0);
+	}
+
+	#[test]
+	fn madRejectReduceMatchesPlainAverageWithoutOutliers() {
+		let samples = [10.0, 11.0, 9.0, 10.0];
+		let expectedMean = samples.iter().sum::<f32>() / samples.len() as f32;
+		let mu = madRejectReduce(&samples, 3.0);
+		assert!((mu - expectedMean).abs() < 0.01);
+	}
+
+	#[test]
+	fn madRejectReduceDetailedReportsZeroSurvivorsOnTheFallbackPath() {
+		let (_, survivorCount) = madRejectReduceDetailed(&[42.0, 42.0, 42.0], 0.0);
+		assert_eq!(survivorCount, 0);
+	}
+
+	#[test]
+	fn mostFrequentReducePicksThePeakBucket() {
+		let samples = [10.0, 10.0, 10.0, 20.0, 30.0];
+		assert_eq!(mostFrequentReduce(&samples), 10.0);
+	}
+
+	#[test]
+	fn mostFrequentReduceResolvesTiesToTheLowerValue() {
+		let samples = [10.0, 10.0, 20.0, 20.0];
+		assert_eq!(mostFrequentReduce(&samples), 10.0);
+	}
+
+	#[test]
+	fn trimmedMeanReduceRejectsOutliersAtBothEnds() {
+		let mu = trimmedMeanReduce(&[1.0, 10.0, 11.0, 9.0, 10.0, 255.0], 0.2);
+		assert!((mu - 10.0).abs() < 1.0, "expected mean near 10.0, got {mu}");
+	}
+
+	#[test]
+	fn trimmedMeanReduceAtZeroMatchesThePlainAverage() {
+		let samples = [10.0, 20.0, 30.0, 255.0];
+		let expectedMean = samples.iter().sum::<f32>() / samples.len() as f32;
+		assert_eq!(trimmedMeanReduce(&samples, 0.0), expectedMean);
+	}
+
+	#[test]
+	fn trimmedMeanReduceAtHalfMatchesTheMedian() {
+		let oddSamples = [5.0, 1.0, 9.0, 3.0, 7.0];
+		assert_eq!(trimmedMeanReduce(&oddSamples, 0.5), medianReduce(&oddSamples));
+
+		let evenSamples = [5.0, 1.0, 9.0, 3.0];
+		assert_eq!(trimmedMeanReduce(&evenSamples, 0.5), medianReduce(&evenSamples));
+	}
+
+	#[test]
+	fn trimmedMeanReduceDetailedReportsSurvivorsAfterTrimmingBothEnds() {
+		let (_, survivorCount) = trimmedMeanReduceDetailed(&[1.0, 10.0, 11.0, 9.0, 10.0, 255.0], 0.2);
+		assert_eq!(survivorCount, 4);
+	}
+
+	#[test]
+	fn everyStreamingReducerIsIdentityForASingleSample() {
+		// A single input is a degenerate case every streaming/rejection mode
+		// should pass through unchanged, since there's nothing to compare it
+		// against yet.
+		let args = Args::parse_from(["imgstack", "-o", "out.png"]);
+		for mode in [
+			Mode::SigmaClip,
+			Mode::WinsorSigma,
+			Mode::Median,
+			Mode::Percentile,
+			Mode::MadReject,
+			Mode::MostFrequent,
+			Mode::TrimmedMean,
+		] {
+			let reduced = streamingReducer(mode, &args)(&[42.0]);
+			assert_eq!(reduced, 42.0, "{mode:?} was not identity for a single sample");
+		}
+	}
+
+	#[test]
+	fn saveCountMapScalesTheMaxCountTo255() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		let counts = ImageBuffer::from_fn(2, 1, |x, _| Luma([if x == 0 { 4u32 } else { 2u32 }]));
+		saveCountMap(&counts, file.path()).unwrap();
+		let saved = image::open(file.path()).unwrap().into_luma8();
+		assert_eq!(*saved.get_pixel(0, 0), Luma([255]));
+		assert_eq!(*saved.get_pixel(1, 0), Luma([128]));
+	}
+
+	#[test]
+	fn decodedFrameRoundTripsLdrToHdrAndBack() {
+		let ldr = RgbImage::from_pixel(1, 1, Rgb([255, 0, 128]));
+		let hdr = DecodedFrame::Ldr(ldr).intoRgb32f();
+		let Rgb([r, g, b]) = *hdr.get_pixel(0, 0);
+		assert!((r - 1.0).abs() < 0.01);
+		assert!((g - 0.0).abs() < 0.01);
+		assert!((b - 0.5).abs() < 0.01);
+
+		let backToLdr = DecodedFrame::Hdr(hdr).intoRgb8();
+		let Rgb([r, g, b]) = *backToLdr.get_pixel(0, 0);
+		assert_eq!(r, 255);
+		assert_eq!(g, 0);
+		assert!((b as i32 - 128).abs() <= 1);
+	}
+
+	#[test]
+	fn accumulatorFromImagePromotesToF32WhenHdr() {
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([10, 20, 30])));
+		let acc = Accumulator::fromImage(Mode::Sum, frame, true, ColorSpace::Linear, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		assert!(matches!(acc, Accumulator::F32 { .. }));
+	}
+
+	#[test]
+	fn accumulatorFromImagePromotesToF32WhenGammaSet() {
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([10, 20, 30])));
+		let acc = Accumulator::fromImage(Mode::Sum, frame, false, ColorSpace::Linear, 1.0, 1.0, 2.2, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		assert!(matches!(acc, Accumulator::F32 { .. }));
+	}
+
+	#[test]
+	fn gammaRoundTripsExactlyAtOne() {
+		assert_eq!(gammaDecode(0.3, 1.0), 0.3);
+		assert_eq!(gammaEncode(0.3, 1.0), 0.3);
+	}
+
+	#[test]
+	fn sumScaledDoesNotSaturateWhereSumWould() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 200, 200])));
+		let a = Accumulator::fromImage(Mode::SumScaled, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::SumScaled, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let c = Accumulator::fromImage(Mode::SumScaled, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let Accumulator::SumScaled { sum, .. } = Accumulator::combine(Mode::SumScaled, Accumulator::combine(Mode::SumScaled, a, b, REC709_LUMA_COEFFS), c, REC709_LUMA_COEFFS) else {
+			panic!("expected SumScaled accumulator");
+		};
+		assert_eq!(*sum.get_pixel(0, 0), Rgb([600, 600, 600]));
+	}
+
+	#[test]
+	fn sumScaledImageRescalesTheObservedMaxTo255() {
+		let sum = ImageBuffer::from_fn(2, 1, |x, _| if x == 0 { Rgb([600u32, 600, 600]) } else { Rgb([300, 300, 300]) });
+		let img = sumScaledImage(&sum, None);
+		assert_eq!(*img.get_pixel(0, 0), Rgb([255, 255, 255]));
+		assert_eq!(*img.get_pixel(1, 0), Rgb([128, 128, 128]));
+	}
+
+	#[test]
+	fn sumScaledImageHonorsAFixedDivisor() {
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([510u32, 510, 510]));
+		let img = sumScaledImage(&sum, Some(1020));
+		assert_eq!(*img.get_pixel(0, 0), Rgb([128, 128, 128]));
+	}
+
+	#[test]
+	fn sumScaledImageLeavesAnAllBlackSumAtZero() {
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([0u32, 0, 0]));
+		let img = sumScaledImage(&sum, None);
+		assert_eq!(*img.get_pixel(0, 0), Rgb([0, 0, 0]));
+	}
+
+	#[test]
+	fn sumOverflowImageKeepsOnlyTheLowByteOfEachChannel() {
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([300u32, 256, 255]));
+		let img = sumOverflowImage(&sum);
+		assert_eq!(*img.get_pixel(0, 0), Rgb([44, 0, 255]));
+	}
+
+	#[test]
+	fn sumRawImageWithoutAShiftPreservesTheExactSum() {
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([100_000u32, 0, 65_535]));
+		let img = sumRawImage(&sum, None);
+		assert_eq!(*img.get_pixel(0, 0), Rgb([100_000.0, 0.0, 65_535.0]));
+	}
+
+	#[test]
+	fn sumRawImageWithAShiftRoundTripsThroughThe16BitConvertPath() {
+		// `saveOutput`'s existing HDR-to-16-bit path multiplies an f32 sample
+		// by `u16::MAX` to get its `u16`; `sumRawImage` pre-divides by the same
+		// constant so that round-trip reconstructs the shifted integer exactly.
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([260_000u32, 4, 0]));
+		let img = sumRawImage(&sum, Some(4));
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert_eq!((r * u16::MAX as f32).round() as u32, 260_000 >> 4);
+		assert_eq!((g * u16::MAX as f32).round() as u32, 4 >> 4);
+		assert_eq!((b * u16::MAX as f32).round() as u32, 0);
+	}
+
+	#[test]
+	fn sumRawAccumulatorMatchesRepeatedAddition() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 200, 200])));
+		let a = Accumulator::fromImage(Mode::SumRaw, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::SumRaw, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let c = Accumulator::fromImage(Mode::SumRaw, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let Accumulator::SumRaw { sum, .. } = Accumulator::combine(Mode::SumRaw, Accumulator::combine(Mode::SumRaw, a, b, REC709_LUMA_COEFFS), c, REC709_LUMA_COEFFS) else {
+			panic!("expected SumRaw accumulator");
+		};
+		assert_eq!(*sum.get_pixel(0, 0), Rgb([600, 600, 600]));
+	}
+
+	#[test]
+	fn csvFieldLeavesPlainTextBare() {
+		assert_eq!(csvField("frame001.png"), "frame001.png");
+	}
+
+	#[test]
+	fn csvFieldQuotesAndEscapesFieldsThatNeedIt() {
+		assert_eq!(csvField("frame,001.png"), "\"frame,001.png\"");
+		assert_eq!(csvField("frame\"001\".png"), "\"frame\"\"001\"\".png\"");
+	}
+
+	#[test]
+	fn formatLogRowRendersAKeptEntry() {
+		let entry = LogEntry {
+			path: PathBuf::from("frame001.png"),
+			dimensions: Some((1920, 1080)),
+			format: "Png".to_string(),
+			exposureTime: Some(0.5),
+			weight: 1.0,
+			gain: 1.0,
+			status: "kept".to_string(),
+		};
+		assert_eq!(formatLogRow(&entry), "frame001.png,1920x1080,Png,0.5,1,1,kept");
+	}
+
+	#[test]
+	fn formatLogRowLeavesMissingResolutionAndExposureTimeBlank() {
+		let entry = LogEntry {
+			path: PathBuf::from("bad.png"),
+			dimensions: None,
+			format: "Png".to_string(),
+			exposureTime: None,
+			weight: 1.0,
+			gain: 1.0,
+			status: "skipped: decode failed".to_string(),
+		};
+		assert_eq!(formatLogRow(&entry), "bad.png,,Png,,1,1,\"skipped: decode failed\"");
+	}
+
+	#[test]
+	fn guessInputFormatRecognizesImagesAndVideosAndFallsBackToUnknown() {
+		assert_eq!(guessInputFormat(Path::new("frame.png")), "Png");
+		assert_eq!(guessInputFormat(Path::new("clip.mp4")), "video");
+		assert_eq!(guessInputFormat(Path::new("frame.noext")), "unknown");
+	}
+
+	#[test]
+	fn sumOverflowAccumulatorMatchesRepeatedWrappingAdd() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 200, 200])));
+		let a = Accumulator::fromImage(Mode::SumOverflow, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::SumOverflow, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::SumOverflow, a, b, REC709_LUMA_COEFFS);
+		let DecodedFrame::Ldr(img) = combined.intoOutput() else {
+			panic!("expected Ldr output");
+		};
+		let expected = 200u8.wrapping_add(200);
+		assert_eq!(*img.get_pixel(0, 0), Rgb([expected, expected, expected]));
+	}
+
+	#[test]
+	fn saveOverflowMapRecordsTheDiscardedHighBits() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		let sum = ImageBuffer::from_fn(1, 1, |_, _| Rgb([600u32, 256, 100]));
+		saveOverflowMap(&sum, file.path()).unwrap();
+		let saved = image::open(file.path()).unwrap().into_rgb8();
+		assert_eq!(*saved.get_pixel(0, 0), Rgb([2, 1, 0]));
+	}
+
+	#[test]
+	fn checkpointRoundTripsAnAverageAccumulator() {
+		let file = tempfile::Builder::new().tempfile().unwrap();
+		let mean = Rgb32FImage::from_pixel(2, 1, Rgb([0.25, 0.5, 0.75]));
+		let acc = Accumulator::Average { mean, weight: 3.0, colorSpace: ColorSpace::Linear, gamma: 2.2 };
+		saveCheckpoint(file.path(), Mode::Average, 3, &acc).unwrap();
+		let (count, loaded) = loadCheckpoint(file.path(), Mode::Average, (2, 1)).unwrap();
+		assert_eq!(count, 3);
+		let Accumulator::Average { mean, weight, colorSpace, gamma } = loaded else { panic!("expected Average accumulator") };
+		assert_eq!(*mean.get_pixel(0, 0), Rgb([0.25, 0.5, 0.75]));
+		assert_eq!(weight, 3.0);
+		assert_eq!(colorSpace, ColorSpace::Linear);
+		assert_eq!(gamma, 2.2);
+	}
+
+	#[test]
+	fn loadCheckpointRejectsAModeMismatch() {
+		let file = tempfile::Builder::new().tempfile().unwrap();
+		let acc = Accumulator::Rms { sumSq: Rgb32FImage::from_pixel(1, 1, Rgb([1.0, 1.0, 1.0])), count: 2 };
+		saveCheckpoint(file.path(), Mode::Rms, 2, &acc).unwrap();
+		assert!(loadCheckpoint(file.path(), Mode::Average, (1, 1)).is_err());
+	}
+
+	#[test]
+	fn loadCheckpointRejectsADimensionMismatch() {
+		let file = tempfile::Builder::new().tempfile().unwrap();
+		let acc = Accumulator::Range { min: Rgb32FImage::new(2, 2), max: Rgb32FImage::new(2, 2) };
+		saveCheckpoint(file.path(), Mode::Range, 1, &acc).unwrap();
+		assert!(loadCheckpoint(file.path(), Mode::Range, (3, 3)).is_err());
+	}
+
+	#[test]
+	fn orientationSwapsDimensionsOnlyForTheFourRotateTransposeVariants() {
+		for orientation in 1..=8u8 {
+			let expectSwap = matches!(orientation, 5..=8);
+			assert_eq!(orientationSwapsDimensions(orientation), expectSwap, "orientation {orientation}");
+		}
+	}
+
+	#[test]
+	fn gammaEncodeUndoesGammaDecode() {
+		let v = 0.4f32;
+		let roundTripped = gammaEncode(gammaDecode(v, 2.2), 2.2);
+		assert!((roundTripped - v).abs() < 1e-5, "expected {v}, got {roundTripped}");
+	}
+
+	#[test]
+	fn combineU8BuffersSaturatesOnSumAndTakesElementwiseMinMax() {
+		let mut sum = RgbImage::from_pixel(1, 1, Rgb([200, 10, 250]));
+		combineU8Buffers(&mut sum, &RgbImage::from_pixel(1, 1, Rgb([100, 20, 10])), Mode::Sum);
+		assert_eq!(*sum.get_pixel(0, 0), Rgb([255, 30, 255]));
+
+		let mut min = RgbImage::from_pixel(1, 1, Rgb([200, 10, 250]));
+		combineU8Buffers(&mut min, &RgbImage::from_pixel(1, 1, Rgb([100, 20, 10])), Mode::Min);
+		assert_eq!(*min.get_pixel(0, 0), Rgb([100, 10, 10]));
+
+		let mut max = RgbImage::from_pixel(1, 1, Rgb([200, 10, 250]));
+		combineU8Buffers(&mut max, &RgbImage::from_pixel(1, 1, Rgb([100, 20, 10])), Mode::Max);
+		assert_eq!(*max.get_pixel(0, 0), Rgb([200, 20, 250]));
+	}
+
+	#[test]
+	fn accumulatorCombineF32TakesPerChannelMax() {
+		let a = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.2, 0.8, 0.1])), gamma: 1.0 };
+		let b = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.3, 0.9])), gamma: 1.0 };
+		let combined = Accumulator::combine(Mode::Max, a, b, REC709_LUMA_COEFFS);
+		let Accumulator::F32 { img, .. } = combined else {
+			panic!("expected F32 accumulator");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert_eq!((r, g, b), (0.5, 0.8, 0.9));
+	}
+
+	#[test]
+	fn parallelTreeReduceMatchesSequentialFoldForSum() {
+		// `Sum` is associative and commutative, so `reduce_with`'s arbitrary
+		// pairing order (used for the real parallel path in `main`) must land
+		// on the same result as folding strictly left-to-right.
+		let pixels = [10u8, 20, 30, 200, 90, 5, 60, 15];
+		let accumulators = || {
+			pixels
+				.iter()
+				.map(|&v| Accumulator::U8(RgbImage::from_pixel(1, 1, Rgb([v, v, v]))))
+				.collect::<Vec<_>>()
+		};
+
+		let sequential = accumulators()
+			.into_iter()
+			.reduce(|a, b| Accumulator::combine(Mode::Sum, a, b, REC709_LUMA_COEFFS))
+			.unwrap();
+		let parallel = accumulators()
+			.into_par_iter()
+			.reduce_with(|a, b| Accumulator::combine(Mode::Sum, a, b, REC709_LUMA_COEFFS))
+			.unwrap();
+
+		let (Accumulator::U8(sequential), Accumulator::U8(parallel)) = (sequential, parallel) else {
+			panic!("expected U8 accumulators");
+		};
+		assert_eq!(sequential.get_pixel(0, 0), parallel.get_pixel(0, 0));
+	}
+
+	#[test]
+	fn parallelTreeReduceMatchesSequentialFoldForMin() {
+		let pixels = [10u8, 20, 30, 200, 90, 5, 60, 15];
+		let accumulators = || {
+			pixels
+				.iter()
+				.map(|&v| Accumulator::U8(RgbImage::from_pixel(1, 1, Rgb([v, v, v]))))
+				.collect::<Vec<_>>()
+		};
+
+		let sequential = accumulators().into_iter().reduce(|a, b| Accumulator::combine(Mode::Min, a, b, REC709_LUMA_COEFFS)).unwrap();
+		let parallel = accumulators().into_par_iter().reduce_with(|a, b| Accumulator::combine(Mode::Min, a, b, REC709_LUMA_COEFFS)).unwrap();
+
+		let (Accumulator::U8(sequential), Accumulator::U8(parallel)) = (sequential, parallel) else {
+			panic!("expected U8 accumulators");
+		};
+		assert_eq!(sequential.get_pixel(0, 0), parallel.get_pixel(0, 0));
+	}
+
+	#[test]
+	fn parallelTreeReduceMatchesSequentialFoldForMax() {
+		let pixels = [10u8, 20, 30, 200, 90, 5, 60, 15];
+		let accumulators = || {
+			pixels
+				.iter()
+				.map(|&v| Accumulator::U8(RgbImage::from_pixel(1, 1, Rgb([v, v, v]))))
+				.collect::<Vec<_>>()
+		};
+
+		let sequential = accumulators().into_iter().reduce(|a, b| Accumulator::combine(Mode::Max, a, b, REC709_LUMA_COEFFS)).unwrap();
+		let parallel = accumulators().into_par_iter().reduce_with(|a, b| Accumulator::combine(Mode::Max, a, b, REC709_LUMA_COEFFS)).unwrap();
+
+		let (Accumulator::U8(sequential), Accumulator::U8(parallel)) = (sequential, parallel) else {
+			panic!("expected U8 accumulators");
+		};
+		assert_eq!(sequential.get_pixel(0, 0), parallel.get_pixel(0, 0));
+	}
+
+	#[test]
+	fn parallelTreeReduceMatchesSequentialFoldForSumOverflow() {
+		// Wrapping addition is still associative and commutative even though
+		// it overflows here (8 * 200 = 1600, well past 255), so the parallel
+		// tree reduction must land on the same wrapped result as a
+		// sequential fold.
+		let pixels = [200u8; 8];
+		let accumulators = || {
+			pixels
+				.iter()
+				.map(|&v| {
+					Accumulator::fromImage(
+						Mode::SumOverflow,
+						DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([v, v, v]))),
+						false,
+						ColorSpace::Srgb,
+						1.0,
+						1.0,
+						1.0,
+						None,
+						None,
+						1.0 / 255.0,
+						1.0 / 255.0,
+					, AccumPrecision::F32)
+				})
+				.collect::<Vec<_>>()
+		};
+
+		let sequential = accumulators().into_iter().reduce(|a, b| Accumulator::combine(Mode::SumOverflow, a, b, REC709_LUMA_COEFFS)).unwrap();
+		let parallel = accumulators().into_par_iter().reduce_with(|a, b| Accumulator::combine(Mode::SumOverflow, a, b, REC709_LUMA_COEFFS)).unwrap();
+
+		let (Accumulator::SumOverflow { sum: sequential }, Accumulator::SumOverflow { sum: parallel }) = (sequential, parallel) else {
+			panic!("expected SumOverflow accumulators");
+		};
+		assert_eq!(sequential.get_pixel(0, 0), parallel.get_pixel(0, 0));
+	}
+
+	#[test]
+	fn averagingBlackAndWhiteInLinearIsBrighterThanInSrgb() {
+		let black = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let white = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+
+		let averageWith = |colorSpace| {
+			let a = Accumulator::fromImage(Mode::Average, black(), false, colorSpace, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+			let b = Accumulator::fromImage(Mode::Average, white(), false, colorSpace, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+			let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+				panic!("expected Hdr output");
+			};
+			img.get_pixel(0, 0).0[0]
+		};
+
+		let srgbAverage = averageWith(ColorSpace::Srgb);
+		let linearAverage = averageWith(ColorSpace::Linear);
+		// Naively averaging gamma-encoded bytes gives 0.5 directly; averaging
+		// in linear light and re-encoding gives a visibly brighter midpoint,
+		// since sRGB's gamma curve compresses bright values.
+		assert!((srgbAverage - 0.5).abs() < 0.01, "expected sRGB average near 0.5, got {srgbAverage}");
+		assert!(linearAverage > srgbAverage + 0.1, "expected linear average visibly brighter, got {linearAverage}");
+	}
+
+	#[test]
+	fn weightedAveragePullsResultTowardHeavierFrame() {
+		let dim = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+
+		let a = Accumulator::fromImage(Mode::Average, dim, false, ColorSpace::Srgb, 3.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Average, bright, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let value = img.get_pixel(0, 0).0[0];
+		// Weighted 3:1 toward the dim frame should land at 0.25, not the
+		// unweighted midpoint of 0.5.
+		assert!((value - 0.25).abs() < 0.01, "expected weighted average near 0.25, got {value}");
+	}
+
+	#[test]
+	fn averageOfManyFramesMatchesTheMeanRegardlessOfMergeOrder() {
+		// Welford's running mean should agree with a plain sum/count average
+		// whether frames are folded left-to-right or reduced pairwise (as
+		// rayon's parallel tree reduce does), even over a few hundred frames
+		// where a naive f32 sum would start accumulating rounding error.
+		let frame = |v: u8| DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([v, v, v])));
+		let makeAccumulators = || {
+			(0u8..=199).map(|i| Accumulator::fromImage(Mode::Average, frame(i), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32))
+		};
+
+		let sequential = makeAccumulators().reduce(|a, b| Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS)).unwrap();
+		let paired = makeAccumulators().reduce(|a, b| Accumulator::combine(Mode::Average, b, a, REC709_LUMA_COEFFS)).unwrap();
+
+		let DecodedFrame::Hdr(sequentialImg) = sequential.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let DecodedFrame::Hdr(pairedImg) = paired.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = (0..=199).sum::<u32>() as f32 / 200.0 / 255.0;
+		let sequentialValue = sequentialImg.get_pixel(0, 0).0[0];
+		let pairedValue = pairedImg.get_pixel(0, 0).0[0];
+		assert!((sequentialValue - expected).abs() < 1e-4, "expected {expected}, got {sequentialValue}");
+		assert!((pairedValue - expected).abs() < 1e-4, "expected {expected}, got {pairedValue}");
+	}
+
+	#[test]
+	fn accumPrecisionF64MatchesF32ForAWeightedAverage() {
+		let dim = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+
+		let a = Accumulator::fromImage(Mode::Average, dim.clone(), false, ColorSpace::Srgb, 3.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F64);
+		let b = Accumulator::fromImage(Mode::Average, bright.clone(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F64);
+		assert!(matches!(a, Accumulator::AverageF64 { .. }));
+		let DecodedFrame::Hdr(f64Result) = Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+
+		let a = Accumulator::fromImage(Mode::Average, dim, false, ColorSpace::Srgb, 3.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Average, bright, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(f32Result) = Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+
+		let (f64Value, f32Value) = (f64Result.get_pixel(0, 0).0[0], f32Result.get_pixel(0, 0).0[0]);
+		assert!((f64Value - f32Value).abs() < 1e-4, "expected f64 and f32 accumulation to agree closely, got {f64Value} vs {f32Value}");
+	}
+
+	#[test]
+	fn stdDevOfConstantFramesIsZero() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 120, 120])));
+		let a = Accumulator::fromImage(Mode::StdDev, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::StdDev, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let c = Accumulator::fromImage(Mode::StdDev, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::StdDev, Accumulator::combine(Mode::StdDev, a, b, REC709_LUMA_COEFFS), c, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert_eq!((r, g, b), (0.0, 0.0, 0.0));
+	}
+
+	#[test]
+	fn rangeOfConstantFramesIsZero() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 120, 120])));
+		let a = Accumulator::fromImage(Mode::Range, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Range, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::Range, a, b, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert_eq!((r, g, b), (0.0, 0.0, 0.0));
+	}
+
+	#[test]
+	fn rangeTracksTheSpreadBetweenTheDarkestAndBrightestFrame() {
+		let dark = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([40, 40, 40])));
+		let mid = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 120, 120])));
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 200, 200])));
+		let a = Accumulator::fromImage(Mode::Range, dark, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Range, mid, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let c = Accumulator::fromImage(Mode::Range, bright, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::Range, Accumulator::combine(Mode::Range, a, b, REC709_LUMA_COEFFS), c, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = (200.0 - 40.0) / 255.0;
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!((r - expected).abs() < 0.001 && (g - expected).abs() < 0.001 && (b - expected).abs() < 0.001, "expected {expected}, got {r} {g} {b}");
+	}
+
+	#[test]
+	fn rmsOfIdenticalFramesEqualsThatFramesValues() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60])));
+		let a = Accumulator::fromImage(Mode::Rms, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Rms, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let c = Accumulator::fromImage(Mode::Rms, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::Rms, Accumulator::combine(Mode::Rms, a, b, REC709_LUMA_COEFFS), c, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60]))).intoRgb32f();
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		let Rgb([er, eg, eb]) = *expected.get_pixel(0, 0);
+		assert!((r - er).abs() < 0.001 && (g - eg).abs() < 0.001 && (b - eb).abs() < 0.001, "expected {expected:?} got {img:?}");
+	}
+
+	#[test]
+	fn geometricMeanOfIdenticalFramesEqualsThatFramesValues() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60])));
+		let a = Accumulator::fromImage(Mode::GeometricMean, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::GeometricMean, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::GeometricMean, a, b, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60]))).intoRgb32f();
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		let Rgb([er, eg, eb]) = *expected.get_pixel(0, 0);
+		assert!((r - er).abs() < 0.001 && (g - eg).abs() < 0.001 && (b - eb).abs() < 0.001, "expected {expected:?} got {img:?}");
+	}
+
+	#[test]
+	fn geometricMeanOfAZeroSampleIsFlooredByEpsilonRatherThanNegativeInfinity() {
+		let zero = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let acc = Accumulator::fromImage(Mode::GeometricMean, zero, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = acc.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!(r.is_finite() && g.is_finite() && b.is_finite(), "expected finite output, got {r} {g} {b}");
+	}
+
+	#[test]
+	fn harmonicMeanOfIdenticalFramesEqualsThatFramesValues() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60])));
+		let a = Accumulator::fromImage(Mode::HarmonicMean, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::HarmonicMean, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let combined = Accumulator::combine(Mode::HarmonicMean, a, b, REC709_LUMA_COEFFS);
+		let DecodedFrame::Hdr(img) = combined.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 180, 60]))).intoRgb32f();
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		let Rgb([er, eg, eb]) = *expected.get_pixel(0, 0);
+		assert!((r - er).abs() < 0.001 && (g - eg).abs() < 0.001 && (b - eb).abs() < 0.001, "expected {expected:?} got {img:?}");
+	}
+
+	#[test]
+	fn harmonicMeanOfAZeroSampleIsFlooredByEpsilonRatherThanInfinity() {
+		let zero = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let acc = Accumulator::fromImage(Mode::HarmonicMean, zero, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = acc.intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!(r.is_finite() && g.is_finite() && b.is_finite(), "expected finite output, got {r} {g} {b}");
+	}
+
+	#[test]
+	fn lightenLumaKeepsTheWholePixelFromTheBrighterFrameRatherThanMixingChannels() {
+		let a = Accumulator::fromImage(Mode::LightenLuma, DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 200]))), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::LightenLuma, DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 0, 0]))), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Ldr(img) = Accumulator::combine(Mode::LightenLuma, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Ldr output");
+		};
+		// A per-channel Max would produce [200, 0, 200]; LightenLuma must keep
+		// one frame's pixel whole. The red frame has higher luminance.
+		assert_eq!(*img.get_pixel(0, 0), Rgb([200, 0, 0]));
+	}
+
+	#[test]
+	fn darkenLumaKeepsTheWholePixelFromTheDimmerFrame() {
+		let a = Accumulator::fromImage(Mode::DarkenLuma, DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 200]))), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::DarkenLuma, DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 0, 0]))), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Ldr(img) = Accumulator::combine(Mode::DarkenLuma, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*img.get_pixel(0, 0), Rgb([0, 0, 200]));
+	}
+
+	#[test]
+	fn screeningAgainstBlackIsANoOp() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 60, 200])));
+		let black = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let a = Accumulator::fromImage(Mode::Screen, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Screen, black(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Screen, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = frame().intoRgb32f();
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		let Rgb([er, eg, eb]) = *expected.get_pixel(0, 0);
+		assert!((r - er).abs() < 0.001 && (g - eg).abs() < 0.001 && (b - eb).abs() < 0.001, "expected {expected:?} got {img:?}");
+	}
+
+	#[test]
+	fn multiplyingByWhiteIsANoOp() {
+		let frame = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([120, 60, 200])));
+		let white = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+		let a = Accumulator::fromImage(Mode::Multiply, frame(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Multiply, white(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Multiply, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let expected = frame().intoRgb32f();
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		let Rgb([er, eg, eb]) = *expected.get_pixel(0, 0);
+		assert!((r - er).abs() < 0.001 && (g - eg).abs() < 0.001 && (b - eb).abs() < 0.001, "expected {expected:?} got {img:?}");
+	}
+
+	#[test]
+	fn overlayBlendingAgainstMidGrayIsANoOp() {
+		let base = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.47, 0.24, 0.78])), gamma: 1.0 };
+		let gray = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.5, 0.5])), gamma: 1.0 };
+		let Accumulator::F32 { img, .. } = Accumulator::combine(Mode::Overlay, base, gray, REC709_LUMA_COEFFS) else {
+			panic!("expected F32 accumulator");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!((r - 0.47).abs() < 0.001 && (g - 0.24).abs() < 0.001 && (b - 0.78).abs() < 0.001, "expected the base unchanged, got {img:?}");
+	}
+
+	#[test]
+	fn softLightBlendingAgainstMidGrayIsANoOp() {
+		let base = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.47, 0.24, 0.78])), gamma: 1.0 };
+		let gray = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.5, 0.5])), gamma: 1.0 };
+		let Accumulator::F32 { img, .. } = Accumulator::combine(Mode::SoftLight, base, gray, REC709_LUMA_COEFFS) else {
+			panic!("expected F32 accumulator");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!((r - 0.47).abs() < 0.001 && (g - 0.24).abs() < 0.001 && (b - 0.78).abs() < 0.001, "expected the base unchanged, got {img:?}");
+	}
+
+	#[test]
+	fn overlayDarkensBelowMidGrayAndLightensAboveIt() {
+		// Below 50% gray, `Overlay`'s blend layer darkens the base like
+		// `Multiply` would; above it, the blend layer lightens the base like
+		// `Screen` would. This holds in both the dark-base and light-base
+		// branches of the formula.
+		let base = || Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.7, 0.7, 0.7])), gamma: 1.0 };
+		let darkBlend = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.1, 0.1, 0.1])), gamma: 1.0 };
+		let lightBlend = Accumulator::F32 { img: Rgb32FImage::from_pixel(1, 1, Rgb([0.9, 0.9, 0.9])), gamma: 1.0 };
+		let Accumulator::F32 { img: darkOut, .. } = Accumulator::combine(Mode::Overlay, base(), darkBlend, REC709_LUMA_COEFFS) else {
+			panic!("expected F32 accumulator");
+		};
+		let Accumulator::F32 { img: lightOut, .. } = Accumulator::combine(Mode::Overlay, base(), lightBlend, REC709_LUMA_COEFFS) else {
+			panic!("expected F32 accumulator");
+		};
+		assert!(darkOut.get_pixel(0, 0).0[0] < 0.7, "expected a dark blend layer to darken the base, got {darkOut:?}");
+		assert!(lightOut.get_pixel(0, 0).0[0] > 0.7, "expected a light blend layer to lighten the base, got {lightOut:?}");
+	}
+
+	#[test]
+	fn absoluteDifferenceIsSymmetricPerChannel() {
+		let base = Rgb32FImage::from_pixel(1, 1, Rgb([0.2, 0.8, 0.5]));
+		let frame = Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.3, 0.5]));
+		let diff = absoluteDifference(frame, &base);
+		let Rgb([r, g, b]) = *diff.get_pixel(0, 0);
+		assert!((r - 0.3).abs() < 1e-6);
+		assert!((g - 0.5).abs() < 1e-6);
+		assert!((b - 0.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn blendFramesAtZeroOpacityIsEntirelyTheFirstFrame() {
+		let a = Rgb32FImage::from_pixel(1, 1, Rgb([0.2, 0.4, 0.6]));
+		let b = Rgb32FImage::from_pixel(1, 1, Rgb([0.8, 0.8, 0.8]));
+		let blended = blendFrames(a, &b, 0.0);
+		let Rgb([r, g, b]) = *blended.get_pixel(0, 0);
+		assert!((r - 0.2).abs() < 1e-6);
+		assert!((g - 0.4).abs() < 1e-6);
+		assert!((b - 0.6).abs() < 1e-6);
+	}
+
+	#[test]
+	fn blendFramesAtHalfOpacityAveragesTheTwoFrames() {
+		let a = Rgb32FImage::from_pixel(1, 1, Rgb([0.2, 0.4, 0.6]));
+		let b = Rgb32FImage::from_pixel(1, 1, Rgb([0.8, 0.8, 0.8]));
+		let blended = blendFrames(a, &b, 0.5);
+		let Rgb([r, g, b]) = *blended.get_pixel(0, 0);
+		assert!((r - 0.5).abs() < 1e-6);
+		assert!((g - 0.6).abs() < 1e-6);
+		assert!((b - 0.7).abs() < 1e-6);
+	}
+
+	#[test]
+	fn subtractBackgroundRemovesTheCommonMinimumFromEveryFrame() {
+		let dim = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([40, 40, 40])));
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([40, 90, 40])));
+		let result = subtractBackground(vec![dim, bright]);
+		let Rgb([r0, g0, b0]) = *result[0].clone().intoRgb8().get_pixel(0, 0);
+		let Rgb([r1, g1, b1]) = *result[1].clone().intoRgb8().get_pixel(0, 0);
+		assert_eq!([r0, g0, b0], [0, 0, 0]);
+		assert_eq!([r1, g1, b1], [0, 50, 0]);
+	}
+
+	#[test]
+	fn ditherNoneTruncateMatchesPlainConversion() {
+		let img = Rgb32FImage::from_pixel(2, 2, Rgb([0.5, 0.25, 0.75]));
+		let dithered = ditherToRgb8(&img, Dither::None, Rounding::Truncate);
+		let plain: RgbImage = img.convert();
+		assert_eq!(dithered, plain);
+	}
+
+	#[test]
+	fn averagingZeroAndOneRoundsUpInsteadOfTruncating() {
+		let zero = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let one = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([1, 1, 1])));
+		let a = Accumulator::fromImage(Mode::Average, zero, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Average, one, false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Average, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let truncated = ditherToRgb8(&img, Dither::None, Rounding::Truncate);
+		let rounded = ditherToRgb8(&img, Dither::None, Rounding::Round);
+		assert_eq!(*truncated.get_pixel(0, 0), Rgb([0, 0, 0]));
+		assert_eq!(*rounded.get_pixel(0, 0), Rgb([1, 1, 1]));
+	}
+
+	#[test]
+	fn fadeWeightsFramesByPositionSoTheLastFrameDominates() {
+		let black = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let white = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+		// Frame 0 (black) weighted 1, frame 1 (white) weighted 2: the result
+		// should sit at 2/3 of the way from black to white, not the midpoint.
+		let a = Accumulator::fromImage(Mode::Fade, black(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Fade, white(), false, ColorSpace::Srgb, 2.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Fade, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		assert!((r - 2.0 / 3.0).abs() < 0.01 && (g - 2.0 / 3.0).abs() < 0.01 && (b - 2.0 / 3.0).abs() < 0.01, "expected ~2/3, got {r} {g} {b}");
+	}
+
+	#[test]
+	fn cometDecaysAnOlderFramesContributionToTheTrailButNotToTheMean() {
+		let white = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+		let dark = || DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([51, 51, 51])));
+		// Frame 0 (white) is older, so its `weight` (the caller-computed
+		// `--comet-decay` falloff, not a mean weight) is heavily decayed to
+		// 0.1; frame 1 (dark, at 0.2) is the most recent, undecayed (1.0).
+		// The trail should end up dominated by frame 1's *undecayed* value
+		// (0.2) rather than frame 0's raw brightness (1.0), while the mean
+		// still averages both frames at full weight, unaffected by decay.
+		let a = Accumulator::fromImage(Mode::Comet, white(), false, ColorSpace::Srgb, 0.1, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let b = Accumulator::fromImage(Mode::Comet, dark(), false, ColorSpace::Srgb, 1.0, 1.0, 1.0, None, None, 1.0 / 255.0, 1.0 / 255.0, AccumPrecision::F32);
+		let DecodedFrame::Hdr(img) = Accumulator::combine(Mode::Comet, a, b, REC709_LUMA_COEFFS).intoOutput() else {
+			panic!("expected Hdr output");
+		};
+		let Rgb([r, g, b]) = *img.get_pixel(0, 0);
+		// mean = (1.0 + 0.2) / 2 = 0.6, trail = max(1.0 * 0.1, 0.2 * 1.0) = 0.2, blended = max(0.6, 0.2) = 0.6.
+		assert!((r - 0.6).abs() < 0.01 && (g - 0.6).abs() < 0.01 && (b - 0.6).abs() < 0.01, "expected ~0.6, got {r} {g} {b}");
+	}
+
+	#[test]
+	fn bayerDitherStaysCloseToTheUnditheredValue() {
+		let img = Rgb32FImage::from_pixel(4, 4, Rgb([0.502, 0.502, 0.502]));
+		let dithered = ditherToRgb8(&img, Dither::Bayer, Rounding::Round);
+		for pixel in dithered.pixels() {
+			for &channel in pixel.0.iter() {
+				assert!((channel as i32 - 128).abs() <= 8, "expected near 128, got {channel}");
+			}
+		}
+	}
+
+	#[test]
+	fn floydSteinbergDitherPreservesAverageBrightness() {
+		let img = Rgb32FImage::from_pixel(8, 8, Rgb([0.3, 0.3, 0.3]));
+		let dithered = floydSteinbergDither(&img);
+		let sum: u64 = dithered.pixels().map(|p| p.0[0] as u64).sum();
+		let mean = sum as f64 / (8 * 8) as f64;
+		assert!((mean - 0.3 * 255.0).abs() < 1.0, "expected mean near {}, got {mean}", 0.3 * 255.0);
+	}
+
+	#[test]
+	fn normalizeFrameStretchesGlobalRangeToFill() {
+		let mut img = RgbImage::from_pixel(2, 1, Rgb([50, 50, 50]));
+		img.put_pixel(1, 0, Rgb([100, 100, 100]));
+		let DecodedFrame::Ldr(result) = normalizeFrame(DecodedFrame::Ldr(img), NormalizeMode::Global) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([0, 0, 0]));
+		assert_eq!(*result.get_pixel(1, 0), Rgb([255, 255, 255]));
+	}
+
+	#[test]
+	fn normalizeFramePerChannelStretchesEachChannelIndependently() {
+		let mut img = RgbImage::from_pixel(2, 1, Rgb([0, 100, 0]));
+		img.put_pixel(1, 0, Rgb([50, 200, 0]));
+		let DecodedFrame::Ldr(result) = normalizeFrame(DecodedFrame::Ldr(img), NormalizeMode::PerChannel) else {
+			panic!("expected Ldr output");
+		};
+		let Rgb([r, g, b]) = *result.get_pixel(0, 0);
+		assert_eq!((r, g), (0, 0));
+		assert_eq!(b, 0);
+		let Rgb([r, g, _]) = *result.get_pixel(1, 0);
+		assert_eq!((r, g), (255, 255));
+	}
+
+	#[test]
+	fn normalizeFrameLeavesAFlatImageUntouched() {
+		let img = RgbImage::from_pixel(2, 1, Rgb([80, 80, 80]));
+		let DecodedFrame::Ldr(result) = normalizeFrame(DecodedFrame::Ldr(img.clone()), NormalizeMode::Global) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(result, img);
+	}
+
+	#[test]
+	fn whiteBalanceParsesAutoCaseInsensitively() {
+		assert_eq!("auto".parse::<WhiteBalance>().unwrap(), WhiteBalance::Auto);
+		assert_eq!("AUTO".parse::<WhiteBalance>().unwrap(), WhiteBalance::Auto);
+	}
+
+	#[test]
+	fn whiteBalanceParsesAnRgbTriple() {
+		assert_eq!("1.5,1.0,0.5".parse::<WhiteBalance>().unwrap(), WhiteBalance::Manual(1.5, 1.0, 0.5));
+	}
+
+	#[test]
+	fn whiteBalanceRejectsAMalformedTriple() {
+		assert!("1.0,2.0".parse::<WhiteBalance>().is_err());
+		assert!("nope".parse::<WhiteBalance>().is_err());
+	}
+
+	#[test]
+	fn whiteBalanceFrameManualMultipliesEachChannelByItsFactor() {
+		let img = RgbImage::from_pixel(1, 1, Rgb([102, 102, 102]));
+		let DecodedFrame::Ldr(result) = whiteBalanceFrame(DecodedFrame::Ldr(img), WhiteBalance::Manual(2.5, 1.0, 0.5)) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([255, 102, 51]));
+	}
+
+	#[test]
+	fn whiteBalanceFrameAutoEqualizesChannelMeans() {
+		let img = RgbImage::from_pixel(1, 1, Rgb([100, 50, 25]));
+		let DecodedFrame::Ldr(result) = whiteBalanceFrame(DecodedFrame::Ldr(img), WhiteBalance::Auto) else {
+			panic!("expected Ldr output");
+		};
+		let Rgb([r, g, b]) = *result.get_pixel(0, 0);
+		assert_eq!(r, g);
+		assert_eq!(g, b);
+	}
+
+	#[test]
+	fn sampleRangeParsesNormalizedFractions() {
+		assert_eq!("0.1,0.9".parse::<SampleRange>().unwrap(), SampleRange { lo: 0.1, hi: 0.9 });
+	}
+
+	#[test]
+	fn sampleRangeParsesBytesAsAFractionOf255() {
+		let range = "10,240".parse::<SampleRange>().unwrap();
+		assert!((range.lo - 10.0 / 255.0).abs() < 0.001);
+		assert!((range.hi - 240.0 / 255.0).abs() < 0.001);
+	}
+
+	#[test]
+	fn sampleRangeRejectsLoGreaterThanHi() {
+		assert!("0.9,0.1".parse::<SampleRange>().is_err());
+	}
+
+	#[test]
+	fn sampleRangeRejectsAMalformedPair() {
+		assert!("0.5".parse::<SampleRange>().is_err());
+		assert!("nope,0.5".parse::<SampleRange>().is_err());
+	}
+
+	#[test]
+	fn frameRangeParsesAnExplicitWindow() {
+		assert_eq!("100:200".parse::<FrameRange>().unwrap(), FrameRange { start: Some(100), end: Some(200) });
+	}
+
+	#[test]
+	fn frameRangeParsesOmittedSides() {
+		assert_eq!(":200".parse::<FrameRange>().unwrap(), FrameRange { start: None, end: Some(200) });
+		assert_eq!("100:".parse::<FrameRange>().unwrap(), FrameRange { start: Some(100), end: None });
+		assert_eq!(":".parse::<FrameRange>().unwrap(), FrameRange { start: None, end: None });
+	}
+
+	#[test]
+	fn frameRangeParsesNegativeIndices() {
+		assert_eq!("-10:".parse::<FrameRange>().unwrap(), FrameRange { start: Some(-10), end: None });
+	}
+
+	#[test]
+	fn frameRangeRejectsAMissingColon() {
+		assert!("100".parse::<FrameRange>().is_err());
+		assert!("nope:200".parse::<FrameRange>().is_err());
+	}
+
+	#[test]
+	fn resolveFrameRangeSlicesAnExplicitWindow() {
+		assert_eq!(resolveFrameRange(FrameRange { start: Some(100), end: Some(200) }, 500), 100..200);
+	}
+
+	#[test]
+	fn resolveFrameRangeFillsInOmittedSides() {
+		assert_eq!(resolveFrameRange(FrameRange { start: None, end: Some(200) }, 500), 0..200);
+		assert_eq!(resolveFrameRange(FrameRange { start: Some(100), end: None }, 500), 100..500);
+	}
+
+	#[test]
+	fn resolveFrameRangeCountsNegativeIndicesFromTheEnd() {
+		assert_eq!(resolveFrameRange(FrameRange { start: Some(-10), end: None }, 100), 90..100);
+	}
+
+	#[test]
+	fn resolveFrameRangeClampsOutOfBoundsIndicesInsteadOfPanicking() {
+		assert_eq!(resolveFrameRange(FrameRange { start: Some(-1000), end: Some(1000) }, 100), 0..100);
+	}
+
+	#[test]
+	fn resolveFrameRangeNeverProducesAnInvertedRange() {
+		assert_eq!(resolveFrameRange(FrameRange { start: Some(200), end: Some(100) }, 500), 200..200);
+	}
+
+	#[test]
+	fn rangeInputsSlicesThenSteps() {
+		let inputs: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("{i}.png"))).collect();
+		let result = rangeInputs(inputs, Some(FrameRange { start: Some(2), end: Some(8) }), 2).unwrap();
+		assert_eq!(result, vec![PathBuf::from("2.png"), PathBuf::from("4.png"), PathBuf::from("6.png")]);
+	}
+
+	#[test]
+	fn rangeInputsRejectsAZeroStep() {
+		let inputs = vec![PathBuf::from("a.png")];
+		assert!(rangeInputs(inputs, None, 0).is_err());
+	}
+
+	#[test]
+	fn clampFrameRangeClampsLdrChannelsToTheByteEquivalent() {
+		let img = RgbImage::from_pixel(1, 1, Rgb([5, 128, 250]));
+		let DecodedFrame::Ldr(result) = clampFrameRange(DecodedFrame::Ldr(img), 10.0 / 255.0, 240.0 / 255.0) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([10, 128, 240]));
+	}
+
+	#[test]
+	fn clampFrameRangeClampsHdrChannelsDirectly() {
+		let img = Rgb32FImage::from_pixel(1, 1, Rgb([-0.2, 0.5, 1.5]));
+		let DecodedFrame::Hdr(result) = clampFrameRange(DecodedFrame::Hdr(img), 0.0, 1.0) else {
+			panic!("expected Hdr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([0.0, 0.5, 1.0]));
+	}
+
+	#[test]
+	fn clipSamplesExcludesOutOfRangeSamples() {
+		let samples = [10.0, 200.0, 50.0, 250.0];
+		let clipped = clipSamples(&samples, Some((20.0, 220.0))).into_owned();
+		assert_eq!(clipped, vec![200.0, 50.0]);
+	}
+
+	#[test]
+	fn clipSamplesFallsBackToUnfilteredWhenEverySampleIsExcluded() {
+		let samples = [10.0, 5.0];
+		let clipped = clipSamples(&samples, Some((100.0, 200.0))).into_owned();
+		assert_eq!(clipped, samples.to_vec());
+	}
+
+	#[test]
+	fn withClipRangeAppliesTheFilterBeforeDelegating() {
+		let reducer = withClipRange::<f32>(Box::new(|samples| samples.iter().sum()), Some((20.0, 220.0)));
+		assert_eq!(reducer(&[10.0, 200.0, 50.0, 250.0]), 250.0);
+	}
+
+	#[test]
+	fn excludeClippedSamplesDropsSamplesAtOrBeyondTheBounds() {
+		let samples = [0.0, 10.0, 128.0, 250.0, 255.0];
+		let filtered = excludeClippedSamples(&samples, Some((0.0, 255.0))).into_owned();
+		assert_eq!(filtered, vec![10.0, 128.0, 250.0]);
+	}
+
+	#[test]
+	fn excludeClippedSamplesFallsBackToUnfilteredWhenEverySampleIsClipped() {
+		let samples = [0.0, 255.0, 0.0];
+		let filtered = excludeClippedSamples(&samples, Some((0.0, 255.0))).into_owned();
+		assert_eq!(filtered, samples.to_vec());
+	}
+
+	#[test]
+	fn withIgnoreClippedAppliesTheFilterBeforeDelegating() {
+		let reducer = withIgnoreClipped::<f32>(Box::new(|samples| samples.iter().sum()), Some((0.0, 255.0)));
+		assert_eq!(reducer(&[0.0, 10.0, 255.0]), 10.0);
+	}
+
+	#[test]
+	fn removeGradientFlattensALinearGradientToUniform() {
+		let width = 6;
+		let height = 6;
+		let mut img = Rgb32FImage::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				let value = 0.1 + 0.05 * x as f32;
+				img.put_pixel(x, y, image::Rgb([value, value, value]));
+			}
+		}
+		removeGradientInPlace(&mut img, 1, REC709_LUMA_COEFFS);
+		let mut values: Vec<f32> = img.pixels().map(|p| p.0[0]).collect();
+		values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		let spread = values[values.len() - 1] - values[0];
+		assert!(spread < 1e-3, "expected a near-uniform result, got spread {spread}");
+	}
+
+	#[test]
+	fn removeGradientPreservesTheBackgroundMean() {
+		let width = 6;
+		let height = 6;
+		let mut img = Rgb32FImage::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				let value = 0.2 + 0.03 * x as f32 - 0.02 * y as f32;
+				img.put_pixel(x, y, image::Rgb([value, value, value]));
+			}
+		}
+		let meanBefore: f32 = img.pixels().map(|p| p.0[0]).sum::<f32>() / (width * height) as f32;
+		removeGradientInPlace(&mut img, 1, REC709_LUMA_COEFFS);
+		let meanAfter: f32 = img.pixels().map(|p| p.0[0]).sum::<f32>() / (width * height) as f32;
+		assert!((meanBefore - meanAfter).abs() < 1e-3, "expected mean brightness to be preserved, got {meanBefore} vs {meanAfter}");
+	}
+
+	#[test]
+	fn removeGradientLeavesATooSmallImageUntouched() {
+		let mut img = Rgb32FImage::new(1, 1);
+		img.put_pixel(0, 0, image::Rgb([0.5, 0.5, 0.5]));
+		removeGradientInPlace(&mut img, 2, REC709_LUMA_COEFFS);
+		assert_eq!(*img.get_pixel(0, 0), image::Rgb([0.5, 0.5, 0.5]));
+	}
+
+	#[test]
+	fn sample1DLinearlyInterpolatesBetweenEntries() {
+		let table = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 1.0]];
+		let mid = sample1D(&table, 0.5, 0);
+		assert!((mid - 0.5).abs() < 1e-6);
+		assert!((sample1D(&table, 0.5, 1) - 0.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn sample3DTrilinearInterpolatesAtTheMidpoint() {
+		// A 2x2x2 cube where the value is just the red coordinate; sampling
+		// halfway between the low and high red corners should land at 0.5
+		// regardless of green/blue.
+		let data = vec![
+			[0.0, 0.0, 0.0],
+			[1.0, 0.0, 0.0],
+			[0.0, 0.0, 0.0],
+			[1.0, 0.0, 0.0],
+			[0.0, 0.0, 0.0],
+			[1.0, 0.0, 0.0],
+			[0.0, 0.0, 0.0],
+			[1.0, 0.0, 0.0],
+		];
+		let [r, _, _] = sample3DTrilinear(2, &data, 0.5, 0.3, 0.7);
+		assert!((r - 0.5).abs() < 1e-6, "expected 0.5, got {r}");
+	}
+
+	#[test]
+	fn parseCubeLutParses1DAndSkipsMetadataLines() {
+		let dir = std::env::temp_dir();
+		let path = dir.join(format!("imgstack-test-lut-{}.cube", std::process::id()));
+		std::fs::write(&path, "TITLE \"test\"\nLUT_1D_SIZE 2\nDOMAIN_MIN 0.0 0.0 0.0\nDOMAIN_MAX 1.0 1.0 1.0\n0.0 0.0 0.0\n1.0 1.0 1.0\n").unwrap();
+		let lut = parseCubeLut(&path).unwrap();
+		std::fs::remove_file(&path).ok();
+		let Lut::OneD(table) = lut else {
+			panic!("expected a 1D LUT");
+		};
+		assert_eq!(table, vec![[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]);
+	}
+
+	#[test]
+	fn parseCubeLutRejectsAMismatchedEntryCount() {
+		let dir = std::env::temp_dir();
+		let path = dir.join(format!("imgstack-test-lut-bad-{}.cube", std::process::id()));
+		std::fs::write(&path, "LUT_1D_SIZE 3\n0.0 0.0 0.0\n1.0 1.0 1.0\n").unwrap();
+		let result = parseCubeLut(&path);
+		std::fs::remove_file(&path).ok();
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parseCubeLutRejectsAnUndersizedTable() {
+		let dir = std::env::temp_dir();
+		let path = dir.join(format!("imgstack-test-lut-empty-{}.cube", std::process::id()));
+		std::fs::write(&path, "LUT_1D_SIZE 0\n").unwrap();
+		let result = parseCubeLut(&path);
+		std::fs::remove_file(&path).ok();
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn applyLutMapsPixelsAndPreservesLdr() {
+		let table = vec![[1.0, 1.0, 1.0], [1.0, 1.0, 1.0]];
+		let lut = Lut::OneD(table);
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])));
+		let DecodedFrame::Ldr(result) = applyLut(frame, &lut) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([255, 255, 255]));
+	}
+
+	#[test]
+	fn subtractDarkSaturatesInsteadOfWrapping() {
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([10, 100, 255])));
+		let dark = RgbImage::from_pixel(1, 1, Rgb([20, 30, 0]));
+		let DecodedFrame::Ldr(result) = subtractDark(frame, &dark) else {
+			panic!("expected Ldr output");
+		};
+		let Rgb([r, g, b]) = *result.get_pixel(0, 0);
+		assert_eq!((r, g, b), (0, 70, 255));
+	}
+
+	#[test]
+	fn biasThenDarkThenFlatYieldsTheExpectedFlattenedResult() {
+		// Bias removes the fixed read-noise floor, dark removes the remaining
+		// thermal signal, and flat corrects the vignetting that's left.
+		let bias = RgbImage::from_pixel(1, 1, Rgb([10, 10, 10]));
+		let dark = RgbImage::from_pixel(1, 1, Rgb([20, 20, 20]));
+		let flat = RgbImage::from_pixel(1, 1, Rgb([128, 128, 128]));
+		let flatMean = imageMeanSample(&flat);
+
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([130, 130, 130])));
+		let frame = subtractDark(frame, &bias);
+		let frame = subtractDark(frame, &dark);
+		let DecodedFrame::Ldr(result) = divideFlat(frame, &flat, flatMean) else {
+			panic!("expected Ldr output");
+		};
+		// (130 - 10 - 20) = 100, then divided by a flat exactly at its own
+		// mean leaves the value unchanged.
+		assert_eq!(*result.get_pixel(0, 0), Rgb([100, 100, 100]));
+	}
+
+	#[test]
+	fn divideFlatCorrectsVignettingTowardFlatMean() {
+		// A flat with one dim corner and one bright corner should pull inputs
+		// at those same positions toward the flat's overall mean level.
+		let mut flat = RgbImage::from_pixel(2, 1, Rgb([200, 200, 200]));
+		flat.put_pixel(0, 0, Rgb([100, 100, 100]));
+		let flatMean = imageMeanSample(&flat);
+
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(2, 1, Rgb([100, 100, 100])));
+		let DecodedFrame::Ldr(result) = divideFlat(frame, &flat, flatMean) else {
+			panic!("expected Ldr output");
+		};
+		let dimCorner = result.get_pixel(0, 0).0[0];
+		let brightCorner = result.get_pixel(1, 0).0[0];
+		assert!(dimCorner > 100, "expected dim corner brightened, got {dimCorner}");
+		assert!(brightCorner < 100, "expected bright corner dimmed, got {brightCorner}");
+	}
+
+	#[test]
+	fn divideFlatTreatsZeroFlatPixelAsUncorrected() {
+		let flat = RgbImage::from_pixel(1, 1, Rgb([0, 0, 0]));
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([42, 42, 42])));
+		let DecodedFrame::Ldr(result) = divideFlat(frame, &flat, 128.0) else {
+			panic!("expected Ldr output");
+		};
+		let Rgb([r, g, b]) = *result.get_pixel(0, 0);
+		assert_eq!((r, g, b), (42, 42, 42));
+	}
+
+	#[test]
+	fn applySelfFlatLeavesAUniformImageUnchanged() {
+		let img = RgbImage::from_pixel(4, 4, Rgb([100, 100, 100]));
+		let DecodedFrame::Ldr(result) = applySelfFlat(DecodedFrame::Ldr(img), 5.0) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(2, 2), Rgb([100, 100, 100]));
+	}
+
+	#[test]
+	fn applySelfFlatFlattensVignetting() {
+		let width = 20;
+		let height = 20;
+		let mut img = Rgb32FImage::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				let dx = x as f32 - width as f32 / 2.0;
+				let dy = y as f32 - height as f32 / 2.0;
+				let dist = (dx * dx + dy * dy).sqrt();
+				img.put_pixel(x, y, Rgb([(0.8 - dist * 0.02).max(0.05); 3]));
+			}
+		}
+		let cornerBefore = img.get_pixel(0, 0).0[0];
+		let DecodedFrame::Hdr(result) = applySelfFlat(DecodedFrame::Hdr(img), 8.0) else {
+			panic!("expected Hdr output");
+		};
+		let center = result.get_pixel(width / 2, height / 2).0[0];
+		let corner = result.get_pixel(0, 0).0[0];
+		assert!(corner > cornerBefore, "expected the dim corner brightened toward the profile mean, got {corner}");
+		assert!((center - corner).abs() < 0.3, "expected illumination flattened, got center {center} corner {corner}");
+	}
+
+	#[test]
+	fn applySelfFlatLeavesAnAllBlackImageUnchanged() {
+		let img = RgbImage::from_pixel(2, 2, Rgb([0, 0, 0]));
+		let DecodedFrame::Ldr(result) = applySelfFlat(DecodedFrame::Ldr(img), 5.0) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([0, 0, 0]));
+	}
+
+	#[test]
+	fn correctBadPixelsReplacesAHotPixelWithItsNeighborMedian() {
+		let mut img = RgbImage::from_pixel(3, 3, Rgb([10, 10, 10]));
+		img.put_pixel(1, 1, Rgb([255, 255, 255]));
+		let frame = DecodedFrame::Ldr(img);
+		let DecodedFrame::Ldr(result) = correctBadPixels(frame, &[(1, 1)]) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(1, 1), Rgb([10, 10, 10]));
+	}
+
+	#[test]
+	fn correctBadPixelsUsesFewerNeighborsAtAnEdge() {
+		let mut img = RgbImage::from_pixel(2, 2, Rgb([10, 10, 10]));
+		img.put_pixel(0, 0, Rgb([255, 255, 255]));
+		let frame = DecodedFrame::Ldr(img);
+		let DecodedFrame::Ldr(result) = correctBadPixels(frame, &[(0, 0)]) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([10, 10, 10]));
+	}
+
+	#[test]
+	fn correctBadPixelsLeavesA1x1FrameUntouched() {
+		let img = RgbImage::from_pixel(1, 1, Rgb([255, 0, 0]));
+		let frame = DecodedFrame::Ldr(img);
+		let DecodedFrame::Ldr(result) = correctBadPixels(frame, &[(0, 0)]) else {
+			panic!("expected Ldr output");
+		};
+		assert_eq!(*result.get_pixel(0, 0), Rgb([255, 0, 0]));
+	}
+
+	#[test]
+	fn parseBadPixelsReadsOneCoordinatePerLineAndSkipsBlanks() {
+		let coords = parseBadPixels("1 2\n\n3 4\n").unwrap();
+		assert_eq!(coords, vec![(1, 2), (3, 4)]);
+	}
+
+	#[test]
+	fn parseBadPixelsRejectsAMalformedLine() {
+		assert!(parseBadPixels("1 2 3").is_err());
+		assert!(parseBadPixels("1").is_err());
+	}
+
+	#[test]
+	fn resizeFrameIfNeededLeavesMatchingSizeAlone() {
+		let img = RgbImage::from_pixel(4, 4, Rgb([1, 2, 3]));
+		let frame = resizeFrameIfNeeded(DecodedFrame::Ldr(img.clone()), Some(ResizeFilter::Nearest), (4, 4));
+		let DecodedFrame::Ldr(result) = frame else { panic!("expected Ldr") };
+		assert_eq!(result.dimensions(), (4, 4));
+	}
+
+	#[test]
+	fn resizeFrameIfNeededResizesToTarget() {
+		let img = RgbImage::from_pixel(4, 4, Rgb([1, 2, 3]));
+		let frame = resizeFrameIfNeeded(DecodedFrame::Ldr(img), Some(ResizeFilter::Nearest), (2, 2));
+		let DecodedFrame::Ldr(result) = frame else { panic!("expected Ldr") };
+		assert_eq!(result.dimensions(), (2, 2));
+	}
+
+	#[test]
+	fn decodeInputFramesRejectsADecodedSizeThatDisagreesWithTheHeaderExpectation() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(4, 4, Rgb([1, 2, 3])).save(file.path()).unwrap();
+		let args = Args::parse_from(["imgstack", "-o", "out.png"]);
+		let calibration = Calibration { bias: None, dark: None, flat: None, badPixels: Vec::new() };
+		let progress = Progress::new(1, true);
+		let err = decodeInputFrames(file.path(), &args, &calibration, &progress, (8, 8), None, (0, 0)).unwrap_err();
+		assert!(format!("{err:#}").contains("4x4"));
+	}
+
+	#[test]
+	fn ensureOutputDirExistsErrorsOnAMissingParent() {
+		let dir = tempfile::tempdir().unwrap();
+		let outFile = dir.path().join("missing").join("out.png");
+		let err = ensureOutputDirExists(&outFile, false).unwrap_err();
+		assert!(format!("{err:#}").contains("--create-dirs"));
+		assert!(!outFile.parent().unwrap().exists());
+	}
+
+	#[test]
+	fn ensureOutputDirExistsCreatesAMissingParentWhenAsked() {
+		let dir = tempfile::tempdir().unwrap();
+		let outFile = dir.path().join("missing").join("nested").join("out.png");
+		ensureOutputDirExists(&outFile, true).unwrap();
+		assert!(outFile.parent().unwrap().is_dir());
+	}
+
+	#[test]
+	fn ensureOutputDirExistsIsANoOpWhenTheParentAlreadyExists() {
+		let dir = tempfile::tempdir().unwrap();
+		let outFile = dir.path().join("out.png");
+		ensureOutputDirExists(&outFile, false).unwrap();
+	}
+
+	#[test]
+	fn decodeImageWithMmapMatchesTheBufferedDecode() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(3, 2, Rgb([10, 20, 30])).save(file.path()).unwrap();
+		let progress = Progress::new(1, true);
+
+		let buffered = decodeImage(file.path(), &progress, None, false, false).unwrap();
+		let mapped = decodeImage(file.path(), &progress, None, true, false).unwrap();
+		assert_eq!(buffered.intoRgb8(), mapped.intoRgb8());
+	}
+
+	#[test]
+	fn decodeImageRejectsAnEmptyFileWithAHelpfulMessage() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		let progress = Progress::new(1, true);
+		let err = decodeImage(file.path(), &progress, None, false, false).unwrap_err();
+		let message = format!("{err:#}");
+		assert!(message.contains("empty"));
+		assert!(message.contains("--skip-errors"));
+	}
+
+	#[test]
+	fn decodeImageRejectsATruncatedPngWithAHelpfulMessage() {
+		let wholeFile = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(4, 4, Rgb([1, 2, 3])).save(wholeFile.path()).unwrap();
+		let wholeBytes = std::fs::read(wholeFile.path()).unwrap();
+
+		let truncated = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		std::fs::write(truncated.path(), &wholeBytes[..wholeBytes.len() / 2]).unwrap();
+
+		let progress = Progress::new(1, true);
+		let err = decodeImage(truncated.path(), &progress, None, false, false).unwrap_err();
+		let message = format!("{err:#}");
+		assert!(message.contains("--skip-errors"));
+	}
+
+	#[test]
+	fn downscaleFrameScalesDownAndRoundsUpToAtLeastOnePixel() {
+		let img = RgbImage::from_pixel(8, 8, Rgb([1, 2, 3]));
+		let DecodedFrame::Ldr(result) = downscaleFrame(DecodedFrame::Ldr(img), 0.25) else {
+			panic!("expected Ldr")
+		};
+		assert_eq!(result.dimensions(), (2, 2));
+
+		let img = RgbImage::from_pixel(2, 2, Rgb([1, 2, 3]));
+		let DecodedFrame::Ldr(result) = downscaleFrame(DecodedFrame::Ldr(img), 0.1) else {
+			panic!("expected Ldr")
+		};
+		assert_eq!(result.dimensions(), (1, 1));
+	}
+
+	#[test]
+	fn downscaleToMaxWidthShrinksAWiderImagePreservingAspectRatio() {
+		let img = RgbImage::from_pixel(400, 200, Rgb([1, 2, 3]));
+		let result = downscaleToMaxWidth(img, 100);
+		assert_eq!(result.dimensions(), (100, 50));
+	}
+
+	#[test]
+	fn downscaleToMaxWidthLeavesANarrowerImageUntouched() {
+		let img = RgbImage::from_pixel(50, 50, Rgb([1, 2, 3]));
+		let result = downscaleToMaxWidth(img, 100);
+		assert_eq!(result.dimensions(), (50, 50));
+	}
+
+	#[test]
+	fn isStdoutOnlyMatchesTheDashPlaceholder() {
+		assert!(isStdout(Path::new("-")));
+		assert!(!isStdout(Path::new("output.png")));
+		assert!(!isStdout(Path::new("./-")));
+	}
+
+	#[test]
+	fn isStdinOnlyMatchesTheDashPlaceholder() {
+		assert!(isStdin(Path::new("-")));
+		assert!(!isStdin(Path::new("input.png")));
+	}
+
+	#[test]
+	fn cropFrameLeavesFrameAloneWhenNoRoiGiven() {
+		let img = RgbImage::from_pixel(4, 4, Rgb([1, 2, 3]));
+		let frame = cropFrame(DecodedFrame::Ldr(img), None);
+		let DecodedFrame::Ldr(result) = frame else { panic!("expected Ldr") };
+		assert_eq!(result.dimensions(), (4, 4));
+	}
+
+	#[test]
+	fn cropFrameExtractsTheRequestedRegion() {
+		let mut img = RgbImage::from_pixel(4, 4, Rgb([0, 0, 0]));
+		img.put_pixel(2, 1, Rgb([9, 9, 9]));
+		let frame = cropFrame(DecodedFrame::Ldr(img), Some((2, 1, 2, 2)));
+		let DecodedFrame::Ldr(result) = frame else { panic!("expected Ldr") };
+		assert_eq!(result.dimensions(), (2, 2));
+		assert_eq!(*result.get_pixel(0, 0), Rgb([9, 9, 9]));
+	}
+
+	#[test]
+	fn applyMaskKeepsTheFirstFrameWhereMasked() {
+		let first = RgbImage::from_pixel(2, 1, Rgb([1, 1, 1]));
+		let stacked = RgbImage::from_pixel(2, 1, Rgb([200, 200, 200]));
+		let mask = GrayImage::from_raw(2, 1, vec![0, 255]).unwrap();
+		let result = applyMask(DecodedFrame::Ldr(stacked), &DecodedFrame::Ldr(first), &mask, 0);
+		let DecodedFrame::Ldr(result) = result else { panic!("expected Ldr") };
+		assert_eq!(*result.get_pixel(0, 0), Rgb([1, 1, 1]));
+		assert_eq!(*result.get_pixel(1, 0), Rgb([200, 200, 200]));
+	}
+
+	#[test]
+	fn estimateStarShiftFindsTheDominantOffset() {
+		// Three stars all shifted by the same (3, -2), plus one candidate
+		// star that doesn't correspond to anything in the reference: the
+		// real shift should still win the vote.
+		let reference = [(10.0, 10.0), (50.0, 50.0), (80.0, 20.0)];
+		let candidate = [(13.0, 8.0), (53.0, 48.0), (83.0, 18.0), (5.0, 5.0)];
+		assert_eq!(estimateStarShift(&reference, &candidate), (-3, 2));
+	}
+
+	#[test]
+	fn estimateStarShiftWithNoStarsIsZero() {
+		assert_eq!(estimateStarShift(&[], &[]), (0, 0));
+	}
+
+	#[test]
+	fn shiftImageFillsVacatedPixelsWithBlack() {
+		let mut img = RgbImage::from_pixel(3, 3, Rgb([9, 9, 9]));
+		img.put_pixel(0, 0, Rgb([255, 0, 0]));
+		let shifted = shiftImage(img, 1, 0);
+		assert_eq!(*shifted.get_pixel(1, 0), Rgb([255, 0, 0]));
+		assert_eq!(*shifted.get_pixel(0, 0), Rgb([0, 0, 0]));
+	}
+
+	#[test]
+	fn alignFramesLeavesTheFirstFrameUnchanged() {
+		let reference = RgbImage::from_pixel(5, 5, Rgb([1, 2, 3]));
+		let other = RgbImage::from_pixel(5, 5, Rgb([4, 5, 6]));
+		let (aligned, offsets) = alignFrames(vec![DecodedFrame::Ldr(reference.clone()), DecodedFrame::Ldr(other)], 10, REC709_LUMA_COEFFS);
+		let DecodedFrame::Ldr(first) = &aligned[0] else { panic!("expected Ldr") };
+		assert_eq!(*first, reference);
+		assert_eq!(offsets[0], (0, 0));
+	}
+
+	#[test]
+	fn overlapRegionIsTheFullCanvasWithNoShift() {
+		assert_eq!(overlapRegion(&[(0, 0), (0, 0)], 100, 50).unwrap(), (0, 0, 100, 50));
+	}
+
+	#[test]
+	fn overlapRegionShrinksToWhatEveryFrameCovers() {
+		// Frame 1 shifted right/down by (3, 2), frame 2 shifted left/up by
+		// (-1, -4): the region every frame still covers loses 3px off the
+		// left, 1px off the right, 2px off the top, and 4px off the bottom.
+		assert_eq!(overlapRegion(&[(0, 0), (3, 2), (-1, -4)], 100, 50).unwrap(), (3, 2, 96, 44));
+	}
+
+	#[test]
+	fn overlapRegionErrorsWhenShiftsLeaveNoCommonRegion() {
+		assert!(overlapRegion(&[(0, 0), (60, 0)], 100, 50).is_err());
+	}
+
+	#[test]
+	fn phaseCorrelationShiftDetectsAKnownShift() {
+		let squareAt = |left: u32, top: u32| {
+			let mut img = GrayImage::from_pixel(32, 32, Luma([0]));
+			for y in top..top + 6 {
+				for x in left..left + 6 {
+					img.put_pixel(x, y, Luma([255]));
+				}
+			}
+			img
+		};
+		let reference = squareAt(4, 4);
+		// The square moved right by 3 and down by 2 in `candidate`, so
+		// aligning it back onto `reference` needs a shift of (-3, -2).
+		let candidate = squareAt(7, 6);
+		assert_eq!(phaseCorrelationShift(&reference, &candidate, 32, 32), (-3, -2));
+	}
+
+	#[test]
+	fn downscaleLuminanceLeavesSmallFramesAtTheirOwnSize() {
+		let img = RgbImage::from_pixel(16, 16, Rgb([100, 100, 100]));
+		let gray = downscaleLuminance(&DecodedFrame::Ldr(img), REC709_LUMA_COEFFS);
+		assert_eq!(gray.dimensions(), (16, 16));
+	}
+
+	#[test]
+	fn parseOffsetsReadsOneShiftPerLineAndSkipsBlanks() {
+		let offsets = parseOffsets("1 2\n\n-3 4\n").unwrap();
+		assert_eq!(offsets, vec![(1, 2), (-3, 4)]);
+	}
+
+	#[test]
+	fn parseOffsetsRejectsAMalformedLine() {
+		assert!(parseOffsets("1 2 3").is_err());
+		assert!(parseOffsets("1").is_err());
+	}
+
+	#[test]
+	fn progressHasNoBarWhenQuiet() {
+		let progress = Progress::new(10, true);
+		assert!(progress.bar.is_none());
+	}
+
+	#[test]
+	fn validateInputsRejectsAMissingFirstFile() {
+		let args = Args::parse_from(["imgstack", "-o", "out.png"]);
+		let inputs = vec![PathBuf::from("does-not-exist-first.png"), PathBuf::from("does-not-exist-second.png")];
+		let progress = Progress::new(inputs.len() as u64, true);
+		let err = validateInputs(&inputs, &args, &progress).unwrap_err();
+		assert!(format!("{err:#}").contains("does-not-exist-first.png"));
+	}
+
+	#[test]
+	fn validateInputsRejectsAMissingLaterFile() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])).save(file.path()).unwrap();
+		let args = Args::parse_from(["imgstack", "-o", "out.png"]);
+		let inputs = vec![file.path().to_path_buf(), PathBuf::from("does-not-exist-second.png")];
+		let progress = Progress::new(inputs.len() as u64, true);
+		let err = validateInputs(&inputs, &args, &progress).unwrap_err();
+		assert!(format!("{err:#}").contains("does-not-exist-second.png"));
+	}
+
+	#[test]
+	fn validateInputsWarnsAboutADuplicateInputByDefault() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])).save(file.path()).unwrap();
+		let args = Args::parse_from(["imgstack", "-o", "out.png"]);
+		let inputs = vec![file.path().to_path_buf(), file.path().to_path_buf()];
+		let progress = Progress::new(inputs.len() as u64, true);
+		let dims = validateInputs(&inputs, &args, &progress).unwrap();
+		assert_eq!(dims, (1, 1));
+		assert!(progress.warnings().iter().any(|w| w.contains("duplicate")));
+	}
+
+	#[test]
+	fn validateInputsRejectsADuplicateInputWithNoDuplicates() {
+		let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+		RgbImage::from_pixel(1, 1, Rgb([0, 0, 0])).save(file.path()).unwrap();
+		let args = Args::parse_from(["imgstack", "-o", "out.png", "--no-duplicates"]);
+		let inputs = vec![file.path().to_path_buf(), file.path().to_path_buf()];
+		let progress = Progress::new(inputs.len() as u64, true);
+		let err = validateInputs(&inputs, &args, &progress).unwrap_err();
+		assert!(format!("{err:#}").contains("duplicate"));
+	}
+
+	#[test]
+	fn isStackableFileAcceptsKnownImageAndVideoExtensionsOnly() {
+		assert!(isStackableFile(Path::new("frame.png")));
+		assert!(isStackableFile(Path::new("clip.mp4")));
+		assert!(!isStackableFile(Path::new("readme.txt")));
+	}
+
+	#[test]
+	fn expandInputGlobsLeavesLiteralPathsUntouched() {
+		let inputs = vec![PathBuf::from("frame1.png"), PathBuf::from("frame2.png")];
+		assert_eq!(expandInputGlobs(inputs.clone()).unwrap(), inputs);
+	}
+
+	#[test]
+	fn sortInputsNoneLeavesOrderUntouched() {
+		let inputs = vec![PathBuf::from("b.png"), PathBuf::from("a.png")];
+		assert_eq!(sortInputs(inputs.clone(), SortOrder::None).unwrap(), inputs);
+	}
+
+	#[test]
+	fn sortInputsNameSortsLexically() {
+		let inputs = vec![PathBuf::from("frame2.png"), PathBuf::from("frame10.png"), PathBuf::from("frame1.png")];
+		assert_eq!(
+			sortInputs(inputs, SortOrder::Name).unwrap(),
+			vec![PathBuf::from("frame1.png"), PathBuf::from("frame10.png"), PathBuf::from("frame2.png")]
+		);
+	}
+
+	#[test]
+	fn sampleIndicesEvenlyAlwaysIncludesTheFirstAndLastIndex() {
+		let indices = sampleIndicesEvenly(10, 3);
+		assert_eq!(indices, vec![0, 4, 9]);
+	}
+
+	#[test]
+	fn sampleIndicesEvenlyOfOneTakesTheFirstFrame() {
+		assert_eq!(sampleIndicesEvenly(10, 1), vec![0]);
+	}
+
+	#[test]
+	fn sampleIndicesRandomlyIsReproducibleForTheSameSeed() {
+		let a = sampleIndicesRandomly(20, 5, 42);
+		let b = sampleIndicesRandomly(20, 5, 42);
+		assert_eq!(a, b);
+		assert_eq!(a.len(), 5);
+	}
+
+	#[test]
+	fn sampleInputsIsANoOpWhenThereAreNotMoreInputsThanCount() {
+		let inputs = vec![PathBuf::from("a.png"), PathBuf::from("b.png")];
+		assert_eq!(sampleInputs(inputs.clone(), 5, SampleStrategy::Even, 0), inputs);
+	}
+
+	#[test]
+	fn sampleInputsEvenlyPreservesInputOrder() {
+		let inputs: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("frame{i}.png"))).collect();
+		let sampled = sampleInputs(inputs, 3, SampleStrategy::Even, 0);
+		assert_eq!(sampled, vec![PathBuf::from("frame0.png"), PathBuf::from("frame4.png"), PathBuf::from("frame9.png")]);
+	}
+
+	#[test]
+	fn compositeOverAnOpaqueTopFullyReplacesBottom() {
+		let bottom = RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 255]));
+		let top = RgbaImage::from_pixel(1, 1, Rgba([200, 150, 100, 255]));
+		let result = compositeOver(bottom, top);
+		assert_eq!(*result.get_pixel(0, 0), Rgba([200, 150, 100, 255]));
+	}
+
+	#[test]
+	fn compositeOverBlendsSemiTransparentTopWithBottom() {
+		let bottom = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]));
+		let top = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128]));
+		let Rgba([r, g, b, a]) = *compositeOver(bottom, top).get_pixel(0, 0);
+		// Roughly a 50/50 blend of white over black, fully opaque result.
+		assert_eq!(a, 255);
+		assert!((120..=135).contains(&r), "expected r near 128, got {r}");
+		assert_eq!(r, g);
+		assert_eq!(g, b);
+	}
+
+	#[test]
+	fn resolveLumaCoeffsNormalizesToSumToOne() {
+		let (r, g, b) = resolveLumaCoeffs(&[1.0, 1.0, 1.0]).unwrap();
+		assert!((r - 1.0 / 3.0).abs() < 1e-6 && (g - 1.0 / 3.0).abs() < 1e-6 && (b - 1.0 / 3.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn resolveLumaCoeffsLeavesAnAlreadyNormalizedTripleUnchanged() {
+		assert_eq!(resolveLumaCoeffs(&[0.2126, 0.7152, 0.0722]).unwrap(), REC709_LUMA_COEFFS);
+	}
+
+	#[test]
+	fn resolveLumaCoeffsRejectsTheWrongNumberOfValues() {
+		assert!(resolveLumaCoeffs(&[1.0, 1.0]).is_err());
+		assert!(resolveLumaCoeffs(&[1.0, 1.0, 1.0, 1.0]).is_err());
+	}
+
+	#[test]
+	fn resolveLumaCoeffsRejectsANonPositiveSum() {
+		assert!(resolveLumaCoeffs(&[0.0, 0.0, 0.0]).is_err());
+		assert!(resolveLumaCoeffs(&[1.0, -1.0, 0.0]).is_err());
+	}
+
+	#[test]
+	fn sharpnessOfAFlatImageIsZero() {
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(4, 4, Rgb([100, 100, 100])));
+		assert_eq!(sharpnessOf(&frame, REC709_LUMA_COEFFS), 0.0);
+	}
+
+	#[test]
+	fn sharpnessOfACheckerboardExceedsAGentleGradient() {
+		let checkerboard = DecodedFrame::Ldr(RgbImage::from_fn(4, 4, |x, y| {
+			let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+			Rgb([value, value, value])
+		}));
+		let gradient = DecodedFrame::Ldr(RgbImage::from_fn(4, 4, |x, _| Rgb([x as u8 * 20, x as u8 * 20, x as u8 * 20])));
+		assert!(sharpnessOf(&checkerboard, REC709_LUMA_COEFFS) > sharpnessOf(&gradient, REC709_LUMA_COEFFS));
+	}
+
+	#[test]
+	fn scaleFrameBrightnessClampsAtWhiteForLdr() {
+		let frame = DecodedFrame::Ldr(RgbImage::from_pixel(1, 1, Rgb([200, 100, 50])));
+		let DecodedFrame::Ldr(scaled) = scaleFrameBrightness(frame, 2.0) else { panic!("expected Ldr") };
+		assert_eq!(*scaled.get_pixel(0, 0), Rgb([255, 200, 100]));
+	}
+
+	#[test]
+	fn matchExposureScalesDimmerFramesUpToTheFirstFramesBrightness() {
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([100, 100, 100])));
+		let dim = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([50, 50, 50])));
+		let progress = Progress::new(2, true);
+		let matched = matchExposure(vec![(bright, 1.0), (dim, 1.0)], None, &progress, REC709_LUMA_COEFFS);
+		let DecodedFrame::Ldr(secondFrame) = &matched[1].0 else { panic!("expected Ldr") };
+		assert_eq!(*secondFrame.get_pixel(0, 0), Rgb([100, 100, 100]));
+	}
+
+	#[test]
+	fn matchExposureClampsScaleForANearBlackFrame() {
+		let bright = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([200, 200, 200])));
+		let black = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([1, 1, 1])));
+		let progress = Progress::new(2, true);
+		let matched = matchExposure(vec![(bright, 1.0), (black, 1.0)], None, &progress, REC709_LUMA_COEFFS);
+		let DecodedFrame::Ldr(secondFrame) = &matched[1].0 else { panic!("expected Ldr") };
+		// Unclamped this would scale by 200x; the clamp keeps it from
+		// amplifying a near-black frame into blown-out noise.
+		assert_eq!(*secondFrame.get_pixel(0, 0), Rgb([10, 10, 10]));
+	}
+
+	#[test]
+	fn matchExposureUsesExposureReferenceInsteadOfTheFirstFrameWhenGiven() {
+		let first = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([100, 100, 100])));
+		let second = DecodedFrame::Ldr(RgbImage::from_pixel(2, 2, Rgb([50, 50, 50])));
+		let progress = Progress::new(2, true);
+		let matched = matchExposure(vec![(first, 1.0), (second, 1.0)], Some(200.0), &progress, REC709_LUMA_COEFFS);
+		let DecodedFrame::Ldr(firstFrame) = &matched[0].0 else { panic!("expected Ldr") };
+		let DecodedFrame::Ldr(secondFrame) = &matched[1].0 else { panic!("expected Ldr") };
+		// Both frames get scaled up toward the 200.0 external reference,
+		// rather than the second frame scaling toward the first's 100.0.
+		assert_eq!(*firstFrame.get_pixel(0, 0), Rgb([200, 200, 200]));
+		assert_eq!(*secondFrame.get_pixel(0, 0), Rgb([100, 100, 100]));
+	}
+
+	#[test]
+	fn fusionPyramidLevelsShrinksToZeroForSmallImages() {
+		assert_eq!(fusionPyramidLevels(16, 16), 0);
+		assert_eq!(fusionPyramidLevels(32, 4096), 0);
+	}
+
+	#[test]
+	fn fusionPyramidLevelsGrowsWithSizeAndCapsAtFive() {
+		assert_eq!(fusionPyramidLevels(64, 64), 1);
+		assert_eq!(fusionPyramidLevels(100_000, 100_000), 5);
+	}
+
+	#[test]
+	fn normalizeFusionWeightsSumsToOneAtEveryPixel() {
+		let a = WeightMap::from_pixel(2, 1, Luma([1.0]));
+		let mut b = WeightMap::from_pixel(2, 1, Luma([3.0]));
+		b.put_pixel(1, 0, Luma([0.0]));
+		let normalized = normalizeFusionWeights(vec![a, b]);
+		for x in 0..2 {
+			let sum: f32 = normalized.iter().map(|w| w.get_pixel(x, 0).0[0]).sum();
+			assert!((sum - 1.0).abs() < 1e-6, "expected weights at x={x} to sum to 1.0, got {sum}");
+		}
+	}
+
+	#[test]
+	fn laplacianEnergyMapIsZeroOnAFlatImage() {
+		let flat = WeightMap::from_pixel(4, 4, Luma([0.5]));
+		let energy = laplacianEnergyMap(&flat);
+		assert!(energy.pixels().all(|p| p.0[0] == 0.0));
+	}
+
+	#[test]
+	fn laplacianEnergyMapSpikesAtASharpEdge() {
+		let mut img = WeightMap::from_pixel(4, 4, Luma([0.0]));
+		for y in 0..4 {
+			img.put_pixel(2, y, Luma([1.0]));
+			img.put_pixel(3, y, Luma([1.0]));
+		}
+		let energy = laplacianEnergyMap(&img);
+		assert!(energy.get_pixel(2, 1).0[0] > 0.0);
+	}
+
+	#[test]
+	fn exposureFusionOfIdenticalFramesReproducesTheInput() {
+		let frame: Rgb32FImage = RgbImage::from_pixel(8, 8, Rgb([120, 120, 120])).convert();
+		let fused = exposureFusion(vec![frame.clone(), frame.clone(), frame.clone()], REC709_LUMA_COEFFS, None).unwrap();
+		for (a, b) in fused.pixels().zip(frame.pixels()) {
+			for c in 0..3 {
+				assert!((a.0[c] - b.0[c]).abs() < 0.01, "expected fused output to match input, got {a:?} vs {b:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn smoothedSharpnessMapWithZeroRadiusMatchesTheRawEnergyMap() {
+		let frame: Rgb32FImage = RgbImage::from_fn(4, 4, |x, y| {
+			let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+			Rgb([value, value, value])
+		})
+		.convert();
+		let gray = WeightMap::from_fn(4, 4, |x, y| Luma([luminanceOf(frame.get_pixel(x, y).0[0], frame.get_pixel(x, y).0[1], frame.get_pixel(x, y).0[2], REC709_LUMA_COEFFS)]));
+		assert_eq!(smoothedSharpnessMap(&frame, 0, REC709_LUMA_COEFFS), laplacianEnergyMap(&gray));
+	}
+
+	#[test]
+	fn focusStackPicksTheSharpFrameAtInteriorPixels() {
+		let sharp: Rgb32FImage = RgbImage::from_fn(4, 4, |x, y| {
+			let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+			Rgb([value, value, value])
+		})
+		.convert();
+		let blurry: Rgb32FImage = RgbImage::from_pixel(4, 4, Rgb([128, 128, 128])).convert();
+		let (stacked, sourceMap) = focusStack(vec![blurry, sharp.clone()], 0, REC709_LUMA_COEFFS).unwrap();
+		assert_eq!(*stacked.get_pixel(1, 1), *sharp.get_pixel(1, 1));
+		assert_eq!(sourceMap.get_pixel(1, 1).0[0], 1, "expected the source map to record the sharp frame (index 1) as the winner");
+	}
+
+	#[test]
+	fn yCbCrRoundTripsLosslessly() {
+		let img: Rgb32FImage = RgbImage::from_fn(2, 2, |x, y| Rgb([(x * 50) as u8, (y * 80) as u8, 200])).convert();
+		let planes = toYCbCr(&img, REC709_LUMA_COEFFS);
+		let restored = fromYCbCr(&planes, img.width(), img.height(), REC709_LUMA_COEFFS);
+		for (a, b) in img.pixels().zip(restored.pixels()) {
+			for c in 0..3 {
+				assert!((a.0[c] - b.0[c]).abs() < 1e-4, "channel {c}: {} != {}", a.0[c], b.0[c]);
+			}
+		}
+	}
+
+	#[test]
+	fn lumaChromaSplitStackOfIdenticalFramesReproducesTheInput() {
+		let frame: Rgb32FImage = RgbImage::from_fn(2, 2, |x, y| Rgb([(x * 60) as u8, (y * 90) as u8, 40])).convert();
+		let stacked = lumaChromaSplitStack(vec![frame.clone(), frame.clone(), frame.clone()], ChromaSource::Median, REC709_LUMA_COEFFS).unwrap();
+		for (a, b) in frame.pixels().zip(stacked.pixels()) {
+			for c in 0..3 {
+				assert!((a.0[c] - b.0[c]).abs() < 1e-3, "channel {c}: {} != {}", a.0[c], b.0[c]);
+			}
+		}
+	}
+
+	#[test]
+	fn lumaChromaSplitStackAveragesLumaButTakesChromaFromTheFirstFrameWhenConfigured() {
+		let dim = Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.5, 0.5]));
+		let bright = Rgb32FImage::from_pixel(1, 1, Rgb([0.5, 0.5, 0.9]));
+		let stacked = lumaChromaSplitStack(vec![dim, bright], ChromaSource::First, REC709_LUMA_COEFFS).unwrap();
+		// The first frame is a neutral gray (zero chroma), so whatever luma
+		// comes out of the average, the recombined pixel stays gray.
+		let averagedY = (luminanceOf(0.5, 0.5, 0.5, REC709_LUMA_COEFFS) + luminanceOf(0.5, 0.5, 0.9, REC709_LUMA_COEFFS)) / 2.0;
+		let Rgb([r, g, b]) = *stacked.get_pixel(0, 0);
+		assert!((r - averagedY).abs() < 1e-4);
+		assert!((g - averagedY).abs() < 1e-4);
+		assert!((b - averagedY).abs() < 1e-4);
+	}
+
+	#[test]
+	fn channelStatsReportsMinMaxMeanPerChannel() {
+		let img = RgbImage::from_fn(2, 1, |x, _| if x == 0 { Rgb([10, 20, 30]) } else { Rgb([30, 40, 50]) });
+		assert_eq!(channelStats(&img), [(10, 30, 20.0), (20, 40, 30.0), (30, 50, 40.0)]);
+	}
+
+	#[test]
+	fn channelStatsOfASolidImageHasEqualMinMaxMean() {
+		let img = RgbImage::from_pixel(3, 3, Rgb([5, 5, 5]));
+		assert_eq!(channelStats(&img), [(5, 5, 5.0), (5, 5, 5.0), (5, 5, 5.0)]);
+	}
+
+	#[test]
+	fn clippedFractionOfAnAllWhiteImageIsOne() {
+		let img = RgbImage::from_pixel(2, 2, Rgb([255, 255, 255]));
+		assert_eq!(clippedFraction(&img), 1.0);
+	}
+
+	#[test]
+	fn clippedFractionCountsBothBlackAndWhiteExtremes() {
+		let img = RgbImage::from_fn(2, 1, |x, _| if x == 0 { Rgb([0, 0, 0]) } else { Rgb([128, 128, 128]) });
+		assert_eq!(clippedFraction(&img), 0.5);
+	}
+
+	#[test]
+	fn clippedFractionOfAMidToneImageIsZero() {
+		let img = RgbImage::from_pixel(2, 2, Rgb([128, 128, 128]));
+		assert_eq!(clippedFraction(&img), 0.0);
+	}
+
+	#[test]
+	fn outputFormatExplicitChoiceOverridesAMismatchedExtension() {
+		let format = OutputFormat::WebP.resolve(Path::new("out.png")).unwrap();
+		assert_eq!(format, image::ImageFormat::WebP);
+	}
+
+	#[test]
+	fn outputFormatAutoInfersFromTheExtension() {
+		let format = OutputFormat::Auto.resolve(Path::new("out.jpg")).unwrap();
+		assert_eq!(format, image::ImageFormat::Jpeg);
+	}
+
+	#[test]
+	fn outputFormatAutoErrorsOnAnUnrecognizedExtension() {
+		assert!(OutputFormat::Auto.resolve(Path::new("out.unknownext")).is_err());
+	}
 }